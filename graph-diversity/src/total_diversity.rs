@@ -95,12 +95,11 @@ fn effective_path_diversity(
         for idx in 0..num {
             let mut div_min_path_i = f32::MAX;
             let mut alternate_paths = k_shortest_paths[0..num].to_vec();
-            // the base path should be the only item returned by drain
-            if let Some(base_path) = alternate_paths.drain(idx..idx + 1).last() {
-                for path in alternate_paths {
-                    let div = simlib::sim::Simulation::calculate_path_diversity(&base_path, &path);
-                    div_min_path_i = f32::min(div_min_path_i, div);
-                }
+            // idx is always in bounds so the base path is always found
+            let base_path = alternate_paths.remove(idx);
+            for path in alternate_paths {
+                let div = simlib::sim::Simulation::calculate_path_diversity(&base_path, &path);
+                div_min_path_i = f32::min(div_min_path_i, div);
             }
             aggregated_div_src_dest += div_min_path_i;
         }
@@ -126,7 +125,7 @@ fn get_shortest_paths(
     let mut path_finder = PathFinder::new(
         source.clone(),
         dest.clone(),
-        0,
+        amount,
         &graph_copy,
         routing_metric,
         simlib::PaymentParts::Single,
@@ -164,11 +163,12 @@ mod tests {
         let routing_metric = RoutingMetric::MinFee;
         let path = std::path::Path::new("../test_data/trivial_connected.json");
         let graph = Graph::to_sim_graph(
-            &network_parser::Graph::from_json_file(&path, network_parser::GraphSource::Lnresearch)
+            &network_parser::Graph::from_json_file(path, network_parser::GraphSource::Lnresearch)
                 .unwrap(),
             network_parser::GraphSource::Lnresearch,
         );
-        let amount = 10;
+        // channels do not forward HTLCs below their htlc_minimum_msat
+        let amount = 100000;
         let pairs = vec![
             ("034".to_owned(), "025".to_owned()),
             ("025".to_owned(), "036".to_owned()),
@@ -187,13 +187,14 @@ mod tests {
         let lambdas = [0.5];
         let path = std::path::Path::new("../test_data/trivial_connected.json");
         let graph = Graph::to_sim_graph(
-            &network_parser::Graph::from_json_file(&path, network_parser::GraphSource::Lnresearch)
+            &network_parser::Graph::from_json_file(path, network_parser::GraphSource::Lnresearch)
                 .unwrap(),
             network_parser::GraphSource::Lnresearch,
         );
         let k = 2;
         let routing_metric = RoutingMetric::MinFee;
-        let amount = 10;
+        // channels do not forward HTLCs below their htlc_minimum_msat
+        let amount = 100000;
         let source = String::from("034");
         let dest = String::from("036");
         let k_shortest_paths =
@@ -218,13 +219,14 @@ mod tests {
     fn calculate_graph_diversity() {
         let path = std::path::Path::new("../test_data/trivial_connected.json");
         let graph = Graph::to_sim_graph(
-            &network_parser::Graph::from_json_file(&path, network_parser::GraphSource::Lnresearch)
+            &network_parser::Graph::from_json_file(path, network_parser::GraphSource::Lnresearch)
                 .unwrap(),
             network_parser::GraphSource::Lnresearch,
         );
         let k = 2;
         let lambdas = [0.5];
-        let amount = 10;
+        // channels do not forward HTLCs below their htlc_minimum_msat
+        let amount = 100000;
         let routing_metric = RoutingMetric::MinFee;
         let ids = graph.get_node_ids();
         let mut diversity = 0.0;
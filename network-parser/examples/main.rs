@@ -4,7 +4,6 @@ use std::io::BufReader;
 
 /// Basic usage example.
 /// Should be executed in the workspace's top level directory because of the path.
-
 fn main() {
     let file_path = "./data/gossip-20220823.json";
     let file = File::open(file_path).expect("Failed to open file");
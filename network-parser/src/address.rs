@@ -0,0 +1,307 @@
+use crate::*;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// Used if an address does not include a port
+pub const DEFAULT_PORT: u16 = 9735;
+
+/// A node's announced network address, see BOLT 7's address descriptors
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Address {
+    IPv4 {
+        addr: Ipv4Addr,
+        port: u16,
+    },
+    IPv6 {
+        addr: Ipv6Addr,
+        port: u16,
+    },
+    /// Host including the `.onion` suffix
+    TorV2 {
+        host: String,
+        port: u16,
+    },
+    TorV3 {
+        host: String,
+        port: u16,
+    },
+    Dns {
+        hostname: String,
+        port: u16,
+    },
+}
+
+/// How a node can be reached according to its announced addresses
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Reachability {
+    /// Only IP addresses or DNS hostnames
+    Clearnet,
+    /// Only onion services
+    Tor,
+    /// Both clearnet and Tor
+    Hybrid,
+    /// No addresses at all
+    Unannounced,
+}
+
+/// Counts of address types and node reachability in a graph
+#[derive(Serialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct AddressStats {
+    pub ipv4: usize,
+    pub ipv6: usize,
+    pub torv2: usize,
+    pub torv3: usize,
+    pub dns: usize,
+    pub clearnet_only_nodes: usize,
+    pub tor_only_nodes: usize,
+    pub hybrid_nodes: usize,
+    pub unannounced_nodes: usize,
+}
+
+impl Address {
+    /// Chooses the type from the host's format; onion v2 hosts have 16 characters and v3 56
+    pub fn new(host: &str, port: u16) -> Self {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if let Some(onion) = host.strip_suffix(".onion") {
+            if onion.len() == 16 {
+                Self::TorV2 {
+                    host: host.to_owned(),
+                    port,
+                }
+            } else {
+                Self::TorV3 {
+                    host: host.to_owned(),
+                    port,
+                }
+            }
+        } else if let Ok(addr) = host.parse::<Ipv4Addr>() {
+            Self::IPv4 { addr, port }
+        } else if let Ok(addr) = host.parse::<Ipv6Addr>() {
+            Self::IPv6 { addr, port }
+        } else {
+            Self::Dns {
+                hostname: host.to_owned(),
+                port,
+            }
+        }
+    }
+
+    /// Parses `host`, `host:port` or `[ipv6]:port`. None if the port is not a number.
+    pub fn parse(addr: &str) -> Option<Self> {
+        // a bare IPv6 address has several colons and no port
+        if addr.parse::<Ipv6Addr>().is_ok() {
+            return Some(Self::new(addr, DEFAULT_PORT));
+        }
+        match addr.rsplit_once(':') {
+            Some((host, port)) => Some(Self::new(host, port.parse().ok()?)),
+            None => Some(Self::new(addr, DEFAULT_PORT)),
+        }
+    }
+
+    /// The address type's name as used by lnresearch and CLN
+    pub fn network(&self) -> &'static str {
+        match self {
+            Self::IPv4 { .. } => "ipv4",
+            Self::IPv6 { .. } => "ipv6",
+            Self::TorV2 { .. } => "torv2",
+            Self::TorV3 { .. } => "torv3",
+            Self::Dns { .. } => "dns",
+        }
+    }
+
+    pub fn host(&self) -> String {
+        match self {
+            Self::IPv4 { addr, .. } => addr.to_string(),
+            Self::IPv6 { addr, .. } => addr.to_string(),
+            Self::TorV2 { host, .. } | Self::TorV3 { host, .. } => host.clone(),
+            Self::Dns { hostname, .. } => hostname.clone(),
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        match self {
+            Self::IPv4 { port, .. }
+            | Self::IPv6 { port, .. }
+            | Self::TorV2 { port, .. }
+            | Self::TorV3 { port, .. }
+            | Self::Dns { port, .. } => *port,
+        }
+    }
+
+    pub fn is_tor(&self) -> bool {
+        matches!(self, Self::TorV2 { .. } | Self::TorV3 { .. })
+    }
+}
+
+/// `host:port` with IPv6 addresses in brackets
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::IPv6 { addr, port } => write!(f, "[{}]:{}", addr, port),
+            _ => write!(f, "{}:{}", self.host(), self.port()),
+        }
+    }
+}
+
+impl Node {
+    pub fn reachability(&self) -> Reachability {
+        let tor = self.addresses.iter().any(|a| a.is_tor());
+        let clearnet = self.addresses.iter().any(|a| !a.is_tor());
+        match (clearnet, tor) {
+            (true, false) => Reachability::Clearnet,
+            (false, true) => Reachability::Tor,
+            (true, true) => Reachability::Hybrid,
+            (false, false) => Reachability::Unannounced,
+        }
+    }
+}
+
+impl AddressStats {
+    pub fn from_nodes<'a>(nodes: impl IntoIterator<Item = &'a Node>) -> Self {
+        let mut stats = Self::default();
+        for node in nodes {
+            for addr in node.addresses.iter() {
+                match addr {
+                    Address::IPv4 { .. } => stats.ipv4 += 1,
+                    Address::IPv6 { .. } => stats.ipv6 += 1,
+                    Address::TorV2 { .. } => stats.torv2 += 1,
+                    Address::TorV3 { .. } => stats.torv3 += 1,
+                    Address::Dns { .. } => stats.dns += 1,
+                }
+            }
+            match node.reachability() {
+                Reachability::Clearnet => stats.clearnet_only_nodes += 1,
+                Reachability::Tor => stats.tor_only_nodes += 1,
+                Reachability::Hybrid => stats.hybrid_nodes += 1,
+                Reachability::Unannounced => stats.unannounced_nodes += 1,
+            }
+        }
+        stats
+    }
+}
+
+impl fmt::Display for AddressStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} clearnet-only, {} Tor-only, {} hybrid and {} unannounced nodes; \
+            addresses: {} IPv4, {} IPv6, {} Tor v2, {} Tor v3, {} DNS",
+            self.clearnet_only_nodes,
+            self.tor_only_nodes,
+            self.hybrid_nodes,
+            self.unannounced_nodes,
+            self.ipv4,
+            self.ipv6,
+            self.torv2,
+            self.torv3,
+            self.dns
+        )
+    }
+}
+
+impl Graph {
+    pub fn address_stats(&self) -> AddressStats {
+        AddressStats::from_nodes(&self.nodes)
+    }
+
+    /// See [nodes_by_reachability]
+    pub fn nodes_by_reachability(&self, reachability: Reachability) -> Vec<ID> {
+        nodes_by_reachability(&self.nodes, reachability)
+    }
+}
+
+/// IDs of the nodes with the given reachability
+pub fn nodes_by_reachability<'a>(
+    nodes: impl IntoIterator<Item = &'a Node>,
+    reachability: Reachability,
+) -> Vec<ID> {
+    nodes
+        .into_iter()
+        .filter(|n| n.reachability() == reachability)
+        .map(|n| n.id.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ONION_V3: &str = "wu5mkpokybtbf6dwdaepnujbzxpm6mqqqm2hwob6ndt5k74iujd2pdyd.onion";
+
+    #[test]
+    fn parse_addresses() {
+        assert_eq!(
+            Address::parse("1.2.3.4:9736"),
+            Some(Address::IPv4 {
+                addr: Ipv4Addr::new(1, 2, 3, 4),
+                port: 9736
+            })
+        );
+        let ipv6 = Address::parse("[2001:db8::1]:9735").unwrap();
+        assert_eq!(ipv6.network(), "ipv6");
+        assert_eq!(ipv6.to_string(), "[2001:db8::1]:9735");
+        assert_eq!(Address::parse("2001:db8::1"), Some(ipv6));
+        assert_eq!(
+            Address::parse(ONION_V3),
+            Some(Address::TorV3 {
+                host: ONION_V3.to_owned(),
+                port: DEFAULT_PORT
+            })
+        );
+        assert_eq!(
+            Address::parse("3g2upl4pq6kufc4m.onion:9735")
+                .unwrap()
+                .network(),
+            "torv2"
+        );
+        assert_eq!(
+            Address::parse("ln.example.com:9735"),
+            Some(Address::Dns {
+                hostname: "ln.example.com".to_owned(),
+                port: 9735
+            })
+        );
+        assert_eq!(Address::parse("1.2.3.4:port"), None);
+    }
+
+    #[test]
+    fn reachability_and_stats() {
+        let node = |id: &str, addresses: &[&str]| Node {
+            id: id.to_owned(),
+            addresses: addresses
+                .iter()
+                .map(|a| Address::parse(a).unwrap())
+                .collect(),
+            ..Default::default()
+        };
+        let mut graph = Graph::default();
+        graph
+            .nodes
+            .insert(node("clear", &["1.2.3.4:9735", "[::1]:9735"]));
+        graph.nodes.insert(node("tor", &[ONION_V3]));
+        graph
+            .nodes
+            .insert(node("hybrid", &["ln.example.com", ONION_V3]));
+        graph.nodes.insert(node("none", &[]));
+        assert_eq!(
+            graph.nodes_by_reachability(Reachability::Tor),
+            vec!["tor".to_owned()]
+        );
+        assert_eq!(
+            graph.address_stats(),
+            AddressStats {
+                ipv4: 1,
+                ipv6: 1,
+                torv2: 0,
+                torv3: 2,
+                dns: 1,
+                clearnet_only_nodes: 1,
+                tor_only_nodes: 1,
+                hybrid_nodes: 1,
+                unannounced_nodes: 1,
+            }
+        );
+    }
+}
@@ -0,0 +1,59 @@
+use clap::Parser;
+use network_parser::{Graph, GraphDiff, GraphSource};
+use std::path::{Path, PathBuf};
+
+#[derive(clap::Parser)]
+#[command(name = "graph-diff", version, about)]
+/// Reports what changed between two snapshots of the channel graph
+struct Cli {
+    /// Path to the JSON file describing the older topology
+    old_graph_file: PathBuf,
+    /// Path to the JSON file describing the newer topology
+    new_graph_file: PathBuf,
+    #[arg(long = "graph-source", short = 'g')]
+    graph_type: GraphSource,
+    /// Graph source of the newer snapshot if it differs from --graph-source
+    #[arg(long = "new-graph-source")]
+    new_graph_type: Option<GraphSource>,
+    /// Write the JSON report to this file instead of stdout
+    #[arg(long = "out", short = 'o')]
+    output_file: Option<PathBuf>,
+    /// Only print the summary counts
+    #[arg(long)]
+    summary: bool,
+}
+
+fn read_graph(path: &Path, graph_source: GraphSource) -> Graph {
+    Graph::from_json_file(path, graph_source).unwrap_or_else(|e| {
+        eprintln!("Error in graph file {}: {}. Exiting.", path.display(), e);
+        std::process::exit(-1)
+    })
+}
+
+fn main() {
+    let args = Cli::parse();
+    let new_graph_type = args
+        .new_graph_type
+        .unwrap_or_else(|| args.graph_type.clone());
+    let old = read_graph(&args.old_graph_file, args.graph_type);
+    let new = read_graph(&args.new_graph_file, new_graph_type);
+    let diff = GraphDiff::from_graphs(&old, &new);
+    let json = if args.summary {
+        serde_json::to_string_pretty(&diff.summary).map_err(network_parser::ParseError::from)
+    } else {
+        diff.to_json_str()
+    };
+    let json = json.unwrap_or_else(|e| {
+        eprintln!("Error serialising diff: {}. Exiting.", e);
+        std::process::exit(-1)
+    });
+    match args.output_file {
+        Some(path) => {
+            if let Err(e) = std::fs::write(&path, json) {
+                eprintln!("Error writing {}: {}. Exiting.", path.display(), e);
+                std::process::exit(-1)
+            }
+        }
+        None => println!("{}", json),
+    }
+}
@@ -0,0 +1,302 @@
+use crate::funding::normalise_scid;
+use crate::*;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// What changed between two snapshots of the same network
+#[derive(Debug, Default, Clone, Serialize, PartialEq, Eq)]
+pub struct GraphDiff {
+    pub summary: DiffSummary,
+    pub nodes_joined: Vec<ID>,
+    pub nodes_left: Vec<ID>,
+    /// Short channel ids in `BxTxO` form, i.e. without the direction some sources append
+    pub channels_opened: Vec<String>,
+    pub channels_closed: Vec<String>,
+    pub policy_changes: Vec<PolicyChange>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, PartialEq, Eq)]
+pub struct DiffSummary {
+    pub nodes_joined: usize,
+    pub nodes_left: usize,
+    pub channels_opened: usize,
+    pub channels_closed: usize,
+    /// Number of directed edges with at least one changed field
+    pub policy_changes: usize,
+}
+
+/// The changed routing policy of one direction of a channel
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct PolicyChange {
+    pub channel_id: String,
+    pub source: ID,
+    pub destination: ID,
+    pub changes: Vec<FieldChange>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub old: usize,
+    pub new: usize,
+}
+
+impl Graph {
+    /// Compares this graph to a later snapshot
+    pub fn diff(&self, new: &Graph) -> GraphDiff {
+        GraphDiff::from_graphs(self, new)
+    }
+
+    fn node_ids(&self) -> BTreeSet<&ID> {
+        self.nodes.iter().map(|n| &n.id).collect()
+    }
+
+    fn short_channel_ids(&self) -> BTreeSet<String> {
+        self.edges
+            .values()
+            .flatten()
+            .map(|e| normalise_scid(&e.channel_id))
+            .collect()
+    }
+
+    /// Directed edges by short channel id and source since LND uses the same id for both
+    /// directions
+    fn directed_edges(&self) -> BTreeMap<(String, &ID), &Edge> {
+        self.edges
+            .values()
+            .flatten()
+            .map(|e| ((normalise_scid(&e.channel_id), &e.source), e))
+            .collect()
+    }
+}
+
+impl GraphDiff {
+    /// Channels are matched by their short channel id and policies by short channel id and
+    /// source, so the snapshots may come from different sources. Policies are only compared for
+    /// directions present in both graphs.
+    pub fn from_graphs(old: &Graph, new: &Graph) -> Self {
+        let (old_nodes, new_nodes) = (old.node_ids(), new.node_ids());
+        let nodes_joined: Vec<ID> = new_nodes.difference(&old_nodes).cloned().cloned().collect();
+        let nodes_left: Vec<ID> = old_nodes.difference(&new_nodes).cloned().cloned().collect();
+        let (old_channels, new_channels) = (old.short_channel_ids(), new.short_channel_ids());
+        let channels_opened: Vec<String> =
+            new_channels.difference(&old_channels).cloned().collect();
+        let channels_closed: Vec<String> =
+            old_channels.difference(&new_channels).cloned().collect();
+        let new_edges = new.directed_edges();
+        let policy_changes: Vec<PolicyChange> = old
+            .directed_edges()
+            .into_iter()
+            .filter_map(|(key, old_edge)| {
+                new_edges
+                    .get(&key)
+                    .and_then(|new_edge| PolicyChange::from_edges(old_edge, new_edge))
+            })
+            .collect();
+        Self {
+            summary: DiffSummary {
+                nodes_joined: nodes_joined.len(),
+                nodes_left: nodes_left.len(),
+                channels_opened: channels_opened.len(),
+                channels_closed: channels_closed.len(),
+                policy_changes: policy_changes.len(),
+            },
+            nodes_joined,
+            nodes_left,
+            channels_opened,
+            channels_closed,
+            policy_changes,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.summary == DiffSummary::default()
+    }
+
+    pub fn to_json_str(&self) -> Result<String, ParseError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+impl PolicyChange {
+    /// None if none of the compared fields changed
+    fn from_edges(old: &Edge, new: &Edge) -> Option<Self> {
+        let changes: Vec<FieldChange> = [
+            ("fee_base_msat", old.fee_base_msat, new.fee_base_msat),
+            (
+                "fee_proportional_millionths",
+                old.fee_proportional_millionths,
+                new.fee_proportional_millionths,
+            ),
+            (
+                "cltv_expiry_delta",
+                old.cltv_expiry_delta,
+                new.cltv_expiry_delta,
+            ),
+            (
+                "htlc_maximum_msat",
+                old.htlc_maximum_msat,
+                new.htlc_maximum_msat,
+            ),
+        ]
+        .into_iter()
+        .filter(|(_, old, new)| old != new)
+        .map(|(field, old, new)| FieldChange { field, old, new })
+        .collect();
+        if changes.is_empty() {
+            None
+        } else {
+            Some(Self {
+                channel_id: new.channel_id.clone(),
+                source: new.source.clone(),
+                destination: new.destination.clone(),
+                changes,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> Graph {
+        let path = Path::new("../test_data/lnbook_example.json");
+        Graph::from_json_file(path, GraphSource::Lnresearch).unwrap()
+    }
+
+    #[test]
+    fn identical_snapshots() {
+        let graph = snapshot();
+        assert!(graph.diff(&graph).is_empty());
+    }
+
+    #[test]
+    fn nodes_channels_and_policies_changed() {
+        let old = snapshot();
+        let mut new = old.clone();
+        // dina leaves together with her channels
+        new.nodes.retain(|n| n.id != "dina");
+        new.edges.remove("dina");
+        for edges in new.edges.values_mut() {
+            edges.retain(|e| e.destination != "dina");
+        }
+        new.nodes.insert(Node {
+            id: "eve".to_owned(),
+            ..Default::default()
+        });
+        new.edges.entry("eve".to_owned()).or_default().insert(Edge {
+            channel_id: "eve1".to_owned(),
+            source: "eve".to_owned(),
+            destination: "bob".to_owned(),
+            ..Default::default()
+        });
+        let mut alice_edges: Vec<Edge> = new.edges.remove("alice").unwrap().into_iter().collect();
+        alice_edges[0].fee_base_msat += 1;
+        alice_edges[0].cltv_expiry_delta += 2;
+        new.edges
+            .insert("alice".to_owned(), alice_edges.into_iter().collect());
+
+        let actual = old.diff(&new);
+        let closed: Vec<String> = old
+            .edges
+            .values()
+            .flatten()
+            .filter(|e| e.source == "dina" || e.destination == "dina")
+            .map(|e| e.channel_id.clone())
+            .collect::<BTreeSet<String>>()
+            .into_iter()
+            .collect();
+        assert_eq!(actual.nodes_joined, vec!["eve".to_owned()]);
+        assert_eq!(actual.nodes_left, vec!["dina".to_owned()]);
+        assert_eq!(actual.channels_opened, vec!["eve1".to_owned()]);
+        assert_eq!(actual.channels_closed, closed);
+        assert_eq!(
+            actual.policy_changes,
+            vec![PolicyChange {
+                channel_id: "alice1".to_owned(),
+                source: "alice".to_owned(),
+                destination: "bob".to_owned(),
+                changes: vec![
+                    FieldChange {
+                        field: "fee_base_msat",
+                        old: 20,
+                        new: 21
+                    },
+                    FieldChange {
+                        field: "cltv_expiry_delta",
+                        old: 5,
+                        new: 7
+                    },
+                ],
+            }]
+        );
+        assert_eq!(actual.summary.channels_closed, closed.len());
+        assert_eq!(actual.summary.policy_changes, 1);
+        let json = actual.to_json_str().unwrap();
+        assert!(json.contains(r#""nodes_joined": 1"#));
+    }
+
+    #[test]
+    fn directions_are_matched_by_channel_and_source() {
+        let mut old = Graph::default();
+        let edge = Edge {
+            channel_id: "1".to_owned(),
+            source: "a".to_owned(),
+            destination: "b".to_owned(),
+            ..Default::default()
+        };
+        // LND uses the same id for both directions
+        let reverse = Edge {
+            source: "b".to_owned(),
+            destination: "a".to_owned(),
+            fee_base_msat: 1000,
+            ..edge.clone()
+        };
+        old.edges.insert("a".to_owned(), HashSet::from([edge]));
+        old.edges.insert("b".to_owned(), HashSet::from([reverse]));
+        let actual = old.diff(&old.clone());
+        assert!(actual.is_empty());
+    }
+
+    #[test]
+    fn snapshots_from_different_sources() {
+        let edge = |channel_id: &str, source: &str, destination: &str| Edge {
+            channel_id: channel_id.to_owned(),
+            source: source.to_owned(),
+            destination: destination.to_owned(),
+            fee_base_msat: 1000,
+            ..Default::default()
+        };
+        let mut lnresearch = Graph::default();
+        lnresearch.edges.insert(
+            "a".to_owned(),
+            HashSet::from([edge("714505x2146x0/0", "a", "b")]),
+        );
+        lnresearch.edges.insert(
+            "b".to_owned(),
+            HashSet::from([edge("714505x2146x0/1", "b", "a")]),
+        );
+        // LND's integer id of the same channel for both directions
+        let scid = ((714505u64 << 40) | (2146 << 16)).to_string();
+        let mut lnd = Graph::default();
+        lnd.edges
+            .insert("a".to_owned(), HashSet::from([edge(&scid, "a", "b")]));
+        let mut reverse = edge(&scid, "b", "a");
+        reverse.fee_base_msat = 0;
+        lnd.edges.insert("b".to_owned(), HashSet::from([reverse]));
+
+        let actual = lnresearch.diff(&lnd);
+        assert!(actual.channels_opened.is_empty());
+        assert!(actual.channels_closed.is_empty());
+        assert_eq!(actual.policy_changes.len(), 1);
+        assert_eq!(actual.policy_changes[0].source, "b");
+        assert_eq!(
+            actual.policy_changes[0].changes,
+            vec![FieldChange {
+                field: "fee_base_msat",
+                old: 1000,
+                new: 0
+            }]
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use log::warn;
+use std::fmt;
+
+/// Errors returned when reading a graph
+#[derive(Debug)]
+pub enum ParseError {
+    /// The file could not be read
+    Io(std::io::Error),
+    /// The input is not valid JSON or does not have the shape of the selected graph source
+    Json(serde_json::Error),
+    /// A node or edge lacks a field we cannot do without
+    MissingField {
+        field: &'static str,
+        context: String,
+    },
+    /// A numeric field does not fit into the type used by the graph
+    NumericOverflow {
+        field: &'static str,
+        context: String,
+    },
+    /// A field could not be parsed, e.g. a non-numeric amount in a CSV file
+    InvalidValue {
+        field: &'static str,
+        context: String,
+    },
+}
+
+/// How to treat malformed nodes and edges
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Fail on the first malformed record
+    #[default]
+    Strict,
+    /// Skip malformed records and collect them in a [ParseReport]
+    Lenient,
+}
+
+/// Records skipped while parsing in [ParseMode::Lenient]
+#[derive(Debug, Default)]
+pub struct ParseReport {
+    /// Number of nodes that were skipped
+    pub skipped_nodes: usize,
+    /// Number of edges that were skipped. LND channels count once per direction.
+    pub skipped_edges: usize,
+    /// Why each record was skipped
+    pub errors: Vec<ParseError>,
+}
+
+impl ParseError {
+    pub(crate) fn missing_field(field: &'static str, context: &str) -> Self {
+        Self::MissingField {
+            field,
+            context: context.to_owned(),
+        }
+    }
+
+    pub(crate) fn numeric_overflow(field: &'static str, context: &str) -> Self {
+        Self::NumericOverflow {
+            field,
+            context: context.to_owned(),
+        }
+    }
+
+    pub(crate) fn invalid_value(field: &'static str, context: &str) -> Self {
+        Self::InvalidValue {
+            field,
+            context: context.to_owned(),
+        }
+    }
+}
+
+impl ParseReport {
+    /// Total number of skipped records
+    pub fn num_skipped(&self) -> usize {
+        self.skipped_nodes + self.skipped_edges
+    }
+
+    /// Passes errors on in strict mode and records them in lenient mode
+    pub(crate) fn check_node<T>(
+        &mut self,
+        result: Result<T, ParseError>,
+        mode: ParseMode,
+    ) -> Result<Option<T>, ParseError> {
+        self.check(result, mode, 1, 0)
+    }
+
+    /// Passes errors on in strict mode and records them in lenient mode
+    pub(crate) fn check_edge<T>(
+        &mut self,
+        result: Result<T, ParseError>,
+        mode: ParseMode,
+        num_edges: usize,
+    ) -> Result<Option<T>, ParseError> {
+        self.check(result, mode, 0, num_edges)
+    }
+
+    fn check<T>(
+        &mut self,
+        result: Result<T, ParseError>,
+        mode: ParseMode,
+        num_nodes: usize,
+        num_edges: usize,
+    ) -> Result<Option<T>, ParseError> {
+        match result {
+            Ok(record) => Ok(Some(record)),
+            Err(e) => match mode {
+                ParseMode::Strict => Err(e),
+                ParseMode::Lenient => {
+                    warn!("Skipping record. {}", e);
+                    self.skipped_nodes += num_nodes;
+                    self.skipped_edges += num_edges;
+                    self.errors.push(e);
+                    Ok(None)
+                }
+            },
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "Error reading file: {}", e),
+            Self::Json(e) => write!(f, "Error deserialising JSON: {}", e),
+            Self::MissingField { field, context } => {
+                write!(f, "Missing field {} in {}", field, context)
+            }
+            Self::NumericOverflow { field, context } => {
+                write!(f, "Value of {} out of range in {}", field, context)
+            }
+            Self::InvalidValue { field, context } => {
+                write!(f, "Invalid value of {} in {}", field, context)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Json(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ParseError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ParseError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
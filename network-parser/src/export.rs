@@ -0,0 +1,392 @@
+use crate::*;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+/// Formats for looking at a graph in tools such as Gephi, networkx or Graphviz
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    #[default]
+    GraphMl,
+    Dot,
+    /// One line per edge
+    Csv,
+}
+
+/// Numeric per-node attributes such as the number of payment paths a node was part of.
+/// Maps the attribute's name to the value of each node; nodes without a value are written as 0.
+pub type NodeAttributes = BTreeMap<String, HashMap<ID, usize>>;
+
+/// Node attributes every export writes, which user attributes must not shadow
+const BUILT_IN_NODE_ATTRIBUTES: [&str; 1] = ["alias"];
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::GraphMl => "graphml",
+            Self::Dot => "dot",
+            Self::Csv => "csv",
+        }
+    }
+}
+
+impl Graph {
+    /// See [export_graph]
+    pub fn export(
+        &self,
+        writer: impl Write,
+        format: ExportFormat,
+        node_attributes: &NodeAttributes,
+    ) -> io::Result<()> {
+        export_graph(
+            writer,
+            &self.nodes,
+            self.edges.values().flatten(),
+            format,
+            node_attributes,
+        )
+    }
+
+    /// See [export_graph]
+    pub fn export_to_file(
+        &self,
+        path: &Path,
+        format: ExportFormat,
+        node_attributes: &NodeAttributes,
+    ) -> io::Result<()> {
+        let writer = io::BufWriter::new(File::create(path)?);
+        self.export(writer, format, node_attributes)
+    }
+}
+
+/// Writes the nodes and directed edges in the given format.
+/// Nodes are sorted by ID and edges by source and channel so that the output is deterministic.
+/// Fails without writing anything if a node attribute is named like a built-in one.
+pub fn export_graph<'a>(
+    mut writer: impl Write,
+    nodes: impl IntoIterator<Item = &'a Node>,
+    edges: impl IntoIterator<Item = &'a Edge>,
+    format: ExportFormat,
+    node_attributes: &NodeAttributes,
+) -> io::Result<()> {
+    if let Some(name) = node_attributes
+        .keys()
+        .find(|name| BUILT_IN_NODE_ATTRIBUTES.contains(&name.as_str()))
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Node attribute {} clashes with a built-in attribute", name),
+        ));
+    }
+    let mut nodes: Vec<&Node> = nodes.into_iter().collect();
+    nodes.sort_by(|a, b| a.id.cmp(&b.id));
+    let mut edges: Vec<&Edge> = edges.into_iter().collect();
+    edges.sort_by(|a, b| (&a.source, &a.channel_id).cmp(&(&b.source, &b.channel_id)));
+    match format {
+        ExportFormat::GraphMl => write_graphml(&mut writer, &nodes, &edges, node_attributes),
+        ExportFormat::Dot => write_dot(&mut writer, &nodes, &edges, node_attributes),
+        ExportFormat::Csv => write_csv(&mut writer, &edges, node_attributes),
+    }?;
+    writer.flush()
+}
+
+/// The numeric edge attributes in the order they are written
+fn edge_attributes(edge: &Edge) -> [(&'static str, usize); 8] {
+    [
+        ("fee_base_msat", edge.fee_base_msat),
+        (
+            "fee_proportional_millionths",
+            edge.fee_proportional_millionths,
+        ),
+        ("htlc_minimim_msat", edge.htlc_minimim_msat),
+        ("htlc_maximum_msat", edge.htlc_maximum_msat),
+        ("cltv_expiry_delta", edge.cltv_expiry_delta),
+        ("capacity", edge.capacity),
+        ("balance", edge.balance),
+        ("liquidity", edge.liquidity),
+    ]
+}
+
+fn node_attribute(node_attributes: &NodeAttributes, name: &str, id: &ID) -> usize {
+    node_attributes
+        .get(name)
+        .and_then(|values| values.get(id))
+        .copied()
+        .unwrap_or_default()
+}
+
+fn write_graphml(
+    writer: &mut impl Write,
+    nodes: &[&Node],
+    edges: &[&Edge],
+    node_attributes: &NodeAttributes,
+) -> io::Result<()> {
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        writer,
+        r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+    )?;
+    // node and edge attributes may share names so we prefix the key IDs
+    writeln!(
+        writer,
+        r#"  <key id="n_alias" for="node" attr.name="alias" attr.type="string"/>"#
+    )?;
+    for name in node_attributes.keys() {
+        let name = escape_xml(name);
+        writeln!(
+            writer,
+            r#"  <key id="n_{name}" for="node" attr.name="{name}" attr.type="long"/>"#
+        )?;
+    }
+    writeln!(
+        writer,
+        r#"  <key id="e_channel_id" for="edge" attr.name="channel_id" attr.type="string"/>"#
+    )?;
+    for (name, _) in edge_attributes(&Edge::default()) {
+        writeln!(
+            writer,
+            r#"  <key id="e_{name}" for="edge" attr.name="{name}" attr.type="long"/>"#
+        )?;
+    }
+    writeln!(writer, r#"  <graph id="G" edgedefault="directed">"#)?;
+    for node in nodes {
+        writeln!(writer, r#"    <node id="{}">"#, escape_xml(&node.id))?;
+        writeln!(
+            writer,
+            r#"      <data key="n_alias">{}</data>"#,
+            escape_xml(&node.alias)
+        )?;
+        for name in node_attributes.keys() {
+            writeln!(
+                writer,
+                r#"      <data key="n_{}">{}</data>"#,
+                escape_xml(name),
+                node_attribute(node_attributes, name, &node.id)
+            )?;
+        }
+        writeln!(writer, "    </node>")?;
+    }
+    for (idx, edge) in edges.iter().enumerate() {
+        writeln!(
+            writer,
+            r#"    <edge id="e{}" source="{}" target="{}">"#,
+            idx,
+            escape_xml(&edge.source),
+            escape_xml(&edge.destination)
+        )?;
+        writeln!(
+            writer,
+            r#"      <data key="e_channel_id">{}</data>"#,
+            escape_xml(&edge.channel_id)
+        )?;
+        for (name, value) in edge_attributes(edge) {
+            writeln!(writer, r#"      <data key="e_{name}">{value}</data>"#)?;
+        }
+        writeln!(writer, "    </edge>")?;
+    }
+    writeln!(writer, "  </graph>")?;
+    writeln!(writer, "</graphml>")
+}
+
+fn write_dot(
+    writer: &mut impl Write,
+    nodes: &[&Node],
+    edges: &[&Edge],
+    node_attributes: &NodeAttributes,
+) -> io::Result<()> {
+    writeln!(writer, "digraph G {{")?;
+    for node in nodes {
+        write!(
+            writer,
+            "  \"{}\" [alias=\"{}\"",
+            escape_dot(&node.id),
+            escape_dot(&node.alias)
+        )?;
+        for name in node_attributes.keys() {
+            write!(
+                writer,
+                ", \"{}\"={}",
+                escape_dot(name),
+                node_attribute(node_attributes, name, &node.id)
+            )?;
+        }
+        writeln!(writer, "];")?;
+    }
+    for edge in edges {
+        write!(
+            writer,
+            "  \"{}\" -> \"{}\" [channel_id=\"{}\"",
+            escape_dot(&edge.source),
+            escape_dot(&edge.destination),
+            escape_dot(&edge.channel_id)
+        )?;
+        for (name, value) in edge_attributes(edge) {
+            write!(writer, ", {name}={value}")?;
+        }
+        writeln!(writer, "];")?;
+    }
+    writeln!(writer, "}}")
+}
+
+/// Node attributes are written once for the source and once for the destination of each edge
+fn write_csv(
+    writer: &mut impl Write,
+    edges: &[&Edge],
+    node_attributes: &NodeAttributes,
+) -> io::Result<()> {
+    let mut header = vec!["source".to_owned(), "destination".to_owned()];
+    header.push("channel_id".to_owned());
+    header.extend(
+        edge_attributes(&Edge::default())
+            .iter()
+            .map(|(name, _)| name.to_string()),
+    );
+    for name in node_attributes.keys() {
+        header.push(format!("source_{}", name));
+        header.push(format!("destination_{}", name));
+    }
+    writeln!(writer, "{}", csv_line(&header))?;
+    for edge in edges {
+        let mut line = vec![
+            edge.source.clone(),
+            edge.destination.clone(),
+            edge.channel_id.clone(),
+        ];
+        line.extend(
+            edge_attributes(edge)
+                .iter()
+                .map(|(_, value)| value.to_string()),
+        );
+        for name in node_attributes.keys() {
+            line.push(node_attribute(node_attributes, name, &edge.source).to_string());
+            line.push(node_attribute(node_attributes, name, &edge.destination).to_string());
+        }
+        writeln!(writer, "{}", csv_line(&line))?;
+    }
+    Ok(())
+}
+
+fn csv_line(fields: &[String]) -> String {
+    fields
+        .iter()
+        .map(|field| {
+            if field.contains([',', '"', '\n']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_owned()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn escape_dot(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl clap::ValueEnum for ExportFormat {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::GraphMl, Self::Dot, Self::Csv]
+    }
+
+    fn to_possible_value<'a>(&self) -> Option<clap::builder::PossibleValue> {
+        match self {
+            Self::GraphMl => Some(clap::builder::PossibleValue::new("graphml")),
+            Self::Dot => Some(clap::builder::PossibleValue::new("dot")),
+            Self::Csv => Some(clap::builder::PossibleValue::new("csv")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph() -> Graph {
+        let path = Path::new("../test_data/lnbook_example.json");
+        Graph::from_json_file(path, GraphSource::Lnresearch).unwrap()
+    }
+
+    fn export_to_string(graph: &Graph, format: ExportFormat, attrs: &NodeAttributes) -> String {
+        let mut buf = Vec::new();
+        graph.export(&mut buf, format, attrs).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    fn node_hits() -> NodeAttributes {
+        NodeAttributes::from([(
+            "node_hits".to_owned(),
+            HashMap::from([("bob".to_owned(), 3)]),
+        )])
+    }
+
+    #[test]
+    fn export_graphml() {
+        let graph = graph();
+        let actual = export_to_string(&graph, ExportFormat::GraphMl, &node_hits());
+        assert_eq!(actual.matches("<node id=").count(), graph.nodes.len());
+        assert_eq!(
+            actual.matches("<edge id=").count(),
+            graph.clone().edge_count()
+        );
+        assert!(actual.contains(r#"<key id="n_node_hits" for="node" attr.name="node_hits""#));
+        assert!(actual.contains(r#"<edge id="e0" source="alice" target="bob">"#));
+        assert!(actual.contains(r#"<data key="e_channel_id">alice1</data>"#));
+        assert!(actual.contains(r#"<data key="n_node_hits">3</data>"#));
+    }
+
+    #[test]
+    fn export_dot() {
+        let graph = graph();
+        let actual = export_to_string(&graph, ExportFormat::Dot, &node_hits());
+        assert!(actual.starts_with("digraph G {"));
+        assert!(actual.contains(r#""bob" [alias="", "node_hits"=3];"#));
+        assert!(actual.contains(
+            r#""alice" -> "bob" [channel_id="alice1", fee_base_msat=20, fee_proportional_millionths=0"#
+        ));
+    }
+
+    #[test]
+    fn export_csv() {
+        let graph = graph();
+        let actual = export_to_string(&graph, ExportFormat::Csv, &node_hits());
+        let lines: Vec<&str> = actual.lines().collect();
+        assert_eq!(lines.len(), graph.clone().edge_count() + 1);
+        assert_eq!(
+            lines[0],
+            "source,destination,channel_id,fee_base_msat,fee_proportional_millionths,\
+            htlc_minimim_msat,htlc_maximum_msat,cltv_expiry_delta,capacity,balance,liquidity,\
+            source_node_hits,destination_node_hits"
+        );
+        assert_eq!(lines[1], "alice,bob,alice1,20,0,1000,140000,5,0,0,0,0,3");
+    }
+
+    #[test]
+    fn reject_built_in_attribute_names() {
+        let graph = graph();
+        let attrs = NodeAttributes::from([("alias".to_owned(), HashMap::new())]);
+        for format in [ExportFormat::GraphMl, ExportFormat::Dot, ExportFormat::Csv] {
+            let mut buf = Vec::new();
+            let actual = graph.export(&mut buf, format, &attrs);
+            assert_eq!(actual.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+            assert!(buf.is_empty());
+        }
+    }
+
+    #[test]
+    fn escape_special_characters() {
+        assert_eq!(escape_xml("<a & 'b'>"), "&lt;a &amp; &apos;b&apos;&gt;");
+        assert_eq!(escape_dot(r#"a "b" \c"#), r#"a \"b\" \\c"#);
+        assert_eq!(
+            csv_line(&["a,b".to_owned(), "c\"d".to_owned(), "e".to_owned()]),
+            "\"a,b\",\"c\"\"d\",e"
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+
+/// Features defined in [BOLT 9](https://github.com/lightning/bolts/blob/master/09-features.md)
+/// that are announced in node or channel announcements
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    DataLossProtect,
+    UpfrontShutdownScript,
+    GossipQueries,
+    VarOnionOptin,
+    GossipQueriesEx,
+    StaticRemoteKey,
+    PaymentSecret,
+    BasicMpp,
+    SupportLargeChannel,
+    AnchorOutputs,
+    AnchorsZeroFeeHtlcTx,
+    RouteBlinding,
+    ShutdownAnySegwit,
+    DualFund,
+    Quiesce,
+    OnionMessages,
+    ChannelType,
+    ScidAlias,
+    PaymentMetadata,
+    ZeroConf,
+}
+
+/// The feature bits of a node or channel. Each feature has a compulsory (even) and an optional
+/// (odd) bit.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub struct FeatureSet(BTreeSet<usize>);
+
+impl Feature {
+    pub const ALL: [Feature; 20] = [
+        Self::DataLossProtect,
+        Self::UpfrontShutdownScript,
+        Self::GossipQueries,
+        Self::VarOnionOptin,
+        Self::GossipQueriesEx,
+        Self::StaticRemoteKey,
+        Self::PaymentSecret,
+        Self::BasicMpp,
+        Self::SupportLargeChannel,
+        Self::AnchorOutputs,
+        Self::AnchorsZeroFeeHtlcTx,
+        Self::RouteBlinding,
+        Self::ShutdownAnySegwit,
+        Self::DualFund,
+        Self::Quiesce,
+        Self::OnionMessages,
+        Self::ChannelType,
+        Self::ScidAlias,
+        Self::PaymentMetadata,
+        Self::ZeroConf,
+    ];
+
+    /// The compulsory bit; the optional bit is the next one
+    pub fn bit(&self) -> usize {
+        match self {
+            Self::DataLossProtect => 0,
+            Self::UpfrontShutdownScript => 4,
+            Self::GossipQueries => 6,
+            Self::VarOnionOptin => 8,
+            Self::GossipQueriesEx => 10,
+            Self::StaticRemoteKey => 12,
+            Self::PaymentSecret => 14,
+            Self::BasicMpp => 16,
+            Self::SupportLargeChannel => 18,
+            Self::AnchorOutputs => 20,
+            Self::AnchorsZeroFeeHtlcTx => 22,
+            Self::RouteBlinding => 24,
+            Self::ShutdownAnySegwit => 26,
+            Self::DualFund => 28,
+            Self::Quiesce => 34,
+            Self::OnionMessages => 38,
+            Self::ChannelType => 44,
+            Self::ScidAlias => 46,
+            Self::PaymentMetadata => 48,
+            Self::ZeroConf => 50,
+        }
+    }
+
+    pub fn from_bit(bit: usize) -> Option<Feature> {
+        Self::ALL.into_iter().find(|f| f.bit() == bit - bit % 2)
+    }
+}
+
+impl FeatureSet {
+    pub fn from_bits(bits: impl IntoIterator<Item = usize>) -> Self {
+        Self(bits.into_iter().collect())
+    }
+
+    /// Parses the big-endian hex encoding used in gossip; ignores characters that are not hex digits
+    pub fn from_hex(hex: &str) -> Self {
+        let nibbles: Vec<u32> = hex.chars().filter_map(|c| c.to_digit(16)).collect();
+        let num_nibbles = nibbles.len();
+        Self::from_bits(nibbles.iter().enumerate().flat_map(|(idx, nibble)| {
+            let offset = (num_nibbles - 1 - idx) * 4;
+            (0..4)
+                .filter(move |bit| nibble & (1 << bit) != 0)
+                .map(move |bit| offset + bit)
+        }))
+    }
+
+    /// Inverse of [FeatureSet::from_hex] using as few bytes as possible
+    pub fn to_hex(&self) -> String {
+        let Some(max_bit) = self.0.last() else {
+            return String::default();
+        };
+        let mut bytes = vec![0u8; max_bit / 8 + 1];
+        let num_bytes = bytes.len();
+        for bit in self.bits() {
+            bytes[num_bytes - 1 - bit / 8] |= 1 << (bit % 8);
+        }
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn bits(&self) -> impl Iterator<Item = usize> + '_ {
+        self.0.iter().copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn set(&mut self, feature: Feature, required: bool) {
+        if required {
+            self.0.insert(feature.bit());
+        } else {
+            self.0.insert(feature.bit() + 1);
+        }
+    }
+
+    /// Either bit is set
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.0.contains(&feature.bit()) || self.0.contains(&(feature.bit() + 1))
+    }
+
+    pub fn requires(&self, feature: Feature) -> bool {
+        self.0.contains(&feature.bit())
+    }
+
+    /// The features we know, in order of their bits
+    pub fn features(&self) -> Vec<Feature> {
+        Feature::ALL
+            .into_iter()
+            .filter(|f| self.supports(*f))
+            .collect()
+    }
+
+    /// Compulsory bits we do not know; a node with any of these cannot be used by us
+    pub fn unknown_required_bits(&self) -> Vec<usize> {
+        self.bits()
+            .filter(|bit| bit % 2 == 0 && Feature::from_bit(*bit).is_none())
+            .collect()
+    }
+
+    /// True if all features are supported and no unknown feature is required.
+    /// Nodes that announce no features at all are assumed to support everything since many
+    /// snapshots do not contain them.
+    pub fn is_compatible(&self, required: &[Feature]) -> bool {
+        self.is_empty()
+            || (required.iter().all(|f| self.supports(*f))
+                && self.unknown_required_bits().is_empty())
+    }
+}
+
+impl From<String> for FeatureSet {
+    fn from(hex: String) -> Self {
+        Self::from_hex(&hex)
+    }
+}
+
+impl From<FeatureSet> for String {
+    fn from(features: FeatureSet) -> Self {
+        features.to_hex()
+    }
+}
+
+impl fmt::Display for FeatureSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_hex())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feature_bits_to_hex_and_back() {
+        let features = FeatureSet::from_bits([1, 14, 17]);
+        assert_eq!(features.to_hex(), "024002");
+        assert_eq!(FeatureSet::from_hex("024002"), features);
+        assert_eq!(FeatureSet::default().to_hex(), "");
+        assert!(FeatureSet::from_hex("").is_empty());
+        // as found in lnresearch snapshots
+        let hex = "888000080a69a2";
+        assert_eq!(FeatureSet::from_hex(hex).to_hex(), hex);
+    }
+
+    #[test]
+    fn decode_features() {
+        let features = FeatureSet::from_hex("024002");
+        assert_eq!(
+            features.features(),
+            vec![
+                Feature::DataLossProtect,
+                Feature::PaymentSecret,
+                Feature::BasicMpp
+            ]
+        );
+        assert!(features.requires(Feature::PaymentSecret));
+        assert!(!features.requires(Feature::BasicMpp));
+        assert!(features.supports(Feature::BasicMpp));
+        assert!(!features.supports(Feature::VarOnionOptin));
+        assert_eq!(Feature::from_bit(17), Some(Feature::BasicMpp));
+        assert_eq!(Feature::from_bit(2), None);
+    }
+
+    #[test]
+    fn compatibility() {
+        let mut features = FeatureSet::default();
+        // nothing announced
+        assert!(features.is_compatible(&[Feature::VarOnionOptin]));
+        features.set(Feature::BasicMpp, false);
+        assert!(!features.is_compatible(&[Feature::VarOnionOptin]));
+        features.set(Feature::VarOnionOptin, true);
+        assert!(features.is_compatible(&[Feature::VarOnionOptin]));
+        // unknown compulsory bit
+        let features = FeatureSet::from_bits([9, 100]);
+        assert_eq!(features.unknown_required_bits(), vec![100]);
+        assert!(!features.is_compatible(&[Feature::VarOnionOptin]));
+        // unknown optional bits are fine
+        assert!(FeatureSet::from_bits([9, 101]).is_compatible(&[Feature::VarOnionOptin]));
+    }
+}
@@ -0,0 +1,193 @@
+use crate::output::short_channel_id;
+use crate::*;
+use std::collections::BTreeMap;
+
+/// On-chain funding amounts in sat by short channel id in `block x tx x output` notation
+pub type FundingAmounts = HashMap<String, usize>;
+
+/// How many of a graph's channels a funding file covered
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FundingReport {
+    /// Channels whose capacity was set from the file
+    pub matched: usize,
+    /// Channels not in the file, which keep their capacity
+    pub unmatched: usize,
+}
+
+/// Reads funding amounts in sat, e.g. from a block explorer dump.
+/// Files ending in `.json` contain an object mapping short channel ids to amounts, anything else
+/// is read as CSV with one `short_channel_id,amount_sat` pair per line; a header is skipped.
+/// Short channel ids may be given as `683464x931x0`, with a direction suffix or in LND's numeric
+/// encoding.
+pub fn read_funding_amounts(path: &Path) -> Result<FundingAmounts, ParseError> {
+    let content = fs::read_to_string(path)?;
+    if path.extension().is_some_and(|ext| ext == "json") {
+        funding_amounts_from_json_str(&content)
+    } else {
+        funding_amounts_from_csv_str(&content)
+    }
+}
+
+pub fn funding_amounts_from_json_str(json: &str) -> Result<FundingAmounts, ParseError> {
+    let amounts: BTreeMap<String, u64> = serde_json::from_str(json)?;
+    amounts
+        .into_iter()
+        .map(|(scid, amount)| {
+            let amount = amount
+                .try_into()
+                .map_err(|_| ParseError::numeric_overflow("amount_sat", &scid))?;
+            Ok((normalise_scid(&scid), amount))
+        })
+        .collect()
+}
+
+pub fn funding_amounts_from_csv_str(csv: &str) -> Result<FundingAmounts, ParseError> {
+    let mut amounts = FundingAmounts::new();
+    for (idx, line) in csv.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let context = format!("line {}", idx + 1);
+        let (scid, amount) = line
+            .split_once(',')
+            .ok_or_else(|| ParseError::missing_field("amount_sat", &context))?;
+        match amount.trim().parse() {
+            Ok(amount) => {
+                amounts.insert(normalise_scid(scid.trim()), amount);
+            }
+            // a header
+            Err(_) if idx == 0 => continue,
+            Err(_) => return Err(ParseError::invalid_value("amount_sat", &context)),
+        }
+    }
+    Ok(amounts)
+}
+
+/// Converts LND's numeric channel ids and strips the direction lnresearch and CLN append
+pub(crate) fn normalise_scid(channel_id: &str) -> String {
+    let scid = short_channel_id(channel_id);
+    match scid.parse::<u64>() {
+        Ok(id) => format!("{}x{}x{}", id >> 40, (id >> 16) & 0xFFFFFF, id & 0xFFFF),
+        Err(_) => scid.to_owned(),
+    }
+}
+
+impl Graph {
+    /// Sets the capacity of both directions of each channel found in `amounts` to its funding
+    /// amount. Other channels keep their capacity, which the simulator then estimates for
+    /// lnresearch graphs. Fails without changing the graph if an amount does not fit in msat.
+    pub fn set_funding_amounts(
+        &mut self,
+        amounts: &FundingAmounts,
+    ) -> Result<FundingReport, ParseError> {
+        if let Some(scid) = amounts
+            .iter()
+            .filter(|(_, amount)| amount.checked_mul(1000).is_none())
+            .map(|(scid, _)| scid)
+            .min()
+        {
+            return Err(ParseError::numeric_overflow("amount_sat", scid));
+        }
+        let mut matched = HashSet::new();
+        let mut unmatched = HashSet::new();
+        for edges in self.edges.values_mut() {
+            // HashSet does not allow mutable access
+            *edges = edges
+                .drain()
+                .map(|mut edge| {
+                    let scid = normalise_scid(&edge.channel_id);
+                    if let Some(amount) = amounts.get(&scid) {
+                        // checked above
+                        edge.capacity = amount * 1000;
+                        matched.insert(scid);
+                    } else {
+                        unmatched.insert(scid);
+                    }
+                    edge
+                })
+                .collect();
+        }
+        Ok(FundingReport {
+            matched: matched.len(),
+            unmatched: unmatched.len(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scids_are_normalised() {
+        assert_eq!(normalise_scid("683464x931x0/1"), "683464x931x0");
+        assert_eq!(normalise_scid("683464x931x0"), "683464x931x0");
+        // 683464x931x0 as used by LND
+        assert_eq!(normalise_scid("751476615227310080"), "683464x931x0");
+    }
+
+    #[test]
+    fn read_csv_and_json() {
+        let csv = "short_channel_id,amount_sat\n683464x931x0,500000\n\n751476615227310081,20\n";
+        let expected = FundingAmounts::from([
+            ("683464x931x0".to_owned(), 500000),
+            ("683464x931x1".to_owned(), 20),
+        ]);
+        assert_eq!(funding_amounts_from_csv_str(csv).unwrap(), expected);
+        let json = r#"{"683464x931x0/0": 500000, "683464x931x1": 20}"#;
+        assert_eq!(funding_amounts_from_json_str(json).unwrap(), expected);
+        assert!(matches!(
+            funding_amounts_from_csv_str("683464x931x0,500000\n683464x931x1,a lot"),
+            Err(ParseError::InvalidValue { .. })
+        ));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("funding.json");
+        fs::write(&path, json).unwrap();
+        assert_eq!(read_funding_amounts(&path).unwrap(), expected);
+        let path = dir.path().join("funding.csv");
+        fs::write(&path, csv).unwrap();
+        assert_eq!(read_funding_amounts(&path).unwrap(), expected);
+    }
+
+    #[test]
+    fn capacities_are_set_for_matched_channels() {
+        let path = Path::new("../test_data/lnbook_example.json");
+        let mut graph = Graph::from_json_file(path, GraphSource::Lnresearch).unwrap();
+        let amounts = FundingAmounts::from([("alice1".to_owned(), 200)]);
+        let report = graph.set_funding_amounts(&amounts).unwrap();
+        let num_channels = graph
+            .edges
+            .values()
+            .flatten()
+            .map(|e| short_channel_id(&e.channel_id))
+            .collect::<HashSet<&str>>()
+            .len();
+        assert_eq!(report.matched, 1);
+        assert_eq!(report.unmatched, num_channels - 1);
+        for edge in graph.edges.values().flatten() {
+            let expected = if edge.channel_id == "alice1" {
+                200000
+            } else {
+                0
+            };
+            assert_eq!(edge.capacity, expected);
+        }
+        // too large to be converted to msat
+        let amounts = FundingAmounts::from([
+            ("alice1".to_owned(), 100),
+            ("bob1".to_owned(), usize::MAX / 10),
+        ]);
+        let actual = graph.set_funding_amounts(&amounts);
+        assert!(matches!(
+            actual,
+            Err(ParseError::NumericOverflow { context, .. }) if context == "bob1"
+        ));
+        assert!(graph
+            .edges
+            .values()
+            .flatten()
+            .all(|e| e.capacity == if e.channel_id == "alice1" { 200000 } else { 0 }));
+    }
+}
@@ -1,48 +1,100 @@
 use log::error;
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_aux::prelude::*;
 use std::hash::{Hash, Hasher};
-use std::{
-    net::{IpAddr, Ipv4Addr, SocketAddr},
-    str::FromStr,
-};
+use std::str::FromStr;
 
 use crate::*;
+use std::collections::BTreeMap;
 
-#[derive(Deserialize, Debug, Default)]
+#[derive(Deserialize, Serialize, Debug, Default)]
 pub struct RawLnresearchGraph {
     pub(crate) nodes: Vec<RawLnresearchNode>,
-    #[serde(alias = "adjacency")]
+    #[serde(alias = "adjacency", rename(serialize = "adjacency"))]
     pub(crate) edges: Vec<Vec<LnresearchRawEdge>>,
 }
-#[derive(Deserialize, Debug, Default)]
+#[derive(Deserialize, Serialize, Debug, Default)]
 pub struct RawLndGraph {
     pub(crate) nodes: Vec<RawLndNode>,
     #[serde(alias = "adjacency")]
     pub(crate) edges: Vec<LndRawEdge>,
 }
+/// The merged output of CLN's `listnodes` and `listchannels`, i.e. `{"nodes": [..], "channels": [..]}`
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct RawClnGraph {
+    pub(crate) nodes: Vec<RawClnNode>,
+    #[serde(alias = "edges")]
+    pub(crate) channels: Vec<ClnRawEdge>,
+}
 
 serde_aux::StringOrVecToVecParser!(parse_between_commas, |c| { c == ',' }, true);
 
-#[derive(Deserialize, Debug, Clone, Default, Eq, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default, Eq, PartialEq)]
 pub struct RawLnresearchNode {
     #[serde(alias = "pub_key")]
     pub(crate) id: Option<String>,
     pub(crate) alias: Option<String>,
     #[serde(deserialize_with = "addr_lnr_deserialize")]
+    #[serde(serialize_with = "addr_lnr_serialize")]
     #[serde(default)]
     pub(crate) addresses: Option<Vec<String>>,
+    pub(crate) timestamp: Option<u64>,
+    pub(crate) features: Option<String>,
+    pub(crate) rgb_color: Option<String>,
+    pub(crate) out_degree: Option<usize>,
+    pub(crate) in_degree: Option<usize>,
 }
 
-#[derive(Deserialize, Debug, Clone, Default, Eq, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default, Eq, PartialEq)]
 pub struct RawLndNode {
-    #[serde(alias = "pub_key")]
+    #[serde(alias = "pub_key", rename(serialize = "pub_key"))]
+    pub(crate) id: Option<String>,
+    pub(crate) alias: Option<String>,
+    pub(crate) addresses: Option<Vec<LndAddress>>,
+    pub(crate) last_update: Option<u64>,
+    /// Hex encoded with leading #
+    pub(crate) color: Option<String>,
+    /// Feature bits mapped to their description
+    pub(crate) features: Option<BTreeMap<String, LndFeature>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default, Eq, PartialEq)]
+pub struct LndAddress {
+    /// Always tcp
+    pub(crate) network: String,
+    /// `host:port`
+    pub(crate) addr: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default, Eq, PartialEq)]
+#[serde(default)]
+pub struct LndFeature {
+    pub(crate) name: String,
+    pub(crate) is_required: bool,
+    pub(crate) is_known: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default, Eq, PartialEq)]
+pub struct RawClnNode {
+    #[serde(alias = "nodeid", rename(serialize = "nodeid"))]
     pub(crate) id: Option<String>,
     pub(crate) alias: Option<String>,
-    pub(crate) addresses: Option<Vec<Address>>,
+    pub(crate) addresses: Option<Vec<ClnAddress>>,
+    pub(crate) last_timestamp: Option<u64>,
+    /// Hex encoded without leading #
+    pub(crate) color: Option<String>,
+    pub(crate) features: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default, Eq, PartialEq)]
+pub struct ClnAddress {
+    #[serde(rename = "type")]
+    pub(crate) network: String,
+    pub(crate) address: Option<String>,
+    pub(crate) port: Option<u16>,
 }
 
-#[derive(Deserialize, Debug, Clone, Default)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct LnresearchRawEdge {
     #[serde(rename = "scid")]
     pub channel_id: Option<String>,
@@ -53,258 +105,359 @@ pub struct LnresearchRawEdge {
     pub htlc_minimim_msat: Option<u64>,
     pub htlc_maximum_msat: Option<u64>,
     pub cltv_expiry_delta: Option<u64>,
+    pub timestamp: Option<u64>,
+    pub features: Option<String>,
 }
 
-#[derive(Deserialize, Debug, Clone, Default)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct LndRawEdge {
     pub channel_id: Option<String>,
-    #[serde(alias = "node1_pub")]
+    #[serde(alias = "node1_pub", rename(serialize = "node1_pub"))]
     pub source: Option<String>,
-    #[serde(alias = "node2_pub")]
+    #[serde(alias = "node2_pub", rename(serialize = "node2_pub"))]
     pub destination: Option<String>,
     #[serde(deserialize_with = "deserialize_option_number_from_string")]
+    #[serde(serialize_with = "serialize_option_as_string")]
     /// Denominated in sat
     pub capacity: Option<u64>,
     pub node1_policy: Option<NodePolicy>,
     pub node2_policy: Option<NodePolicy>,
 }
 
-#[derive(Deserialize, Debug, Clone, Default)]
+/// One direction of a channel as returned by CLN's `listchannels`
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ClnRawEdge {
+    pub short_channel_id: Option<String>,
+    pub source: Option<String>,
+    pub destination: Option<String>,
+    /// 0 or 1 depending on which of the two peers is the source
+    pub direction: Option<u8>,
+    /// Denominated in msat
+    #[serde(default, deserialize_with = "deserialize_option_msat")]
+    pub amount_msat: Option<u64>,
+    pub base_fee_millisatoshi: Option<u64>,
+    pub fee_per_millionth: Option<u64>,
+    pub delay: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_option_msat")]
+    pub htlc_minimum_msat: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_option_msat")]
+    pub htlc_maximum_msat: Option<u64>,
+    /// False if the source has disabled the channel
+    pub active: Option<bool>,
+    pub last_update: Option<u64>,
+    pub features: Option<String>,
+}
+
+/// Serialised using LND's field names
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct NodePolicy {
     /// Denominated in msat
     #[serde(deserialize_with = "deserialize_option_number_from_string")]
+    #[serde(serialize_with = "serialize_option_as_string")]
     pub fee_base_msat: Option<u64>,
     /// Denominated in ppm msat
-    #[serde(alias = "fee_rate_milli_msat")]
+    #[serde(
+        alias = "fee_rate_milli_msat",
+        rename(serialize = "fee_rate_milli_msat")
+    )]
     #[serde(deserialize_with = "deserialize_option_number_from_string")]
+    #[serde(serialize_with = "serialize_option_as_string")]
     pub fee_proportional_millionths: Option<u64>,
     /// Denominated in msat
-    #[serde(alias = "min_htlc")]
+    #[serde(alias = "min_htlc", rename(serialize = "min_htlc"))]
     #[serde(deserialize_with = "deserialize_option_number_from_string")]
+    #[serde(serialize_with = "serialize_option_as_string")]
     pub htlc_minimim_msat: Option<u64>,
-    #[serde(alias = "max_htlc_msat")]
+    #[serde(alias = "max_htlc_msat", rename(serialize = "max_htlc_msat"))]
     #[serde(deserialize_with = "deserialize_option_number_from_string")]
+    #[serde(serialize_with = "serialize_option_as_string")]
     pub htlc_maximum_msat: Option<u64>,
-    #[serde(alias = "time_lock_delta")]
+    #[serde(alias = "time_lock_delta", rename(serialize = "time_lock_delta"))]
     pub cltv_expiry_delta: Option<u64>,
+    pub disabled: Option<bool>,
+    pub last_update: Option<u64>,
 }
 
 impl Node {
-    pub(crate) fn from_raw_lnresearch(raw_node: RawLnresearchNode) -> Node {
+    pub(crate) fn from_raw_lnresearch(raw_node: RawLnresearchNode) -> Result<Node, ParseError> {
         let mut addresses = vec![];
-        if let Some(raw_addresses) = raw_node.addresses {
-            for raw_addr in raw_addresses {
-                let mut addr = Address::default();
-                for (i, part) in raw_addr.split("://").enumerate() {
-                    if i == 0 {
-                        // the network part which always seems to be tcp
-                        match part {
-                            "ipv4" | "ipv6" | "torv2" | "torv3" => addr.network = "tcp".to_owned(),
-                            _ => {
-                                break;
-                            }
-                        }
-                    } else if i == 1 {
-                        // the addr part
-                        addr.addr = parse_net_address(part);
-                    }
+        for raw_addr in raw_node.addresses.unwrap_or_default() {
+            // e.g. ipv4://1.2.3.4:9735; the type follows from the address itself
+            match raw_addr.split_once("://") {
+                Some(("ipv4" | "ipv6" | "torv2" | "torv3" | "dns", addr)) => {
+                    addresses.extend(parse_net_address(addr))
                 }
-                addresses.push(addr);
+                _ => continue,
             }
         }
-        Node {
-            id: raw_node.id.expect("Error in node ID"),
+        Ok(Node {
+            id: raw_node.id.ok_or_else(|| {
+                ParseError::missing_field("id", &format!("node {:?}", raw_node.alias))
+            })?,
             alias: raw_node.alias.unwrap_or_default(),
             addresses,
-        }
+            timestamp: raw_node.timestamp.unwrap_or_default(),
+            features: FeatureSet::from_hex(&raw_node.features.unwrap_or_default()),
+            rgb_color: raw_node.rgb_color.unwrap_or_default(),
+            in_degree: raw_node.in_degree.unwrap_or_default(),
+            out_degree: raw_node.out_degree.unwrap_or_default(),
+        })
     }
-    pub(crate) fn from_raw_lnd(raw_node: RawLndNode) -> Node {
+    pub(crate) fn from_raw_cln(raw_node: RawClnNode) -> Result<Node, ParseError> {
         let mut addresses = vec![];
-        if let Some(raw_addresses) = raw_node.addresses {
-            for raw_addr in raw_addresses {
-                addresses.push(Address {
-                    network: raw_addr.network,
-                    addr: parse_net_address(&raw_addr.addr),
-                });
+        for raw_addr in raw_node.addresses.unwrap_or_default() {
+            // websocket addresses are not announced via gossip
+            match (raw_addr.network.as_str(), raw_addr.address) {
+                ("ipv4" | "ipv6" | "torv2" | "torv3" | "dns", Some(host)) => {
+                    addresses.push(Address::new(&host, raw_addr.port.unwrap_or(DEFAULT_PORT)))
+                }
+                _ => continue,
             }
         }
-        Node {
-            id: raw_node.id.expect("Error in node ID"),
+        Ok(Node {
+            id: raw_node.id.ok_or_else(|| {
+                ParseError::missing_field("id", &format!("node {:?}", raw_node.alias))
+            })?,
             alias: raw_node.alias.unwrap_or_default(),
             addresses,
-        }
+            timestamp: raw_node.last_timestamp.unwrap_or_default(),
+            features: FeatureSet::from_hex(&raw_node.features.unwrap_or_default()),
+            rgb_color: raw_node.color.unwrap_or_default(),
+            ..Default::default()
+        })
+    }
+    pub(crate) fn from_raw_lnd(raw_node: RawLndNode) -> Result<Node, ParseError> {
+        let addresses = raw_node
+            .addresses
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|raw_addr| parse_net_address(&raw_addr.addr))
+            .collect();
+        Ok(Node {
+            id: raw_node.id.ok_or_else(|| {
+                ParseError::missing_field("id", &format!("node {:?}", raw_node.alias))
+            })?,
+            alias: raw_node.alias.unwrap_or_default(),
+            addresses,
+            timestamp: raw_node.last_update.unwrap_or_default(),
+            features: raw_node
+                .features
+                .map(|features| {
+                    FeatureSet::from_bits(features.keys().filter_map(|bit| bit.parse().ok()))
+                })
+                .unwrap_or_default(),
+            rgb_color: raw_node
+                .color
+                .map(|color| color.trim_start_matches('#').to_owned())
+                .unwrap_or_default(),
+            ..Default::default()
+        })
     }
 }
 
 impl Edge {
     /// We remove "orphaned" edges - edges where the source node is not in the list of nodes
-    pub(crate) fn from_lnresearch_raw(raw_edge: &LnresearchRawEdge) -> Option<Edge> {
-        if raw_edge.fee_base_msat.is_none()
-            || raw_edge.fee_proportional_millionths.is_none()
-            || raw_edge.htlc_maximum_msat.is_none()
-        {
-            None
-        } else {
-            Some(Edge {
-                channel_id: raw_edge.channel_id.clone().expect("scid not found"),
-                source: raw_edge.source.clone().unwrap_or_default(),
-                destination: raw_edge.destination.clone().unwrap_or_default(),
-                fee_base_msat: raw_edge
-                    .fee_base_msat
-                    .expect("Error in fee_base_msat field")
-                    .try_into()
-                    .expect("Error in fee_base_msat field"),
-                fee_proportional_millionths: raw_edge
-                    .fee_proportional_millionths
-                    .expect("Error in fee_proportional_millionths field")
-                    .try_into()
-                    .expect("Error in fee_proportional_millionths field"),
-                htlc_minimim_msat: raw_edge
-                    .htlc_minimim_msat
-                    .unwrap_or_default()
-                    .try_into()
-                    .unwrap_or(usize::default()),
-                htlc_maximum_msat: raw_edge
-                    .htlc_maximum_msat
-                    .unwrap_or_default()
-                    .try_into()
-                    .unwrap_or(usize::default()),
-                cltv_expiry_delta: raw_edge
-                    .cltv_expiry_delta
-                    .unwrap_or_default()
-                    .try_into()
-                    .unwrap_or(usize::default()),
-                balance: 0,
-                liquidity: 0,
-                capacity: 0,
-            })
-        }
-    }
-    /// We remove "orphaned" edges - edges where the source node is not in the list of nodes
-    pub(crate) fn from_lnd_raw(raw_edge: &LndRawEdge) -> Option<(Edge, Edge)> {
-        if raw_edge.node1_policy.is_none()
-            || raw_edge.node2_policy.is_none()
-            || raw_edge
-                .node1_policy
-                .as_ref()
-                .unwrap()
-                .fee_base_msat
-                .is_none()
-            || raw_edge
-                .node1_policy
-                .as_ref()
-                .unwrap()
-                .fee_proportional_millionths
-                .is_none()
-            || raw_edge
-                .node1_policy
-                .as_ref()
-                .unwrap()
-                .htlc_maximum_msat
-                .is_none()
-            || raw_edge
-                .node2_policy
-                .as_ref()
-                .unwrap()
-                .fee_base_msat
-                .is_none()
-            || raw_edge
-                .node2_policy
-                .as_ref()
-                .unwrap()
-                .fee_proportional_millionths
-                .is_none()
-            || raw_edge
-                .node2_policy
-                .as_ref()
-                .unwrap()
-                .htlc_maximum_msat
-                .is_none()
-        {
-            None
-        } else {
-            let node1_policy = raw_edge.node1_policy.clone().unwrap(); // safe because of the earlier check
-            let node2_policy = raw_edge.node2_policy.clone().unwrap();
-            Some((
-                Edge {
-                    channel_id: raw_edge.channel_id.clone().expect("scid not found"),
+    /// Returns None for edges without the fee fields needed for routing
+    pub(crate) fn from_lnresearch_raw(
+        raw_edge: &LnresearchRawEdge,
+    ) -> Result<Option<Edge>, ParseError> {
+        let context = edge_context(
+            &raw_edge.channel_id,
+            &raw_edge.source,
+            &raw_edge.destination,
+        );
+        let channel_id = raw_edge
+            .channel_id
+            .clone()
+            .ok_or_else(|| ParseError::missing_field("scid", &context))?;
+        match (
+            raw_edge.fee_base_msat,
+            raw_edge.fee_proportional_millionths,
+            raw_edge.htlc_maximum_msat,
+        ) {
+            (Some(fee_base_msat), Some(fee_proportional_millionths), Some(htlc_maximum_msat)) => {
+                Ok(Some(Edge {
+                    channel_id,
                     source: raw_edge.source.clone().unwrap_or_default(),
                     destination: raw_edge.destination.clone().unwrap_or_default(),
-                    fee_base_msat: node1_policy
-                        .fee_base_msat
-                        .expect("Error in fee_base_msat field")
-                        .try_into()
-                        .expect("Error in fee_base_msat field"),
-                    fee_proportional_millionths: node1_policy
-                        .fee_proportional_millionths
-                        .expect("Error in fee_proportional_millionths field")
-                        .try_into()
-                        .expect("Error in fee_proportional_millionths field"),
-                    htlc_minimim_msat: node1_policy
-                        .htlc_minimim_msat
-                        .unwrap_or_default()
-                        .try_into()
-                        .unwrap_or(usize::default()),
-                    htlc_maximum_msat: node1_policy
-                        .htlc_maximum_msat
-                        .unwrap_or_default()
-                        .try_into()
-                        .unwrap_or(usize::default()),
-                    cltv_expiry_delta: node1_policy
-                        .cltv_expiry_delta
-                        .unwrap_or_default()
-                        .try_into()
-                        .unwrap_or(usize::default()),
+                    fee_base_msat: to_usize(fee_base_msat, "fee_base_msat", &context)?,
+                    fee_proportional_millionths: to_usize(
+                        fee_proportional_millionths,
+                        "fee_proportional_millionths",
+                        &context,
+                    )?,
+                    htlc_minimim_msat: to_usize(
+                        raw_edge.htlc_minimim_msat.unwrap_or_default(),
+                        "htlc_minimim_msat",
+                        &context,
+                    )?,
+                    htlc_maximum_msat: to_usize(htlc_maximum_msat, "htlc_maximum_msat", &context)?,
+                    cltv_expiry_delta: to_usize(
+                        raw_edge.cltv_expiry_delta.unwrap_or_default(),
+                        "cltv_expiry_delta",
+                        &context,
+                    )?,
                     balance: 0,
                     liquidity: 0,
-                    capacity: raw_edge
-                        .capacity
-                        .unwrap_or_default()
-                        .try_into()
-                        .unwrap_or(usize::default())
-                        * 1000,
-                },
-                Edge {
-                    channel_id: raw_edge.channel_id.clone().expect("scid not found"),
-                    destination: raw_edge.source.clone().unwrap_or_default(),
-                    source: raw_edge.destination.clone().unwrap_or_default(),
-                    fee_base_msat: node2_policy
-                        .fee_base_msat
-                        .expect("Error in fee_base_msat field")
-                        .try_into()
-                        .expect("Error in fee_base_msat field"),
-                    fee_proportional_millionths: node2_policy
-                        .fee_proportional_millionths
-                        .expect("Error in fee_proportional_millionths field")
-                        .try_into()
-                        .expect("Error in fee_proportional_millionths field"),
-                    htlc_minimim_msat: node2_policy
-                        .htlc_minimim_msat
-                        .unwrap_or_default()
-                        .try_into()
-                        .unwrap_or(usize::default()),
-                    htlc_maximum_msat: node2_policy
-                        .htlc_maximum_msat
-                        .unwrap_or_default()
-                        .try_into()
-                        .unwrap_or(usize::default()),
-                    cltv_expiry_delta: node2_policy
-                        .cltv_expiry_delta
-                        .unwrap_or_default()
-                        .try_into()
-                        .unwrap_or(usize::default()),
+                    capacity: 0,
+                    timestamp: raw_edge.timestamp.unwrap_or_default(),
+                    features: FeatureSet::from_hex(
+                        raw_edge.features.as_deref().unwrap_or_default(),
+                    ),
+                    disabled: false,
+                }))
+            }
+            _ => Ok(None),
+        }
+    }
+    /// Both directions of a CLN channel share the short channel id so we append the direction
+    /// as is done in the lnresearch snapshots
+    pub(crate) fn from_cln_raw(raw_edge: &ClnRawEdge) -> Result<Option<Edge>, ParseError> {
+        let context = edge_context(
+            &raw_edge.short_channel_id,
+            &raw_edge.source,
+            &raw_edge.destination,
+        );
+        let scid = raw_edge
+            .short_channel_id
+            .clone()
+            .ok_or_else(|| ParseError::missing_field("short_channel_id", &context))?;
+        match (
+            raw_edge.base_fee_millisatoshi,
+            raw_edge.fee_per_millionth,
+            raw_edge.htlc_maximum_msat,
+        ) {
+            (Some(fee_base_msat), Some(fee_proportional_millionths), Some(htlc_maximum_msat)) => {
+                Ok(Some(Edge {
+                    channel_id: format!("{}/{}", scid, raw_edge.direction.unwrap_or_default()),
+                    source: raw_edge.source.clone().unwrap_or_default(),
+                    destination: raw_edge.destination.clone().unwrap_or_default(),
+                    fee_base_msat: to_usize(fee_base_msat, "base_fee_millisatoshi", &context)?,
+                    fee_proportional_millionths: to_usize(
+                        fee_proportional_millionths,
+                        "fee_per_millionth",
+                        &context,
+                    )?,
+                    htlc_minimim_msat: to_usize(
+                        raw_edge.htlc_minimum_msat.unwrap_or_default(),
+                        "htlc_minimum_msat",
+                        &context,
+                    )?,
+                    htlc_maximum_msat: to_usize(htlc_maximum_msat, "htlc_maximum_msat", &context)?,
+                    cltv_expiry_delta: to_usize(
+                        raw_edge.delay.unwrap_or_default(),
+                        "delay",
+                        &context,
+                    )?,
                     balance: 0,
                     liquidity: 0,
-                    capacity: raw_edge
-                        .capacity
-                        .unwrap_or_default()
-                        .try_into()
-                        .unwrap_or(usize::default())
-                        * 1000,
-                },
-            ))
+                    capacity: to_usize(
+                        raw_edge.amount_msat.unwrap_or_default(),
+                        "amount_msat",
+                        &context,
+                    )?,
+                    timestamp: raw_edge.last_update.unwrap_or_default(),
+                    features: FeatureSet::from_hex(
+                        raw_edge.features.as_deref().unwrap_or_default(),
+                    ),
+                    disabled: !raw_edge.active.unwrap_or(true),
+                }))
+            }
+            _ => Ok(None),
         }
     }
+    /// We remove "orphaned" edges - edges where the source node is not in the list of nodes
+    /// Returns None for channels where either policy lacks the fee fields needed for routing
+    pub(crate) fn from_lnd_raw(raw_edge: &LndRawEdge) -> Result<Option<(Edge, Edge)>, ParseError> {
+        let context = edge_context(
+            &raw_edge.channel_id,
+            &raw_edge.source,
+            &raw_edge.destination,
+        );
+        let channel_id = raw_edge
+            .channel_id
+            .clone()
+            .ok_or_else(|| ParseError::missing_field("channel_id", &context))?;
+        let (node1_policy, node2_policy) = match (&raw_edge.node1_policy, &raw_edge.node2_policy) {
+            (Some(node1_policy), Some(node2_policy))
+                if node1_policy.is_complete() && node2_policy.is_complete() =>
+            {
+                (node1_policy, node2_policy)
+            }
+            _ => return Ok(None),
+        };
+        // LND reports the capacity in sat
+        let capacity = to_usize(raw_edge.capacity.unwrap_or_default(), "capacity", &context)?
+            .checked_mul(1000)
+            .ok_or_else(|| ParseError::numeric_overflow("capacity", &context))?;
+        let source = raw_edge.source.clone().unwrap_or_default();
+        let destination = raw_edge.destination.clone().unwrap_or_default();
+        Ok(Some((
+            node1_policy.to_edge(
+                channel_id.clone(),
+                source.clone(),
+                destination.clone(),
+                capacity,
+                &context,
+            )?,
+            node2_policy.to_edge(channel_id, destination, source, capacity, &context)?,
+        )))
+    }
+}
+
+impl NodePolicy {
+    /// True if the policy contains all fields needed for routing
+    fn is_complete(&self) -> bool {
+        self.fee_base_msat.is_some()
+            && self.fee_proportional_millionths.is_some()
+            && self.htlc_maximum_msat.is_some()
+    }
+
+    fn to_edge(
+        &self,
+        channel_id: String,
+        source: String,
+        destination: String,
+        capacity: usize,
+        context: &str,
+    ) -> Result<Edge, ParseError> {
+        Ok(Edge {
+            channel_id,
+            source,
+            destination,
+            fee_base_msat: to_usize(
+                self.fee_base_msat.unwrap_or_default(),
+                "fee_base_msat",
+                context,
+            )?,
+            fee_proportional_millionths: to_usize(
+                self.fee_proportional_millionths.unwrap_or_default(),
+                "fee_proportional_millionths",
+                context,
+            )?,
+            htlc_minimim_msat: to_usize(
+                self.htlc_minimim_msat.unwrap_or_default(),
+                "htlc_minimim_msat",
+                context,
+            )?,
+            htlc_maximum_msat: to_usize(
+                self.htlc_maximum_msat.unwrap_or_default(),
+                "htlc_maximum_msat",
+                context,
+            )?,
+            cltv_expiry_delta: to_usize(
+                self.cltv_expiry_delta.unwrap_or_default(),
+                "cltv_expiry_delta",
+                context,
+            )?,
+            balance: 0,
+            liquidity: 0,
+            capacity,
+            timestamp: self.last_update.unwrap_or_default(),
+            features: FeatureSet::default(),
+            disabled: self.disabled.unwrap_or_default(),
+        })
+    }
 }
 
 impl Hash for LnresearchRawEdge {
@@ -347,18 +500,98 @@ where
     }
 }
 
-fn parse_net_address(addr: &str) -> String {
-    if !addr.contains("onion") {
-        let sock_addr: SocketAddr = addr.parse().unwrap_or_else(|_| {
-            error!("Failed to parse {:#?} as IpAddr", addr);
-            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 0)
-        });
-        sock_addr.ip().to_string()
-    } else {
-        addr.split(':').next().unwrap_or_default().to_string()
+/// Inverse of [addr_lnr_deserialize]
+fn addr_lnr_serialize<S>(addresses: &Option<Vec<String>>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&addresses.as_deref().unwrap_or_default().join(","))
+}
+
+/// LND encodes 64 bit integers as strings
+fn serialize_option_as_string<S>(value: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match value {
+        Some(value) => serializer.serialize_str(&value.to_string()),
+        None => serializer.serialize_none(),
     }
 }
 
+/// CLN reports msat values either as plain numbers or, in older versions, as strings with an
+/// "msat" suffix
+fn deserialize_option_msat<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Msat {
+        Number(u64),
+        Str(String),
+    }
+    match Option::<Msat>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Msat::Number(msat)) => Ok(Some(msat)),
+        Some(Msat::Str(s)) => s
+            .trim_end_matches("msat")
+            .parse::<u64>()
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+    }
+}
+
+/// Errors if an endpoint is missing and returns false if an endpoint is not in the list of nodes
+pub(crate) fn endpoints_known(
+    nodes: &HashSet<Node>,
+    channel_id: &Option<String>,
+    source: &Option<String>,
+    destination: &Option<String>,
+) -> Result<bool, ParseError> {
+    let context = edge_context(channel_id, source, destination);
+    let is_known = |endpoint: &Option<String>, field: &'static str| {
+        // We only need the ID to know if the node exists
+        endpoint
+            .as_ref()
+            .map(|id| {
+                nodes.contains(&Node {
+                    id: id.clone(),
+                    ..Default::default()
+                })
+            })
+            .ok_or_else(|| ParseError::missing_field(field, &context))
+    };
+    Ok(is_known(source, "source")? && is_known(destination, "destination")?)
+}
+
+fn edge_context(
+    channel_id: &Option<String>,
+    source: &Option<String>,
+    destination: &Option<String>,
+) -> String {
+    format!(
+        "edge {} from {} to {}",
+        channel_id.as_deref().unwrap_or("<unknown>"),
+        source.as_deref().unwrap_or("<unknown>"),
+        destination.as_deref().unwrap_or("<unknown>")
+    )
+}
+
+fn to_usize(value: u64, field: &'static str, context: &str) -> Result<usize, ParseError> {
+    value
+        .try_into()
+        .map_err(|_| ParseError::numeric_overflow(field, context))
+}
+
+fn parse_net_address(addr: &str) -> Option<Address> {
+    let parsed = Address::parse(addr);
+    if parsed.is_none() {
+        error!("Failed to parse {:#?} as address", addr);
+    }
+    parsed
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -478,6 +711,7 @@ mod tests {
             balance: 0,
             liquidity: 0,
             capacity: 0,
+            ..Default::default()
         }]);
         let actual = graph.edges.get("validnode").unwrap().clone();
         assert_eq!(expected, actual);
@@ -500,7 +734,7 @@ mod tests {
             ],
             "edges": []
             }"##;
-        let graph = Graph::from_lnd_json_str(&json_str).unwrap();
+        let graph = Graph::from_lnd_json_str(json_str).unwrap();
         assert_eq!(graph.nodes.len(), 3);
     }
 
@@ -548,7 +782,7 @@ mod tests {
                 }
               ]
             }"##;
-        let graph = Graph::from_lnd_json_str(&json_str).unwrap();
+        let graph = Graph::from_lnd_json_str(json_str).unwrap();
         let actual = graph.edge_count();
         let expected = 0;
         assert_eq!(expected, actual);
@@ -599,7 +833,7 @@ mod tests {
             ]
             }"##;
         let expected = 1000 * 1000;
-        let graph = Graph::from_lnd_json_str(&json_str).unwrap();
+        let graph = Graph::from_lnd_json_str(json_str).unwrap();
         assert_eq!(graph.nodes.len(), 2);
         assert_eq!(graph.edges.len(), 2);
         for e in graph.get_edges_as_vec_vec().into_iter().flatten() {
@@ -641,30 +875,23 @@ mod tests {
             ],
             "edges": []
             }"##;
-        let graph = Graph::from_lnd_json_str(&json_str).unwrap();
+        let graph = Graph::from_lnd_json_str(json_str).unwrap();
         assert_eq!(graph.nodes.len(), 3);
         let expected: HashMap<ID, Vec<Address>> = HashMap::from([
             (
                 "021fa5be893f5b3ec37cf5a0f4e984f35a32".to_owned(),
                 vec![
-                    Address {
-                        network: "tcp".to_owned(),
-                        addr: "159.69.16.168".to_owned(),
-                    },
-                    Address {
-                        network: "tcp".to_owned(),
-                        addr: "2a01:4f8:1c1e:abc1::1".to_owned(),
-                    },
+                    Address::parse("159.69.16.168:9735").unwrap(),
+                    Address::parse("[2a01:4f8:1c1e:abc1::1]:9735").unwrap(),
                 ],
             ),
             ("00e332bc1b7d8db0e705df3f087d285f9c06".to_owned(), vec![]),
             (
                 "026cf8782a7735ac62f0e71da85c93f1d864".to_owned(),
-                vec![Address {
-                    network: "tcp".to_owned(),
-                    addr: "br4uj734xva77u7yt6oevyp2ropqjl7nw2jyzeejwmd7dzlouenkfmid.onion"
-                        .to_owned(),
-                }],
+                vec![Address::parse(
+                    "br4uj734xva77u7yt6oevyp2ropqjl7nw2jyzeejwmd7dzlouenkfmid.onion:9735",
+                )
+                .unwrap()],
             ),
         ]);
         for node in graph.nodes {
@@ -679,30 +906,20 @@ mod tests {
         let expected: HashMap<ID, Vec<Address>> = HashMap::from([
             (
                 "034".to_owned(),
-                vec![Address {
-                    network: "tcp".to_owned(),
-                    addr: "212.108.220.135".to_owned(),
-                }],
+                vec![Address::parse("212.108.220.135:9735").unwrap()],
             ),
             (
                 "025".to_owned(),
-                vec![Address {
-                    network: "tcp".to_owned(),
-                    addr: "104.236.54.112".to_owned(),
-                }],
+                vec![Address::parse("104.236.54.112:9735").unwrap()],
             ),
             (
                 "036".to_owned(),
                 vec![
-                    Address {
-                        network: "tcp".to_owned(),
-                        addr: "218.250.157.241".to_owned(),
-                    },
-                    Address {
-                        network: "tcp".to_string(),
-                        addr: "wu5mkpokybtbf6dwdaepnujbzxpm6mqqqm2hwob6ndt5k74iujd2pdyd.onion"
-                            .to_owned(),
-                    },
+                    Address::parse("218.250.157.241:9735").unwrap(),
+                    Address::parse(
+                        "wu5mkpokybtbf6dwdaepnujbzxpm6mqqqm2hwob6ndt5k74iujd2pdyd.onion:9735",
+                    )
+                    .unwrap(),
                 ],
             ),
         ]);
@@ -710,4 +927,136 @@ mod tests {
             assert_eq!(*expected.get(&node.id).unwrap(), node.addresses);
         }
     }
+
+    #[test]
+    fn edges_from_cln_json_str() {
+        let json_str = r##"{
+            "nodes": [
+                {
+                    "nodeid": "0298f6074a454a1f5345cb2a7c6f9fce206cd0bf675d177cdbf0ca7508dd28852f",
+                    "alias": "node1",
+                    "addresses": [
+                        {
+                            "type": "ipv4",
+                            "address": "159.69.16.168",
+                            "port": 9735
+                        },
+                        {
+                            "type": "dns",
+                            "address": "example.com",
+                            "port": 9736
+                        }
+                    ]
+                },
+                {
+                    "nodeid": "02899d09a65c5ca768c42b12e57d0497bfdf8ac1c46b0dcc0d4faefcdbc01304c1"
+                }
+            ],
+            "channels": [
+                {
+                    "source": "0298f6074a454a1f5345cb2a7c6f9fce206cd0bf675d177cdbf0ca7508dd28852f",
+                    "destination": "02899d09a65c5ca768c42b12e57d0497bfdf8ac1c46b0dcc0d4faefcdbc01304c1",
+                    "short_channel_id": "599709x2052x0",
+                    "direction": 0,
+                    "public": true,
+                    "amount_msat": 1000000000,
+                    "active": true,
+                    "last_update": 1571278793,
+                    "base_fee_millisatoshi": 1000,
+                    "fee_per_millionth": 1,
+                    "delay": 14,
+                    "htlc_minimum_msat": 1000,
+                    "htlc_maximum_msat": 990000000,
+                    "features": ""
+                },
+                {
+                    "source": "02899d09a65c5ca768c42b12e57d0497bfdf8ac1c46b0dcc0d4faefcdbc01304c1",
+                    "destination": "0298f6074a454a1f5345cb2a7c6f9fce206cd0bf675d177cdbf0ca7508dd28852f",
+                    "short_channel_id": "599709x2052x0",
+                    "direction": 1,
+                    "public": true,
+                    "amount_msat": "1000000000msat",
+                    "active": true,
+                    "last_update": 1571278793,
+                    "base_fee_millisatoshi": 0,
+                    "fee_per_millionth": 500,
+                    "delay": 40,
+                    "htlc_minimum_msat": "1msat",
+                    "htlc_maximum_msat": "990000000msat",
+                    "features": ""
+                }
+            ]
+            }"##;
+        let graph = Graph::from_cln_json_str(json_str).unwrap();
+        assert_eq!(graph.nodes.len(), 2);
+        assert_eq!(graph.edges.len(), 2);
+        let out_edges = graph.get_edges_for_node(
+            &"02899d09a65c5ca768c42b12e57d0497bfdf8ac1c46b0dcc0d4faefcdbc01304c1".to_owned(),
+        );
+        let actual = out_edges.iter().next().unwrap();
+        assert_eq!(actual.channel_id, "599709x2052x0/1");
+        assert_eq!(actual.fee_base_msat, 0);
+        assert_eq!(actual.fee_proportional_millionths, 500);
+        assert_eq!(actual.cltv_expiry_delta, 40);
+        assert_eq!(actual.htlc_minimim_msat, 1);
+        assert_eq!(actual.htlc_maximum_msat, 990000000);
+        // already denominated in msat
+        for e in graph.clone().get_edges_as_vec_vec().into_iter().flatten() {
+            assert_eq!(e.capacity, 1000000000);
+        }
+        let node = graph
+            .nodes
+            .iter()
+            .find(|n| n.alias == "node1")
+            .unwrap()
+            .clone();
+        let expected = vec![
+            Address::parse("159.69.16.168:9735").unwrap(),
+            Address::Dns {
+                hostname: "example.com".to_owned(),
+                port: 9736,
+            },
+        ];
+        assert_eq!(node.addresses, expected);
+    }
+
+    #[test]
+    fn discard_cln_edges_without_necessary_fields() {
+        let json_str = r##"{
+            "nodes": [
+                {
+                    "nodeid": "0298f6074a454a1f5345cb2a7c6f9fce206cd0bf675d177cdbf0ca7508dd28852f"
+                },
+                {
+                    "nodeid": "02899d09a65c5ca768c42b12e57d0497bfdf8ac1c46b0dcc0d4faefcdbc01304c1"
+                }
+            ],
+            "channels": [
+                {
+                    "source": "0298f6074a454a1f5345cb2a7c6f9fce206cd0bf675d177cdbf0ca7508dd28852f",
+                    "destination": "02899d09a65c5ca768c42b12e57d0497bfdf8ac1c46b0dcc0d4faefcdbc01304c1",
+                    "short_channel_id": "599709x2052x0",
+                    "direction": 0,
+                    "amount_msat": 1000000000,
+                    "base_fee_millisatoshi": 1000,
+                    "delay": 14,
+                    "htlc_maximum_msat": 990000000
+                },
+                {
+                    "source": "0298f6074a454a1f5345cb2a7c6f9fce206cd0bf675d177cdbf0ca7508dd28852f",
+                    "destination": "unknown",
+                    "short_channel_id": "599709x2053x0",
+                    "direction": 0,
+                    "amount_msat": 1000000000,
+                    "base_fee_millisatoshi": 1000,
+                    "fee_per_millionth": 1,
+                    "delay": 14,
+                    "htlc_maximum_msat": 990000000
+                }
+            ]
+            }"##;
+        let graph = Graph::from_json_str(json_str, GraphSource::Cln).unwrap();
+        assert_eq!(graph.nodes.len(), 2);
+        assert_eq!(graph.edge_count(), 0);
+    }
 }
@@ -1,11 +1,24 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::fs::{self, File};
 use std::hash::{Hash, Hasher};
 use std::io::{BufRead, BufReader};
 use std::path::Path;
 
+mod address;
+mod diff;
+mod error;
+mod export;
+mod features;
+mod funding;
 mod helpers;
+mod output;
+pub use address::*;
+pub use diff::*;
+pub use error::*;
+pub use export::*;
+pub use features::*;
+pub use funding::*;
 use helpers::*;
 
 #[derive(Clone, Debug, Default)]
@@ -13,6 +26,8 @@ pub enum GraphSource {
     Lnresearch,
     #[default]
     Lnd,
+    /// Merged output of Core Lightning's `listnodes` and `listchannels`
+    Cln,
 }
 #[derive(Deserialize, Clone, Debug, Default)]
 pub struct Graph {
@@ -27,12 +42,15 @@ pub struct Node {
     pub id: ID,
     pub alias: String,
     pub addresses: Vec<Address>,
-}
-
-#[derive(Deserialize, Clone, Debug, Default, PartialEq, Eq)]
-pub struct Address {
-    pub network: String,
-    pub addr: String,
+    /// Unix time of the node's latest announcement
+    pub timestamp: u64,
+    /// Announced feature bits
+    pub features: FeatureSet,
+    /// Hex encoded without leading #
+    pub rgb_color: String,
+    /// As reported by lnresearch snapshots, 0 otherwise
+    pub in_degree: usize,
+    pub out_degree: usize,
 }
 
 #[derive(Deserialize, Clone, Debug, Default)]
@@ -61,123 +79,203 @@ pub struct Edge {
     /// channel capacity which is either calculated after graph creation as the min of the involved nodes'
     /// max msat or available in LND graph as sats
     pub capacity: usize,
+    /// Unix time of the source's latest channel update
+    pub timestamp: u64,
+    /// Announced channel feature bits
+    pub features: FeatureSet,
+    /// The source does not forward via this channel
+    pub disabled: bool,
 }
 
 pub type ID = String;
 pub type NodeRanks = Vec<ID>;
 
 impl Graph {
-    pub fn from_json_str(
+    pub fn from_json_str(json_str: &str, graph_source: GraphSource) -> Result<Graph, ParseError> {
+        Self::parse_json_str(json_str, graph_source, ParseMode::Strict).map(|(graph, _)| graph)
+    }
+
+    pub fn from_json_file(path: &Path, graph_source: GraphSource) -> Result<Graph, ParseError> {
+        let json_str = fs::read_to_string(path)?;
+        Self::from_json_str(&json_str, graph_source)
+    }
+
+    /// Skips malformed nodes and edges instead of failing and reports what was skipped.
+    /// Fails only if the input cannot be read or is not valid JSON.
+    pub fn from_json_str_lenient(
         json_str: &str,
         graph_source: GraphSource,
-    ) -> Result<Graph, serde_json::Error> {
-        match graph_source {
-            GraphSource::Lnd => Self::from_lnd_json_str(json_str),
-            GraphSource::Lnresearch => Self::from_lnresearch_json_str(json_str),
-        }
+    ) -> Result<(Graph, ParseReport), ParseError> {
+        Self::parse_json_str(json_str, graph_source, ParseMode::Lenient)
     }
 
-    pub fn from_json_file(
+    /// See [Graph::from_json_str_lenient]
+    pub fn from_json_file_lenient(
         path: &Path,
         graph_source: GraphSource,
-    ) -> Result<Graph, serde_json::Error> {
-        let json_str = fs::read_to_string(path).expect("Error reading file");
-        Self::from_json_str(&json_str, graph_source)
+    ) -> Result<(Graph, ParseReport), ParseError> {
+        Self::parse_json_file(path, graph_source, ParseMode::Lenient)
     }
 
-    fn nodes_from_raw_lnd_graph(nodes: &[RawLndNode]) -> HashSet<Node> {
-        // discard nodes without ID
-        nodes
-            .iter()
-            .filter(|raw_node| raw_node.id.clone().unwrap_or_default() != ID::default())
-            .map(|raw_node| Node::from_raw_lnd(raw_node.clone()))
-            .collect()
+    /// See [Graph::parse_json_str]
+    pub fn parse_json_file(
+        path: &Path,
+        graph_source: GraphSource,
+        mode: ParseMode,
+    ) -> Result<(Graph, ParseReport), ParseError> {
+        let json_str = fs::read_to_string(path)?;
+        Self::parse_json_str(&json_str, graph_source, mode)
     }
 
-    fn nodes_from_raw_lnresearch_graph(nodes: &[RawLnresearchNode]) -> HashSet<Node> {
-        // discard nodes without ID
+    /// Treats malformed nodes and edges according to the given mode.
+    /// The report is empty in strict mode except for nodes without ID, which are always skipped.
+    pub fn parse_json_str(
+        json_str: &str,
+        graph_source: GraphSource,
+        mode: ParseMode,
+    ) -> Result<(Graph, ParseReport), ParseError> {
+        match graph_source {
+            GraphSource::Lnd => Self::parse_lnd_json_str(json_str, mode),
+            GraphSource::Lnresearch => Self::parse_lnresearch_json_str(json_str, mode),
+            GraphSource::Cln => Self::parse_cln_json_str(json_str, mode),
+        }
+    }
+
+    /// Nodes without ID have always been discarded so we do so regardless of the mode
+    fn collect_nodes(
+        nodes: impl Iterator<Item = Result<Node, ParseError>>,
+        report: &mut ParseReport,
+    ) -> HashSet<Node> {
         nodes
-            .iter()
-            .filter(|raw_node| raw_node.id.clone().unwrap_or_default() != ID::default())
-            .map(|raw_node| Node::from_raw_lnresearch(raw_node.clone()))
+            .map(|node| match node {
+                Ok(node) if node.id == ID::default() => Err(ParseError::missing_field(
+                    "id",
+                    &format!("node {:?}", node.alias),
+                )),
+                node => node,
+            })
+            .filter_map(|node| report.check_node(node, ParseMode::Lenient).ok().flatten())
             .collect()
     }
 
-    pub fn from_lnresearch_json_str(json_str: &str) -> Result<Graph, serde_json::Error> {
-        let raw_graph: RawLnresearchGraph =
-            serde_json::from_str(json_str).expect("Error deserialising JSON str!");
-        let nodes = Self::nodes_from_raw_lnresearch_graph(&raw_graph.nodes);
+    fn insert_edge(edges: &mut HashMap<ID, HashSet<Edge>>, edge: Edge) {
+        edges.entry(edge.source.clone()).or_default().insert(edge);
+    }
+
+    pub fn from_lnresearch_json_str(json_str: &str) -> Result<Graph, ParseError> {
+        Self::parse_lnresearch_json_str(json_str, ParseMode::Strict).map(|(graph, _)| graph)
+    }
+
+    fn parse_lnresearch_json_str(
+        json_str: &str,
+        mode: ParseMode,
+    ) -> Result<(Graph, ParseReport), ParseError> {
+        let raw_graph: RawLnresearchGraph = serde_json::from_str(json_str)?;
+        let mut report = ParseReport::default();
+        let nodes = Self::collect_nodes(
+            raw_graph.nodes.into_iter().map(Node::from_raw_lnresearch),
+            &mut report,
+        );
         let mut edges: HashMap<ID, HashSet<Edge>> = HashMap::with_capacity(raw_graph.edges.len());
-        // discard edges with unknown IDs
-        let edges_vec: Vec<HashSet<Edge>> = raw_graph
-            .edges
-            .iter()
-            .map(|adj| {
-                adj.iter()
-                    .filter(|raw_edge| {
-                        // We only need the ID to know if the node exists
-                        let src_node = Node {
-                            id: raw_edge.source.clone().unwrap(),
-                            ..Default::default()
-                        };
-                        let dest_node = Node {
-                            id: raw_edge.destination.clone().unwrap(),
-                            ..Default::default()
-                        };
-                        nodes.contains(&src_node) && nodes.contains(&dest_node)
-                    })
-                    .filter(|raw_edge| Edge::from_lnresearch_raw(&(*raw_edge).clone()).is_some())
-                    .map(|raw_edge| Edge::from_lnresearch_raw(raw_edge).unwrap())
-                    .collect()
-            })
-            .collect();
-        for node_adj in edges_vec {
-            for edge in node_adj {
-                match edges.get_mut(&edge.source) {
-                    Some(node) => node.insert(edge),
-                    None => {
-                        edges.insert(edge.source.clone(), HashSet::from([edge]));
-                        true // weird so that match arms return same type
-                    }
-                };
+        for raw_edge in raw_graph.edges.iter().flatten() {
+            // discard edges with unknown IDs
+            let edge = endpoints_known(
+                &nodes,
+                &raw_edge.channel_id,
+                &raw_edge.source,
+                &raw_edge.destination,
+            )
+            .and_then(|known| {
+                if known {
+                    Edge::from_lnresearch_raw(raw_edge)
+                } else {
+                    Ok(None)
+                }
+            });
+            if let Some(Some(edge)) = report.check_edge(edge, mode, 1)? {
+                Self::insert_edge(&mut edges, edge);
             }
         }
-        Ok(Graph { nodes, edges })
+        Ok((Graph { nodes, edges }, report))
+    }
+
+    pub fn from_lnd_json_str(json_str: &str) -> Result<Graph, ParseError> {
+        Self::parse_lnd_json_str(json_str, ParseMode::Strict).map(|(graph, _)| graph)
     }
-    pub fn from_lnd_json_str(json_str: &str) -> Result<Graph, serde_json::Error> {
-        let raw_graph: RawLndGraph =
-            serde_json::from_str(json_str).expect("Error deserialising JSON str!");
-        let nodes = Self::nodes_from_raw_lnd_graph(&raw_graph.nodes);
+
+    fn parse_lnd_json_str(
+        json_str: &str,
+        mode: ParseMode,
+    ) -> Result<(Graph, ParseReport), ParseError> {
+        let raw_graph: RawLndGraph = serde_json::from_str(json_str)?;
+        let mut report = ParseReport::default();
+        let nodes = Self::collect_nodes(
+            raw_graph.nodes.into_iter().map(Node::from_raw_lnd),
+            &mut report,
+        );
         let mut edges: HashMap<ID, HashSet<Edge>> = HashMap::with_capacity(raw_graph.edges.len());
-        // discard edges with unknown IDs
-        let mut edges_vec = vec![];
         for raw_edge in raw_graph.edges {
-            let src_node = Node {
-                id: raw_edge.source.clone().unwrap(),
-                ..Default::default()
-            };
-            let dest_node = Node {
-                id: raw_edge.destination.clone().unwrap(),
-                ..Default::default()
-            };
-            if nodes.contains(&src_node) && nodes.contains(&dest_node) {
-                if let Some(edge) = Edge::from_lnd_raw(&(raw_edge).clone()) {
-                    edges_vec.push(edge.0);
-                    edges_vec.push(edge.1);
+            // discard edges with unknown IDs
+            let channel = endpoints_known(
+                &nodes,
+                &raw_edge.channel_id,
+                &raw_edge.source,
+                &raw_edge.destination,
+            )
+            .and_then(|known| {
+                if known {
+                    Edge::from_lnd_raw(&raw_edge)
+                } else {
+                    Ok(None)
                 }
+            });
+            if let Some(Some((edge1, edge2))) = report.check_edge(channel, mode, 2)? {
+                Self::insert_edge(&mut edges, edge1);
+                Self::insert_edge(&mut edges, edge2);
             }
         }
-        for edge in edges_vec {
-            match edges.get_mut(&edge.source) {
-                Some(node) => node.insert(edge),
-                None => {
-                    edges.insert(edge.source.clone(), HashSet::from([edge]));
-                    true // weird so that match arms return same type
+        Ok((Graph { nodes, edges }, report))
+    }
+
+    /// Expects `{"nodes": [..], "channels": [..]}` as produced by merging the output of
+    /// `lightning-cli listnodes` and `lightning-cli listchannels`
+    pub fn from_cln_json_str(json_str: &str) -> Result<Graph, ParseError> {
+        Self::parse_cln_json_str(json_str, ParseMode::Strict).map(|(graph, _)| graph)
+    }
+
+    fn parse_cln_json_str(
+        json_str: &str,
+        mode: ParseMode,
+    ) -> Result<(Graph, ParseReport), ParseError> {
+        let raw_graph: RawClnGraph = serde_json::from_str(json_str)?;
+        let mut report = ParseReport::default();
+        let nodes = Self::collect_nodes(
+            raw_graph.nodes.into_iter().map(Node::from_raw_cln),
+            &mut report,
+        );
+        let mut edges: HashMap<ID, HashSet<Edge>> = HashMap::with_capacity(nodes.len());
+        for raw_edge in raw_graph.channels {
+            // discard edges with unknown IDs
+            let edge = endpoints_known(
+                &nodes,
+                &raw_edge.short_channel_id,
+                &raw_edge.source,
+                &raw_edge.destination,
+            )
+            .and_then(|known| {
+                if known {
+                    Edge::from_cln_raw(&raw_edge)
+                } else {
+                    Ok(None)
                 }
-            };
+            });
+            if let Some(Some(edge)) = report.check_edge(edge, mode, 1)? {
+                Self::insert_edge(&mut edges, edge);
+            }
         }
-        Ok(Graph { nodes, edges })
+        Ok((Graph { nodes, edges }, report))
     }
+
     pub fn get_nodes(self) -> HashSet<Node> {
         self.nodes
     }
@@ -203,6 +301,33 @@ impl Graph {
         self.get_edges_as_vec_vec().iter().map(Vec::len).sum()
     }
 
+    /// Removes directions their source has disabled.
+    /// Returns the number of removed edges.
+    pub fn remove_disabled_edges(&mut self) -> usize {
+        self.retain_edges(|edge| !edge.disabled)
+    }
+
+    /// Removes directions whose latest update is more than `max_age_days` older than the newest
+    /// update in the graph, similar to how nodes prune zombie channels.
+    /// Edges without timestamp are kept. Returns the number of removed edges.
+    pub fn remove_stale_edges(&mut self, max_age_days: u64) -> usize {
+        let Some(newest) = self.edges.values().flatten().map(|e| e.timestamp).max() else {
+            return 0;
+        };
+        let oldest_allowed = newest.saturating_sub(max_age_days.saturating_mul(86_400));
+        self.retain_edges(|edge| edge.timestamp == 0 || edge.timestamp >= oldest_allowed)
+    }
+
+    fn retain_edges(&mut self, keep: impl Fn(&Edge) -> bool) -> usize {
+        let mut num_removed = 0;
+        for edges in self.edges.values_mut() {
+            let num_edges = edges.len();
+            edges.retain(&keep);
+            num_removed += num_edges - edges.len();
+        }
+        num_removed
+    }
+
     #[allow(unused)]
     pub(crate) fn get_node_ids(&self) -> Vec<String> {
         self.nodes.iter().map(|n| n.id.clone()).collect()
@@ -213,7 +338,7 @@ pub fn read_node_rankings_from_file(
     nodes: &[ID],
     path: &Path,
 ) -> Result<NodeRanks, std::io::Error> {
-    let file = File::open(path).unwrap_or_else(|_| panic!("Error reading {}.", path.display()));
+    let file = File::open(path)?;
     let reader = BufReader::new(file);
     let mut ranks: NodeRanks = vec![];
     for line in reader.lines().map_while(Result::ok) {
@@ -237,6 +362,28 @@ impl PartialEq for Node {
     }
 }
 
+impl Edge {
+    /// The channel id without the direction lnresearch and CLN append, i.e. the same for both
+    /// directions of a channel
+    pub fn short_channel_id(&self) -> &str {
+        output::short_channel_id(&self.channel_id)
+    }
+
+    /// True if `other` is the opposite direction of the same channel
+    pub fn is_reverse_of(&self, other: &Edge) -> bool {
+        self.source == other.destination
+            && self.destination == other.source
+            && self.short_channel_id() == other.short_channel_id()
+    }
+
+    /// True if the source's policy allows forwarding an HTLC of the given amount. A maximum of 0
+    /// means the graph did not announce one.
+    pub fn htlc_limits_allow(&self, amount_msat: usize) -> bool {
+        amount_msat >= self.htlc_minimim_msat
+            && (self.htlc_maximum_msat == 0 || amount_msat <= self.htlc_maximum_msat)
+    }
+}
+
 impl Hash for Edge {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.channel_id.hash(state);
@@ -252,13 +399,14 @@ impl PartialEq for Edge {
 
 impl clap::ValueEnum for GraphSource {
     fn value_variants<'a>() -> &'a [Self] {
-        &[Self::Lnd, Self::Lnresearch]
+        &[Self::Lnd, Self::Lnresearch, Self::Cln]
     }
 
     fn to_possible_value<'a>(&self) -> Option<clap::builder::PossibleValue> {
         match self {
             Self::Lnd => Some(clap::builder::PossibleValue::new("lnd")),
             Self::Lnresearch => Some(clap::builder::PossibleValue::new("lnr")),
+            Self::Cln => Some(clap::builder::PossibleValue::new("cln")),
         }
     }
 }
@@ -294,10 +442,8 @@ mod tests {
         let expected = Node {
             id: "021f0f2a5b46871b23f690a5be893f5b3ec37cf5a0fd8b89872234e984df35ea32".to_string(),
             alias: "MilliBit".to_string(),
-            addresses: vec![Address {
-                network: "tcp".to_string(),
-                addr: "80.115.186.52:9735".to_string(),
-            }],
+            addresses: vec![Address::parse("80.115.186.52:9735").unwrap()],
+            ..Default::default()
         };
         assert_eq!(*actual, expected);
     }
@@ -374,6 +520,7 @@ mod tests {
                     balance: 0,
                     capacity: 0,
                     liquidity: 0,
+                    ..Default::default()
                 },
                 Edge {
                     channel_id: "714116x477x0/0".to_string(),
@@ -390,6 +537,7 @@ mod tests {
                     balance: 0,
                     liquidity: 0,
                     capacity: 0,
+                    ..Default::default()
                 },
             ]),
         )]);
@@ -414,6 +562,7 @@ mod tests {
             id: "021f0f2a5b46871b23f690a5be893f5b3ec37cf5a0fd8b89872234e984df35ea32".to_string(),
             alias: String::default(),
             addresses: vec![],
+            ..Default::default()
         };
         assert_eq!(*actual, expected);
     }
@@ -513,6 +662,7 @@ mod tests {
                 balance: 0,
                 liquidity: 0,
                 capacity: 0,
+                ..Default::default()
             },
             Edge {
                 channel_id: "714116x477x0/0".to_string(),
@@ -528,6 +678,7 @@ mod tests {
                 balance: 0,
                 liquidity: 0,
                 capacity: 0,
+                ..Default::default()
             },
         ];
         for edge in expected {
@@ -553,7 +704,7 @@ mod tests {
             "adjacency": [
               ]
             }"##;
-        let graph = Graph::from_lnresearch_json_str(&json_str).unwrap();
+        let graph = Graph::from_lnresearch_json_str(json_str).unwrap();
         let actual = graph.get_edges_for_node(
             &"021f0f2a5b46871b23f690a5be893f5b3ec37cf5a0fd8b89872234e984df35ea32".to_string(),
         );
@@ -627,7 +778,7 @@ mod tests {
                 ]
               ]
             }"##;
-        let graph = Graph::from_lnresearch_json_str(&json_str).unwrap();
+        let graph = Graph::from_lnresearch_json_str(json_str).unwrap();
         let actual = graph.edge_count();
         let expected = 2;
         assert_eq!(actual, expected);
@@ -683,7 +834,7 @@ mod tests {
                 ]
               ]
             }"##;
-        let graph = Graph::from_lnresearch_json_str(&json_str).unwrap();
+        let graph = Graph::from_lnresearch_json_str(json_str).unwrap();
         let actual = graph.edge_count();
         let expected = 0;
         assert_eq!(expected, actual);
@@ -694,7 +845,7 @@ mod tests {
         let path_to_file = Path::new("../test_data/trivial_connected.json");
         let graph = Graph::from_json_file(path_to_file, GraphSource::Lnresearch).unwrap();
         let actual = graph.get_node_ids();
-        let expected = vec!["025".to_owned(), "034".to_owned(), "036".to_owned()];
+        let expected = ["025".to_owned(), "034".to_owned(), "036".to_owned()];
         assert_eq!(actual.len(), expected.len());
         for id in actual {
             assert!(expected.contains(&id));
@@ -712,7 +863,7 @@ mod tests {
         assert!(actual.is_ok());
         let actual = actual.unwrap();
         assert_eq!(actual.len(), nodes.len());
-        let expected = vec!["036".to_owned(), "025".to_owned(), "034".to_owned()];
+        let expected = ["036".to_owned(), "025".to_owned(), "034".to_owned()];
         assert_eq!(actual.len(), expected.len());
         for id in actual {
             assert!(expected.contains(&id));
@@ -723,7 +874,7 @@ mod tests {
         assert!(actual.is_ok());
         let actual = actual.unwrap();
         assert_eq!(actual.len(), nodes.len());
-        let expected = vec!["036".to_owned(), "025".to_owned(), "034".to_owned()];
+        let expected = ["036".to_owned(), "025".to_owned(), "034".to_owned()];
         assert_eq!(actual.len(), expected.len());
         for id in actual {
             assert!(expected.contains(&id));
@@ -774,7 +925,7 @@ mod tests {
                 }
             ]
             }"##;
-        let graph = Graph::from_lnd_json_str(&json_str).unwrap();
+        let graph = Graph::from_lnd_json_str(json_str).unwrap();
         assert_eq!(graph.nodes.len(), 2);
         assert_eq!(graph.edges.len(), 2);
     }
@@ -815,8 +966,257 @@ mod tests {
                 }
             ]
             }"##;
-        let graph = Graph::from_lnd_json_str(&json_str).unwrap();
+        let graph = Graph::from_lnd_json_str(json_str).unwrap();
         assert_eq!(graph.nodes.len(), 2);
         assert_eq!(graph.edges.len(), 0);
     }
+
+    const EDGE_WO_SOURCE: &str = r##"{
+            "nodes": [
+                { "id": "a", "alias": "a", "addresses": "" },
+                { "id": "b", "alias": "b", "addresses": "" },
+                { "alias": "no_id", "addresses": "" }
+            ],
+            "adjacency": [
+                [
+                    {
+                        "scid": "1/0",
+                        "destination": "b",
+                        "fee_base_msat": 1,
+                        "fee_proportional_millionths": 1,
+                        "htlc_minimim_msat": 1,
+                        "htlc_maximum_msat": 1000,
+                        "cltv_expiry_delta": 40
+                    },
+                    {
+                        "scid": "2/0",
+                        "source": "a",
+                        "destination": "b",
+                        "fee_base_msat": 1,
+                        "fee_proportional_millionths": 1,
+                        "htlc_minimim_msat": 1,
+                        "htlc_maximum_msat": 1000,
+                        "cltv_expiry_delta": 40
+                    }
+                ]
+            ]
+        }"##;
+
+    #[test]
+    fn strict_parsing_fails_on_malformed_edge() {
+        let actual = Graph::from_lnresearch_json_str(EDGE_WO_SOURCE);
+        assert!(matches!(
+            actual,
+            Err(ParseError::MissingField {
+                field: "source",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn lenient_parsing_skips_malformed_edge() {
+        let (graph, report) =
+            Graph::from_json_str_lenient(EDGE_WO_SOURCE, GraphSource::Lnresearch).unwrap();
+        assert_eq!(graph.nodes.len(), 2);
+        assert_eq!(graph.edge_count(), 1);
+        assert_eq!(report.skipped_nodes, 1);
+        assert_eq!(report.skipped_edges, 1);
+        assert_eq!(report.num_skipped(), 2);
+        assert_eq!(report.errors.len(), 2);
+    }
+
+    #[test]
+    fn invalid_json_and_missing_file_are_errors() {
+        let actual = Graph::from_json_str("{\"nodes\": [", GraphSource::Lnd);
+        assert!(matches!(actual, Err(ParseError::Json(_))));
+        let actual = Graph::from_json_str_lenient("{\"nodes\": [", GraphSource::Lnresearch);
+        assert!(matches!(actual, Err(ParseError::Json(_))));
+        let path = Path::new("../test_data/does_not_exist.json");
+        let actual = Graph::from_json_file(path, GraphSource::Lnresearch);
+        assert!(matches!(actual, Err(ParseError::Io(_))));
+        assert!(read_node_rankings_from_file(&[], path).is_err());
+    }
+
+    #[test]
+    fn node_and_edge_metadata_is_kept() {
+        let json_str = r##"{
+            "nodes": [
+                {
+                    "id": "a",
+                    "timestamp": 1657607504,
+                    "features": "888000080a69a2",
+                    "rgb_color": "550055",
+                    "alias": "MilliBit",
+                    "addresses": "",
+                    "out_degree": 25,
+                    "in_degree": 9
+                },
+                { "id": "b", "addresses": "" }
+            ],
+            "adjacency": [
+                [
+                    {
+                        "scid": "714105x2146x0/0",
+                        "source": "a",
+                        "destination": "b",
+                        "timestamp": 1656588194,
+                        "features": "02",
+                        "fee_base_msat": 5,
+                        "fee_proportional_millionths": 270,
+                        "htlc_minimim_msat": 1000,
+                        "htlc_maximum_msat": 5564111000,
+                        "cltv_expiry_delta": 34
+                    }
+                ]
+            ]
+        }"##;
+        let graph = Graph::from_lnresearch_json_str(json_str).unwrap();
+        let node = graph.nodes.iter().find(|n| n.id == "a").unwrap();
+        assert_eq!(node.timestamp, 1657607504);
+        assert_eq!(node.features.to_hex(), "888000080a69a2");
+        assert_eq!(node.rgb_color, "550055");
+        assert_eq!((node.in_degree, node.out_degree), (9, 25));
+        let edge = graph.edges.get("a").unwrap().iter().next().unwrap();
+        assert_eq!(edge.timestamp, 1656588194);
+        assert_eq!(edge.features.to_hex(), "02");
+        assert!(!edge.disabled);
+    }
+
+    const LND_POLICIES: &str = r##"{
+            "nodes": [
+                {
+                    "last_update": 1567764428,
+                    "pub_key": "a",
+                    "alias": "node1",
+                    "color": "#3399ff",
+                    "features": {
+                        "1": { "name": "data-loss-protect", "is_required": false, "is_known": true },
+                        "14": { "name": "payment-addr", "is_required": true, "is_known": true },
+                        "17": { "name": "multi-path-payments", "is_required": false, "is_known": true }
+                    }
+                },
+                { "pub_key": "b", "alias": "node2" },
+                { "pub_key": "c", "alias": "node3" }
+            ],
+            "edges": [
+                {
+                    "channel_id": "1",
+                    "node1_pub": "a",
+                    "node2_pub": "b",
+                    "capacity": "1000000",
+                    "node1_policy": {
+                        "time_lock_delta": 14,
+                        "min_htlc": "1000",
+                        "fee_base_msat": "1000",
+                        "fee_rate_milli_msat": "1",
+                        "disabled": true,
+                        "max_htlc_msat": "990000000",
+                        "last_update": 1700000000
+                    },
+                    "node2_policy": {
+                        "time_lock_delta": 14,
+                        "min_htlc": "1000",
+                        "fee_base_msat": "1000",
+                        "fee_rate_milli_msat": "1",
+                        "disabled": false,
+                        "max_htlc_msat": "990000000",
+                        "last_update": 1700000000
+                    }
+                },
+                {
+                    "channel_id": "2",
+                    "node1_pub": "b",
+                    "node2_pub": "c",
+                    "capacity": "1000000",
+                    "node1_policy": {
+                        "time_lock_delta": 14,
+                        "min_htlc": "1000",
+                        "fee_base_msat": "1000",
+                        "fee_rate_milli_msat": "1",
+                        "disabled": false,
+                        "max_htlc_msat": "990000000",
+                        "last_update": 1698000000
+                    },
+                    "node2_policy": {
+                        "time_lock_delta": 14,
+                        "min_htlc": "1000",
+                        "fee_base_msat": "1000",
+                        "fee_rate_milli_msat": "1",
+                        "disabled": false,
+                        "max_htlc_msat": "990000000",
+                        "last_update": 1699990000
+                    }
+                }
+            ]
+        }"##;
+
+    #[test]
+    fn lnd_metadata_is_kept() {
+        let graph = Graph::from_lnd_json_str(LND_POLICIES).unwrap();
+        let node = graph.nodes.iter().find(|n| n.id == "a").unwrap();
+        assert_eq!(node.timestamp, 1567764428);
+        assert_eq!(node.rgb_color, "3399ff");
+        // bits 1, 14 and 17
+        assert_eq!(node.features.to_hex(), "024002");
+        let edge = graph.edges.get("a").unwrap().iter().next().unwrap();
+        assert!(edge.disabled);
+        assert_eq!(edge.timestamp, 1700000000);
+    }
+
+    #[test]
+    fn remove_disabled_and_stale_edges() {
+        let mut graph = Graph::from_lnd_json_str(LND_POLICIES).unwrap();
+        assert_eq!(graph.clone().edge_count(), 4);
+        assert_eq!(graph.remove_disabled_edges(), 1);
+        assert!(graph.edges.get("a").unwrap().is_empty());
+        // b's policy for channel 2 is about 23 days older than the newest update
+        assert_eq!(graph.remove_stale_edges(u64::MAX), 0);
+        assert_eq!(graph.remove_stale_edges(30), 0);
+        assert_eq!(graph.remove_stale_edges(14), 1);
+        assert_eq!(graph.clone().edge_count(), 2);
+        assert!(graph
+            .edges
+            .get("b")
+            .unwrap()
+            .iter()
+            .all(|e| e.channel_id == "1"));
+    }
+
+    #[test]
+    fn edge_directions() {
+        let edge = |channel_id: &str, source: &str, destination: &str| Edge {
+            channel_id: channel_id.to_owned(),
+            source: source.to_owned(),
+            destination: destination.to_owned(),
+            ..Default::default()
+        };
+        let forward = edge("100x1x0/0", "alice", "bob");
+        assert_eq!(forward.short_channel_id(), "100x1x0");
+        assert!(forward.is_reverse_of(&edge("100x1x0/1", "bob", "alice")));
+        // a parallel channel
+        assert!(!forward.is_reverse_of(&edge("100x2x0/1", "bob", "alice")));
+        assert!(!forward.is_reverse_of(&edge("100x1x0/1", "alice", "bob")));
+        // LND uses the same id for both directions
+        let lnd = edge("659379322247708673", "alice", "bob");
+        assert!(lnd.is_reverse_of(&edge("659379322247708673", "bob", "alice")));
+    }
+
+    #[test]
+    fn htlc_limits() {
+        let edge = Edge {
+            htlc_minimim_msat: 1000,
+            htlc_maximum_msat: 5000,
+            ..Default::default()
+        };
+        assert!(!edge.htlc_limits_allow(999));
+        assert!(edge.htlc_limits_allow(1000));
+        assert!(edge.htlc_limits_allow(5000));
+        assert!(!edge.htlc_limits_allow(5001));
+        let unlimited = Edge {
+            htlc_maximum_msat: 0,
+            ..edge
+        };
+        assert!(unlimited.htlc_limits_allow(usize::MAX));
+    }
 }
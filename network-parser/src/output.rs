@@ -0,0 +1,387 @@
+use crate::helpers::*;
+use crate::*;
+use std::collections::BTreeMap;
+
+impl Graph {
+    /// Serialises the graph in the shape of the given source so that it can be read again using
+    /// [Graph::from_json_str].
+    /// Balances and liquidity are not part of any of the formats and are therefore not written.
+    pub fn to_json_str(&self, graph_source: GraphSource) -> Result<String, ParseError> {
+        let json_str = match graph_source {
+            GraphSource::Lnresearch => {
+                serde_json::to_string_pretty(&RawLnresearchGraph::from_graph(self))
+            }
+            GraphSource::Lnd => serde_json::to_string_pretty(&RawLndGraph::from_graph(self)),
+            GraphSource::Cln => serde_json::to_string_pretty(&RawClnGraph::from_graph(self)),
+        }?;
+        Ok(json_str)
+    }
+
+    /// See [Graph::to_json_str]
+    pub fn to_json_file(&self, path: &Path, graph_source: GraphSource) -> Result<(), ParseError> {
+        fs::write(path, self.to_json_str(graph_source)?)?;
+        Ok(())
+    }
+
+    /// Sorted by ID so that the output is deterministic
+    fn sorted_nodes(&self) -> Vec<&Node> {
+        let mut nodes: Vec<&Node> = self.nodes.iter().collect();
+        nodes.sort_by(|a, b| a.id.cmp(&b.id));
+        nodes
+    }
+
+    /// Sorted by channel ID so that the output is deterministic
+    fn sorted_out_edges(&self, node_id: &ID) -> Vec<&Edge> {
+        let mut edges: Vec<&Edge> = self
+            .edges
+            .get(node_id)
+            .map(|edges| edges.iter().collect())
+            .unwrap_or_default();
+        edges.sort_by(|a, b| a.channel_id.cmp(&b.channel_id));
+        edges
+    }
+
+    /// Groups the directions of each channel by their short channel id
+    fn channels(&self) -> BTreeMap<&str, Vec<&Edge>> {
+        let mut channels: BTreeMap<&str, Vec<&Edge>> = BTreeMap::new();
+        for edge in self.edges.values().flatten() {
+            channels
+                .entry(short_channel_id(&edge.channel_id))
+                .or_default()
+                .push(edge);
+        }
+        for edges in channels.values_mut() {
+            edges.sort_by(|a, b| a.source.cmp(&b.source));
+        }
+        channels
+    }
+}
+
+impl RawLnresearchGraph {
+    pub(crate) fn from_graph(graph: &Graph) -> Self {
+        let nodes = graph.sorted_nodes();
+        let edges = nodes
+            .iter()
+            .map(|node| {
+                graph
+                    .sorted_out_edges(&node.id)
+                    .into_iter()
+                    .map(LnresearchRawEdge::from_edge)
+                    .collect()
+            })
+            .collect();
+        Self {
+            nodes: nodes
+                .into_iter()
+                .map(|node| RawLnresearchNode {
+                    id: Some(node.id.clone()),
+                    alias: Some(node.alias.clone()),
+                    addresses: Some(
+                        node.addresses
+                            .iter()
+                            .map(|addr| format!("{}://{}", addr.network(), addr))
+                            .collect(),
+                    ),
+                    timestamp: Some(node.timestamp),
+                    features: Some(node.features.to_hex()),
+                    rgb_color: Some(node.rgb_color.clone()),
+                    out_degree: Some(node.out_degree),
+                    in_degree: Some(node.in_degree),
+                })
+                .collect(),
+            edges,
+        }
+    }
+}
+
+impl LnresearchRawEdge {
+    fn from_edge(edge: &Edge) -> Self {
+        Self {
+            channel_id: Some(edge.channel_id.clone()),
+            source: Some(edge.source.clone()),
+            destination: Some(edge.destination.clone()),
+            fee_base_msat: Some(edge.fee_base_msat as u64),
+            fee_proportional_millionths: Some(edge.fee_proportional_millionths as u64),
+            htlc_minimim_msat: Some(edge.htlc_minimim_msat as u64),
+            htlc_maximum_msat: Some(edge.htlc_maximum_msat as u64),
+            cltv_expiry_delta: Some(edge.cltv_expiry_delta as u64),
+            timestamp: Some(edge.timestamp),
+            features: Some(edge.features.to_hex()),
+        }
+    }
+}
+
+impl RawLndGraph {
+    /// Directions are paired by their short channel id. A channel with only one known direction
+    /// is written with a null policy, which means it is discarded when reading the graph again.
+    pub(crate) fn from_graph(graph: &Graph) -> Self {
+        let nodes = graph
+            .sorted_nodes()
+            .into_iter()
+            .map(|node| RawLndNode {
+                id: Some(node.id.clone()),
+                alias: Some(node.alias.clone()),
+                addresses: Some(
+                    node.addresses
+                        .iter()
+                        .map(|addr| LndAddress {
+                            network: "tcp".to_owned(),
+                            addr: addr.to_string(),
+                        })
+                        .collect(),
+                ),
+                last_update: Some(node.timestamp),
+                color: Some(format!("#{}", node.rgb_color)),
+                features: Some(
+                    node.features
+                        .bits()
+                        .map(|bit| {
+                            let feature = LndFeature {
+                                // even bits are compulsory
+                                is_required: bit % 2 == 0,
+                                ..Default::default()
+                            };
+                            (bit.to_string(), feature)
+                        })
+                        .collect(),
+                ),
+            })
+            .collect();
+        let edges = graph
+            .channels()
+            .into_iter()
+            .map(|(scid, edges)| {
+                // edges are sorted by source so node1 is the lesser ID, as in LND
+                let node1 = edges[0].source.clone();
+                let node2 = edges[0].destination.clone();
+                let policy = |source: &ID| {
+                    edges
+                        .iter()
+                        .find(|e| e.source == *source)
+                        .map(|e| NodePolicy::from_edge(e))
+                };
+                // LND reports the capacity in sat
+                let capacity = edges.iter().map(|e| e.capacity).max().unwrap_or_default() / 1000;
+                LndRawEdge {
+                    channel_id: Some(scid.to_owned()),
+                    node1_policy: policy(&node1),
+                    node2_policy: policy(&node2),
+                    source: Some(node1),
+                    destination: Some(node2),
+                    capacity: Some(capacity as u64),
+                }
+            })
+            .collect();
+        Self { nodes, edges }
+    }
+}
+
+impl NodePolicy {
+    fn from_edge(edge: &Edge) -> Self {
+        Self {
+            fee_base_msat: Some(edge.fee_base_msat as u64),
+            fee_proportional_millionths: Some(edge.fee_proportional_millionths as u64),
+            htlc_minimim_msat: Some(edge.htlc_minimim_msat as u64),
+            htlc_maximum_msat: Some(edge.htlc_maximum_msat as u64),
+            cltv_expiry_delta: Some(edge.cltv_expiry_delta as u64),
+            disabled: Some(edge.disabled),
+            last_update: Some(edge.timestamp),
+        }
+    }
+}
+
+impl RawClnGraph {
+    pub(crate) fn from_graph(graph: &Graph) -> Self {
+        let nodes = graph.sorted_nodes();
+        let channels = nodes
+            .iter()
+            .flat_map(|node| graph.sorted_out_edges(&node.id))
+            .map(ClnRawEdge::from_edge)
+            .collect();
+        Self {
+            nodes: nodes
+                .into_iter()
+                .map(|node| RawClnNode {
+                    id: Some(node.id.clone()),
+                    alias: Some(node.alias.clone()),
+                    addresses: Some(
+                        node.addresses
+                            .iter()
+                            .map(|addr| ClnAddress {
+                                network: addr.network().to_owned(),
+                                address: Some(addr.host()),
+                                port: Some(addr.port()),
+                            })
+                            .collect(),
+                    ),
+                    last_timestamp: Some(node.timestamp),
+                    color: Some(node.rgb_color.clone()),
+                    features: Some(node.features.to_hex()),
+                })
+                .collect(),
+            channels,
+        }
+    }
+}
+
+impl ClnRawEdge {
+    fn from_edge(edge: &Edge) -> Self {
+        // CLN's direction is 0 if the source has the lesser node id
+        let direction = match edge.channel_id.rsplit_once('/') {
+            Some((_, "0")) => 0,
+            Some((_, "1")) => 1,
+            _ => u8::from(edge.source > edge.destination),
+        };
+        Self {
+            short_channel_id: Some(short_channel_id(&edge.channel_id).to_owned()),
+            source: Some(edge.source.clone()),
+            destination: Some(edge.destination.clone()),
+            direction: Some(direction),
+            amount_msat: Some(edge.capacity as u64),
+            base_fee_millisatoshi: Some(edge.fee_base_msat as u64),
+            fee_per_millionth: Some(edge.fee_proportional_millionths as u64),
+            delay: Some(edge.cltv_expiry_delta as u64),
+            htlc_minimum_msat: Some(edge.htlc_minimim_msat as u64),
+            htlc_maximum_msat: Some(edge.htlc_maximum_msat as u64),
+            active: Some(!edge.disabled),
+            last_update: Some(edge.timestamp),
+            features: Some(edge.features.to_hex()),
+        }
+    }
+}
+
+/// Strips the direction lnresearch and CLN channel ids carry, e.g. `"683464x931x0/1"`
+pub(crate) fn short_channel_id(channel_id: &str) -> &str {
+    match channel_id.rsplit_once('/') {
+        Some((scid, "0" | "1")) => scid,
+        _ => channel_id,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Node and Edge only compare IDs so we compare all fields that are read from file
+    fn assert_same_graph(expected: &Graph, actual: &Graph) {
+        assert_eq!(expected.sorted_nodes().len(), actual.sorted_nodes().len());
+        for (e, a) in expected.sorted_nodes().iter().zip(actual.sorted_nodes()) {
+            assert_eq!(
+                (&e.id, &e.alias, &e.addresses, e.timestamp, &e.features),
+                (&a.id, &a.alias, &a.addresses, a.timestamp, &a.features)
+            );
+            assert_eq!(
+                (&e.rgb_color, e.in_degree, e.out_degree),
+                (&a.rgb_color, a.in_degree, a.out_degree)
+            );
+            let expected_edges = expected.sorted_out_edges(&e.id);
+            let actual_edges = actual.sorted_out_edges(&a.id);
+            assert_eq!(expected_edges.len(), actual_edges.len());
+            for (e, a) in expected_edges.iter().zip(actual_edges) {
+                assert_eq!(format!("{:?}", e), format!("{:?}", a));
+            }
+        }
+    }
+
+    #[test]
+    fn lnresearch_round_trip() {
+        for file in ["lnbook_example.json", "trivial_connected.json"] {
+            let path = Path::new("../test_data").join(file);
+            let expected = Graph::from_json_file(&path, GraphSource::Lnresearch).unwrap();
+            let json_str = expected.to_json_str(GraphSource::Lnresearch).unwrap();
+            let actual = Graph::from_json_str(&json_str, GraphSource::Lnresearch).unwrap();
+            assert_same_graph(&expected, &actual);
+        }
+    }
+
+    #[test]
+    fn lnd_round_trip() {
+        let json_str = r##"{
+            "nodes": [
+                {
+                    "pub_key": "02a",
+                    "alias": "node1",
+                    "last_update": 1567764428,
+                    "color": "#3399ff",
+                    "features": {
+                        "14": { "name": "payment-addr", "is_required": true, "is_known": true },
+                        "17": { "name": "multi-path-payments", "is_required": false, "is_known": true }
+                    },
+                    "addresses": [
+                        { "network": "tcp", "addr": "1.2.3.4:9736" },
+                        { "network": "tcp", "addr": "[2001:db8::1]:9735" }
+                    ]
+                },
+                {
+                    "pub_key": "02b",
+                    "alias": "node2",
+                    "addresses": [
+                        { "network": "tcp", "addr": "wu5mkpokybtbf6dwdaepnujbzxpm6mqqqm2hwob6ndt5k74iujd2pdyd.onion:9735" }
+                    ]
+                }
+            ],
+            "edges": [
+                {
+                    "channel_id": "659379322247708673",
+                    "node1_pub": "02a",
+                    "node2_pub": "02b",
+                    "capacity": "1000000",
+                    "node1_policy": {
+                        "time_lock_delta": 14,
+                        "min_htlc": "1000",
+                        "fee_base_msat": "1000",
+                        "fee_rate_milli_msat": "1",
+                        "max_htlc_msat": "990000000"
+                    },
+                    "node2_policy": {
+                        "time_lock_delta": 40,
+                        "min_htlc": "1",
+                        "fee_base_msat": "0",
+                        "fee_rate_milli_msat": "100",
+                        "max_htlc_msat": "500000000",
+                        "disabled": true,
+                        "last_update": 1571278793
+                    }
+                }
+            ]
+        }"##;
+        let expected = Graph::from_lnd_json_str(json_str).unwrap();
+        let written = expected.to_json_str(GraphSource::Lnd).unwrap();
+        // LND encodes the channel's integers as strings
+        assert!(written.contains(r#""capacity": "1000000""#));
+        assert!(written.contains(r#""node1_pub": "02a""#));
+        let actual = Graph::from_lnd_json_str(&written).unwrap();
+        assert_same_graph(&expected, &actual);
+        assert_eq!(actual.edge_count(), 2);
+    }
+
+    #[test]
+    fn file_round_trip_across_sources() {
+        let path = Path::new("../test_data/trivial_connected.json");
+        let mut expected = Graph::from_json_file(path, GraphSource::Lnresearch).unwrap();
+        // CLN does not report node degrees
+        expected.nodes = expected
+            .nodes
+            .into_iter()
+            .map(|node| Node {
+                in_degree: 0,
+                out_degree: 0,
+                ..node
+            })
+            .collect();
+        let file = tempfile::NamedTempFile::new().unwrap();
+        expected
+            .to_json_file(file.path(), GraphSource::Cln)
+            .unwrap();
+        // the channel ids carry the direction so CLN keeps them as they are
+        let actual = Graph::from_json_file(file.path(), GraphSource::Cln).unwrap();
+        assert_same_graph(&expected, &actual);
+    }
+
+    #[test]
+    fn scid_without_direction() {
+        assert_eq!(short_channel_id("683464x931x0/1"), "683464x931x0");
+        assert_eq!(short_channel_id("659379322247708673"), "659379322247708673");
+        assert_eq!(short_channel_id("alice/bob"), "alice/bob");
+    }
+}
@@ -1,19 +1,30 @@
 use simlib::SimResult;
 use simlib::{
-    core_types::graph::Graph,
+    core_types::{
+        amounts::{read_payment_amounts, AmountDistribution},
+        arrival::{read_arrival_times, ArrivalProcess},
+        balances::{read_channel_balances, write_channel_balances, BalanceDistribution},
+        graph::Graph,
+        htlc::read_hold_invoices,
+        latency::{read_node_latencies, HopLatency, LatencyModel},
+        preprocessing::Stage,
+        reserve::{read_channel_limits, ChannelReserves},
+        topology::{read_topology_events, ChurnProcess},
+        workload::{read_workload, unknown_nodes},
+    },
     io::{Output, Results},
     sim::Simulation,
     AdversarySelection, WeightPartsCombi,
 };
 
 use rayon::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::{error::Error, time::Instant};
 
 use clap::Parser;
 use env_logger::Env;
-use log::{error, info};
+use log::{error, info, warn};
 
 #[derive(clap::Parser)]
 #[command(name = "batch-simulator", version, about)]
@@ -42,11 +53,108 @@ struct Cli {
     /// Select adversaries using random sampling
     #[arg(long = "random")]
     random_selection: bool,
+    /// Select adversaries using random sampling among nodes only reachable via clearnet
+    #[arg(long = "clearnet-only")]
+    clearnet_only_selection: bool,
+    /// Select adversaries using random sampling among nodes only reachable via Tor
+    #[arg(long = "tor-only")]
+    tor_only_selection: bool,
     /// Min shard when using MPP
     #[arg(long = "min")]
     min_shard: Option<usize>,
     #[arg(long = "graph-source", short = 'g')]
     graph_type: network_parser::GraphSource,
+    /// Skip malformed nodes and edges in the graph file instead of exiting
+    #[arg(long)]
+    lenient: bool,
+    /// CSV or JSON file mapping short channel ids to their on-chain funding amount in sat.
+    /// Used as channel capacity instead of estimating it for lnresearch graphs
+    #[arg(long = "funding")]
+    funding_file: Option<PathBuf>,
+    /// Remove channel directions their source has disabled
+    #[arg(long = "remove-disabled")]
+    remove_disabled: bool,
+    /// Remove channel directions whose latest update is older than this many days relative to
+    /// the newest update in the graph
+    #[arg(long = "max-policy-age")]
+    max_policy_age: Option<u64>,
+    /// Comma-separated preprocessing stages applied in order: scc, bidirectional,
+    /// min-capacity:<sat>, min-degree:<k> and no-disabled
+    #[arg(long, value_delimiter = ',', default_value = "scc,bidirectional")]
+    preprocess: Vec<Stage>,
+    /// How channel capacities are split between the two directions: uniform,
+    /// beta:<alpha>:<beta>, bimodal:<scale>, exponential:<mean> or funder
+    #[arg(long = "balances", default_value = "uniform")]
+    balance_distribution: BalanceDistribution,
+    /// Share of a channel's capacity each side keeps as reserve and cannot spend, e.g. 0.01
+    #[arg(long, default_value_t = 0.0)]
+    reserve: f64,
+    /// HTLCs below this amount in sat cannot be created
+    #[arg(long = "dust-limit", default_value_t = 0)]
+    dust_limit: usize,
+    /// CSV file with per-channel limits as `short_channel_id,reserve_sat,dust_limit_sat`,
+    /// replacing --reserve and --dust-limit for those channels
+    #[arg(long = "reserve-file")]
+    reserve_file: Option<PathBuf>,
+    /// Seconds the HTLCs of a delivered payment stay in flight, locking liquidity and HTLC slots
+    /// on every hop. Settled immediately if 0.
+    #[arg(long = "htlc-hold", default_value_t = 0.0)]
+    htlc_hold: f32,
+    /// HTLCs a node accepts per channel before it rejects further ones
+    #[arg(long = "max-accepted-htlcs", default_value_t = simlib::htlc::MAX_ACCEPTED_HTLCS)]
+    max_accepted_htlcs: usize,
+    /// CSV file with receivers that hold payments to them as `node_id,hold_secs` where
+    /// `hold_secs` may be `forever`. Payments held until their HTLCs expire fail.
+    #[arg(long = "hold-invoice-file")]
+    hold_invoice_file: Option<PathBuf>,
+    /// Delay each hop adds to HTLC messages: fixed:<ms>, uniform:<min>:<max> or
+    /// exponential:<mean>. Routing is instantaneous unless this or --latency-file is set.
+    #[arg(long = "hop-latency")]
+    hop_latency: Option<HopLatency>,
+    /// CSV file with per-node delays as `node_id,latency_ms`, replacing --hop-latency for those
+    /// nodes
+    #[arg(long = "latency-file")]
+    latency_file: Option<PathBuf>,
+    /// When payments are dispatched: fixed:<secs>, poisson:<rate>,
+    /// bursty:<low>:<high>:<period>:<burst> or diurnal:<mean>:<amplitude> with rates per second
+    #[arg(long = "arrivals", default_value = "fixed:120")]
+    arrivals: ArrivalProcess,
+    /// CSV file with a `time_secs` column of dispatch times, replacing --arrivals
+    #[arg(long = "arrival-file")]
+    arrival_file: Option<PathBuf>,
+    /// How much each payment transfers: fixed, lognormal:<median_sat>:<sigma> or
+    /// list:<sat>,<sat>,... instead of
+    /// simulating each of a fixed list of amounts
+    #[arg(long = "amounts", default_value = "fixed")]
+    amounts: AmountDistribution,
+    /// CSV file with an `amount_msat` column of per-payment amounts or a histogram as
+    /// `lower_sat,upper_sat,weight`, replacing --amounts
+    #[arg(long = "amount-file")]
+    amount_file: Option<PathBuf>,
+    /// CSV or JSON file of payments as `time_secs,source,destination,amount_msat` with optional
+    /// `max_fee_msat` and `parts` columns, replacing the random pairs, --arrivals and --amounts
+    #[arg(long = "workload")]
+    workload: Option<PathBuf>,
+    /// JSON lines file with channel opens, closes, policy updates and nodes going offline or
+    /// online during the run, each with a `time_secs`
+    #[arg(long = "topology-file")]
+    topology_file: Option<PathBuf>,
+    /// Random topology changes until the last payment: <close>:<open>:<offline>:<offline_secs>
+    /// with rates per second over the whole network
+    #[arg(long = "churn")]
+    churn: Option<ChurnProcess>,
+    /// CSV file with known balances as `channel_id,source,balance_msat`. Channels not in the file
+    /// are split uniformly
+    #[arg(long = "balance-file")]
+    balance_file: Option<PathBuf>,
+    /// Write the balance of each channel direction before the simulations to this file, in the
+    /// format read by --balance-file
+    #[arg(long = "export-start-balances")]
+    export_start_balances: Option<PathBuf>,
+    /// Write the balance of each channel direction after each simulation to this file, with the
+    /// simulation's routing, parts and amount appended to the file name
+    #[arg(long = "export-end-balances")]
+    export_end_balances: Option<PathBuf>,
     #[arg(long)]
     verbose: bool,
 }
@@ -60,14 +168,95 @@ fn main() {
     env_logger::init_from_env(env);
 
     let graph_source = args.graph_type;
-    let g = network_parser::Graph::from_json_file(
+    let parse_mode = if args.lenient {
+        network_parser::ParseMode::Lenient
+    } else {
+        network_parser::ParseMode::Strict
+    };
+    let g = network_parser::Graph::parse_json_file(
         std::path::Path::new(&args.graph_file),
         graph_source.clone(),
+        parse_mode,
     );
     let seed = args.run;
     let number_of_sim_pairs = args.num_pairs;
     let graph = match g {
-        Ok(graph) => Graph::to_sim_graph(&graph, graph_source),
+        Ok((mut graph, report)) => {
+            if report.num_skipped() > 0 {
+                warn!(
+                    "Skipped {} nodes and {} edges in graph file.",
+                    report.skipped_nodes, report.skipped_edges
+                );
+            }
+            info!("Graph addresses: {}.", graph.address_stats());
+            if let Some(path) = &args.funding_file {
+                match network_parser::read_funding_amounts(path)
+                    .and_then(|amounts| graph.set_funding_amounts(&amounts))
+                {
+                    Ok(report) => {
+                        info!(
+                            "Funding amounts matched {} channels, {} unmatched.",
+                            report.matched, report.unmatched
+                        );
+                    }
+                    Err(e) => {
+                        error!("Error in funding file {}. Exiting.", e);
+                        std::process::exit(-1)
+                    }
+                }
+            }
+            if args.remove_disabled {
+                info!("Removed {} disabled edges.", graph.remove_disabled_edges());
+            }
+            if let Some(days) = args.max_policy_age {
+                info!(
+                    "Removed {} edges not updated within {} days.",
+                    graph.remove_stale_edges(days),
+                    days
+                );
+            }
+            let balances = match &args.balance_file {
+                Some(path) => match read_channel_balances(path) {
+                    Ok(balances) => BalanceDistribution::Known(balances),
+                    Err(e) => {
+                        error!("Error in balance file {}. Exiting.", e);
+                        std::process::exit(-1)
+                    }
+                },
+                None => args.balance_distribution.clone(),
+            };
+            info!("Using {} channel balances.", balances);
+            let mut graph = Graph::from_net_graph(&graph);
+            for summary in graph.preprocess(&args.preprocess) {
+                info!("Preprocessing {}.", summary);
+            }
+            graph.set_channel_balances(graph_source, &balances);
+            if !(0.0..=1.0).contains(&args.reserve) {
+                error!("Reserve must be a share between 0 and 1. Exiting.");
+                std::process::exit(-1)
+            }
+            let overrides = match &args.reserve_file {
+                Some(path) => match read_channel_limits(path) {
+                    Ok(overrides) => overrides,
+                    Err(e) => {
+                        error!("Error in reserve file {}. Exiting.", e);
+                        std::process::exit(-1)
+                    }
+                },
+                None => Default::default(),
+            };
+            graph.set_reserves(ChannelReserves {
+                reserve_share: args.reserve,
+                dust_limit_msat: simlib::to_millisatoshi(args.dust_limit),
+                overrides,
+            });
+            info!(
+                "{} sat of liquidity are locked as channel reserve.",
+                simlib::to_sat(graph.locked_reserve())
+            );
+            graph.set_max_accepted_htlcs(args.max_accepted_htlcs);
+            graph
+        }
         Err(e) => {
             error!("Error in graph file {}. Exiting.", e);
             std::process::exit(-1)
@@ -84,6 +273,12 @@ fn main() {
         "Simulation results will be written to {:#?}/ directory.",
         output_dir
     );
+    if let Some(path) = &args.export_start_balances {
+        match write_channel_balances(path, &graph.channel_balances()) {
+            Ok(()) => info!("Start balances written to {}.", path.display()),
+            Err(e) => error!("Error writing balances to {}: {}", path.display(), e),
+        }
+    }
     let mut adversary_selection = match args.betweenness_file {
         Some(file) => vec![AdversarySelection::HighBetweenness(file)],
         None => vec![],
@@ -94,29 +289,151 @@ fn main() {
     if args.random_selection {
         adversary_selection.push(AdversarySelection::Random);
     };
+    if args.clearnet_only_selection {
+        adversary_selection.push(AdversarySelection::ClearnetOnly);
+    };
+    if args.tor_only_selection {
+        adversary_selection.push(AdversarySelection::TorOnly);
+    };
 
-    let amounts = vec![
-        100, 500, 1000, 5000, 10000, 50000, 100000, 500000, 1000000, 5000000, 10000000,
-    ];
+    let latency_model = if args.hop_latency.is_some() || args.latency_file.is_some() {
+        let node_latencies = match &args.latency_file {
+            Some(path) => match read_node_latencies(path) {
+                Ok(latencies) => latencies,
+                Err(e) => {
+                    error!("Error in latency file {}. Exiting.", e);
+                    std::process::exit(-1)
+                }
+            },
+            None => Default::default(),
+        };
+        let hop_latency = args.hop_latency.clone().unwrap_or(HopLatency::Fixed(0.0));
+        info!("Using {} hop latency.", hop_latency);
+        Some(LatencyModel {
+            hop_latency,
+            node_latencies,
+        })
+    } else {
+        None
+    };
+    let arrivals = match &args.arrival_file {
+        Some(path) => match read_arrival_times(path) {
+            Ok(arrivals) => arrivals,
+            Err(e) => {
+                error!("Error in arrival file {}. Exiting.", e);
+                std::process::exit(-1)
+            }
+        },
+        None => args.arrivals.clone(),
+    };
+    info!("Payments arrive by {}.", arrivals);
+    let amounts = match &args.amount_file {
+        Some(path) => match read_payment_amounts(path) {
+            Ok(amounts) => amounts,
+            Err(e) => {
+                error!("Error in amount file {}. Exiting.", e);
+                std::process::exit(-1)
+            }
+        },
+        None => args.amounts.clone(),
+    };
+    info!("Payment amounts are {}.", amounts);
+    let hold_invoices = match &args.hold_invoice_file {
+        Some(path) => match read_hold_invoices(path) {
+            Ok(hold_invoices) => hold_invoices,
+            Err(e) => {
+                error!("Error in hold invoice file {}. Exiting.", e);
+                std::process::exit(-1)
+            }
+        },
+        None => Default::default(),
+    };
+    let topology_events = match &args.topology_file {
+        Some(path) => match read_topology_events(path) {
+            Ok(topology_events) => topology_events,
+            Err(e) => {
+                error!("Error in topology file {}. Exiting.", e);
+                std::process::exit(-1)
+            }
+        },
+        None => vec![],
+    };
+    let workload = match &args.workload {
+        Some(path) => match read_workload(path) {
+            Ok(records) => {
+                let unknown = unknown_nodes(&records, &graph);
+                if !unknown.is_empty() {
+                    error!(
+                        "Workload nodes {:?} are not in the graph. Exiting.",
+                        unknown
+                    );
+                    std::process::exit(-1)
+                }
+                info!("Making the {} payments of the workload.", records.len());
+                Some(records)
+            }
+            Err(e) => {
+                error!("Error in workload file {}. Exiting.", e);
+                std::process::exit(-1)
+            }
+        },
+        None => None,
+    };
+    // a single simulation per scenario if the amounts vary or come from a workload, reported
+    // with amount 0
+    let fixed_amounts = if amounts == AmountDistribution::Fixed && workload.is_none() {
+        vec![
+            100, 500, 1000, 5000, 10000, 50000, 100000, 500000, 1000000, 5000000, 10000000,
+        ]
+    } else {
+        vec![0]
+    };
     let weight_parts = vec![
         WeightPartsCombi::MinFeeSingle,
         WeightPartsCombi::MaxProbSingle,
         WeightPartsCombi::MinFeeMulti,
         WeightPartsCombi::MaxProbMulti,
     ];
+    let number_of_sim_pairs = if workload.is_some() {
+        0
+    } else {
+        number_of_sim_pairs
+    };
     let pairs = Simulation::draw_n_pairs_for_simulation(&graph, number_of_sim_pairs);
     let mut results = Vec::with_capacity(4);
     for combi in weight_parts {
-        let sim_results = Arc::new(Mutex::new(Vec::with_capacity(amounts.len())));
-        amounts.par_iter().for_each(|amount| {
+        let sim_results = Arc::new(Mutex::new(Vec::with_capacity(fixed_amounts.len())));
+        fixed_amounts.par_iter().for_each(|amount| {
             let start = Instant::now();
             let msat = simlib::to_millisatoshi(*amount);
-            let sim = init_sim(seed, graph.clone(), msat, combi, &adversary_selection);
+            let mut sim = init_sim(seed, graph.clone(), msat, combi, &adversary_selection);
+            sim.set_htlc_hold_time(args.htlc_hold);
+            sim.set_hold_invoices(hold_invoices.clone());
+            sim.set_arrival_process(arrivals.clone());
+            sim.set_amount_distribution(amounts.clone());
+            sim.set_topology_events(topology_events.clone());
+            if let Some(churn) = &args.churn {
+                sim.set_churn(churn.clone());
+            }
+            if let Some(records) = &workload {
+                sim.set_workload(records.clone())
+                    .expect("Workload nodes were checked.");
+            }
+            if let Some(latency_model) = &latency_model {
+                sim.set_latency_model(latency_model.clone());
+            }
             info!(
                 "Starting {:?} simulation of {} pairs of {} sats.",
                 combi, number_of_sim_pairs, amount,
             );
-            let sim_result = simulate(sim, pairs.clone(), args.min_shard);
+            let (sim_result, sim) = simulate(sim, pairs.clone(), args.min_shard);
+            if let Some(path) = &args.export_end_balances {
+                let path = end_balances_path(path, combi, *amount);
+                match sim.export_balances(&path) {
+                    Ok(()) => info!("End balances written to {}.", path.display()),
+                    Err(e) => error!("Error writing balances to {}: {}", path.display(), e),
+                }
+            }
             let duration_in_ms = start.elapsed().as_millis();
             info!(
                 "Simulation {:?} of amount {} sat completed after {} ms.",
@@ -148,8 +465,20 @@ fn simulate(
     mut sim: Simulation,
     payment_pairs: impl Iterator<Item = (std::string::String, std::string::String)> + Clone,
     min_shard: Option<usize>,
-) -> SimResult {
-    sim.run(payment_pairs, min_shard, true)
+) -> (SimResult, Simulation) {
+    let result = sim.run(payment_pairs, min_shard, true);
+    (result, sim)
+}
+
+/// The path with the simulation's routing, parts and amount appended to the file name, e.g.
+/// `end_MinFeeSingle_100.csv` for `end.csv`
+fn end_balances_path(path: &Path, weight_parts: WeightPartsCombi, amount: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut file_name = format!("{}_{:?}_{}", stem, weight_parts, amount);
+    if let Some(extension) = path.extension() {
+        file_name = format!("{}.{}", file_name, extension.to_string_lossy());
+    }
+    path.with_file_name(file_name)
 }
 
 fn report_to_file(
@@ -2,8 +2,24 @@ use std::path::PathBuf;
 
 use clap::Parser;
 use env_logger::Env;
-use log::{error, info};
-use simlib::{core_types::graph, sim::Simulation, AdversarySelection};
+use log::{error, info, warn};
+use simlib::{
+    core_types::{
+        amounts::{read_payment_amounts, AmountDistribution},
+        arrival::{read_arrival_times, ArrivalProcess},
+        balances::{read_channel_balances, write_channel_balances, BalanceDistribution},
+        graph,
+        htlc::read_hold_invoices,
+        latency::{read_node_latencies, HopLatency, LatencyModel},
+        preprocessing::Stage,
+        reserve::{read_channel_limits, ChannelReserves},
+        topology::{read_topology_events, ChurnProcess},
+        trace::read_trace,
+        workload::read_workload,
+    },
+    sim::Simulation,
+    AdversarySelection,
+};
 
 #[derive(clap::Parser)]
 #[command(name = "lightning-simulator", version, about)]
@@ -43,11 +59,124 @@ struct Cli {
     /// Select adversaries using random sampling
     #[arg(long = "random")]
     random_selection: bool,
+    /// Select adversaries using random sampling among nodes only reachable via clearnet
+    #[arg(long = "clearnet-only")]
+    clearnet_only_selection: bool,
+    /// Select adversaries using random sampling among nodes only reachable via Tor
+    #[arg(long = "tor-only")]
+    tor_only_selection: bool,
     /// Min shard when using MPP
     #[arg(long = "min")]
     min_shard: Option<usize>,
     #[arg(long = "graph-source", short = 'g')]
     graph_type: network_parser::GraphSource,
+    /// Skip malformed nodes and edges in the graph file instead of exiting
+    #[arg(long)]
+    lenient: bool,
+    /// CSV or JSON file mapping short channel ids to their on-chain funding amount in sat.
+    /// Used as channel capacity instead of estimating it for lnresearch graphs
+    #[arg(long = "funding")]
+    funding_file: Option<PathBuf>,
+    /// Remove channel directions their source has disabled
+    #[arg(long = "remove-disabled")]
+    remove_disabled: bool,
+    /// Remove channel directions whose latest update is older than this many days relative to
+    /// the newest update in the graph
+    #[arg(long = "max-policy-age")]
+    max_policy_age: Option<u64>,
+    /// Comma-separated preprocessing stages applied in order: scc, bidirectional,
+    /// min-capacity:<sat>, min-degree:<k> and no-disabled
+    #[arg(long, value_delimiter = ',', default_value = "scc,bidirectional")]
+    preprocess: Vec<Stage>,
+    /// How channel capacities are split between the two directions: uniform,
+    /// beta:<alpha>:<beta>, bimodal:<scale>, exponential:<mean> or funder
+    #[arg(long = "balances", default_value = "uniform")]
+    balance_distribution: BalanceDistribution,
+    /// Share of a channel's capacity each side keeps as reserve and cannot spend, e.g. 0.01
+    #[arg(long, default_value_t = 0.0)]
+    reserve: f64,
+    /// HTLCs below this amount in sat cannot be created
+    #[arg(long = "dust-limit", default_value_t = 0)]
+    dust_limit: usize,
+    /// CSV file with per-channel limits as `short_channel_id,reserve_sat,dust_limit_sat`,
+    /// replacing --reserve and --dust-limit for those channels
+    #[arg(long = "reserve-file")]
+    reserve_file: Option<PathBuf>,
+    /// Seconds the HTLCs of a delivered payment stay in flight, locking liquidity and HTLC slots
+    /// on every hop. Settled immediately if 0.
+    #[arg(long = "htlc-hold", default_value_t = 0.0)]
+    htlc_hold: f32,
+    /// HTLCs a node accepts per channel before it rejects further ones
+    #[arg(long = "max-accepted-htlcs", default_value_t = simlib::htlc::MAX_ACCEPTED_HTLCS)]
+    max_accepted_htlcs: usize,
+    /// CSV file with receivers that hold payments to them as `node_id,hold_secs` where
+    /// `hold_secs` may be `forever`. Payments held until their HTLCs expire fail.
+    #[arg(long = "hold-invoice-file")]
+    hold_invoice_file: Option<PathBuf>,
+    /// Delay each hop adds to HTLC messages: fixed:<ms>, uniform:<min>:<max> or
+    /// exponential:<mean>. Routing is instantaneous unless this or --latency-file is set.
+    #[arg(long = "hop-latency")]
+    hop_latency: Option<HopLatency>,
+    /// CSV file with per-node delays as `node_id,latency_ms`, replacing --hop-latency for those
+    /// nodes
+    #[arg(long = "latency-file")]
+    latency_file: Option<PathBuf>,
+    /// When payments are dispatched: fixed:<secs>, poisson:<rate>,
+    /// bursty:<low>:<high>:<period>:<burst> or diurnal:<mean>:<amplitude> with rates per second
+    #[arg(long = "arrivals", default_value = "fixed:120")]
+    arrivals: ArrivalProcess,
+    /// CSV file with a `time_secs` column of dispatch times, replacing --arrivals
+    #[arg(long = "arrival-file")]
+    arrival_file: Option<PathBuf>,
+    /// How much each payment transfers: fixed, lognormal:<median_sat>:<sigma> or
+    /// list:<sat>,<sat>,... replacing --amount
+    #[arg(long = "amounts", default_value = "fixed")]
+    amounts: AmountDistribution,
+    /// CSV file with an `amount_msat` column of per-payment amounts or a histogram as
+    /// `lower_sat,upper_sat,weight`, replacing --amounts
+    #[arg(long = "amount-file")]
+    amount_file: Option<PathBuf>,
+    /// CSV or JSON file of payments as `time_secs,source,destination,amount_msat` with optional
+    /// `max_fee_msat` and `parts` columns, replacing the random pairs, --arrivals and --amounts
+    #[arg(long = "workload")]
+    workload: Option<PathBuf>,
+    /// JSON lines file with channel opens, closes, policy updates and nodes going offline or
+    /// online during the run, each with a `time_secs`
+    #[arg(long = "topology-file")]
+    topology_file: Option<PathBuf>,
+    /// Random topology changes until the last payment: <close>:<open>:<offline>:<offline_secs>
+    /// with rates per second over the whole network
+    #[arg(long = "churn")]
+    churn: Option<ChurnProcess>,
+    /// CSV file with known balances as `channel_id,source,balance_msat`. Channels not in the file
+    /// are split uniformly
+    #[arg(long = "balance-file")]
+    balance_file: Option<PathBuf>,
+    /// Write the balance of each channel direction before the simulation to this file, in the
+    /// format read by --balance-file
+    #[arg(long = "export-start-balances")]
+    export_start_balances: Option<PathBuf>,
+    /// Write the balance of each channel direction after the simulation to this file, e.g. to
+    /// start the next run from it using --balance-file
+    #[arg(long = "export-end-balances")]
+    export_end_balances: Option<PathBuf>,
+    /// Write every processed event, path attempt and balance change to this file as JSON lines
+    #[arg(long = "trace")]
+    trace: Option<PathBuf>,
+    /// Instead of simulating, re-apply a file written by --trace to the graph and check that the
+    /// final balances match
+    #[arg(long = "replay")]
+    replay: Option<PathBuf>,
+    /// Write the reduced topology used in the simulation to this file in the graph source's format
+    #[arg(long = "export-graph")]
+    export_graph: Option<PathBuf>,
+    /// Write the graph with the final balances and the number of times each node was on a
+    /// payment path to this file
+    #[arg(long = "export-traffic")]
+    export_traffic: Option<PathBuf>,
+    /// Format used by --export-traffic
+    #[arg(long = "export-format", default_value = "graphml")]
+    export_format: network_parser::ExportFormat,
     #[arg(long)]
     verbose: bool,
 }
@@ -61,9 +190,15 @@ fn main() {
     env_logger::init_from_env(env);
 
     let graph_source = args.graph_type;
-    let g = network_parser::Graph::from_json_file(
+    let parse_mode = if args.lenient {
+        network_parser::ParseMode::Lenient
+    } else {
+        network_parser::ParseMode::Strict
+    };
+    let g = network_parser::Graph::parse_json_file(
         std::path::Path::new(&args.graph_file),
         graph_source.clone(),
+        parse_mode,
     );
     let seed = args.run;
     let payment_amt = simlib::to_millisatoshi(args.amount);
@@ -76,12 +211,99 @@ fn main() {
         simlib::PaymentParts::Single
     };
     let graph = match g {
-        Ok(graph) => graph::Graph::to_sim_graph(&graph, graph_source),
+        Ok((mut graph, report)) => {
+            if report.num_skipped() > 0 {
+                warn!(
+                    "Skipped {} nodes and {} edges in graph file.",
+                    report.skipped_nodes, report.skipped_edges
+                );
+            }
+            info!("Graph addresses: {}.", graph.address_stats());
+            if let Some(path) = &args.funding_file {
+                match network_parser::read_funding_amounts(path)
+                    .and_then(|amounts| graph.set_funding_amounts(&amounts))
+                {
+                    Ok(report) => {
+                        info!(
+                            "Funding amounts matched {} channels, {} unmatched.",
+                            report.matched, report.unmatched
+                        );
+                    }
+                    Err(e) => {
+                        error!("Error in funding file {}. Exiting.", e);
+                        std::process::exit(-1)
+                    }
+                }
+            }
+            if args.remove_disabled {
+                info!("Removed {} disabled edges.", graph.remove_disabled_edges());
+            }
+            if let Some(days) = args.max_policy_age {
+                info!(
+                    "Removed {} edges not updated within {} days.",
+                    graph.remove_stale_edges(days),
+                    days
+                );
+            }
+            let balances = match &args.balance_file {
+                Some(path) => match read_channel_balances(path) {
+                    Ok(balances) => BalanceDistribution::Known(balances),
+                    Err(e) => {
+                        error!("Error in balance file {}. Exiting.", e);
+                        std::process::exit(-1)
+                    }
+                },
+                None => args.balance_distribution.clone(),
+            };
+            info!("Using {} channel balances.", balances);
+            let mut graph = graph::Graph::from_net_graph(&graph);
+            for summary in graph.preprocess(&args.preprocess) {
+                info!("Preprocessing {}.", summary);
+            }
+            graph.set_channel_balances(graph_source.clone(), &balances);
+            if !(0.0..=1.0).contains(&args.reserve) {
+                error!("Reserve must be a share between 0 and 1. Exiting.");
+                std::process::exit(-1)
+            }
+            let overrides = match &args.reserve_file {
+                Some(path) => match read_channel_limits(path) {
+                    Ok(overrides) => overrides,
+                    Err(e) => {
+                        error!("Error in reserve file {}. Exiting.", e);
+                        std::process::exit(-1)
+                    }
+                },
+                None => Default::default(),
+            };
+            graph.set_reserves(ChannelReserves {
+                reserve_share: args.reserve,
+                dust_limit_msat: simlib::to_millisatoshi(args.dust_limit),
+                overrides,
+            });
+            info!(
+                "{} sat of liquidity are locked as channel reserve.",
+                simlib::to_sat(graph.locked_reserve())
+            );
+            graph.set_max_accepted_htlcs(args.max_accepted_htlcs);
+            graph
+        }
         Err(e) => {
             error!("Error in graph file {}. Exiting.", e);
             std::process::exit(-1)
         }
     };
+    if let Some(path) = args.export_graph {
+        match graph.to_net_graph().to_json_file(&path, graph_source) {
+            Ok(()) => info!("Reduced graph written to {}.", path.display()),
+            Err(e) => error!("Error writing graph to {}: {}", path.display(), e),
+        }
+    }
+    if let Some(path) = args.export_start_balances {
+        match write_channel_balances(&path, &graph.channel_balances()) {
+            Ok(()) => info!("Start balances written to {}.", path.display()),
+            Err(e) => error!("Error writing balances to {}: {}", path.display(), e),
+        }
+    }
     let output_dir = if let Some(output_dir) = args.output_dir {
         output_dir
     } else {
@@ -101,7 +323,75 @@ fn main() {
     if args.random_selection {
         adversary_selection.push(AdversarySelection::Random);
     };
+    if args.clearnet_only_selection {
+        adversary_selection.push(AdversarySelection::ClearnetOnly);
+    };
+    if args.tor_only_selection {
+        adversary_selection.push(AdversarySelection::TorOnly);
+    };
 
+    let latency_model = if args.hop_latency.is_some() || args.latency_file.is_some() {
+        let node_latencies = match &args.latency_file {
+            Some(path) => match read_node_latencies(path) {
+                Ok(latencies) => latencies,
+                Err(e) => {
+                    error!("Error in latency file {}. Exiting.", e);
+                    std::process::exit(-1)
+                }
+            },
+            None => Default::default(),
+        };
+        let hop_latency = args.hop_latency.clone().unwrap_or(HopLatency::Fixed(0.0));
+        info!("Using {} hop latency.", hop_latency);
+        Some(LatencyModel {
+            hop_latency,
+            node_latencies,
+        })
+    } else {
+        None
+    };
+    let arrivals = match &args.arrival_file {
+        Some(path) => match read_arrival_times(path) {
+            Ok(arrivals) => arrivals,
+            Err(e) => {
+                error!("Error in arrival file {}. Exiting.", e);
+                std::process::exit(-1)
+            }
+        },
+        None => args.arrivals.clone(),
+    };
+    info!("Payments arrive by {}.", arrivals);
+    let amounts = match &args.amount_file {
+        Some(path) => match read_payment_amounts(path) {
+            Ok(amounts) => amounts,
+            Err(e) => {
+                error!("Error in amount file {}. Exiting.", e);
+                std::process::exit(-1)
+            }
+        },
+        None => args.amounts.clone(),
+    };
+    info!("Payment amounts are {}.", amounts);
+    let hold_invoices = match &args.hold_invoice_file {
+        Some(path) => match read_hold_invoices(path) {
+            Ok(hold_invoices) => hold_invoices,
+            Err(e) => {
+                error!("Error in hold invoice file {}. Exiting.", e);
+                std::process::exit(-1)
+            }
+        },
+        None => Default::default(),
+    };
+    let topology_events = match &args.topology_file {
+        Some(path) => match read_topology_events(path) {
+            Ok(topology_events) => topology_events,
+            Err(e) => {
+                error!("Error in topology file {}. Exiting.", e);
+                std::process::exit(-1)
+            }
+        },
+        None => vec![],
+    };
     let mut simulator = Simulation::new(
         seed,
         graph.clone(),
@@ -111,6 +401,89 @@ fn main() {
         number_of_adversaries,
         &adversary_selection,
     );
+    simulator.set_htlc_hold_time(args.htlc_hold);
+    simulator.set_hold_invoices(hold_invoices);
+    simulator.set_arrival_process(arrivals);
+    simulator.set_amount_distribution(amounts);
+    simulator.set_topology_events(topology_events);
+    if let Some(churn) = args.churn {
+        simulator.set_churn(churn);
+    }
+    if let Some(path) = &args.workload {
+        let records = match read_workload(path) {
+            Ok(records) => records,
+            Err(e) => {
+                error!("Error in workload file {}. Exiting.", e);
+                std::process::exit(-1)
+            }
+        };
+        info!("Making the {} payments of the workload.", records.len());
+        if let Err(unknown) = simulator.set_workload(records) {
+            error!(
+                "Workload nodes {:?} are not in the graph. Exiting.",
+                unknown
+            );
+            std::process::exit(-1)
+        }
+    }
+    if let Some(latency_model) = latency_model {
+        simulator.set_latency_model(latency_model);
+    }
+    if let Some(path) = args.replay {
+        let records = match read_trace(&path) {
+            Ok(records) => records,
+            Err(e) => {
+                error!("Error in trace file {}. Exiting.", e);
+                std::process::exit(-1)
+            }
+        };
+        let mismatches = simulator.replay(&records);
+        if mismatches.is_empty() {
+            info!(
+                "Replayed {} trace records, final balances match.",
+                records.len()
+            );
+        } else {
+            for m in mismatches.iter() {
+                error!(
+                    "Channel {} of {}: recorded balance {:?}, replayed {:?}.",
+                    m.channel_id, m.source, m.recorded, m.replayed
+                );
+            }
+            error!("{} channel balances differ after replay.", mismatches.len());
+            std::process::exit(-1)
+        }
+        return;
+    }
+    if args.trace.is_some() {
+        simulator.record_trace();
+    }
+    let number_of_sim_pairs = if args.workload.is_some() {
+        0
+    } else {
+        number_of_sim_pairs
+    };
     let pairs = Simulation::draw_n_pairs_for_simulation(&graph, number_of_sim_pairs);
     _ = simulator.run(pairs, args.min_shard, true);
+    if let Some(path) = args.trace {
+        match simulator.export_trace(&path) {
+            Ok(()) => info!("Event trace written to {}.", path.display()),
+            Err(e) => error!("Error writing trace to {}: {}", path.display(), e),
+        }
+    }
+    if let Some(path) = args.export_end_balances {
+        match simulator.export_balances(&path) {
+            Ok(()) => info!("End balances written to {}.", path.display()),
+            Err(e) => error!("Error writing balances to {}: {}", path.display(), e),
+        }
+    }
+    if let Some(path) = args.export_traffic {
+        match simulator.export_graph(&path, args.export_format) {
+            Ok(()) => info!(
+                "Graph with simulated traffic written to {}.",
+                path.display()
+            ),
+            Err(e) => error!("Error writing graph to {}: {}", path.display(), e),
+        }
+    }
 }
@@ -0,0 +1,248 @@
+use crate::{balances::sample_standard_normal, input::CsvRecords};
+
+use rand::Rng;
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+    str::FromStr,
+};
+
+/// How much each payment transfers
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum AmountDistribution {
+    /// Every payment transfers the simulation's amount
+    #[default]
+    Fixed,
+    /// The amount in sat is log-normally distributed around the median
+    LogNormal { median: f64, sigma: f64 },
+    /// Each amount in sat in the list is equally likely
+    List(Vec<usize>),
+    /// Bins of amounts in sat drawn by their weight, uniformly within a bin
+    Histogram(Vec<HistogramBin>),
+    /// One amount in msat per payment in the given order, read using [read_payment_amounts]
+    Sequence(Vec<usize>),
+}
+
+/// Amounts in sat in `[lower, upper)`
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistogramBin {
+    pub lower: usize,
+    pub upper: usize,
+    pub weight: f64,
+}
+
+impl AmountDistribution {
+    /// Amounts in msat of up to `n` payments; a sequence may cover fewer. Fixed amounts are
+    /// `amount_msat`.
+    pub(crate) fn amounts(&self, n: usize, amount_msat: usize, rng: &mut impl Rng) -> Vec<usize> {
+        let to_msat = |sat: f64| ((sat * crate::SAT_SCALE as f64).round() as usize).max(1);
+        match self {
+            Self::Fixed => vec![amount_msat; n],
+            Self::Sequence(amounts) => amounts.iter().take(n).copied().collect(),
+            Self::LogNormal { median, sigma } => (0..n)
+                .map(|_| to_msat(median * (sigma * sample_standard_normal(rng)).exp()))
+                .collect(),
+            Self::List(amounts) => (0..n)
+                .map(|_| crate::to_millisatoshi(amounts[rng.gen_range(0..amounts.len())]))
+                .collect(),
+            Self::Histogram(bins) => {
+                let total: f64 = bins.iter().map(|b| b.weight).sum();
+                (0..n)
+                    .map(|_| {
+                        let mut x = rng.gen::<f64>() * total;
+                        // rounding may leave x slightly above the last bin's weight
+                        let bin = bins
+                            .iter()
+                            .find(|b| {
+                                x -= b.weight;
+                                x < 0.0
+                            })
+                            .or_else(|| bins.iter().rfind(|b| b.weight > 0.0))
+                            .expect("Histogram without weight");
+                        to_msat(rng.gen_range(bin.lower as f64..bin.upper as f64))
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// Parses `fixed`, `lognormal:<median_sat>:<sigma>` and `list:<sat>,<sat>,...`. Histograms and
+/// sequences are read using [read_payment_amounts].
+impl FromStr for AmountDistribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, params) = s.split_once(':').unwrap_or((s, ""));
+        let invalid = |p: &str| format!("Invalid parameter {} in {}", p, s);
+        match name {
+            "fixed" if params.is_empty() => Ok(Self::Fixed),
+            "lognormal" => {
+                let params: Vec<f64> = params
+                    .split(':')
+                    .map(|p| p.parse().map_err(|_| invalid(p)))
+                    .collect::<Result<_, _>>()?;
+                match params.as_slice() {
+                    [median, sigma] if *median > 0.0 && *sigma >= 0.0 => Ok(Self::LogNormal {
+                        median: *median,
+                        sigma: *sigma,
+                    }),
+                    _ => Err(format!(
+                        "Expected lognormal:<median_sat>:<sigma>, got {}",
+                        s
+                    )),
+                }
+            }
+            "list" => {
+                let amounts: Vec<usize> = params
+                    .split(',')
+                    .map(|p| match p.trim().parse() {
+                        Ok(amount) if amount > 0 => Ok(amount),
+                        _ => Err(invalid(p)),
+                    })
+                    .collect::<Result<_, _>>()?;
+                Ok(Self::List(amounts))
+            }
+            _ => Err(format!(
+                "Unknown amount distribution {}. Expected fixed, lognormal:<median_sat>:<sigma> \
+                or list:<sat>,<sat>,...",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for AmountDistribution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Fixed => write!(f, "fixed"),
+            Self::LogNormal { median, sigma } => write!(f, "lognormal:{}:{}", median, sigma),
+            Self::List(amounts) => {
+                let amounts: Vec<String> = amounts.iter().map(|a| a.to_string()).collect();
+                write!(f, "list:{}", amounts.join(","))
+            }
+            Self::Histogram(bins) => write!(f, "histogram of {} bins", bins.len()),
+            Self::Sequence(amounts) => write!(f, "{} amounts", amounts.len()),
+        }
+    }
+}
+
+/// Reads a CSV file with either an `amount_msat` header and one amount per payment or a
+/// `lower_sat,upper_sat,weight` header and one histogram bin per line
+pub fn read_payment_amounts(path: &Path) -> io::Result<AmountDistribution> {
+    payment_amounts_from_reader(BufReader::new(File::open(path)?))
+}
+
+/// See [read_payment_amounts]
+pub fn payment_amounts_from_reader(reader: impl BufRead) -> io::Result<AmountDistribution> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let records = CsvRecords::new(reader)?;
+    let header = records.header.clone();
+    match header.trim() {
+        "amount_msat" => {
+            let amounts = records.parse("amount", |fields| match fields {
+                [amount] => amount.parse::<usize>().ok().filter(|amount| *amount > 0),
+                _ => None,
+            })?;
+            Ok(AmountDistribution::Sequence(amounts))
+        }
+        "lower_sat,upper_sat,weight" => {
+            let bins = records.parse("amount", |fields| {
+                let [lower, upper, weight] = fields else {
+                    return None;
+                };
+                let bin = HistogramBin {
+                    lower: lower.parse().ok()?,
+                    upper: upper.parse().ok()?,
+                    weight: weight.parse().ok()?,
+                };
+                (bin.lower < bin.upper && bin.weight.is_finite() && bin.weight >= 0.0)
+                    .then_some(bin)
+            })?;
+            if !bins.iter().any(|b| b.weight > 0.0) {
+                return Err(invalid("Histogram without weight".to_owned()));
+            }
+            Ok(AmountDistribution::Histogram(bins))
+        }
+        _ => Err(invalid(format!(
+            "Expected an amount_msat or lower_sat,upper_sat,weight header, got {}",
+            header
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::tests::assert_round_trip;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn parse_amount_distributions() {
+        assert_round_trip::<AmountDistribution>(&[
+            "fixed",
+            "lognormal:1000:1.5",
+            "list:10,500,100000",
+        ]);
+        assert!("lognormal:0:1".parse::<AmountDistribution>().is_err());
+        assert!("lognormal:1000".parse::<AmountDistribution>().is_err());
+        assert!("list:10,0".parse::<AmountDistribution>().is_err());
+        assert!("list:".parse::<AmountDistribution>().is_err());
+        assert!("fixed:10".parse::<AmountDistribution>().is_err());
+        assert!("pareto:1".parse::<AmountDistribution>().is_err());
+    }
+
+    #[test]
+    fn sample_amounts() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let n = 10000;
+        assert_eq!(
+            AmountDistribution::Fixed.amounts(3, 7, &mut rng),
+            vec![7; 3]
+        );
+        let mut amounts = AmountDistribution::LogNormal {
+            median: 1000.0,
+            sigma: 2.0,
+        }
+        .amounts(n, 0, &mut rng);
+        amounts.sort();
+        let median = amounts[n / 2] as f64;
+        assert!((median / 1_000_000.0 - 1.0).abs() < 0.1);
+        let amounts = AmountDistribution::List(vec![1, 2]).amounts(n, 0, &mut rng);
+        assert!(amounts.iter().all(|a| *a == 1000 || *a == 2000));
+        let ones = amounts.iter().filter(|a| **a == 1000).count();
+        assert!((ones as f64 / n as f64 - 0.5).abs() < 0.05);
+        let amounts = AmountDistribution::Sequence(vec![5, 6]).amounts(3, 0, &mut rng);
+        assert_eq!(amounts, vec![5, 6]);
+    }
+
+    #[test]
+    fn read_amounts() {
+        let sequence = "amount_msat\n1000\n\n250\n";
+        assert_eq!(
+            payment_amounts_from_reader(sequence.as_bytes()).unwrap(),
+            AmountDistribution::Sequence(vec![1000, 250])
+        );
+        let histogram = "lower_sat,upper_sat,weight\n1,10,3\n1000,2000,1\n";
+        let distribution = payment_amounts_from_reader(histogram.as_bytes()).unwrap();
+        let mut rng = StdRng::seed_from_u64(1);
+        let amounts = distribution.amounts(10000, 0, &mut rng);
+        let small = amounts.iter().filter(|a| **a < 10_000).count();
+        assert!(amounts
+            .iter()
+            .all(|a| (1000..10_000).contains(a) || (1_000_000..2_000_000).contains(a)));
+        assert!((small as f64 / 10000.0 - 0.75).abs() < 0.05);
+        for invalid in [
+            "amount\n1\n",
+            "amount_msat\n0\n",
+            "amount_msat\nten\n",
+            "lower_sat,upper_sat,weight\n10,1,1\n",
+            "lower_sat,upper_sat,weight\n1,10\n",
+            "lower_sat,upper_sat,weight\n1,10,0\n",
+        ] {
+            assert!(payment_amounts_from_reader(invalid.as_bytes()).is_err());
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use crate::{input::CsvRecords, time::Time};
+
+use rand::Rng;
+use std::{
+    f64::consts::PI,
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+    str::FromStr,
+};
+
+/// Seconds in a day, the period of [ArrivalProcess::Diurnal]
+static DAY_IN_SECS: f64 = 86400.0;
+
+/// When payments are dispatched. Rates are payments per second.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ArrivalProcess {
+    /// A payment every given number of seconds
+    Fixed(f64),
+    /// Exponentially distributed inter-arrival times
+    Poisson { rate: f64 },
+    /// A Poisson process running at the high rate for the first `burst` seconds of every `period`
+    /// and at the low rate otherwise
+    Bursty {
+        low: f64,
+        high: f64,
+        period: f64,
+        burst: f64,
+    },
+    /// A Poisson process whose rate follows a sine over the day:
+    /// `mean * (1 + amplitude * sin(2 pi t / 1 day))` with an amplitude in [0, 1]
+    Diurnal { mean: f64, amplitude: f64 },
+    /// Seconds since the start of the simulation read using [read_arrival_times]
+    Timestamps(Vec<f64>),
+}
+
+impl Default for ArrivalProcess {
+    fn default() -> Self {
+        Self::Fixed(crate::SIM_DELAY_IN_SECS as f64)
+    }
+}
+
+impl ArrivalProcess {
+    /// Dispatch times of up to `n` payments in ascending order. Timestamps may cover fewer.
+    pub(crate) fn arrival_times(&self, n: usize, rng: &mut impl Rng) -> Vec<Time> {
+        let secs: Vec<f64> = match self {
+            Self::Fixed(interval) => (0..n).map(|i| i as f64 * interval).collect(),
+            Self::Timestamps(timestamps) => timestamps.iter().take(n).copied().collect(),
+            Self::Poisson { rate } => {
+                let mut now = 0.0;
+                (0..n)
+                    .map(|_| {
+                        now += sample_exponential(*rate, rng);
+                        now
+                    })
+                    .collect()
+            }
+            Self::Bursty { low, high, .. } => self.thinning(n, low.max(*high), rng),
+            Self::Diurnal { mean, amplitude } => self.thinning(n, mean * (1.0 + amplitude), rng),
+        };
+        secs.into_iter()
+            .map(|s| Time::from_secs(s as f32))
+            .collect()
+    }
+
+    /// Rate at the given second, only varies for the inhomogeneous processes
+    fn rate(&self, secs: f64) -> f64 {
+        match self {
+            Self::Bursty {
+                low,
+                high,
+                period,
+                burst,
+            } => {
+                if secs % period < *burst {
+                    *high
+                } else {
+                    *low
+                }
+            }
+            Self::Diurnal { mean, amplitude } => {
+                mean * (1.0 + amplitude * (2.0 * PI * secs / DAY_IN_SECS).sin())
+            }
+            Self::Poisson { rate } => *rate,
+            Self::Fixed(interval) => 1.0 / interval,
+            Self::Timestamps(_) => 0.0,
+        }
+    }
+
+    /// Lewis and Shedler's thinning of a Poisson process with the maximum rate
+    fn thinning(&self, n: usize, max_rate: f64, rng: &mut impl Rng) -> Vec<f64> {
+        let mut arrivals = Vec::with_capacity(n);
+        let mut now = 0.0;
+        while arrivals.len() < n {
+            now += sample_exponential(max_rate, rng);
+            if rng.gen::<f64>() * max_rate < self.rate(now) {
+                arrivals.push(now);
+            }
+        }
+        arrivals
+    }
+}
+
+pub(crate) fn sample_exponential(rate: f64, rng: &mut impl Rng) -> f64 {
+    let u: f64 = rng.gen();
+    -(1.0 - u).ln() / rate
+}
+
+/// Parses `fixed:<secs>`, `poisson:<rate>`, `bursty:<low>:<high>:<period>:<burst>` and
+/// `diurnal:<mean>:<amplitude>`. Timestamps are read using [read_arrival_times].
+impl FromStr for ArrivalProcess {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let name = parts.next().unwrap_or_default();
+        let params: Vec<f64> = parts
+            .map(|p| {
+                p.parse()
+                    .map_err(|_| format!("Invalid parameter {} in {}", p, s))
+            })
+            .collect::<Result<_, _>>()?;
+        match (name, params.as_slice()) {
+            ("fixed", [interval]) if *interval >= 0.0 => Ok(Self::Fixed(*interval)),
+            ("poisson", [rate]) if *rate > 0.0 => Ok(Self::Poisson { rate: *rate }),
+            ("bursty", [low, high, period, burst])
+                if *low >= 0.0
+                    && *high > 0.0
+                    && *period > 0.0
+                    && (0.0..=*period).contains(burst) =>
+            {
+                Ok(Self::Bursty {
+                    low: *low,
+                    high: *high,
+                    period: *period,
+                    burst: *burst,
+                })
+            }
+            ("diurnal", [mean, amplitude]) if *mean > 0.0 && (0.0..=1.0).contains(amplitude) => {
+                Ok(Self::Diurnal {
+                    mean: *mean,
+                    amplitude: *amplitude,
+                })
+            }
+            _ => Err(format!(
+                "Unknown arrival process {}. Expected fixed:<secs>, poisson:<rate>, \
+                bursty:<low>:<high>:<period>:<burst> or diurnal:<mean>:<amplitude>",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ArrivalProcess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Fixed(interval) => write!(f, "fixed:{}", interval),
+            Self::Poisson { rate } => write!(f, "poisson:{}", rate),
+            Self::Bursty {
+                low,
+                high,
+                period,
+                burst,
+            } => write!(f, "bursty:{}:{}:{}:{}", low, high, period, burst),
+            Self::Diurnal { mean, amplitude } => write!(f, "diurnal:{}:{}", mean, amplitude),
+            Self::Timestamps(timestamps) => write!(f, "{} timestamps", timestamps.len()),
+        }
+    }
+}
+
+/// Reads a CSV file with a `time_secs` header and one timestamp per line. The timestamps are
+/// sorted.
+pub fn read_arrival_times(path: &Path) -> io::Result<ArrivalProcess> {
+    arrival_times_from_reader(BufReader::new(File::open(path)?))
+}
+
+/// See [read_arrival_times]
+pub fn arrival_times_from_reader(reader: impl BufRead) -> io::Result<ArrivalProcess> {
+    let mut timestamps = CsvRecords::new(reader)?.parse("arrival time", |fields| match fields {
+        [secs] => secs.parse::<f64>().ok().filter(|secs| *secs >= 0.0),
+        _ => None,
+    })?;
+    timestamps.sort_by(f64::total_cmp);
+    Ok(ArrivalProcess::Timestamps(timestamps))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::tests::{assert_missing_file_fails, assert_round_trip};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn parse_arrival_processes() {
+        assert_round_trip::<ArrivalProcess>(&[
+            "fixed:120",
+            "poisson:0.5",
+            "bursty:0.1:2:3600:600",
+            "diurnal:0.2:0.8",
+        ]);
+        assert!("poisson:0".parse::<ArrivalProcess>().is_err());
+        assert!("poisson".parse::<ArrivalProcess>().is_err());
+        assert!("bursty:0.1:2:600:3600".parse::<ArrivalProcess>().is_err());
+        assert!("diurnal:0.2:2".parse::<ArrivalProcess>().is_err());
+        assert!("uniform:1".parse::<ArrivalProcess>().is_err());
+    }
+
+    #[test]
+    fn arrival_times_are_ascending() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let n = 1000;
+        for process in [
+            ArrivalProcess::default(),
+            ArrivalProcess::Poisson { rate: 0.5 },
+            ArrivalProcess::Bursty {
+                low: 0.1,
+                high: 2.0,
+                period: 3600.0,
+                burst: 600.0,
+            },
+            ArrivalProcess::Diurnal {
+                mean: 0.01,
+                amplitude: 1.0,
+            },
+        ] {
+            let times = process.arrival_times(n, &mut rng);
+            assert_eq!(times.len(), n);
+            assert!(times.windows(2).all(|w| w[0] <= w[1]));
+        }
+        let times = ArrivalProcess::default().arrival_times(3, &mut rng);
+        assert_eq!(times[2], Time::from_secs(240.0));
+    }
+
+    #[test]
+    fn rates_shape_arrivals() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let times = ArrivalProcess::Poisson { rate: 0.5 }.arrival_times(10000, &mut rng);
+        let rate = 10000.0 / times.last().unwrap().as_secs();
+        assert!((rate - 0.5).abs() < 0.05);
+        // 600 s at 2 per second and 3000 s at 0.1 per second
+        let bursty = ArrivalProcess::Bursty {
+            low: 0.1,
+            high: 2.0,
+            period: 3600.0,
+            burst: 600.0,
+        };
+        let times = bursty.arrival_times(15000, &mut rng);
+        let in_bursts = times
+            .iter()
+            .filter(|t| t.as_secs() % 3600.0 < 600.0)
+            .count();
+        assert!(in_bursts as f64 / times.len() as f64 > 0.75);
+    }
+
+    #[test]
+    fn read_timestamps() {
+        let csv = "time_secs\n10\n5.5\n\n100\n";
+        let process = arrival_times_from_reader(csv.as_bytes()).unwrap();
+        assert_eq!(process, ArrivalProcess::Timestamps(vec![5.5, 10.0, 100.0]));
+        let mut rng = StdRng::seed_from_u64(0);
+        assert_eq!(process.arrival_times(10, &mut rng).len(), 3);
+        assert!(arrival_times_from_reader("time_secs\n-1\n".as_bytes()).is_err());
+        assert!(arrival_times_from_reader("time_secs\nnow\n".as_bytes()).is_err());
+        assert_missing_file_fails(read_arrival_times);
+    }
+}
@@ -0,0 +1,284 @@
+use crate::{input::CsvRecords, ID};
+
+use rand::Rng;
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    str::FromStr,
+};
+
+/// Balances in msat by channel id and source, i.e. one entry per direction
+pub type ChannelBalances = HashMap<(String, ID), usize>;
+
+/// How a channel's capacity is split between its two directions
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum BalanceDistribution {
+    /// Each share of the capacity is equally likely
+    #[default]
+    Uniform,
+    /// The source's share follows Beta(alpha, beta); `alpha = beta < 1` is bimodal
+    Beta { alpha: f64, beta: f64 },
+    /// Most channels are depleted on one side: the share has a density proportional to
+    /// `exp(-x / scale) + exp(-(1 - x) / scale)`
+    Bimodal { scale: f64 },
+    /// The source's share is exponentially distributed with the given mean, capped at 1
+    Exponential { mean: f64 },
+    /// Gossip does not tell us who funded a channel so we pick one side at random that holds the
+    /// entire capacity
+    Funder,
+    /// Balances read using [read_channel_balances]; other channels are split uniformly
+    Known(ChannelBalances),
+}
+
+impl BalanceDistribution {
+    /// Share of the capacity on one side of a channel in [0, 1]
+    pub fn sample_share(&self, rng: &mut impl Rng) -> f64 {
+        let share = match self {
+            Self::Uniform | Self::Known(_) => rng.gen(),
+            Self::Beta { alpha, beta } => sample_beta(*alpha, *beta, rng),
+            Self::Bimodal { scale } => {
+                // inverse transform sampling of an exponential truncated to [0, 1]
+                let u: f64 = rng.gen();
+                let x = -scale * (1.0 - u * (1.0 - (-1.0 / scale).exp())).ln();
+                if rng.gen_bool(0.5) {
+                    x
+                } else {
+                    1.0 - x
+                }
+            }
+            Self::Exponential { mean } => {
+                let u: f64 = rng.gen();
+                -mean * (1.0 - u).ln()
+            }
+            Self::Funder => {
+                if rng.gen_bool(0.5) {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+        };
+        share.clamp(0.0, 1.0)
+    }
+}
+
+pub(crate) fn sample_standard_normal(rng: &mut impl Rng) -> f64 {
+    // Box-Muller; 1 - gen() is in (0, 1]
+    let u1: f64 = 1.0 - rng.gen::<f64>();
+    let u2: f64 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+/// Marsaglia and Tsang's method
+fn sample_gamma(shape: f64, rng: &mut impl Rng) -> f64 {
+    if shape < 1.0 {
+        let u: f64 = rng.gen();
+        return sample_gamma(shape + 1.0, rng) * u.powf(1.0 / shape);
+    }
+    let d = shape - 1.0 / 3.0;
+    let c = 1.0 / (9.0 * d).sqrt();
+    loop {
+        let x = sample_standard_normal(rng);
+        let v = (1.0 + c * x).powi(3);
+        if v <= 0.0 {
+            continue;
+        }
+        let u: f64 = rng.gen();
+        if u.ln() < 0.5 * x * x + d - d * v + d * v.ln() {
+            return d * v;
+        }
+    }
+}
+
+fn sample_beta(alpha: f64, beta: f64, rng: &mut impl Rng) -> f64 {
+    let x = sample_gamma(alpha, rng);
+    let y = sample_gamma(beta, rng);
+    if x + y == 0.0 {
+        // both underflowed for tiny parameters which puts all mass on the edges
+        if rng.gen_bool(alpha / (alpha + beta)) {
+            1.0
+        } else {
+            0.0
+        }
+    } else {
+        x / (x + y)
+    }
+}
+
+/// Parses `uniform`, `beta:<alpha>:<beta>`, `bimodal:<scale>`, `exponential:<mean>` and `funder`.
+/// Known balances are read using [read_channel_balances].
+impl FromStr for BalanceDistribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let name = parts.next().unwrap_or_default();
+        let params: Vec<f64> = parts
+            .map(|p| {
+                p.parse()
+                    .map_err(|_| format!("Invalid parameter {} in {}", p, s))
+            })
+            .collect::<Result<_, _>>()?;
+        let positive = |params: &[f64]| params.iter().all(|p| *p > 0.0);
+        match (name, params.as_slice()) {
+            ("uniform", []) => Ok(Self::Uniform),
+            ("beta", [alpha, beta]) if positive(&params) => Ok(Self::Beta {
+                alpha: *alpha,
+                beta: *beta,
+            }),
+            ("bimodal", [scale]) if positive(&params) => Ok(Self::Bimodal { scale: *scale }),
+            ("exponential", [mean]) if positive(&params) => Ok(Self::Exponential { mean: *mean }),
+            ("funder", []) => Ok(Self::Funder),
+            _ => Err(format!(
+                "Unknown balance distribution {}. Expected uniform, beta:<alpha>:<beta>, \
+                bimodal:<scale>, exponential:<mean> or funder with positive parameters",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for BalanceDistribution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Uniform => write!(f, "uniform"),
+            Self::Beta { alpha, beta } => write!(f, "beta:{}:{}", alpha, beta),
+            Self::Bimodal { scale } => write!(f, "bimodal:{}", scale),
+            Self::Exponential { mean } => write!(f, "exponential:{}", mean),
+            Self::Funder => write!(f, "funder"),
+            Self::Known(balances) => write!(f, "known ({} directions)", balances.len()),
+        }
+    }
+}
+
+/// Reads a CSV file with a `channel_id,source,balance_msat` header and one line per direction
+pub fn read_channel_balances(path: &Path) -> io::Result<ChannelBalances> {
+    channel_balances_from_reader(BufReader::new(File::open(path)?))
+}
+
+/// See [read_channel_balances]
+pub fn channel_balances_from_reader(reader: impl BufRead) -> io::Result<ChannelBalances> {
+    let balances = CsvRecords::new(reader)?.parse("balance", |fields| match fields {
+        [channel_id, source, balance] => Some((
+            (channel_id.to_string(), source.to_string()),
+            balance.parse().ok()?,
+        )),
+        _ => None,
+    })?;
+    Ok(balances.into_iter().collect())
+}
+
+/// Writes balances in the format [read_channel_balances] expects, sorted by channel id and source
+pub fn write_channel_balances(path: &Path, balances: &ChannelBalances) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    channel_balances_to_writer(&mut writer, balances)?;
+    writer.flush()
+}
+
+/// See [write_channel_balances]
+pub fn channel_balances_to_writer(
+    mut writer: impl Write,
+    balances: &ChannelBalances,
+) -> io::Result<()> {
+    writeln!(writer, "channel_id,source,balance_msat")?;
+    let mut sorted: Vec<_> = balances.iter().collect();
+    sorted.sort();
+    for ((channel_id, source), balance) in sorted {
+        writeln!(writer, "{},{},{}", channel_id, source, balance)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::tests::{assert_missing_file_fails, assert_round_trip};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn mean_and_edge_mass(distribution: &BalanceDistribution) -> (f64, f64) {
+        let mut rng = StdRng::seed_from_u64(1);
+        let samples: Vec<f64> = (0..10000)
+            .map(|_| distribution.sample_share(&mut rng))
+            .collect();
+        assert!(samples.iter().all(|s| (0.0..=1.0).contains(s)));
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        let edges = samples.iter().filter(|s| **s < 0.1 || **s > 0.9).count();
+        (mean, edges as f64 / samples.len() as f64)
+    }
+
+    #[test]
+    fn sample_shares() {
+        let (mean, edges) = mean_and_edge_mass(&BalanceDistribution::Uniform);
+        assert!((mean - 0.5).abs() < 0.02);
+        assert!((edges - 0.2).abs() < 0.02);
+        let (mean, _) = mean_and_edge_mass(&BalanceDistribution::Beta {
+            alpha: 2.0,
+            beta: 6.0,
+        });
+        assert!((mean - 0.25).abs() < 0.02);
+        let (_, edges) = mean_and_edge_mass(&BalanceDistribution::Beta {
+            alpha: 0.2,
+            beta: 0.2,
+        });
+        assert!(edges > 0.6);
+        let (mean, edges) = mean_and_edge_mass(&BalanceDistribution::Bimodal { scale: 0.05 });
+        assert!((mean - 0.5).abs() < 0.02);
+        assert!(edges > 0.8);
+        let (mean, _) = mean_and_edge_mass(&BalanceDistribution::Exponential { mean: 0.1 });
+        assert!((mean - 0.1).abs() < 0.02);
+        let (mean, edges) = mean_and_edge_mass(&BalanceDistribution::Funder);
+        assert!((mean - 0.5).abs() < 0.02);
+        assert_eq!(edges, 1.0);
+    }
+
+    #[test]
+    fn parse_distributions() {
+        assert_round_trip::<BalanceDistribution>(&[
+            "uniform",
+            "beta:0.5:0.5",
+            "bimodal:0.1",
+            "exponential:0.2",
+            "funder",
+        ]);
+        assert!("beta:0.5".parse::<BalanceDistribution>().is_err());
+        assert!("bimodal:-1".parse::<BalanceDistribution>().is_err());
+        assert!("bimodal:x".parse::<BalanceDistribution>().is_err());
+        assert!("normal".parse::<BalanceDistribution>().is_err());
+    }
+
+    #[test]
+    fn read_balances() {
+        let csv = "channel_id,source,balance_msat\nalice1,alice,100\nbob1,bob,40000\n";
+        let actual = channel_balances_from_reader(csv.as_bytes()).unwrap();
+        let expected = ChannelBalances::from([
+            (("alice1".to_owned(), "alice".to_owned()), 100),
+            (("bob1".to_owned(), "bob".to_owned()), 40000),
+        ]);
+        assert_eq!(actual, expected);
+        let csv = "channel_id,source,balance_msat\nalice1,alice\n";
+        assert!(channel_balances_from_reader(csv.as_bytes()).is_err());
+        assert_missing_file_fails(read_channel_balances);
+    }
+
+    #[test]
+    fn write_balances() {
+        let balances = ChannelBalances::from([
+            (("bob1".to_owned(), "bob".to_owned()), 40000),
+            (("alice1".to_owned(), "bob".to_owned()), 0),
+            (("alice1".to_owned(), "alice".to_owned()), 100),
+        ]);
+        let mut csv = vec![];
+        channel_balances_to_writer(&mut csv, &balances).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let expected =
+            "channel_id,source,balance_msat\nalice1,alice,100\nalice1,bob,0\nbob1,bob,40000\n";
+        assert_eq!(csv, expected);
+        assert_eq!(
+            channel_balances_from_reader(csv.as_bytes()).unwrap(),
+            balances
+        );
+    }
+}
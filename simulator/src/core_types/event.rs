@@ -1,14 +1,92 @@
+use crate::htlc::Htlc;
 use crate::payment::Payment;
 use crate::time::Time;
+use crate::topology::TopologyChange;
+use crate::{PaymentId, ID};
 
 use std::collections::BTreeMap;
 use std::collections::VecDeque;
 
 #[derive(Eq, PartialEq, Debug, Clone)]
 pub enum PaymentEvent {
-    Scheduled { payment: Payment },
-    UpdateFailed { payment: Payment },
-    UpdateSuccesful { payment: Payment },
+    Scheduled {
+        payment: Payment,
+    },
+    UpdateFailed {
+        payment: Payment,
+    },
+    UpdateSuccesful {
+        payment: Payment,
+    },
+    /// The payment's HTLCs are settled and release the liquidity they locked
+    SettleHtlcs {
+        payment_id: PaymentId,
+    },
+    /// The payment's HTLCs are failed back, reverting the transfers made when they were offered
+    FailHtlcs {
+        payment_id: PaymentId,
+        to_revert: Vec<(ID, String, usize)>,
+    },
+    /// The payment's HTLCs were not resolved before the earliest of them expired, so they time
+    /// out on-chain and the transfers made when they were offered are reverted
+    ExpireHtlcs {
+        payment_id: PaymentId,
+        to_revert: Vec<(ID, String, usize)>,
+    },
+    /// An update_add_htlc of the payment reaches the node
+    UpdateAddHtlc {
+        payment_id: PaymentId,
+        node: ID,
+    },
+    /// An update_fulfill_htlc of the payment reaches the node, settling the HTLC it offered
+    UpdateFulfillHtlc {
+        payment_id: PaymentId,
+        node: ID,
+        htlc: Htlc,
+    },
+    /// An update_fail_htlc of the payment reaches the node, failing the HTLC it offered
+    UpdateFailHtlc {
+        payment_id: PaymentId,
+        node: ID,
+        htlc: Htlc,
+    },
+    /// The graph changes for all later payments
+    ChangeTopology {
+        change: TopologyChange,
+    },
+}
+
+impl PaymentEvent {
+    /// The payment the event belongs to, None for changes to the graph
+    pub(crate) fn payment_id(&self) -> Option<PaymentId> {
+        match self {
+            Self::Scheduled { payment }
+            | Self::UpdateFailed { payment }
+            | Self::UpdateSuccesful { payment } => Some(payment.payment_id),
+            Self::SettleHtlcs { payment_id }
+            | Self::FailHtlcs { payment_id, .. }
+            | Self::ExpireHtlcs { payment_id, .. }
+            | Self::UpdateAddHtlc { payment_id, .. }
+            | Self::UpdateFulfillHtlc { payment_id, .. }
+            | Self::UpdateFailHtlc { payment_id, .. } => Some(*payment_id),
+            Self::ChangeTopology { .. } => None,
+        }
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Scheduled { .. } => "scheduled",
+            Self::UpdateFailed { .. } => "update_failed",
+            Self::UpdateSuccesful { .. } => "update_successful",
+            Self::SettleHtlcs { .. } => "settle_htlcs",
+            Self::FailHtlcs { .. } => "fail_htlcs",
+            Self::ExpireHtlcs { .. } => "expire_htlcs",
+            Self::UpdateAddHtlc { .. } => "update_add_htlc",
+            Self::UpdateFulfillHtlc { .. } => "update_fulfill_htlc",
+            Self::UpdateFailHtlc { .. } => "update_fail_htlc",
+            Self::ChangeTopology { .. } => "change_topology",
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -63,10 +141,41 @@ impl EventQueue {
         result
     }
 
+    /// Removes the earliest scheduled event that matches and returns it
+    pub(crate) fn take_first(
+        &mut self,
+        matches: impl Fn(&PaymentEvent) -> bool,
+    ) -> Option<PaymentEvent> {
+        let (time, idx) = self.events.iter().find_map(|(time, event_list)| {
+            event_list.iter().position(&matches).map(|idx| (*time, idx))
+        })?;
+        let event_list = self.events.get_mut(&time)?;
+        let event = event_list.remove(idx);
+        if event_list.is_empty() {
+            self.events.remove(&time);
+        }
+        event
+    }
+
     pub(crate) fn now(&self) -> Time {
         self.last_tick
     }
 
+    /// Height of the chain at the current simtime, starting at 0
+    pub(crate) fn block_height(&self) -> usize {
+        self.now().as_blocks()
+    }
+
+    /// Delay from now until the block at the given height is mined
+    pub(crate) fn until_height(&self, height: usize) -> Time {
+        Time::from_blocks(height) - self.now()
+    }
+
+    /// Time of the last event scheduled so far
+    pub(crate) fn last_scheduled(&self) -> Option<Time> {
+        self.events.keys().next_back().copied()
+    }
+
     pub(crate) fn queue_length(&self) -> usize {
         self.events.len()
     }
@@ -167,6 +276,20 @@ mod tests {
         assert_eq!(queue.now(), Time::from_secs(23.0));
     }
 
+    #[test]
+    fn eventqueue_block_height() {
+        let mut queue = EventQueue::new();
+        let e = PaymentEvent::Scheduled {
+            payment: Payment::default(),
+        };
+        assert_eq!(queue.block_height(), 0);
+        queue.schedule(Time::from_secs(1900.0), e);
+        queue.next();
+        assert_eq!(queue.block_height(), 3);
+        assert_eq!(queue.until_height(4), Time::from_secs(500.0));
+        assert_eq!(queue.until_height(2), Time::from_secs(0.0));
+    }
+
     #[test]
     fn eventqueue_queued_times_work() {
         let mut rng = rand::thread_rng();
@@ -180,7 +303,7 @@ mod tests {
         };
         let mut times = Vec::new();
         for _ in 1..100 {
-            let rand_time: f32 = rng.gen_range(0.0..u64::max_value() as f32) / 1000.0;
+            let rand_time: f32 = rng.gen_range(0.0..u64::MAX as f32) / 1000.0;
             times.push(rand_time);
 
             let t = Time::from_millis(rand_time);
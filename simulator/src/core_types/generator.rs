@@ -0,0 +1,337 @@
+use crate::{Edge, Node, ID};
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Synthetic topologies. Nodes are named `n0`, `n1`, ... and all channels are undirected, i.e.
+/// announced in both directions.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Topology {
+    /// Each pair of nodes has a channel with probability `p`
+    ErdosRenyi {
+        nodes: usize,
+        p: f64,
+    },
+    /// Preferential attachment starting with a complete graph of `m + 1` nodes; each further
+    /// node opens `m` channels
+    BarabasiAlbert {
+        nodes: usize,
+        m: usize,
+    },
+    /// Ring lattice where each node has channels to its `k` nearest neighbours, `k` even.
+    /// Each channel is rewired to a random node with probability `beta`.
+    WattsStrogatz {
+        nodes: usize,
+        k: usize,
+        beta: f64,
+    },
+    /// `n0` has a channel to every other node
+    Star {
+        nodes: usize,
+    },
+    Ring {
+        nodes: usize,
+    },
+    /// Hubs have channels to each other and each spoke has one channel to a hub, assigned round
+    /// robin
+    HubAndSpoke {
+        hubs: usize,
+        spokes: usize,
+    },
+}
+
+/// How values of channels and policies are drawn
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Distribution {
+    Constant(usize),
+    /// Inclusive bounds
+    Uniform {
+        min: usize,
+        max: usize,
+    },
+    Exponential {
+        mean: f64,
+    },
+}
+
+/// Capacities are drawn once per channel, the remaining values once per direction
+#[derive(Clone, Debug, PartialEq)]
+pub struct GeneratorConfig {
+    pub seed: u64,
+    pub capacity_msat: Distribution,
+    pub fee_base_msat: Distribution,
+    pub fee_proportional_millionths: Distribution,
+    pub cltv_expiry_delta: Distribution,
+    pub htlc_minimum_msat: Distribution,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            capacity_msat: Distribution::Uniform {
+                min: crate::to_millisatoshi(100_000),
+                max: crate::to_millisatoshi(10_000_000),
+            },
+            fee_base_msat: Distribution::Constant(1000),
+            fee_proportional_millionths: Distribution::Constant(1),
+            cltv_expiry_delta: Distribution::Constant(40),
+            htlc_minimum_msat: Distribution::Constant(1000),
+        }
+    }
+}
+
+impl Distribution {
+    pub fn sample(&self, rng: &mut impl Rng) -> usize {
+        match *self {
+            Self::Constant(value) => value,
+            Self::Uniform { min, max } => rng.gen_range(min..=max),
+            Self::Exponential { mean } => {
+                // inverse transform sampling; gen() is in [0, 1)
+                let u: f64 = rng.gen();
+                (-mean * (1.0 - u).ln()).round() as usize
+            }
+        }
+    }
+}
+
+impl Topology {
+    pub fn num_nodes(&self) -> usize {
+        match *self {
+            Self::ErdosRenyi { nodes, .. }
+            | Self::BarabasiAlbert { nodes, .. }
+            | Self::WattsStrogatz { nodes, .. }
+            | Self::Star { nodes }
+            | Self::Ring { nodes } => nodes,
+            Self::HubAndSpoke { hubs, spokes } => hubs + spokes,
+        }
+    }
+
+    /// Generates the graph. Use [network_parser::GraphSource::Cln] when transforming it using
+    /// [crate::graph::Graph::to_sim_graph] since capacities are set and channel ids carry a
+    /// direction.
+    ///
+    /// Panics if the parameters do not describe a valid graph, e.g. `m >= nodes`.
+    pub fn generate(&self, config: &GeneratorConfig) -> network_parser::Graph {
+        let mut rng = StdRng::seed_from_u64(config.seed);
+        let channels = self.channels(&mut rng);
+        let nodes: HashSet<Node> = (0..self.num_nodes())
+            .map(|idx| Node {
+                id: node_id(idx),
+                alias: node_id(idx),
+                ..Default::default()
+            })
+            .collect();
+        let mut edges: HashMap<ID, HashSet<Edge>> = HashMap::new();
+        for (scid, (a, b)) in channels.into_iter().enumerate() {
+            let capacity = config.capacity_msat.sample(&mut rng);
+            // direction 0 starts at the lesser node as in CLN
+            for (direction, (src, dest)) in [(a, b), (b, a)].into_iter().enumerate() {
+                let edge = Edge {
+                    channel_id: format!("{}x0x0/{}", scid + 1, direction),
+                    source: node_id(src),
+                    destination: node_id(dest),
+                    fee_base_msat: config.fee_base_msat.sample(&mut rng),
+                    fee_proportional_millionths: config
+                        .fee_proportional_millionths
+                        .sample(&mut rng),
+                    htlc_minimim_msat: config.htlc_minimum_msat.sample(&mut rng),
+                    htlc_maximum_msat: capacity,
+                    cltv_expiry_delta: config.cltv_expiry_delta.sample(&mut rng),
+                    capacity,
+                    ..Default::default()
+                };
+                edges.entry(edge.source.clone()).or_default().insert(edge);
+            }
+        }
+        network_parser::Graph { nodes, edges }
+    }
+
+    /// Undirected channels as pairs of node indices, lesser index first
+    fn channels(&self, rng: &mut StdRng) -> BTreeSet<(usize, usize)> {
+        let mut channels = BTreeSet::new();
+        let mut add = |a: usize, b: usize| {
+            if a != b {
+                channels.insert((a.min(b), a.max(b)));
+            }
+        };
+        match *self {
+            Self::ErdosRenyi { nodes, p } => {
+                assert!((0.0..=1.0).contains(&p), "p must be a probability");
+                for a in 0..nodes {
+                    for b in a + 1..nodes {
+                        if rng.gen_bool(p) {
+                            add(a, b);
+                        }
+                    }
+                }
+            }
+            Self::BarabasiAlbert { nodes, m } => {
+                assert!(m > 0 && m < nodes, "m must be in [1, nodes)");
+                // each node appears once per channel so that sampling is proportional to degree
+                let mut endpoints = vec![];
+                for a in 0..=m {
+                    for b in a + 1..=m {
+                        add(a, b);
+                        endpoints.extend([a, b]);
+                    }
+                }
+                for new in m + 1..nodes {
+                    let mut targets = BTreeSet::new();
+                    while targets.len() < m {
+                        targets.insert(*endpoints.choose(rng).unwrap());
+                    }
+                    for target in targets {
+                        add(new, target);
+                        endpoints.extend([new, target]);
+                    }
+                }
+            }
+            Self::WattsStrogatz { nodes, k, beta } => {
+                assert!(
+                    k % 2 == 0 && k < nodes,
+                    "k must be even and less than nodes"
+                );
+                assert!((0.0..=1.0).contains(&beta), "beta must be a probability");
+                let mut lattice = BTreeSet::new();
+                for a in 0..nodes {
+                    for offset in 1..=k / 2 {
+                        let b = (a + offset) % nodes;
+                        lattice.insert((a.min(b), a.max(b)));
+                    }
+                }
+                let mut rewired = lattice.clone();
+                for (a, b) in lattice {
+                    if rng.gen_bool(beta) {
+                        let candidates: Vec<usize> = (0..nodes)
+                            .filter(|c| *c != a && !rewired.contains(&(a.min(*c), a.max(*c))))
+                            .collect();
+                        if let Some(c) = candidates.choose(rng) {
+                            rewired.remove(&(a, b));
+                            rewired.insert((a.min(*c), a.max(*c)));
+                        }
+                    }
+                }
+                for (a, b) in rewired {
+                    add(a, b);
+                }
+            }
+            Self::Star { nodes } => {
+                for b in 1..nodes {
+                    add(0, b);
+                }
+            }
+            Self::Ring { nodes } => {
+                for a in 0..nodes {
+                    add(a, (a + 1) % nodes);
+                }
+            }
+            Self::HubAndSpoke { hubs, spokes } => {
+                assert!(hubs > 0 || spokes == 0, "spokes need at least one hub");
+                for a in 0..hubs {
+                    for b in a + 1..hubs {
+                        add(a, b);
+                    }
+                }
+                for spoke in 0..spokes {
+                    add(hubs + spoke, spoke % hubs);
+                }
+            }
+        }
+        channels
+    }
+}
+
+fn node_id(idx: usize) -> ID {
+    format!("n{}", idx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graph::Graph;
+
+    fn degree(graph: &network_parser::Graph, node: &str) -> usize {
+        graph.edges.get(node).map(|e| e.len()).unwrap_or_default()
+    }
+
+    #[test]
+    fn deterministic_topologies() {
+        let config = GeneratorConfig::default();
+        let star = Topology::Star { nodes: 5 }.generate(&config);
+        assert_eq!(star.nodes.len(), 5);
+        assert_eq!(star.clone().edge_count(), 8);
+        assert_eq!(degree(&star, "n0"), 4);
+        assert_eq!(degree(&star, "n4"), 1);
+        let ring = Topology::Ring { nodes: 6 }.generate(&config);
+        assert_eq!(ring.clone().edge_count(), 12);
+        assert!((0..6).all(|i| degree(&ring, &node_id(i)) == 2));
+        let hub_and_spoke = Topology::HubAndSpoke { hubs: 3, spokes: 7 }.generate(&config);
+        assert_eq!(hub_and_spoke.nodes.len(), 10);
+        // 3 channels between hubs and one per spoke
+        assert_eq!(hub_and_spoke.clone().edge_count(), 2 * (3 + 7));
+        assert_eq!(degree(&hub_and_spoke, "n0"), 2 + 3);
+        let complete = Topology::ErdosRenyi { nodes: 5, p: 1.0 }.generate(&config);
+        assert_eq!(complete.edge_count(), 5 * 4);
+        let empty = Topology::ErdosRenyi { nodes: 5, p: 0.0 }.generate(&config);
+        assert_eq!(empty.edge_count(), 0);
+    }
+
+    #[test]
+    fn random_topologies() {
+        let config = GeneratorConfig {
+            seed: 7,
+            ..Default::default()
+        };
+        let (nodes, m) = (30, 2);
+        let ba = Topology::BarabasiAlbert { nodes, m }.generate(&config);
+        // the initial triangle plus m channels per further node
+        assert_eq!(ba.clone().edge_count(), 2 * (3 + (nodes - m - 1) * m));
+        assert!(ba.nodes.iter().all(|n| degree(&ba, &n.id) >= m));
+        let (k, beta) = (4, 0.3);
+        let ws = Topology::WattsStrogatz { nodes, k, beta }.generate(&config);
+        // rewiring keeps the number of channels
+        assert_eq!(ws.clone().edge_count(), nodes * k);
+        let lattice = Topology::WattsStrogatz {
+            nodes,
+            k,
+            beta: 0.0,
+        }
+        .generate(&config);
+        assert!(lattice.nodes.iter().all(|n| degree(&lattice, &n.id) == k));
+        // same seed, same graph
+        let again = Topology::WattsStrogatz { nodes, k, beta }.generate(&config);
+        for (id, edges) in ws.edges.iter() {
+            let mut expected: Vec<&Edge> = edges.iter().collect();
+            let mut actual: Vec<&Edge> = again.edges[id].iter().collect();
+            expected.sort_by(|a, b| a.channel_id.cmp(&b.channel_id));
+            actual.sort_by(|a, b| a.channel_id.cmp(&b.channel_id));
+            assert_eq!(format!("{:?}", expected), format!("{:?}", actual));
+        }
+    }
+
+    #[test]
+    fn distributions_and_sim_graph() {
+        let config = GeneratorConfig {
+            capacity_msat: Distribution::Uniform {
+                min: 1000,
+                max: 2000,
+            },
+            fee_base_msat: Distribution::Exponential { mean: 100.0 },
+            cltv_expiry_delta: Distribution::Constant(144),
+            ..Default::default()
+        };
+        let net_graph = Topology::Ring { nodes: 4 }.generate(&config);
+        for edge in net_graph.edges.values().flatten() {
+            assert!((1000..=2000).contains(&edge.capacity));
+            assert_eq!(edge.htlc_maximum_msat, edge.capacity);
+            assert_eq!(edge.cltv_expiry_delta, 144);
+        }
+        let graph = Graph::to_sim_graph(&net_graph, network_parser::GraphSource::Cln);
+        assert_eq!(graph.node_count(), 4);
+        assert_eq!(graph.edge_count(), 8);
+        for e in graph.get_edges().values().flatten() {
+            assert!(e.balance <= e.capacity);
+        }
+    }
+}
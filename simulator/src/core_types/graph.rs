@@ -1,41 +1,227 @@
-use crate::ID;
+use crate::{
+    balances::{BalanceDistribution, ChannelBalances},
+    htlc::InFlightHtlcs,
+    preprocessing::Stage,
+    reserve::{ChannelLimits, ChannelReserves},
+    ID,
+};
 use network_parser::{Edge, Node};
 
 use itertools::Itertools;
 use log::{debug, info, warn};
 use pathfinding::directed::strongly_connected_components::strongly_connected_components;
-use rand::{seq::SliceRandom, Rng};
+use rand::seq::SliceRandom;
 use serde::Deserialize;
-use std::{cmp, collections::HashMap};
+use std::{
+    cmp,
+    collections::{HashMap, HashSet},
+    sync::{Arc, OnceLock},
+};
 
-#[derive(Clone, Deserialize, Debug)]
+#[derive(Deserialize, Debug)]
 pub struct Graph {
     pub(crate) nodes: Vec<Node>,
     #[serde(rename = "adjacency")]
     pub(crate) edges: HashMap<ID, Vec<Edge>>,
+    /// Built on first use and kept up to date by the methods that remove nodes and edges.
+    /// Not cloned since most copies are only used for path finding.
+    #[serde(skip)]
+    indices: OnceLock<Indices>,
+    /// Shared by all copies
+    #[serde(skip)]
+    reserves: Arc<ChannelReserves>,
+    /// Copied so that path finding sees the HTLCs in flight
+    #[serde(skip)]
+    pub(crate) in_flight: InFlightHtlcs,
+}
+
+/// Lookups that would otherwise scan the whole graph
+#[derive(Debug, Default)]
+struct Indices {
+    /// Position of each node in `nodes`
+    nodes: HashMap<ID, usize>,
+    /// Source and position in the source's out-edges of each direction with the channel id.
+    /// LND uses the same id for both directions.
+    channels: HashMap<String, Vec<(ID, usize)>>,
+    /// Sources of the edges into each node
+    incoming: HashMap<ID, HashSet<ID>>,
+}
+
+impl Clone for Graph {
+    fn clone(&self) -> Self {
+        Self {
+            reserves: self.reserves.clone(),
+            in_flight: self.in_flight.clone(),
+            ..Self::new(self.nodes.clone(), self.edges.clone())
+        }
+    }
+}
+
+impl Indices {
+    fn new(nodes: &[Node], edges: &HashMap<ID, Vec<Edge>>) -> Self {
+        let mut indices = Self::default();
+        indices.index_nodes(nodes);
+        for (src, edges) in edges.iter() {
+            for (idx, e) in edges.iter().enumerate() {
+                indices
+                    .channels
+                    .entry(e.channel_id.clone())
+                    .or_default()
+                    .push((src.clone(), idx));
+                indices
+                    .incoming
+                    .entry(e.destination.clone())
+                    .or_default()
+                    .insert(src.clone());
+            }
+        }
+        indices
+    }
+
+    fn index_nodes(&mut self, nodes: &[Node]) {
+        self.nodes = nodes
+            .iter()
+            .enumerate()
+            .map(|(idx, n)| (n.id.clone(), idx))
+            .collect();
+    }
+
+    /// Position of the channel direction in the source's out-edges
+    fn edge_index(&self, src: &ID, channel_id: &str) -> Option<usize> {
+        self.channels
+            .get(channel_id)?
+            .iter()
+            .find(|(source, _)| source == src)
+            .map(|(_, idx)| *idx)
+    }
+
+    /// Called after `removed` were taken out of the source's out-edges, which are now `remaining`
+    fn remove_out_edges(&mut self, src: &ID, removed: &[Edge], remaining: &[Edge]) {
+        for e in removed {
+            if let Some(directions) = self.channels.get_mut(&e.channel_id) {
+                directions.retain(|(source, _)| source != src);
+                if directions.is_empty() {
+                    self.channels.remove(&e.channel_id);
+                }
+            }
+            if !remaining.iter().any(|out| out.destination == e.destination) {
+                if let Some(sources) = self.incoming.get_mut(&e.destination) {
+                    sources.remove(src);
+                }
+            }
+        }
+        // the remaining edges moved
+        for (idx, e) in remaining.iter().enumerate() {
+            if let Some(directions) = self.channels.get_mut(&e.channel_id) {
+                for (source, pos) in directions.iter_mut() {
+                    if source == src {
+                        *pos = idx;
+                    }
+                }
+            }
+        }
+    }
 }
 
 impl Graph {
+    pub(crate) fn new(nodes: Vec<Node>, edges: HashMap<ID, Vec<Edge>>) -> Self {
+        Self {
+            nodes,
+            edges,
+            indices: OnceLock::new(),
+            reserves: Arc::default(),
+            in_flight: InFlightHtlcs::default(),
+        }
+    }
+
+    fn indices(&self) -> &Indices {
+        self.indices
+            .get_or_init(|| Indices::new(&self.nodes, &self.edges))
+    }
+
+    pub(crate) fn get_channel(&self, src: &ID, channel_id: &str) -> Option<&Edge> {
+        let idx = self.indices().edge_index(src, channel_id)?;
+        self.edges.get(src)?.get(idx)
+    }
+
+    pub(crate) fn get_channel_mut(&mut self, src: &ID, channel_id: &str) -> Option<&mut Edge> {
+        let idx = self.indices().edge_index(src, channel_id)?;
+        self.edges.get_mut(src)?.get_mut(idx)
+    }
+
+    /// Removes the source's out-edges matching `remove` and updates the indices
+    pub(crate) fn remove_out_edges(&mut self, src: &ID, remove: impl Fn(&Edge) -> bool) {
+        let Some(out_edges) = self.edges.get_mut(src) else {
+            return;
+        };
+        let (removed, kept): (Vec<Edge>, Vec<Edge>) = out_edges.drain(..).partition(remove);
+        *out_edges = kept;
+        if let Some(indices) = self.indices.get_mut() {
+            indices.remove_out_edges(src, &removed, out_edges);
+        }
+    }
+
     /// Transform to another type of graph to allow graph operations such as SCC and shortest path computations
     pub fn to_sim_graph(
         net_graph: &network_parser::Graph,
         graph_source: network_parser::GraphSource,
     ) -> Graph {
-        let nodes: Vec<Node> = net_graph.nodes.clone().into_iter().collect();
+        Self::to_sim_graph_with_balances(net_graph, graph_source, &BalanceDistribution::Uniform)
+    }
+
+    /// Same as [Graph::to_sim_graph] but splits channel capacities using the given distribution
+    pub fn to_sim_graph_with_balances(
+        net_graph: &network_parser::Graph,
+        graph_source: network_parser::GraphSource,
+        balances: &BalanceDistribution,
+    ) -> Graph {
+        let mut graph = Self::from_net_graph(net_graph);
+        graph.preprocess(&Stage::default_pipeline());
+        graph.set_channel_balances(graph_source, balances);
+        graph
+    }
+
+    /// The whole topology without any preprocessing or balances, see [Graph::preprocess] and
+    /// [Graph::set_channel_balances]
+    pub fn from_net_graph(net_graph: &network_parser::Graph) -> Graph {
+        let nodes: Vec<Node> = net_graph.nodes.iter().cloned().collect();
         let edges: HashMap<ID, Vec<Edge>> = net_graph
-            .clone()
             .edges
-            .into_iter()
-            .map(|(id, edge)| (id, Vec::from_iter(edge)))
+            .iter()
+            .map(|(id, edges)| (id.clone(), edges.iter().cloned().collect()))
             .collect();
-        let graph = Graph { nodes, edges };
-        let greatest_scc = graph.reduce_to_greatest_scc();
-        let mut greatest_scc = greatest_scc.remove_unidrectional_edges();
-        greatest_scc.set_channel_balances(graph_source);
-        greatest_scc
+        Graph::new(nodes, edges)
+    }
+
+    /// Transform back, e.g. to write the reduced topology using [network_parser::Graph::to_json_file]
+    pub fn to_net_graph(&self) -> network_parser::Graph {
+        network_parser::Graph {
+            nodes: self.nodes.iter().cloned().collect(),
+            edges: self
+                .edges
+                .iter()
+                .map(|(id, edges)| (id.clone(), edges.iter().cloned().collect()))
+                .collect(),
+        }
+    }
+
+    /// See [network_parser::export_graph]
+    pub fn export(
+        &self,
+        writer: impl std::io::Write,
+        format: network_parser::ExportFormat,
+        node_attributes: &network_parser::NodeAttributes,
+    ) -> std::io::Result<()> {
+        network_parser::export_graph(
+            writer,
+            &self.nodes,
+            self.edges.values().flatten(),
+            format,
+            node_attributes,
+        )
     }
 
-    fn reduce_to_greatest_scc(&self) -> Graph {
+    pub(crate) fn reduce_to_greatest_scc(&self) -> Graph {
         info!(
             "Reducing graph with {} nodes and {} edges to greatest SCC.",
             self.node_count(),
@@ -51,22 +237,8 @@ impl Graph {
                 greatest_scc_idx = idx;
             }
         }
-        let greatest_scc_nodes: Vec<Node> = self
-            .nodes
-            .clone()
-            .into_iter()
-            .filter(|n| sccs[greatest_scc_idx].contains(&n.id))
-            .clone()
-            .collect();
-        let greatest_scc_edges: HashMap<ID, Vec<Edge>> = greatest_scc_nodes
-            .iter()
-            .map(|n| (n.id.clone(), self.edges.get(&n.id).unwrap().clone()))
-            .collect();
-
-        let g = Graph {
-            nodes: greatest_scc_nodes,
-            edges: greatest_scc_edges,
-        };
+        let greatest_scc: HashSet<&ID> = sccs[greatest_scc_idx].iter().collect();
+        let g = self.subgraph(|node| greatest_scc.contains(node));
         info!(
             "Reduced to graph with {} nodes and {} edges.",
             g.node_count(),
@@ -75,11 +247,28 @@ impl Graph {
         g
     }
 
+    /// The nodes for which `keep` is true and the edges between them
+    pub(crate) fn subgraph(&self, keep: impl Fn(&ID) -> bool) -> Graph {
+        let nodes: Vec<Node> = self.nodes.iter().filter(|n| keep(&n.id)).cloned().collect();
+        let edges: HashMap<ID, Vec<Edge>> = nodes
+            .iter()
+            .map(|n| {
+                let out_edges = self
+                    .out_edges(&n.id)
+                    .filter(|e| keep(&e.destination))
+                    .cloned()
+                    .collect();
+                (n.id.clone(), out_edges)
+            })
+            .collect();
+        Graph::new(nodes, edges)
+    }
+
     pub fn node_count(&self) -> usize {
         self.nodes.len()
     }
     pub fn edge_count(&self) -> usize {
-        self.edges.clone().into_values().map(|v| v.len()).sum()
+        self.edges.values().map(|v| v.len()).sum()
     }
 
     pub fn get_node_ids(&self) -> Vec<ID> {
@@ -90,12 +279,24 @@ impl Graph {
         &self.edges
     }
 
+    pub fn get_node_ids_by_reachability(
+        &self,
+        reachability: network_parser::Reachability,
+    ) -> Vec<ID> {
+        network_parser::nodes_by_reachability(&self.nodes, reachability)
+    }
+
     pub fn get_nodes(&self) -> Vec<Node> {
         self.nodes.clone()
     }
 
+    pub(crate) fn get_node(&self, node_id: &ID) -> Option<&Node> {
+        self.nodes.get(*self.indices().nodes.get(node_id)?)
+    }
+
     pub fn set_edges(&mut self, edges: HashMap<ID, Vec<Edge>>) {
         self.edges = edges;
+        self.indices = OnceLock::new();
     }
 
     pub fn get_edges_for_node(&self, node_id: &ID) -> Option<Vec<Edge>> {
@@ -112,33 +313,96 @@ impl Graph {
         }
     }
 
-    /// Will try to remove the edge in both directions
-    /// FIXME: This will remove all parallel edges between src and dest. Instead use channel id
-    pub(crate) fn remove_edge(&mut self, src: &ID, dest: &ID) {
-        // The edge (src, dest) exists
-        if let Some(src_edges) = self.edges.get_mut(src) {
-            src_edges.retain(|edges| edges.destination != dest.clone());
+    /// Removes the channel with the given id starting at src in both directions. Parallel
+    /// channels between the same nodes are kept.
+    pub(crate) fn remove_edge(&mut self, src: &ID, channel_id: &ID) {
+        let Some(edge) = self.get_channel(src, channel_id).cloned() else {
+            return;
+        };
+        let reverse_edge = self.get_reverse_edge(&edge).cloned();
+        self.remove_out_edges(src, |e| e.channel_id == *channel_id);
+        if let Some(reverse_edge) = reverse_edge {
+            self.remove_out_edges(&edge.destination, |e| {
+                e.channel_id == reverse_edge.channel_id
+            });
         }
-        // The edge (dest, src) exists
-        if let Some(dest_edges) = self.edges.get_mut(dest) {
-            dest_edges.retain(|edges| edges.destination != src.clone());
+    }
+
+    /// True if at least one direction of the channel is in the graph
+    pub(crate) fn has_channel(&self, channel_id: &str) -> bool {
+        self.indices().channels.contains_key(channel_id)
+    }
+
+    /// Adds the edges and any of their nodes that are not in the graph yet
+    pub(crate) fn add_edges(&mut self, edges: Vec<Edge>) {
+        for edge in edges {
+            for node in [&edge.source, &edge.destination] {
+                if !self.node_is_in_graph(node) {
+                    self.nodes.push(Node {
+                        id: node.clone(),
+                        ..Default::default()
+                    });
+                    self.indices = OnceLock::new();
+                }
+            }
+            self.edges
+                .entry(edge.source.clone())
+                .or_default()
+                .push(edge);
         }
+        self.indices = OnceLock::new();
     }
 
-    /// Discard the given channel_id from the graph
+    /// Discard the channel with the given id from the graph in both directions
     pub(crate) fn remove_channel(&mut self, channel_id: &ID) {
-        for node in self.edges.iter_mut() {
-            node.1
-                .retain(|edges| edges.channel_id != channel_id.clone())
+        let src = self
+            .indices()
+            .channels
+            .get(channel_id)
+            .and_then(|directions| directions.first())
+            .map(|(src, _)| src.clone());
+        if let Some(src) = src {
+            self.remove_edge(&src, channel_id);
         }
     }
 
     /// Discard the given node and its edges from the graph
     pub fn remove_node(&mut self, node: &ID) {
         self.nodes.retain(|n| *n.id != *node);
-        for n in self.get_outedges(node) {
-            self.remove_edge(node, &n.destination);
+        self.indices();
+        if let Some(indices) = self.indices.get_mut() {
+            indices.index_nodes(&self.nodes);
         }
+        for e in self.get_outedges(node) {
+            self.remove_edge(node, &e.channel_id);
+        }
+        // edges into node whose channel could not be paired
+        let sources = self
+            .indices
+            .get_mut()
+            .and_then(|indices| indices.incoming.remove(node))
+            .unwrap_or_default();
+        for src in sources {
+            self.remove_out_edges(&src, |e| e.destination == *node);
+        }
+    }
+
+    /// Like get_outedges without copying them
+    fn out_edges<'a>(&'a self, node_id: &ID) -> impl Iterator<Item = &'a Edge> {
+        self.edges.get(node_id).into_iter().flatten()
+    }
+
+    /// The edges into the node, found through the sources indexed for it
+    pub(crate) fn in_edges<'a>(&'a self, node_id: &'a ID) -> impl Iterator<Item = &'a Edge> {
+        self.indices()
+            .incoming
+            .get(node_id)
+            .into_iter()
+            .flatten()
+            .flat_map(move |src| {
+                self.out_edges(src)
+                    .filter(move |e| e.destination == *node_id)
+            })
     }
 
     pub(crate) fn get_outedges(&self, node_id: &ID) -> Vec<Edge> {
@@ -153,122 +417,211 @@ impl Graph {
         }
     }
 
-    pub(crate) fn update_channel_balance(&mut self, channel_id: &ID, balance: usize) {
-        for edge_lists in self.edges.values_mut() {
-            for edge in edge_lists {
-                if edge.channel_id == channel_id.clone() {
-                    edge.balance = balance;
-                }
-            }
+    /// Sets the balance of the channel's direction starting at src
+    pub(crate) fn update_channel_balance(&mut self, src: &ID, channel_id: &str, balance: usize) {
+        let locked = self.in_flight.locked(src, channel_id);
+        if let Some(edge) = self.get_channel_mut(src, channel_id) {
+            edge.balance = balance;
+            edge.liquidity = balance.saturating_sub(locked);
         }
     }
 
+    /// Applies to channels added later as well
+    pub fn set_reserves(&mut self, reserves: ChannelReserves) {
+        self.reserves = Arc::new(reserves);
+    }
+
+    pub(crate) fn channel_limits(&self, edge: &Edge) -> ChannelLimits {
+        self.reserves.limits(edge)
+    }
+
+    /// The part of the edge's balance above the reserve that is not locked in HTLCs
+    pub(crate) fn spendable_balance(&self, edge: &Edge) -> usize {
+        edge.balance
+            .saturating_sub(self.channel_limits(edge).reserve_msat)
+            .saturating_sub(self.in_flight.locked(&edge.source, &edge.channel_id))
+    }
+
+    pub(crate) fn get_spendable_balance(&self, src_node: &ID, channel_id: &ID) -> usize {
+        self.get_channel(src_node, channel_id)
+            .map(|e| self.spendable_balance(e))
+            .unwrap_or_default()
+    }
+
+    /// Liquidity in msat that cannot be spent since it is kept as reserve
+    pub fn locked_reserve(&self) -> usize {
+        self.edges
+            .values()
+            .flatten()
+            .map(|e| cmp::min(e.balance, self.channel_limits(e).reserve_msat))
+            .sum()
+    }
+
     pub(crate) fn get_channel_balance(&self, src_node: &ID, channel_id: &ID) -> usize {
-        self.get_outedges(src_node)
-            .iter()
-            .find(|out| out.channel_id == *channel_id)
+        self.get_channel(src_node, channel_id)
             .map(|e| e.balance)
             .unwrap_or_else(|| 0)
     }
 
-    /// True if the channel's balance after transferring the amount will not exceed the channel capacity
-    pub(crate) fn channel_can_receive_amount(&self, channel_id: &ID, amount: usize) -> bool {
-        for edges in self.get_edges().values() {
-            for edge in edges {
-                if edge.channel_id.eq_ignore_ascii_case(channel_id) {
-                    return edge.capacity > (edge.balance + amount);
-                }
-            }
-        }
-        false
+    /// True if the channel's balance after transferring the amount will not exceed the channel
+    /// capacity minus the reserve the other side keeps. `src` is the side whose balance grows.
+    pub(crate) fn channel_can_receive_amount(
+        &self,
+        src: &ID,
+        channel_id: &str,
+        amount: usize,
+    ) -> bool {
+        self.get_channel(src, channel_id).is_some_and(|edge| {
+            edge.capacity > (edge.balance + amount + self.channel_limits(edge).reserve_msat)
+        })
     }
 
+    /// The largest spendable balance of the node's channels
     pub(crate) fn get_max_node_balance(&self, node: &ID) -> usize {
-        let out_edges = self.get_outedges(node);
-        let max_balance = out_edges.iter().map(|e| e.balance).max();
+        let max_balance = self
+            .out_edges(node)
+            .map(|e| self.spendable_balance(e))
+            .max();
         if max_balance.is_none() {
             warn!("Node {} not found. Returning 0 as balance.", node);
         }
         max_balance.unwrap_or(0)
     }
 
+    /// The node's spendable balance over all channels
     pub(crate) fn get_total_node_balance(&self, node: &ID) -> usize {
-        self.get_outedges(node).iter().map(|e| e.balance).sum()
+        self.out_edges(node)
+            .map(|e| self.spendable_balance(e))
+            .sum()
+    }
+
+    /// The current balance of each channel direction
+    pub fn channel_balances(&self) -> ChannelBalances {
+        self.edges
+            .values()
+            .flatten()
+            .map(|e| ((e.channel_id.clone(), e.source.clone()), e.balance))
+            .collect()
     }
 
     // Get all edges going to 'node' then check how much of the channel capacity is already with
     // 'node'.
     pub(crate) fn get_max_receive_amount(&self, node: &ID) -> usize {
         let mut max_receive = 0;
-        for n in self.get_node_ids() {
-            if n != *node {
-                let edges_to_node = self.get_all_src_dest_edges(&n, node);
-                for e in edges_to_node {
-                    max_receive += e.capacity - e.balance;
+        for n in self.indices().incoming.get(node).into_iter().flatten() {
+            if n != node {
+                for e in self.edges[n].iter().filter(|e| e.destination == *node) {
+                    max_receive += (e.capacity - e.balance)
+                        .saturating_sub(self.channel_limits(e).reserve_msat);
                 }
             }
         }
         max_receive
     }
 
-    /// We calculate balances based on the edges' max_sat values using a random uniform
-    /// distribution. We set the liquidity to the calculated balance
-    fn set_channel_balances(&mut self, graph_source: network_parser::GraphSource) {
-        info!("Calculating channel balances.");
-        // hm
-        let graph_copy = self.clone();
+    /// We split each channel's capacity between its two directions using the given distribution
+    /// and set the liquidity to the resulting balance.
+    /// lnresearch capacities are the lower htlc_maximum_msat value unless set from a funding file.
+    /// A direction without a reverse edge gets its share of its own capacity.
+    pub fn set_channel_balances(
+        &mut self,
+        graph_source: network_parser::GraphSource,
+        distribution: &BalanceDistribution,
+    ) {
+        info!("Calculating channel balances using {}.", distribution);
         let mut rng = crate::RNG.lock().unwrap();
-        for (src, edges) in self.edges.iter_mut() {
-            for out_edge in edges.iter_mut() {
-                // means we haven't visited the edge before; might break if htlc_maximum_msat == 0
-                if out_edge.balance == usize::default() {
-                    // Channel capacity is assumed to be the lower htlc_maximum_msat value
-                    if let Some(mut reverse_edge) = graph_copy.get_edge(&out_edge.destination, src)
+        // (channel_id, source) -> (capacity, balance)
+        let mut assigned: HashMap<(String, ID), (usize, usize)> = HashMap::new();
+        let mut num_unknown = 0;
+        for (src, edges) in self.edges.iter() {
+            for out_edge in edges {
+                if assigned.contains_key(&(out_edge.channel_id.clone(), src.clone())) {
+                    continue;
+                }
+                let out_key = (out_edge.channel_id.clone(), src.clone());
+                let Some(reverse_edge) = self.get_reverse_edge(out_edge) else {
+                    // kept by a pipeline without the bidirectional stage
+                    let capacity = match graph_source {
+                        network_parser::GraphSource::Lnresearch if out_edge.capacity == 0 => {
+                            out_edge.htlc_maximum_msat
+                        }
+                        _ => out_edge.capacity,
+                    };
+                    let balance = match distribution {
+                        BalanceDistribution::Known(balances) if balances.contains_key(&out_key) => {
+                            balances[&out_key]
+                        }
+                        _ => (distribution.sample_share(&mut *rng) * capacity as f64).round()
+                            as usize,
+                    };
+                    assigned.insert(out_key, (capacity, cmp::min(balance, capacity)));
+                    continue;
+                };
+                let capacity = match graph_source {
+                    network_parser::GraphSource::Lnresearch
+                        if out_edge.capacity == 0 || reverse_edge.capacity == 0 =>
                     {
-                        let src_capacity_dist: f32 = rng.gen();
-                        let capacity = match graph_source {
-                            network_parser::GraphSource::Lnresearch => {
-                                let max_src_htlc = &out_edge.htlc_maximum_msat;
-                                let max_dest_htlc = reverse_edge.htlc_maximum_msat;
-                                *cmp::min(max_src_htlc, &max_dest_htlc) as f32
-                            }
-                            network_parser::GraphSource::Lnd =>
-                            // should not be necessary since the library ensures both edges are
-                            // there
-                            {
-                                cmp::min(out_edge.capacity, reverse_edge.capacity) as f32
-                            }
-                        };
-                        out_edge.capacity = capacity as usize;
-                        reverse_edge.capacity = capacity as usize;
-                        let src_balance = (src_capacity_dist * capacity).round();
-                        let dest_balance = capacity - src_balance;
-                        reverse_edge.balance = dest_balance as usize;
-                        reverse_edge.liquidity = reverse_edge.balance;
-                        out_edge.balance = src_balance as usize;
-                        out_edge.liquidity = out_edge.balance;
+                        cmp::min(out_edge.htlc_maximum_msat, reverse_edge.htlc_maximum_msat)
+                    }
+                    // should not be necessary since the library ensures both edges are there
+                    _ => cmp::min(out_edge.capacity, reverse_edge.capacity),
+                };
+                let reverse_key = (reverse_edge.channel_id.clone(), reverse_edge.source.clone());
+                let known = match distribution {
+                    BalanceDistribution::Known(balances) => {
+                        (balances.get(&out_key), balances.get(&reverse_key))
+                    }
+                    _ => (None, None),
+                };
+                let (src_balance, dest_balance) = match known {
+                    (Some(src_balance), Some(dest_balance)) => (*src_balance, *dest_balance),
+                    (Some(src_balance), None) => {
+                        (*src_balance, capacity.saturating_sub(*src_balance))
                     }
+                    (None, Some(dest_balance)) => {
+                        (capacity.saturating_sub(*dest_balance), *dest_balance)
+                    }
+                    (None, None) => {
+                        if matches!(distribution, BalanceDistribution::Known(_)) {
+                            num_unknown += 1;
+                        }
+                        let share = distribution.sample_share(&mut *rng);
+                        let src_balance = (share * capacity as f64).round() as usize;
+                        (src_balance, capacity - src_balance)
+                    }
+                };
+                assigned.insert(out_key, (capacity, cmp::min(src_balance, capacity)));
+                assigned.insert(reverse_key, (capacity, cmp::min(dest_balance, capacity)));
+            }
+        }
+        if num_unknown > 0 {
+            warn!(
+                "No known balances for {} channels. Splitting them uniformly.",
+                num_unknown
+            );
+        }
+        for (src, edges) in self.edges.iter_mut() {
+            for edge in edges.iter_mut() {
+                if let Some((capacity, balance)) =
+                    assigned.get(&(edge.channel_id.clone(), src.clone()))
+                {
+                    edge.capacity = *capacity;
+                    edge.balance = *balance;
+                    edge.liquidity = *balance;
                 }
             }
         }
     }
 
-    fn remove_unidrectional_edges(&self) -> Self {
+    pub(crate) fn remove_unidrectional_edges(&self) -> Self {
         info!("Deleting unidirectional edges from graph.");
         let mut graph_copy = self.clone();
         let mut num_removed = 0;
         for (src, edges) in self.edges.iter() {
-            let from = src;
             for out in edges.iter() {
-                let to = &out.destination;
-                // check if to->from exists
-                let edges_from_to: Vec<ID> = if let Some(edges) = self.get_edges_for_node(to) {
-                    edges.iter().map(|edge| edge.destination.clone()).collect()
-                } else {
-                    Vec::default()
-                };
-                if !edges_from_to.contains(from) {
-                    graph_copy.remove_edge(from, to);
+                // a parallel channel in the opposite direction does not count
+                if self.get_reverse_edge(out).is_none() {
+                    graph_copy.remove_edge(src, &out.channel_id);
                     num_removed += 1;
                 }
             }
@@ -282,7 +635,9 @@ impl Graph {
         graph_copy
     }
 
-    /// Use get_all_src_dest_edges to get all such edges
+    /// The first edge from `from` to `to`. Use get_all_src_dest_edges to get all such edges or
+    /// get_reverse_edge to get the opposite direction of a specific channel.
+    #[allow(unused)]
     pub(crate) fn get_edge(&self, from: &ID, to: &ID) -> Option<Edge> {
         let out_edges = self.get_outedges(from);
         // Assumes there is at most one edge from dest to src
@@ -292,18 +647,47 @@ impl Graph {
             .cloned()
     }
 
+    /// The opposite direction of the edge's channel, found by short channel id.
+    /// For graphs whose channel ids do not pair up, e.g. hand-written ones, a single edge in each
+    /// direction between the two nodes is treated as one channel.
+    pub(crate) fn get_reverse_edge(&self, edge: &Edge) -> Option<&Edge> {
+        let reverse_edges: Vec<&Edge> = self
+            .edges
+            .get(&edge.destination)?
+            .iter()
+            .filter(|e| e.destination == edge.source)
+            .collect();
+        if let Some(reverse_edge) = reverse_edges.iter().find(|e| e.is_reverse_of(edge)) {
+            return Some(reverse_edge);
+        }
+        let num_parallel = self
+            .edges
+            .get(&edge.source)
+            .map(|edges| {
+                edges
+                    .iter()
+                    .filter(|e| e.destination == edge.destination)
+                    .count()
+            })
+            .unwrap_or_default();
+        match reverse_edges.as_slice() {
+            [reverse_edge] if num_parallel == 1 => Some(reverse_edge),
+            _ => None,
+        }
+    }
+
     /// Returns all edges between two nodes. Empty if there are none
     pub(crate) fn get_all_src_dest_edges(&self, from: &ID, to: &ID) -> Vec<Edge> {
-        self.get_outedges(from)
-            .into_iter()
-            .filter(|edge| edge.destination == to.clone())
+        self.out_edges(from)
+            .filter(|edge| edge.destination == *to)
+            .cloned()
             .collect()
     }
 
     pub(crate) fn get_random_pairs_of_nodes(
         &self,
         num_nodes: usize,
-    ) -> (impl Iterator<Item = (ID, ID)> + Clone) {
+    ) -> impl Iterator<Item = (ID, ID)> + Clone {
         let mut node_ids = self.get_node_ids();
         assert!(
             !node_ids.is_empty(),
@@ -329,7 +713,7 @@ impl Graph {
     }
 
     pub(crate) fn node_is_in_graph(&self, node: &ID) -> bool {
-        self.get_node_ids().contains(node)
+        self.indices().nodes.contains_key(node)
     }
 
     fn get_sccs(&self) -> Vec<Vec<ID>> {
@@ -462,6 +846,71 @@ mod tests {
         assert_eq!(num_edges, 2);
     }
 
+    #[test]
+    fn funding_amounts_replace_estimated_capacities() {
+        let path = std::path::Path::new("../test_data/lnbook_example.json");
+        let mut net_graph =
+            network_parser::Graph::from_json_file(path, network_parser::GraphSource::Lnresearch)
+                .unwrap();
+        // lnbook's channels use a different id per direction
+        let amounts = network_parser::FundingAmounts::from([
+            ("alice1".to_owned(), 1000),
+            ("bob1".to_owned(), 1000),
+        ]);
+        let report = net_graph.set_funding_amounts(&amounts).unwrap();
+        assert_eq!(report.matched, 2);
+        let graph = Graph::to_sim_graph(&net_graph, network_parser::GraphSource::Lnresearch);
+        for edge in graph.edges.values().flatten() {
+            let expected = match edge.channel_id.as_str() {
+                "alice1" | "bob1" => 1000000,
+                // min htlc_maximum_msat of both directions
+                _ => edge.htlc_maximum_msat,
+            };
+            assert_eq!(edge.capacity, expected);
+        }
+    }
+
+    #[test]
+    fn write_reduced_graph() {
+        let json_str = json_str();
+        let source = network_parser::GraphSource::Lnresearch;
+        let graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_str(&json_str, source.clone()).unwrap(),
+            source.clone(),
+        );
+        let written = graph.to_net_graph().to_json_str(source.clone()).unwrap();
+        // reading the graph again must not reduce it any further
+        let actual = network_parser::Graph::from_json_str(&written, source).unwrap();
+        assert_eq!(actual.nodes.len(), graph.node_count());
+        assert_eq!(actual.edge_count(), graph.edge_count());
+    }
+
+    #[test]
+    fn export_includes_balances() {
+        let path = Path::new("../test_data/lnbook_example.json");
+        let graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(path, network_parser::GraphSource::Lnresearch)
+                .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        let mut buf = Vec::new();
+        graph
+            .export(
+                &mut buf,
+                network_parser::ExportFormat::Csv,
+                &network_parser::NodeAttributes::default(),
+            )
+            .unwrap();
+        let actual = String::from_utf8(buf).unwrap();
+        assert_eq!(actual.lines().count(), graph.edge_count() + 1);
+        let edge = &graph.get_edges_for_node(&"alice".to_owned()).unwrap()[0];
+        let expected = format!(
+            "alice,bob,alice1,20,0,1000,140000,5,{},{},{}",
+            edge.capacity, edge.balance, edge.liquidity
+        );
+        assert!(actual.lines().any(|line| line == expected));
+    }
+
     #[test]
     fn scc_compuatation() {
         let json_str = json_str();
@@ -539,7 +988,7 @@ mod tests {
             .unwrap(),
             network_parser::GraphSource::Lnresearch,
         );
-        let random_pair: Vec<(ID, ID)> = graph.get_random_pairs_of_nodes(n).into_iter().collect();
+        let random_pair: Vec<(ID, ID)> = graph.get_random_pairs_of_nodes(n).collect();
         assert!(graph.get_node_ids().contains(&random_pair[0].0));
         assert!(graph.get_node_ids().contains(&random_pair[0].1));
     }
@@ -573,6 +1022,7 @@ mod tests {
             balance: actual.clone().unwrap().balance, // hacky because it depends on the RNG
             liquidity: 0,
             capacity: 0,
+            ..Default::default()
         });
         assert_eq!(actual, expected);
     }
@@ -602,6 +1052,7 @@ mod tests {
             balance: 0,
             liquidity: 0,
             capacity: 0,
+            ..Default::default()
         }];
         assert_eq!(actual, expected);
     }
@@ -623,7 +1074,8 @@ mod tests {
         let node2_edge_len = graph.edges[&node2].len();
         assert!(graph.get_edge(&node1, &node2).is_some());
         assert!(graph.get_edge(&node2, &node1).is_some());
-        graph.remove_edge(&node1, &node2);
+        let channel_id = graph.get_edge(&node1, &node2).unwrap().channel_id;
+        graph.remove_edge(&node1, &channel_id);
         let node1_edge_new_len = graph.edges[&node1].len();
         let node2_edge_new_len = graph.edges[&node2].len();
         assert_eq!(node1_edge_len - 1, node1_edge_new_len);
@@ -643,7 +1095,10 @@ mod tests {
             .unwrap(),
             network_parser::GraphSource::Lnresearch,
         );
-        graph.set_channel_balances(network_parser::GraphSource::Lnresearch);
+        graph.set_channel_balances(
+            network_parser::GraphSource::Lnresearch,
+            &BalanceDistribution::Uniform,
+        );
         for edges in graph.edges.into_values() {
             for e in edges {
                 assert!(e.balance != usize::default());
@@ -652,11 +1107,74 @@ mod tests {
         }
     }
 
+    #[test]
+    fn balances_of_both_directions_add_up() {
+        let net_graph = network_parser::Graph::from_json_file(
+            Path::new("../test_data/lnbook_example.json"),
+            network_parser::GraphSource::Lnresearch,
+        )
+        .unwrap();
+        for distribution in [
+            BalanceDistribution::Uniform,
+            BalanceDistribution::Bimodal { scale: 0.1 },
+            BalanceDistribution::Funder,
+        ] {
+            let graph = Graph::to_sim_graph_with_balances(
+                &net_graph,
+                network_parser::GraphSource::Lnresearch,
+                &distribution,
+            );
+            for e in graph.edges.values().flatten() {
+                let reverse = graph.get_edge(&e.destination, &e.source).unwrap();
+                assert_eq!(e.balance + reverse.balance, e.capacity);
+                assert_eq!(e.liquidity, e.balance);
+                if distribution == BalanceDistribution::Funder {
+                    assert!(e.balance == 0 || e.balance == e.capacity);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn known_balances() {
+        let net_graph = network_parser::Graph::from_json_file(
+            Path::new("../test_data/lnbook_example.json"),
+            network_parser::GraphSource::Lnresearch,
+        )
+        .unwrap();
+        let known = crate::balances::ChannelBalances::from([
+            (("alice1".to_owned(), "alice".to_owned()), 40000),
+            // larger than the capacity
+            (("chan2".to_owned(), "chan".to_owned()), 1000000000),
+        ]);
+        let graph = Graph::to_sim_graph_with_balances(
+            &net_graph,
+            network_parser::GraphSource::Lnresearch,
+            &BalanceDistribution::Known(known),
+        );
+        assert_eq!(
+            graph.get_channel_balance(&"alice".to_owned(), &"alice1".to_owned()),
+            40000
+        );
+        assert_eq!(
+            graph.get_channel_balance(&"bob".to_owned(), &"bob1".to_owned()),
+            100000
+        );
+        assert_eq!(
+            graph.get_channel_balance(&"chan".to_owned(), &"chan2".to_owned()),
+            270000
+        );
+        assert_eq!(
+            graph.get_channel_balance(&"dina".to_owned(), &"dina1".to_owned()),
+            0
+        );
+    }
+
     #[test]
     fn all_edges_between_two_nodes() {
         let graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &Path::new("../test_data/trivial_connected.json"),
+                Path::new("../test_data/trivial_connected.json"),
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -667,7 +1185,7 @@ mod tests {
             let from = node;
             let to = nodes[idx + 1 % nodes.len() - 1].clone();
             if *from != to {
-                let actual = graph.get_all_src_dest_edges(&from, &to);
+                let actual = graph.get_all_src_dest_edges(from, &to);
                 assert_eq!(actual.len(), 1);
             }
         }
@@ -678,7 +1196,7 @@ mod tests {
         let json_file = std::path::Path::new("../test_data/lnbook_example.json");
         let mut graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &json_file,
+                json_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -702,7 +1220,7 @@ mod tests {
         let json_file = std::path::Path::new("../test_data/lnbook_example.json");
         let mut graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &json_file,
+                json_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -711,7 +1229,7 @@ mod tests {
         let node = String::from("alice");
         let channel_id = String::from("alice1");
         let new_balance = 1234;
-        graph.update_channel_balance(&channel_id, new_balance);
+        graph.update_channel_balance(&node, &channel_id, new_balance);
         assert_eq!(new_balance, graph.get_channel_balance(&node, &channel_id));
     }
 
@@ -720,7 +1238,7 @@ mod tests {
         let json_file = std::path::Path::new("../test_data/lnbook_example.json");
         let mut graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &json_file,
+                json_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -729,10 +1247,10 @@ mod tests {
         let node = String::from("bob");
         let channel_id = String::from("bob1");
         let new_balance = 10000;
-        graph.update_channel_balance(&channel_id, new_balance);
+        graph.update_channel_balance(&node, &channel_id, new_balance);
         let channel_id = String::from("bob2");
         let new_balance = 50;
-        graph.update_channel_balance(&channel_id, new_balance);
+        graph.update_channel_balance(&node, &channel_id, new_balance);
         let actual = graph.get_max_node_balance(&node);
         let expected = 10000;
         assert_eq!(actual, expected);
@@ -743,7 +1261,7 @@ mod tests {
         let json_file = std::path::Path::new("../test_data/lnbook_example.json");
         let mut graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &json_file,
+                json_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -752,15 +1270,59 @@ mod tests {
         let node = String::from("bob");
         let channel_id = String::from("bob1");
         let new_balance = 10000;
-        graph.update_channel_balance(&channel_id, new_balance);
+        graph.update_channel_balance(&node, &channel_id, new_balance);
         let channel_id = String::from("bob2");
         let new_balance = 50;
-        graph.update_channel_balance(&channel_id, new_balance);
+        graph.update_channel_balance(&node, &channel_id, new_balance);
         let actual = graph.get_total_node_balance(&node);
         let expected = 10050;
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn reserves_are_not_spendable() {
+        let path = std::path::Path::new("../test_data/trivial_multigraph.json");
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(path, network_parser::GraphSource::Lnresearch)
+                .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        for e in graph.edges.values_mut().flatten() {
+            e.balance = e.capacity / 2;
+        }
+        let alice = String::from("alice");
+        assert_eq!(graph.get_max_node_balance(&alice), 250000);
+        assert_eq!(graph.locked_reserve(), 0);
+        graph.set_reserves(ChannelReserves {
+            reserve_share: 0.01,
+            ..Default::default()
+        });
+        // 100x1x0 and 100x3x0 have a capacity of 500000, 100x2x0 of 50000
+        assert_eq!(graph.get_max_node_balance(&alice), 245000);
+        assert_eq!(graph.get_total_node_balance(&alice), 245000 + 24500);
+        assert_eq!(graph.locked_reserve(), 2 * (5000 + 500 + 5000));
+        let channel_id = String::from("100x1x0/0");
+        assert!(graph.channel_can_receive_amount(&alice, &channel_id, 244999));
+        assert!(!graph.channel_can_receive_amount(&alice, &channel_id, 245000));
+        // the clones used for path finding share the reserves
+        assert_eq!(graph.clone().get_max_node_balance(&alice), 245000);
+
+        graph.set_reserves(ChannelReserves {
+            reserve_share: 0.01,
+            overrides: HashMap::from([(
+                "100x2x0".to_owned(),
+                ChannelLimits {
+                    reserve_msat: 30000,
+                    dust_limit_msat: 0,
+                },
+            )]),
+            ..Default::default()
+        });
+        // more than the balance of either side
+        assert_eq!(graph.get_total_node_balance(&alice), 245000);
+        assert_eq!(graph.locked_reserve(), 2 * (5000 + 25000 + 5000));
+    }
+
     #[test]
     fn delete_channel() {
         let json_str = json_str();
@@ -781,12 +1343,51 @@ mod tests {
         assert_eq!(node1_edge_len - 1, node1_edge_new_len);
     }
 
+    #[test]
+    fn add_channel() {
+        let json_file = std::path::Path::new("../test_data/lnbook_example.json");
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                json_file,
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        let (alice, erin) = (String::from("alice"), String::from("erin"));
+        let edge = |source: &ID, destination: &ID| Edge {
+            channel_id: String::from("new"),
+            source: source.clone(),
+            destination: destination.clone(),
+            balance: 500,
+            ..Default::default()
+        };
+        // looked up once so the indices have to be rebuilt
+        assert!(!graph.node_is_in_graph(&erin));
+        graph.add_edges(vec![edge(&alice, &erin), edge(&erin, &alice)]);
+        assert!(graph.node_is_in_graph(&erin));
+        assert_eq!(graph.node_count(), 5);
+        assert_eq!(graph.get_channel_balance(&erin, &String::from("new")), 500);
+        assert!(graph.get_edge(&alice, &erin).is_some());
+        // both directions share the id, as in LND graphs, but are updated and checked separately
+        graph.update_channel_balance(&alice, "new", 100);
+        assert_eq!(graph.get_channel_balance(&alice, &String::from("new")), 100);
+        assert_eq!(graph.get_channel_balance(&erin, &String::from("new")), 500);
+        for e in graph.edges.values_mut().flatten() {
+            e.capacity = 1000;
+        }
+        assert!(graph.channel_can_receive_amount(&alice, "new", 800));
+        assert!(!graph.channel_can_receive_amount(&erin, "new", 800));
+        graph.remove_channel(&String::from("new"));
+        assert!(graph.get_edge(&alice, &erin).is_none());
+    }
+
     #[test]
     fn channel_can_receive() {
         let json_file = std::path::Path::new("../test_data/lnbook_example.json");
         let mut graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &json_file,
+                json_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -802,10 +1403,10 @@ mod tests {
             }
         }
         let amount = 2000;
-        let channel_id = "alice1".to_string();
-        assert!(graph.channel_can_receive_amount(&channel_id, amount));
+        let (alice, channel_id) = ("alice".to_string(), "alice1".to_string());
+        assert!(graph.channel_can_receive_amount(&alice, &channel_id, amount));
         let amount = capacity * 2;
-        assert!(!graph.channel_can_receive_amount(&channel_id, amount));
+        assert!(!graph.channel_can_receive_amount(&alice, &channel_id, amount));
     }
 
     #[test]
@@ -813,7 +1414,7 @@ mod tests {
         let json_file = std::path::Path::new("../test_data/lnbook_example.json");
         let mut graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &json_file,
+                json_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -843,7 +1444,7 @@ mod tests {
         let json_file = std::path::Path::new("../test_data/lnbook_example.json");
         let mut graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &json_file,
+                json_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -861,7 +1462,7 @@ mod tests {
         let json_file = std::path::Path::new("../test_data/lnbook_example.json");
         let mut graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &json_file,
+                json_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -872,4 +1473,83 @@ mod tests {
         graph.remove_node(&node);
         assert!(!graph.node_is_in_graph(&node));
     }
+
+    #[test]
+    fn parallel_channels() {
+        let json_file = std::path::Path::new("../test_data/trivial_multigraph.json");
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                json_file,
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        let (alice, bob, carol) = ("alice".to_owned(), "bob".to_owned(), "carol".to_owned());
+        // 100x5x0 is only announced from bob to carol
+        assert_eq!(graph.edge_count(), 6);
+        assert_eq!(graph.get_all_src_dest_edges(&bob, &carol).len(), 1);
+        assert_eq!(graph.get_all_src_dest_edges(&alice, &bob).len(), 2);
+        for e in graph.edges.values().flatten() {
+            let reverse = graph.get_reverse_edge(e).unwrap();
+            assert_eq!(reverse.short_channel_id(), e.short_channel_id());
+            assert_eq!(reverse.capacity, e.capacity);
+            assert_eq!(e.balance + reverse.balance, e.capacity);
+        }
+        assert_eq!(
+            graph.get_channel_balance(&alice, &"100x2x0/0".to_owned())
+                + graph.get_channel_balance(&bob, &"100x2x0/1".to_owned()),
+            50000
+        );
+        graph.remove_edge(&alice, &"100x2x0/0".to_owned());
+        let channel_ids = |graph: &Graph, from: &ID, to: &ID| -> Vec<String> {
+            graph
+                .get_all_src_dest_edges(from, to)
+                .into_iter()
+                .map(|e| e.channel_id)
+                .collect()
+        };
+        assert_eq!(channel_ids(&graph, &alice, &bob), vec!["100x1x0/0"]);
+        assert_eq!(channel_ids(&graph, &bob, &alice), vec!["100x1x0/1"]);
+        graph.remove_channel(&"100x3x0/1".to_owned());
+        assert!(channel_ids(&graph, &bob, &carol).is_empty());
+        assert!(channel_ids(&graph, &carol, &bob).is_empty());
+        graph.remove_node(&bob);
+        assert_eq!(graph.edge_count(), 0);
+    }
+
+    #[test]
+    fn indices_follow_removals() {
+        let json_file = std::path::Path::new("../test_data/trivial_multigraph.json");
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                json_file,
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        let (alice, bob, carol) = ("alice".to_owned(), "bob".to_owned(), "carol".to_owned());
+        for e in graph.edges.values_mut().flatten() {
+            e.capacity = 1000;
+            e.balance = 100;
+        }
+        assert_eq!(graph.get_max_receive_amount(&bob), 3 * 900);
+        // moves the remaining channels of alice and bob to other positions
+        let first = graph.edges[&alice][0].channel_id.clone();
+        let second = graph.edges[&alice][1].channel_id.clone();
+        graph.remove_channel(&first);
+        assert_eq!(graph.get_max_receive_amount(&bob), 2 * 900);
+        graph.update_channel_balance(&alice, &second, 7);
+        assert_eq!(graph.get_channel_balance(&alice, &second), 7);
+        assert_eq!(graph.edges[&alice][0].balance, 7);
+        assert!(graph.channel_can_receive_amount(&alice, &second, 900));
+        assert!(!graph.channel_can_receive_amount(&alice, &first, 1));
+        assert_eq!(graph.get_channel_balance(&alice, &first), 0);
+        graph.remove_node(&alice);
+        assert!(!graph.node_is_in_graph(&alice));
+        assert_eq!(graph.get_node(&carol).unwrap().id, carol);
+        assert_eq!(graph.get_max_receive_amount(&bob), 900);
+        assert_eq!(graph.get_max_receive_amount(&alice), 0);
+    }
 }
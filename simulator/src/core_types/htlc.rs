@@ -0,0 +1,284 @@
+use crate::{graph::Graph, input::CsvRecords, ID};
+use network_parser::Edge;
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+    str::FromStr,
+};
+
+/// Number of HTLCs a node accepts per channel unless configured otherwise, see
+/// [BOLT 2](https://github.com/lightning/bolts/blob/master/02-peer-protocol.md)
+pub static MAX_ACCEPTED_HTLCS: usize = 483;
+
+/// Blocks the HTLC offered to the receiver stays valid, the default of
+/// [BOLT 11](https://github.com/lightning/bolts/blob/master/11-payment-encoding.md)
+pub static MIN_FINAL_CLTV_EXPIRY_DELTA: usize = 18;
+
+/// An HTLC a node has offered over one of its channels
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Htlc {
+    pub source: ID,
+    pub channel_id: String,
+    /// Liquidity the HTLC locks on the channel in msat
+    pub amount: usize,
+    /// Block height at which the HTLC times out on-chain
+    pub expiry: usize,
+}
+
+/// How long a receiver holds on to the HTLCs of a delivered payment before it releases the
+/// preimage
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InvoiceHold {
+    Secs(f32),
+    /// The payment is stuck until its HTLCs expire
+    Forever,
+}
+
+/// Parses a number of seconds or `forever`
+impl FromStr for InvoiceHold {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "forever" => Ok(Self::Forever),
+            _ => match s.parse::<f32>() {
+                Ok(secs) if secs >= 0.0 && secs.is_finite() => Ok(Self::Secs(secs)),
+                _ => Err(format!(
+                    "Invalid invoice hold {}. Expected seconds or forever",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for InvoiceHold {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Secs(secs) => write!(f, "{}", secs),
+            Self::Forever => write!(f, "forever"),
+        }
+    }
+}
+
+/// Receivers that issue hold invoices, see [read_hold_invoices]
+pub type HoldInvoices = HashMap<ID, InvoiceHold>;
+
+/// Reads a CSV file with a `node_id,hold_secs` header and one line per receiver. `hold_secs` is
+/// a number of seconds or `forever`.
+pub fn read_hold_invoices(path: &Path) -> io::Result<HoldInvoices> {
+    hold_invoices_from_reader(BufReader::new(File::open(path)?))
+}
+
+/// See [read_hold_invoices]
+pub fn hold_invoices_from_reader(reader: impl BufRead) -> io::Result<HoldInvoices> {
+    let holds = CsvRecords::new(reader)?.parse("invoice hold", |fields| match fields {
+        [node_id, hold] => Some((node_id.to_string(), hold.parse().ok()?)),
+        _ => None,
+    })?;
+    Ok(holds.into_iter().collect())
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Pending {
+    amount: usize,
+    count: usize,
+}
+
+/// HTLCs that were offered but are neither settled nor failed yet
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InFlightHtlcs {
+    /// Pending HTLCs by channel id and source
+    pending: HashMap<String, HashMap<ID, Pending>>,
+    max_accepted_htlcs: usize,
+    /// Channels that are being closed and accept no new HTLCs
+    shutdown: HashSet<String>,
+}
+
+impl Default for InFlightHtlcs {
+    fn default() -> Self {
+        Self {
+            pending: HashMap::default(),
+            max_accepted_htlcs: MAX_ACCEPTED_HTLCS,
+            shutdown: HashSet::default(),
+        }
+    }
+}
+
+impl InFlightHtlcs {
+    fn get(&self, src: &ID, channel_id: &str) -> Pending {
+        self.pending
+            .get(channel_id)
+            .and_then(|sources| sources.get(src))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Liquidity in msat the source has locked in HTLCs on the channel
+    pub(crate) fn locked(&self, src: &ID, channel_id: &str) -> usize {
+        self.get(src, channel_id).amount
+    }
+
+    fn add(&mut self, htlc: &Htlc) {
+        let pending = self
+            .pending
+            .entry(htlc.channel_id.clone())
+            .or_default()
+            .entry(htlc.source.clone())
+            .or_default();
+        pending.amount += htlc.amount;
+        pending.count += 1;
+    }
+
+    fn remove(&mut self, htlc: &Htlc) {
+        if let Some(sources) = self.pending.get_mut(&htlc.channel_id) {
+            if let Some(pending) = sources.get_mut(&htlc.source) {
+                pending.amount = pending.amount.saturating_sub(htlc.amount);
+                pending.count = pending.count.saturating_sub(1);
+                if pending.count == 0 {
+                    sources.remove(&htlc.source);
+                }
+            }
+            if sources.is_empty() {
+                self.pending.remove(&htlc.channel_id);
+            }
+        }
+    }
+}
+
+impl Graph {
+    /// Applies to every channel, the default is [MAX_ACCEPTED_HTLCS]
+    pub fn set_max_accepted_htlcs(&mut self, max_accepted_htlcs: usize) {
+        self.in_flight.max_accepted_htlcs = max_accepted_htlcs;
+    }
+
+    /// True if the edge's destination accepts another HTLC over it
+    pub(crate) fn htlc_slot_available(&self, edge: &Edge) -> bool {
+        !self.in_flight.shutdown.contains(&edge.channel_id)
+            && self.in_flight.get(&edge.source, &edge.channel_id).count
+                < self.in_flight.max_accepted_htlcs
+    }
+
+    /// The channel accepts no new HTLCs in either direction, or does again if `shutdown` is false
+    pub(crate) fn set_shutdown(&mut self, channel_id: &str, shutdown: bool) {
+        if shutdown {
+            self.in_flight.shutdown.insert(channel_id.to_owned());
+        } else {
+            self.in_flight.shutdown.remove(channel_id);
+        }
+    }
+
+    /// Number of HTLCs in flight over the channel in either direction
+    pub(crate) fn htlcs_over(&self, channel_id: &str) -> usize {
+        self.in_flight
+            .pending
+            .get(channel_id)
+            .map_or(0, |sources| sources.values().map(|p| p.count).sum())
+    }
+
+    /// Locks the HTLC's amount and one slot on its channel until [Graph::remove_htlc]
+    pub(crate) fn add_htlc(&mut self, htlc: &Htlc) {
+        self.in_flight.add(htlc);
+        self.update_liquidity(&htlc.source, &htlc.channel_id);
+    }
+
+    /// Releases what [Graph::add_htlc] locked
+    pub(crate) fn remove_htlc(&mut self, htlc: &Htlc) {
+        self.in_flight.remove(htlc);
+        self.update_liquidity(&htlc.source, &htlc.channel_id);
+    }
+
+    fn update_liquidity(&mut self, src: &ID, channel_id: &str) {
+        let locked = self.in_flight.locked(src, channel_id);
+        if let Some(edge) = self.get_channel_mut(src, channel_id) {
+            edge.liquidity = edge.balance.saturating_sub(locked);
+        }
+    }
+
+    /// Number of HTLCs in flight over all channels
+    pub fn pending_htlcs(&self) -> usize {
+        self.in_flight
+            .pending
+            .values()
+            .flat_map(|sources| sources.values())
+            .map(|p| p.count)
+            .sum()
+    }
+
+    /// Liquidity in msat locked in HTLCs over all channels
+    pub fn locked_in_htlcs(&self) -> usize {
+        self.in_flight
+            .pending
+            .values()
+            .flat_map(|sources| sources.values())
+            .map(|p| p.amount)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::tests::assert_missing_file_fails;
+    use network_parser::GraphSource;
+
+    #[test]
+    fn htlcs_lock_liquidity_and_slots() {
+        let path = Path::new("../test_data/lnbook_example.json");
+        let net_graph =
+            network_parser::Graph::from_json_file(path, GraphSource::Lnresearch).unwrap();
+        let mut graph = Graph::to_sim_graph(&net_graph, GraphSource::Lnresearch);
+        graph.set_max_accepted_htlcs(2);
+        let edge = graph.get_edges()["bob"][0].clone();
+        let htlc = Htlc {
+            source: edge.source.clone(),
+            channel_id: edge.channel_id.clone(),
+            amount: 1000,
+            expiry: 100,
+        };
+        let spendable = graph.spendable_balance(&edge);
+        graph.add_htlc(&htlc);
+        assert!(graph.htlc_slot_available(&edge));
+        graph.add_htlc(&htlc);
+        assert!(!graph.htlc_slot_available(&edge));
+        assert_eq!(graph.pending_htlcs(), 2);
+        assert_eq!(graph.locked_in_htlcs(), 2000);
+        let locked = graph.get_channel(&edge.source, &edge.channel_id).unwrap();
+        assert_eq!(locked.liquidity, edge.balance - 2000);
+        assert_eq!(graph.spendable_balance(locked), spendable - 2000);
+        // copies used for path finding see the HTLCs as well
+        assert!(!graph.clone().htlc_slot_available(&edge));
+        assert_eq!(graph.htlcs_over(&edge.channel_id), 2);
+
+        graph.remove_htlc(&htlc);
+        graph.remove_htlc(&htlc);
+        assert!(graph.htlc_slot_available(&edge));
+        graph.set_shutdown(&edge.channel_id, true);
+        assert!(!graph.htlc_slot_available(&edge));
+        graph.set_shutdown(&edge.channel_id, false);
+        assert_eq!(graph.pending_htlcs(), 0);
+        assert_eq!(graph.locked_in_htlcs(), 0);
+        let released = graph.get_channel(&edge.source, &edge.channel_id).unwrap();
+        assert_eq!(released.liquidity, edge.balance);
+        assert_eq!(graph.spendable_balance(released), spendable);
+    }
+
+    #[test]
+    fn read_invoice_holds() {
+        let csv = "node_id,hold_secs\nalice,60\nbob, forever\n";
+        let actual = hold_invoices_from_reader(csv.as_bytes()).unwrap();
+        let expected = HoldInvoices::from([
+            ("alice".to_owned(), InvoiceHold::Secs(60.0)),
+            ("bob".to_owned(), InvoiceHold::Forever),
+        ]);
+        assert_eq!(actual, expected);
+        assert!(hold_invoices_from_reader("node_id,hold_secs\nalice,-1\n".as_bytes()).is_err());
+        assert!(hold_invoices_from_reader("node_id,hold_secs\nalice,inf\n".as_bytes()).is_err());
+        assert_missing_file_fails(read_hold_invoices);
+    }
+}
@@ -0,0 +1,94 @@
+use std::io::{self, BufRead, Lines};
+
+/// The lines of a CSV input file following its header
+pub(crate) struct CsvRecords<R> {
+    /// The first line of the file, empty if the file is
+    pub(crate) header: String,
+    lines: Lines<R>,
+}
+
+impl<R: BufRead> CsvRecords<R> {
+    /// Reads the header
+    pub(crate) fn new(reader: R) -> io::Result<Self> {
+        let mut lines = reader.lines();
+        let header = lines.next().transpose()?.unwrap_or_default();
+        Ok(Self { header, lines })
+    }
+
+    /// The trimmed columns of the header
+    pub(crate) fn columns(&self) -> Vec<&str> {
+        self.header.split(',').map(str::trim).collect()
+    }
+
+    /// Parses the trimmed fields of every non-empty line after the header. Lines that `parse`
+    /// rejects are reported as invalid `what` together with their line number.
+    pub(crate) fn parse<T>(
+        self,
+        what: &str,
+        mut parse: impl FnMut(&[&str]) -> Option<T>,
+    ) -> io::Result<Vec<T>> {
+        let mut records = vec![];
+        for (idx, line) in self.lines.enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            match parse(&fields) {
+                Some(record) => records.push(record),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Invalid {} in line {}: {}", what, idx + 2, line),
+                    ))
+                }
+            }
+        }
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::{fmt, path::Path, str::FromStr};
+
+    /// Asserts that every spec parses and is displayed the same way again
+    pub(crate) fn assert_round_trip<T>(specs: &[&str])
+    where
+        T: FromStr + fmt::Display,
+        T::Err: fmt::Debug,
+    {
+        for s in specs {
+            let parsed: T = s.parse().unwrap();
+            assert_eq!(parsed.to_string(), *s);
+        }
+    }
+
+    /// Asserts that reading a file that does not exist fails
+    pub(crate) fn assert_missing_file_fails<T>(read: impl FnOnce(&Path) -> io::Result<T>) {
+        assert!(read(Path::new("does_not_exist.csv")).is_err());
+    }
+
+    #[test]
+    fn parse_records() {
+        let csv = "node_id,latency_ms\nalice,50\n\n bob , 12\n";
+        let records = CsvRecords::new(csv.as_bytes()).unwrap();
+        assert_eq!(records.columns(), vec!["node_id", "latency_ms"]);
+        let parse = |fields: &[&str]| match fields {
+            [node_id, millis] => Some((node_id.to_string(), millis.parse::<usize>().ok()?)),
+            _ => None,
+        };
+        assert_eq!(
+            records.parse("latency", parse).unwrap(),
+            vec![("alice".to_owned(), 50), ("bob".to_owned(), 12)]
+        );
+        let csv = "node_id,latency_ms\nalice,50\nbob\n";
+        let err = CsvRecords::new(csv.as_bytes())
+            .unwrap()
+            .parse("latency", parse)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Invalid latency in line 3: bob");
+        assert!(CsvRecords::new("".as_bytes()).unwrap().header.is_empty());
+    }
+}
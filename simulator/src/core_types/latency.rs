@@ -0,0 +1,194 @@
+use crate::{input::CsvRecords, time::Time, PaymentId, ID};
+
+use rand::Rng;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+    str::FromStr,
+};
+
+/// Delay in ms a hop adds when an HTLC update is passed to it
+#[derive(Clone, Debug, PartialEq)]
+pub enum HopLatency {
+    Fixed(f32),
+    /// Each delay in [min, max] is equally likely
+    Uniform {
+        min: f32,
+        max: f32,
+    },
+    Exponential {
+        mean: f32,
+    },
+}
+
+impl HopLatency {
+    pub fn sample_millis(&self, rng: &mut impl Rng) -> f32 {
+        match self {
+            Self::Fixed(millis) => *millis,
+            Self::Uniform { min, max } => min + (max - min) * rng.gen::<f32>(),
+            Self::Exponential { mean } => {
+                let u: f32 = rng.gen();
+                -mean * (1.0 - u).ln()
+            }
+        }
+    }
+}
+
+/// Parses `fixed:<ms>`, `uniform:<min>:<max>` and `exponential:<mean>`
+impl FromStr for HopLatency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let name = parts.next().unwrap_or_default();
+        let params: Vec<f32> = parts
+            .map(|p| {
+                p.parse()
+                    .map_err(|_| format!("Invalid parameter {} in {}", p, s))
+            })
+            .collect::<Result<_, _>>()?;
+        let non_negative = |params: &[f32]| params.iter().all(|p| *p >= 0.0);
+        match (name, params.as_slice()) {
+            ("fixed", [millis]) if non_negative(&params) => Ok(Self::Fixed(*millis)),
+            ("uniform", [min, max]) if non_negative(&params) && min <= max => Ok(Self::Uniform {
+                min: *min,
+                max: *max,
+            }),
+            ("exponential", [mean]) if non_negative(&params) => {
+                Ok(Self::Exponential { mean: *mean })
+            }
+            _ => Err(format!(
+                "Unknown hop latency {}. Expected fixed:<ms>, uniform:<min>:<max> or \
+                exponential:<mean> with non-negative parameters",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for HopLatency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Fixed(millis) => write!(f, "fixed:{}", millis),
+            Self::Uniform { min, max } => write!(f, "uniform:{}:{}", min, max),
+            Self::Exponential { mean } => write!(f, "exponential:{}", mean),
+        }
+    }
+}
+
+/// Delay in ms by node, see [read_node_latencies]
+pub type NodeLatencies = HashMap<ID, f32>;
+
+/// How long it takes to pass an HTLC update to a node
+#[derive(Clone, Debug, PartialEq)]
+pub struct LatencyModel {
+    pub hop_latency: HopLatency,
+    /// Replace the sampled delay for these nodes
+    pub node_latencies: NodeLatencies,
+}
+
+impl LatencyModel {
+    pub(crate) fn delay(&self, node: &ID, rng: &mut impl Rng) -> Time {
+        let millis = match self.node_latencies.get(node) {
+            Some(millis) => *millis,
+            None => self.hop_latency.sample_millis(rng),
+        };
+        Time::from_millis(millis)
+    }
+}
+
+/// The HTLC message a node received
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum HtlcMessage {
+    UpdateAddHtlc,
+    UpdateFulfillHtlc,
+    UpdateFailHtlc,
+}
+
+/// A node receiving an HTLC message of a payment at some simulation time, i.e. what a node
+/// observes while payments are routed through it
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HtlcUpdate {
+    pub payment_id: PaymentId,
+    pub node: ID,
+    pub message: HtlcMessage,
+    pub time_ms: f32,
+}
+
+/// Reads a CSV file with a `node_id,latency_ms` header and one line per node
+pub fn read_node_latencies(path: &Path) -> io::Result<NodeLatencies> {
+    node_latencies_from_reader(BufReader::new(File::open(path)?))
+}
+
+/// See [read_node_latencies]
+pub fn node_latencies_from_reader(reader: impl BufRead) -> io::Result<NodeLatencies> {
+    let latencies = CsvRecords::new(reader)?.parse("node latency", |fields| match fields {
+        [node_id, millis] => {
+            let millis: f32 = millis.parse().ok()?;
+            (millis >= 0.0).then(|| (node_id.to_string(), millis))
+        }
+        _ => None,
+    })?;
+    Ok(latencies.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::tests::{assert_missing_file_fails, assert_round_trip};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn parse_hop_latencies() {
+        assert_round_trip::<HopLatency>(&["fixed:50", "uniform:10:100", "exponential:30"]);
+        assert_eq!(
+            "uniform:10:100".parse::<HopLatency>().unwrap(),
+            HopLatency::Uniform {
+                min: 10.0,
+                max: 100.0
+            }
+        );
+        assert!("fixed".parse::<HopLatency>().is_err());
+        assert!("fixed:-1".parse::<HopLatency>().is_err());
+        assert!("uniform:100:10".parse::<HopLatency>().is_err());
+        assert!("normal:10".parse::<HopLatency>().is_err());
+    }
+
+    #[test]
+    fn sample_delays() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let model = LatencyModel {
+            hop_latency: HopLatency::Uniform {
+                min: 10.0,
+                max: 20.0,
+            },
+            node_latencies: NodeLatencies::from([("bob".to_owned(), 100.0)]),
+        };
+        for _ in 0..100 {
+            let delay = model.delay(&"alice".to_owned(), &mut rng);
+            assert!(delay >= Time::from_millis(10.0) && delay <= Time::from_millis(20.0));
+        }
+        assert_eq!(
+            model.delay(&"bob".to_owned(), &mut rng),
+            Time::from_millis(100.0)
+        );
+        let mean = HopLatency::Exponential { mean: 30.0 };
+        let total: f32 = (0..10000).map(|_| mean.sample_millis(&mut rng)).sum();
+        assert!((total / 10000.0 - 30.0).abs() < 3.0);
+    }
+
+    #[test]
+    fn read_latencies() {
+        let csv = "node_id,latency_ms\nalice,50\nbob, 12.5\n";
+        let actual = node_latencies_from_reader(csv.as_bytes()).unwrap();
+        let expected = NodeLatencies::from([("alice".to_owned(), 50.0), ("bob".to_owned(), 12.5)]);
+        assert_eq!(actual, expected);
+        let csv = "node_id,latency_ms\nalice,-50\n";
+        assert!(node_latencies_from_reader(csv.as_bytes()).is_err());
+        assert_missing_file_fails(read_node_latencies);
+    }
+}
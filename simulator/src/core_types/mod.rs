@@ -1,3 +1,15 @@
+pub mod amounts;
+pub mod arrival;
+pub mod balances;
 pub(crate) mod event;
+pub mod generator;
 pub mod graph;
+pub mod htlc;
+pub(crate) mod input;
+pub mod latency;
+pub mod preprocessing;
+pub mod reserve;
 pub(crate) mod time;
+pub mod topology;
+pub mod trace;
+pub mod workload;
@@ -0,0 +1,256 @@
+use crate::{graph::Graph, ID};
+use network_parser::Edge;
+
+use std::{collections::HashSet, fmt, str::FromStr};
+
+/// A step that removes parts of the graph before balances are assigned
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// Keep only the greatest strongly connected component
+    GreatestScc,
+    /// Remove channels that are only announced in one direction
+    RemoveUnidirectional,
+    /// Remove channels with less capacity in msat. lnresearch channels without a funding amount
+    /// use htlc_maximum_msat.
+    MinCapacity(usize),
+    /// Remove nodes with fewer outgoing edges in a single pass, so nodes may drop below the
+    /// threshold afterwards
+    MinDegree(usize),
+    /// Remove channel directions their source has disabled
+    RemoveDisabled,
+}
+
+impl Stage {
+    /// The steps [Graph::to_sim_graph] always applied
+    pub fn default_pipeline() -> Vec<Stage> {
+        vec![Stage::GreatestScc, Stage::RemoveUnidirectional]
+    }
+}
+
+/// Parses `scc`, `bidirectional`, `min-capacity:<sat>`, `min-degree:<k>` and `no-disabled`
+impl FromStr for Stage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, param) = match s.split_once(':') {
+            Some((name, param)) => (name, Some(param)),
+            None => (s, None),
+        };
+        let number = |param: &str| -> Result<usize, String> {
+            param
+                .parse()
+                .map_err(|_| format!("Invalid parameter {} in {}", param, s))
+        };
+        match (name, param) {
+            ("scc", None) => Ok(Self::GreatestScc),
+            ("bidirectional", None) => Ok(Self::RemoveUnidirectional),
+            ("min-capacity", Some(sat)) => {
+                Ok(Self::MinCapacity(crate::to_millisatoshi(number(sat)?)))
+            }
+            ("min-degree", Some(k)) => Ok(Self::MinDegree(number(k)?)),
+            ("no-disabled", None) => Ok(Self::RemoveDisabled),
+            _ => Err(format!(
+                "Unknown preprocessing stage {}. Expected scc, bidirectional, \
+                min-capacity:<sat>, min-degree:<k> or no-disabled",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::GreatestScc => write!(f, "scc"),
+            Self::RemoveUnidirectional => write!(f, "bidirectional"),
+            Self::MinCapacity(msat) => write!(f, "min-capacity:{}", crate::to_sat(*msat)),
+            Self::MinDegree(k) => write!(f, "min-degree:{}", k),
+            Self::RemoveDisabled => write!(f, "no-disabled"),
+        }
+    }
+}
+
+/// What a [Stage] removed and what was left afterwards
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StageSummary {
+    pub stage: Stage,
+    pub removed_nodes: usize,
+    pub removed_edges: usize,
+    pub nodes: usize,
+    pub edges: usize,
+}
+
+impl fmt::Display for StageSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: removed {} nodes and {} edges, {} nodes and {} edges left",
+            self.stage, self.removed_nodes, self.removed_edges, self.nodes, self.edges
+        )
+    }
+}
+
+impl Graph {
+    /// Applies the stages in the given order, e.g. [Stage::default_pipeline]
+    pub fn preprocess(&mut self, stages: &[Stage]) -> Vec<StageSummary> {
+        let mut summaries = vec![];
+        for stage in stages {
+            let (nodes, edges) = (self.node_count(), self.edge_count());
+            match stage {
+                Stage::GreatestScc => *self = self.reduce_to_greatest_scc(),
+                Stage::RemoveUnidirectional => *self = self.remove_unidrectional_edges(),
+                Stage::MinCapacity(min_capacity) => self.remove_small_channels(*min_capacity),
+                Stage::MinDegree(min_degree) => self.remove_low_degree_nodes(*min_degree),
+                Stage::RemoveDisabled => self.remove_disabled_edges(),
+            }
+            summaries.push(StageSummary {
+                stage: stage.clone(),
+                removed_nodes: nodes - self.node_count(),
+                removed_edges: edges - self.edge_count(),
+                nodes: self.node_count(),
+                edges: self.edge_count(),
+            });
+        }
+        summaries
+    }
+
+    fn remove_small_channels(&mut self, min_capacity: usize) {
+        let capacity = |e: &Edge| {
+            if e.capacity == 0 {
+                e.htlc_maximum_msat
+            } else {
+                e.capacity
+            }
+        };
+        let small_channels: Vec<(ID, String)> = self
+            .edges
+            .iter()
+            .flat_map(|(src, edges)| edges.iter().map(move |e| (src, e)))
+            .filter(|(_, e)| capacity(e) < min_capacity)
+            .map(|(src, e)| (src.clone(), e.channel_id.clone()))
+            .collect();
+        // the reverse direction may already be gone
+        for (src, channel_id) in small_channels {
+            self.remove_edge(&src, &channel_id);
+        }
+    }
+
+    fn remove_low_degree_nodes(&mut self, min_degree: usize) {
+        let low_degree: HashSet<ID> = self
+            .nodes
+            .iter()
+            .filter(|n| self.edges.get(&n.id).map_or(0, Vec::len) < min_degree)
+            .map(|n| n.id.clone())
+            .collect();
+        *self = self.subgraph(|node| !low_degree.contains(node));
+    }
+
+    fn remove_disabled_edges(&mut self) {
+        let sources: Vec<ID> = self.edges.keys().cloned().collect();
+        for src in sources {
+            self.remove_out_edges(&src, |e| e.disabled);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::tests::assert_round_trip;
+    use network_parser::GraphSource;
+    use std::path::Path;
+
+    fn multigraph() -> Graph {
+        let path = Path::new("../test_data/trivial_multigraph.json");
+        let net_graph =
+            network_parser::Graph::from_json_file(path, GraphSource::Lnresearch).unwrap();
+        Graph::from_net_graph(&net_graph)
+    }
+
+    #[test]
+    fn parse_stages() {
+        assert_round_trip::<Stage>(&[
+            "scc",
+            "bidirectional",
+            "min-capacity:1000",
+            "min-degree:2",
+            "no-disabled",
+        ]);
+        assert_eq!(
+            "min-capacity:1".parse::<Stage>().unwrap(),
+            Stage::MinCapacity(1000)
+        );
+        assert!("min-degree".parse::<Stage>().is_err());
+        assert!("min-degree:x".parse::<Stage>().is_err());
+        assert!("scc:1".parse::<Stage>().is_err());
+        assert!("max-degree:2".parse::<Stage>().is_err());
+    }
+
+    #[test]
+    fn default_pipeline_matches_to_sim_graph() {
+        let path = Path::new("../test_data/lnbook_example.json");
+        let net_graph =
+            network_parser::Graph::from_json_file(path, GraphSource::Lnresearch).unwrap();
+        let expected = Graph::to_sim_graph(&net_graph, GraphSource::Lnresearch);
+        let mut graph = Graph::from_net_graph(&net_graph);
+        let (nodes, edges) = (graph.node_count(), graph.edge_count());
+        let summaries = graph.preprocess(&Stage::default_pipeline());
+        assert_eq!(graph.node_count(), expected.node_count());
+        assert_eq!(graph.edge_count(), expected.edge_count());
+        assert_eq!(summaries.len(), 2);
+        let removed_edges: usize = summaries.iter().map(|s| s.removed_edges).sum();
+        assert_eq!(removed_edges, edges - graph.edge_count());
+        let removed_nodes: usize = summaries.iter().map(|s| s.removed_nodes).sum();
+        assert_eq!(removed_nodes, nodes - graph.node_count());
+        assert_eq!(summaries[1].edges, graph.edge_count());
+    }
+
+    #[test]
+    fn keep_unidirectional_channels() {
+        let mut graph = multigraph();
+        let summaries = graph.preprocess(&[Stage::GreatestScc]);
+        assert_eq!(summaries[0].removed_edges, 0);
+        assert_eq!(graph.edge_count(), 7);
+        graph.set_channel_balances(GraphSource::Lnresearch, &Default::default());
+        let unidirectional = graph.get_edges()["bob"]
+            .iter()
+            .find(|e| e.channel_id == "100x5x0/0")
+            .unwrap();
+        assert_eq!(unidirectional.capacity, 500000);
+        assert!(unidirectional.balance <= unidirectional.capacity);
+
+        let summaries = graph.preprocess(&[Stage::RemoveUnidirectional]);
+        assert_eq!(summaries[0].removed_edges, 1);
+        assert_eq!(summaries[0].removed_nodes, 0);
+    }
+
+    #[test]
+    fn filter_stages() {
+        let mut graph = multigraph();
+        // only 100x2x0 has less than 100 sat
+        let summaries = graph.preprocess(&[Stage::MinCapacity(100000)]);
+        assert_eq!(summaries[0].removed_edges, 2);
+        assert!(graph
+            .get_edges()
+            .values()
+            .flatten()
+            .all(|e| !e.channel_id.starts_with("100x2x0")));
+
+        let mut graph = multigraph();
+        // carol has a single outgoing edge
+        let summaries = graph.preprocess(&[Stage::MinDegree(2)]);
+        assert_eq!(summaries[0].removed_nodes, 1);
+        assert_eq!(summaries[0].removed_edges, 3);
+        assert!(!graph.node_is_in_graph(&"carol".to_owned()));
+
+        let mut graph = multigraph();
+        graph.edges.get_mut("alice").unwrap()[0].disabled = true;
+        let summaries = graph.preprocess(&[Stage::RemoveDisabled, Stage::RemoveUnidirectional]);
+        assert_eq!(summaries[0].removed_edges, 1);
+        assert_eq!(summaries[1].removed_edges, 2);
+        assert_eq!(
+            summaries[1].to_string(),
+            "bidirectional: removed 0 nodes and 2 edges, 3 nodes and 4 edges left"
+        );
+    }
+}
@@ -0,0 +1,118 @@
+use crate::input::CsvRecords;
+use network_parser::Edge;
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+};
+
+/// Reserve and dust limit of one channel in msat
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChannelLimits {
+    /// Balance each side has to keep and cannot spend
+    pub reserve_msat: usize,
+    /// HTLCs below this amount cannot be created
+    pub dust_limit_msat: usize,
+}
+
+/// Limits by channel id without a direction suffix, see [read_channel_limits]
+pub type ChannelLimitOverrides = HashMap<String, ChannelLimits>;
+
+/// Global defaults applied to every channel unless it has an override.
+/// The default keeps no reserve and has no dust limit.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChannelReserves {
+    /// Share of the capacity each side keeps as reserve, typically 0.01
+    pub reserve_share: f64,
+    pub dust_limit_msat: usize,
+    pub overrides: ChannelLimitOverrides,
+}
+
+impl ChannelReserves {
+    /// The edge's limits; the reserve is the same for both directions of a channel
+    pub fn limits(&self, edge: &Edge) -> ChannelLimits {
+        match self.overrides.get(edge.short_channel_id()) {
+            Some(limits) => *limits,
+            None => ChannelLimits {
+                reserve_msat: (self.reserve_share * edge.capacity as f64).round() as usize,
+                dust_limit_msat: self.dust_limit_msat,
+            },
+        }
+    }
+}
+
+/// Reads a CSV file with a `short_channel_id,reserve_sat,dust_limit_sat` header and one line
+/// per channel
+pub fn read_channel_limits(path: &Path) -> io::Result<ChannelLimitOverrides> {
+    channel_limits_from_reader(BufReader::new(File::open(path)?))
+}
+
+/// See [read_channel_limits]
+pub fn channel_limits_from_reader(reader: impl BufRead) -> io::Result<ChannelLimitOverrides> {
+    let limits = CsvRecords::new(reader)?.parse("channel limits", |fields| match fields {
+        [channel_id, reserve, dust_limit] => Some((
+            channel_id.to_string(),
+            ChannelLimits {
+                reserve_msat: crate::to_millisatoshi(reserve.parse().ok()?),
+                dust_limit_msat: crate::to_millisatoshi(dust_limit.parse().ok()?),
+            },
+        )),
+        _ => None,
+    })?;
+    Ok(limits.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::tests::assert_missing_file_fails;
+
+    #[test]
+    fn read_limits() {
+        let csv = "short_channel_id,reserve_sat,dust_limit_sat\n100x1x0,1000,354\n";
+        let actual = channel_limits_from_reader(csv.as_bytes()).unwrap();
+        let expected = ChannelLimitOverrides::from([(
+            "100x1x0".to_owned(),
+            ChannelLimits {
+                reserve_msat: 1000000,
+                dust_limit_msat: 354000,
+            },
+        )]);
+        assert_eq!(actual, expected);
+        let csv = "short_channel_id,reserve_sat,dust_limit_sat\n100x1x0,1000\n";
+        assert!(channel_limits_from_reader(csv.as_bytes()).is_err());
+        assert_missing_file_fails(read_channel_limits);
+    }
+
+    #[test]
+    fn overrides_replace_defaults() {
+        let edge = |channel_id: &str| Edge {
+            channel_id: channel_id.to_owned(),
+            capacity: 500000,
+            ..Default::default()
+        };
+        let reserves = ChannelReserves {
+            reserve_share: 0.01,
+            dust_limit_msat: 354000,
+            overrides: ChannelLimitOverrides::from([(
+                "100x1x0".to_owned(),
+                ChannelLimits::default(),
+            )]),
+        };
+        assert_eq!(
+            reserves.limits(&edge("100x1x0/1")),
+            ChannelLimits::default()
+        );
+        let expected = ChannelLimits {
+            reserve_msat: 5000,
+            dust_limit_msat: 354000,
+        };
+        assert_eq!(reserves.limits(&edge("100x2x0/1")), expected);
+        assert_eq!(
+            ChannelReserves::default().limits(&edge("100x2x0/1")),
+            ChannelLimits::default()
+        );
+    }
+}
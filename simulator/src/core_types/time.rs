@@ -4,11 +4,13 @@ use std::ops::{Add, AddAssign, Sub};
 
 use std::fmt;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Default)]
 pub struct Time(u64);
 
 static SIMTIME_SCALING_FACTOR_SECS: f32 = 1000000.0; // in nano secs.
 static SIMTIME_SCALING_FACTOR_MILLIS: f32 = 1000.0;
+/// Expected time between two blocks
+pub static BLOCK_INTERVAL_IN_SECS: u64 = 600;
 
 impl Time {
     pub(crate) fn as_secs(&self) -> f32 {
@@ -39,6 +41,16 @@ impl Time {
     pub fn from_nanos(nanos: f32) -> Self {
         Time(nanos as u64)
     }
+
+    /// Time at which the given number of blocks were mined, one every [BLOCK_INTERVAL_IN_SECS]
+    pub fn from_blocks(blocks: usize) -> Self {
+        Time(blocks as u64 * BLOCK_INTERVAL_IN_SECS * SIMTIME_SCALING_FACTOR_SECS as u64)
+    }
+
+    /// Number of blocks mined by this time
+    pub(crate) fn as_blocks(&self) -> usize {
+        (self.0 / (BLOCK_INTERVAL_IN_SECS * SIMTIME_SCALING_FACTOR_SECS as u64)) as usize
+    }
 }
 
 impl Ord for Time {
@@ -105,6 +117,13 @@ mod tests {
         assert_eq!(s0, s1);
     }
 
+    #[test]
+    fn block_conversion() {
+        assert_eq!(Time::from_blocks(2), Time::from_secs(1200.0));
+        assert_eq!(Time::from_secs(1199.0).as_blocks(), 1);
+        assert_eq!(Time::from_blocks(144).as_blocks(), 144);
+    }
+
     #[test]
     fn cmp_time() {
         let smaller_millis = 1000.0;
@@ -0,0 +1,419 @@
+use crate::{arrival::sample_exponential, time::Time, Edge, ID};
+
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+    str::FromStr,
+};
+
+/// What a channel update announces for one direction of a channel
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ChannelPolicy {
+    pub fee_base_msat: usize,
+    pub fee_proportional_millionths: usize,
+    pub cltv_expiry_delta: usize,
+    pub htlc_minimum_msat: usize,
+    /// The channel's capacity if not set
+    pub htlc_maximum_msat: Option<usize>,
+}
+
+impl Default for ChannelPolicy {
+    fn default() -> Self {
+        Self {
+            fee_base_msat: 1000,
+            fee_proportional_millionths: 1,
+            cltv_expiry_delta: 40,
+            htlc_minimum_msat: 1000,
+            htlc_maximum_msat: None,
+        }
+    }
+}
+
+impl ChannelPolicy {
+    fn of(edge: &Edge) -> Self {
+        Self {
+            fee_base_msat: edge.fee_base_msat,
+            fee_proportional_millionths: edge.fee_proportional_millionths,
+            cltv_expiry_delta: edge.cltv_expiry_delta,
+            htlc_minimum_msat: edge.htlc_minimim_msat,
+            htlc_maximum_msat: Some(edge.htlc_maximum_msat),
+        }
+    }
+
+    pub(crate) fn apply(&self, edge: &mut Edge) {
+        edge.fee_base_msat = self.fee_base_msat;
+        edge.fee_proportional_millionths = self.fee_proportional_millionths;
+        edge.cltv_expiry_delta = self.cltv_expiry_delta;
+        edge.htlc_minimim_msat = self.htlc_minimum_msat;
+        edge.htlc_maximum_msat = self.htlc_maximum_msat.unwrap_or(edge.capacity);
+    }
+}
+
+/// A change to the graph during a simulation
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TopologyChange {
+    /// node1 funds the channel and pushes `push_msat` to node2. Unknown nodes are added.
+    OpenChannel {
+        channel_id: String,
+        node1: ID,
+        node2: ID,
+        capacity_msat: usize,
+        #[serde(default)]
+        push_msat: usize,
+        #[serde(default)]
+        node1_policy: ChannelPolicy,
+        #[serde(default)]
+        node2_policy: ChannelPolicy,
+    },
+    /// The channel stops forwarding and is removed once none of its HTLCs are in flight
+    CloseChannel {
+        channel_id: String,
+    },
+    /// The channel is removed immediately, its HTLCs in flight are resolved on-chain
+    ForceCloseChannel {
+        channel_id: String,
+    },
+    /// The source's direction of the channel uses the new policy
+    UpdatePolicy {
+        channel_id: String,
+        source: ID,
+        #[serde(flatten)]
+        policy: ChannelPolicy,
+    },
+    /// The node's channels cannot be used until it is online again
+    NodeOffline {
+        node: ID,
+    },
+    NodeOnline {
+        node: ID,
+    },
+}
+
+impl TopologyChange {
+    /// Both directions of an opened channel
+    pub(crate) fn channel_edges(&self) -> Option<Vec<Edge>> {
+        let Self::OpenChannel {
+            channel_id,
+            node1,
+            node2,
+            capacity_msat,
+            push_msat,
+            node1_policy,
+            node2_policy,
+        } = self
+        else {
+            return None;
+        };
+        let push_msat = (*push_msat).min(*capacity_msat);
+        let edge = |source: &ID, destination: &ID, balance: usize, policy: &ChannelPolicy| {
+            let mut edge = Edge {
+                channel_id: channel_id.clone(),
+                source: source.clone(),
+                destination: destination.clone(),
+                capacity: *capacity_msat,
+                balance,
+                liquidity: balance,
+                ..Default::default()
+            };
+            policy.apply(&mut edge);
+            edge
+        };
+        Some(vec![
+            edge(node1, node2, capacity_msat - push_msat, node1_policy),
+            edge(node2, node1, push_msat, node2_policy),
+        ])
+    }
+}
+
+impl fmt::Display for TopologyChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::OpenChannel {
+                channel_id,
+                node1,
+                node2,
+                ..
+            } => write!(f, "open {} between {} and {}", channel_id, node1, node2),
+            Self::CloseChannel { channel_id } => write!(f, "close {}", channel_id),
+            Self::ForceCloseChannel { channel_id } => write!(f, "force close {}", channel_id),
+            Self::UpdatePolicy {
+                channel_id, source, ..
+            } => write!(f, "update {}'s policy of {}", source, channel_id),
+            Self::NodeOffline { node } => write!(f, "{} goes offline", node),
+            Self::NodeOnline { node } => write!(f, "{} comes online", node),
+        }
+    }
+}
+
+/// A change and the second of the simulation it happens at
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct TopologyEvent {
+    pub time_secs: f64,
+    #[serde(flatten)]
+    pub change: TopologyChange,
+}
+
+impl TopologyEvent {
+    pub(crate) fn time(&self) -> Time {
+        Time::from_secs(self.time_secs as f32)
+    }
+}
+
+/// Reads a file with one JSON object per line, e.g.
+/// `{"time_secs": 600, "type": "close_channel", "channel_id": "123x1x0"}`. Empty lines are
+/// skipped.
+pub fn read_topology_events(path: &Path) -> io::Result<Vec<TopologyEvent>> {
+    topology_events_from_reader(BufReader::new(File::open(path)?))
+}
+
+/// See [read_topology_events]
+pub fn topology_events_from_reader(reader: impl BufRead) -> io::Result<Vec<TopologyEvent>> {
+    let mut events = vec![];
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event: TopologyEvent = serde_json::from_str(&line).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid topology event in line {}: {}", idx + 1, e),
+            )
+        })?;
+        if event.time_secs < 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Negative time in line {}", idx + 1),
+            ));
+        }
+        events.push(event);
+    }
+    Ok(events)
+}
+
+/// Random topology changes, each kind as a Poisson process with a rate per second
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChurnProcess {
+    /// Cooperative closes of random channels
+    pub close_rate: f64,
+    /// Channels between random nodes, copying the capacity and policies of a random channel
+    pub open_rate: f64,
+    /// Random nodes going offline
+    pub offline_rate: f64,
+    /// How long a node stays offline
+    pub offline_secs: f64,
+}
+
+impl ChurnProcess {
+    /// Changes to the channels and nodes of the given edges until the horizon, in ascending
+    /// order of time
+    pub(crate) fn events(
+        &self,
+        edges: &[&Edge],
+        horizon_secs: f64,
+        rng: &mut impl Rng,
+    ) -> Vec<TopologyEvent> {
+        let mut nodes: Vec<&ID> = edges.iter().map(|e| &e.source).collect();
+        nodes.sort();
+        nodes.dedup();
+        let mut events = vec![];
+        if edges.is_empty() || nodes.len() < 2 {
+            return events;
+        }
+        for time_secs in Self::times(self.close_rate, horizon_secs, rng) {
+            let channel_id = edges.choose(rng).unwrap().channel_id.clone();
+            events.push(TopologyEvent {
+                time_secs,
+                change: TopologyChange::CloseChannel { channel_id },
+            });
+        }
+        for (idx, time_secs) in Self::times(self.open_rate, horizon_secs, rng)
+            .into_iter()
+            .enumerate()
+        {
+            let mut pair = nodes.choose_multiple(rng, 2);
+            let (node1, node2) = (pair.next().unwrap(), pair.next().unwrap());
+            let template = edges.choose(rng).unwrap();
+            let policy = ChannelPolicy::of(template);
+            events.push(TopologyEvent {
+                time_secs,
+                change: TopologyChange::OpenChannel {
+                    channel_id: format!("churn{}", idx),
+                    node1: node1.to_string(),
+                    node2: node2.to_string(),
+                    capacity_msat: template.capacity,
+                    push_msat: 0,
+                    node1_policy: policy.clone(),
+                    node2_policy: policy,
+                },
+            });
+        }
+        for time_secs in Self::times(self.offline_rate, horizon_secs, rng) {
+            let node = nodes.choose(rng).unwrap().to_string();
+            events.push(TopologyEvent {
+                time_secs,
+                change: TopologyChange::NodeOffline { node: node.clone() },
+            });
+            events.push(TopologyEvent {
+                time_secs: time_secs + self.offline_secs,
+                change: TopologyChange::NodeOnline { node },
+            });
+        }
+        events.sort_by(|a, b| a.time_secs.total_cmp(&b.time_secs));
+        events
+    }
+
+    fn times(rate: f64, horizon_secs: f64, rng: &mut impl Rng) -> Vec<f64> {
+        let mut times = vec![];
+        if rate <= 0.0 {
+            return times;
+        }
+        let mut now = sample_exponential(rate, rng);
+        while now < horizon_secs {
+            times.push(now);
+            now += sample_exponential(rate, rng);
+        }
+        times
+    }
+}
+
+/// Parses `<close_rate>:<open_rate>:<offline_rate>:<offline_secs>`
+impl FromStr for ChurnProcess {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let params: Vec<f64> = s
+            .split(':')
+            .map(|p| {
+                p.parse()
+                    .map_err(|_| format!("Invalid parameter {} in {}", p, s))
+            })
+            .collect::<Result<_, _>>()?;
+        match params.as_slice() {
+            [close_rate, open_rate, offline_rate, offline_secs]
+                if params.iter().all(|p| *p >= 0.0 && p.is_finite()) =>
+            {
+                Ok(Self {
+                    close_rate: *close_rate,
+                    open_rate: *open_rate,
+                    offline_rate: *offline_rate,
+                    offline_secs: *offline_secs,
+                })
+            }
+            _ => Err(format!(
+                "Invalid churn {}. Expected <close_rate>:<open_rate>:<offline_rate>:<offline_secs> \
+                with non-negative parameters",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ChurnProcess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}:{}",
+            self.close_rate, self.open_rate, self.offline_rate, self.offline_secs
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn read_events() {
+        let jsonl = r#"{"time_secs": 60, "type": "open_channel", "channel_id": "new", "node1": "alice", "node2": "erin", "capacity_msat": 100000, "push_msat": 40000, "node2_policy": {"fee_base_msat": 0}}
+
+{"time_secs": 120.5, "type": "update_policy", "channel_id": "new", "source": "alice", "fee_proportional_millionths": 100, "cltv_expiry_delta": 144}
+{"time_secs": 600, "type": "force_close_channel", "channel_id": "new"}
+{"time_secs": 900, "type": "node_offline", "node": "bob"}
+"#;
+        let events = topology_events_from_reader(jsonl.as_bytes()).unwrap();
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[1].time_secs, 120.5);
+        assert_eq!(
+            events[1].change,
+            TopologyChange::UpdatePolicy {
+                channel_id: "new".to_owned(),
+                source: "alice".to_owned(),
+                policy: ChannelPolicy {
+                    fee_proportional_millionths: 100,
+                    cltv_expiry_delta: 144,
+                    ..Default::default()
+                },
+            }
+        );
+        let edges = events[0].change.channel_edges().unwrap();
+        assert_eq!((edges[0].balance, edges[1].balance), (60000, 40000));
+        assert_eq!(edges[0].fee_base_msat, 1000);
+        assert_eq!(edges[1].fee_base_msat, 0);
+        assert_eq!(edges[1].htlc_maximum_msat, 100000);
+        assert_eq!(edges[1].source, "erin");
+        assert!(events[2].change.channel_edges().is_none());
+
+        assert!(topology_events_from_reader(r#"{"type": "node_offline"}"#.as_bytes()).is_err());
+        let negative = r#"{"time_secs": -1, "type": "node_online", "node": "bob"}"#;
+        assert!(topology_events_from_reader(negative.as_bytes()).is_err());
+        assert!(read_topology_events(Path::new("does_not_exist.jsonl")).is_err());
+    }
+
+    #[test]
+    fn parse_churn() {
+        let churn: ChurnProcess = "0.01:0.02:0.001:3600".parse().unwrap();
+        assert_eq!(churn.offline_secs, 3600.0);
+        assert_eq!(churn.to_string(), "0.01:0.02:0.001:3600");
+        assert!("0.01:0.02:0.001".parse::<ChurnProcess>().is_err());
+        assert!("0.01:-0.02:0.001:60".parse::<ChurnProcess>().is_err());
+    }
+
+    #[test]
+    fn churn_events() {
+        let edge = |channel_id: &str, source: &str, destination: &str| Edge {
+            channel_id: channel_id.to_owned(),
+            source: source.to_owned(),
+            destination: destination.to_owned(),
+            capacity: 5000,
+            ..Default::default()
+        };
+        let edges = [
+            edge("ab", "a", "b"),
+            edge("ab", "b", "a"),
+            edge("bc", "b", "c"),
+        ];
+        let edges: Vec<&Edge> = edges.iter().collect();
+        let churn = ChurnProcess {
+            close_rate: 0.01,
+            open_rate: 0.01,
+            offline_rate: 0.01,
+            offline_secs: 60.0,
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let events = churn.events(&edges, 10000.0, &mut rng);
+        assert!(events.windows(2).all(|w| w[0].time_secs <= w[1].time_secs));
+        let count = |f: fn(&TopologyChange) -> bool| events.iter().filter(|e| f(&e.change)).count();
+        let offline = count(|c| matches!(c, TopologyChange::NodeOffline { .. }));
+        assert!(offline > 50 && offline < 150);
+        assert_eq!(
+            offline,
+            count(|c| matches!(c, TopologyChange::NodeOnline { .. }))
+        );
+        assert!(count(|c| matches!(c, TopologyChange::CloseChannel { .. })) > 50);
+        for event in events.iter() {
+            if let Some(opened) = event.change.channel_edges() {
+                assert_ne!(opened[0].source, opened[1].source);
+                assert_eq!(opened[0].capacity, 5000);
+            }
+        }
+        assert!(churn.events(&[], 10000.0, &mut rng).is_empty());
+    }
+}
@@ -0,0 +1,266 @@
+use crate::{
+    balances::ChannelBalances, htlc::Htlc, payment::FailureReason, topology::TopologyChange,
+};
+use crate::{PaymentId, ID};
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+/// One line of an event trace. Every record carries the simulation time and the payment the
+/// simulation was processing, if any.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TraceRecord {
+    /// The balances and the HTLCs in flight before the first event
+    Start {
+        balances: Vec<ChannelBalance>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        htlcs: Vec<InFlightHtlc>,
+    },
+    /// An event the simulation processed
+    Event {
+        time_ms: f32,
+        payment_id: Option<PaymentId>,
+        event: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        change: Option<TopologyChange>,
+    },
+    /// A path the payment or one of its shards was sent along
+    Attempt {
+        time_ms: f32,
+        payment_id: Option<PaymentId>,
+        amount_msat: usize,
+        hops: Vec<HopTrace>,
+    },
+    /// The balance of a channel direction was set
+    Balance {
+        time_ms: f32,
+        payment_id: Option<PaymentId>,
+        channel_id: String,
+        source: ID,
+        balance_msat: usize,
+    },
+    /// The balances after the last event
+    End {
+        time_ms: f32,
+        balances: Vec<ChannelBalance>,
+    },
+}
+
+/// A channel direction's balance
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub struct ChannelBalance {
+    pub channel_id: String,
+    pub source: ID,
+    pub balance_msat: usize,
+}
+
+impl ChannelBalance {
+    /// Sorted so that traces of the same run are equal
+    pub(crate) fn from_balances(balances: ChannelBalances) -> Vec<Self> {
+        let mut balances: Vec<Self> = balances
+            .into_iter()
+            .map(|((channel_id, source), balance_msat)| Self {
+                channel_id,
+                source,
+                balance_msat,
+            })
+            .collect();
+        balances.sort();
+        balances
+    }
+}
+
+/// An HTLC of a payment that was still in flight
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct InFlightHtlc {
+    pub payment_id: PaymentId,
+    #[serde(flatten)]
+    pub htlc: Htlc,
+}
+
+/// How far an attempt got at a hop
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HopOutcome {
+    /// The node passed the amount on or, if it is the receiver, accepted it
+    Forwarded,
+    /// The attempt failed at the node
+    Failed,
+    /// The attempt failed before it reached the node
+    NotReached,
+}
+
+/// A node on an attempted path and the channel it forwards over
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct HopTrace {
+    pub node: ID,
+    pub channel_id: String,
+    pub outcome: HopOutcome,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure_reason: Option<FailureReason>,
+}
+
+/// The records of a run in the order they occurred
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EventTrace {
+    pub records: Vec<TraceRecord>,
+    /// The payment whose event is processed, attached to the records it causes
+    pub(crate) payment_id: Option<PaymentId>,
+}
+
+impl EventTrace {
+    /// The balances recorded at the start and the end of the trace
+    pub fn start_and_end_balances(
+        records: &[TraceRecord],
+    ) -> (Option<ChannelBalances>, Option<ChannelBalances>) {
+        let to_map = |balances: &[ChannelBalance]| {
+            balances
+                .iter()
+                .map(|b| ((b.channel_id.clone(), b.source.clone()), b.balance_msat))
+                .collect()
+        };
+        let mut start = None;
+        let mut end = None;
+        for record in records {
+            match record {
+                TraceRecord::Start { balances, .. } => start = Some(to_map(balances)),
+                TraceRecord::End { balances, .. } => end = Some(to_map(balances)),
+                _ => {}
+            }
+        }
+        (start, end)
+    }
+}
+
+/// A channel direction whose balance after replaying a trace differs from the recorded one.
+/// None if the channel is missing on that side.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BalanceMismatch {
+    pub channel_id: String,
+    pub source: ID,
+    pub recorded: Option<usize>,
+    pub replayed: Option<usize>,
+}
+
+/// Writes one JSON object per line
+pub fn write_trace(path: &Path, records: &[TraceRecord]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    trace_to_writer(&mut writer, records)?;
+    writer.flush()
+}
+
+/// See [write_trace]
+pub fn trace_to_writer(mut writer: impl Write, records: &[TraceRecord]) -> io::Result<()> {
+    for record in records {
+        serde_json::to_writer(&mut writer, record)?;
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// Reads a trace written by [write_trace]. Empty lines are skipped.
+pub fn read_trace(path: &Path) -> io::Result<Vec<TraceRecord>> {
+    trace_from_reader(BufReader::new(File::open(path)?))
+}
+
+/// See [read_trace]
+pub fn trace_from_reader(reader: impl BufRead) -> io::Result<Vec<TraceRecord>> {
+    let mut records = vec![];
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid trace record in line {}: {}", idx + 1, e),
+            )
+        })?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trace_round_trip() {
+        let balances = ChannelBalance::from_balances(ChannelBalances::from([
+            (("c2".to_owned(), "bob".to_owned()), 20),
+            (("c1".to_owned(), "alice".to_owned()), 10),
+        ]));
+        assert_eq!(balances[0].channel_id, "c1");
+        let records = vec![
+            TraceRecord::Start {
+                balances: balances.clone(),
+                htlcs: vec![InFlightHtlc {
+                    payment_id: 3,
+                    htlc: Htlc {
+                        source: "alice".to_owned(),
+                        channel_id: "c1".to_owned(),
+                        amount: 4,
+                        expiry: 100,
+                    },
+                }],
+            },
+            TraceRecord::Event {
+                time_ms: 0.0,
+                payment_id: None,
+                event: "change_topology".to_owned(),
+                change: Some(TopologyChange::CloseChannel {
+                    channel_id: "c2".to_owned(),
+                }),
+            },
+            TraceRecord::Attempt {
+                time_ms: 120000.0,
+                payment_id: Some(0),
+                amount_msat: 5,
+                hops: vec![
+                    HopTrace {
+                        node: "alice".to_owned(),
+                        channel_id: "c1".to_owned(),
+                        outcome: HopOutcome::Forwarded,
+                        failure_reason: None,
+                    },
+                    HopTrace {
+                        node: "bob".to_owned(),
+                        channel_id: "c2".to_owned(),
+                        outcome: HopOutcome::Failed,
+                        failure_reason: Some(FailureReason::NoInvoice),
+                    },
+                ],
+            },
+            TraceRecord::Balance {
+                time_ms: 120000.0,
+                payment_id: Some(0),
+                channel_id: "c1".to_owned(),
+                source: "alice".to_owned(),
+                balance_msat: 5,
+            },
+            TraceRecord::End {
+                time_ms: 120000.0,
+                balances: balances[..1].to_vec(),
+            },
+        ];
+        let mut buf = vec![];
+        trace_to_writer(&mut buf, &records).unwrap();
+        let lines = String::from_utf8(buf.clone()).unwrap();
+        assert_eq!(lines.lines().count(), 5);
+        assert!(lines.contains(r#""type":"balance""#));
+        assert!(lines.contains(r#""payment_id":3,"source":"alice""#));
+        assert_eq!(trace_from_reader(buf.as_slice()).unwrap(), records);
+
+        let (start, end) = EventTrace::start_and_end_balances(&records);
+        assert_eq!(start.unwrap().len(), 2);
+        assert_eq!(end.unwrap()[&("c1".to_owned(), "alice".to_owned())], 10);
+        assert!(trace_from_reader("{\"type\":\"unknown\"}".as_bytes()).is_err());
+    }
+}
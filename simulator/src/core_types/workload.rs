@@ -0,0 +1,168 @@
+use crate::{core_types::graph::Graph, input::CsvRecords, PaymentParts, ID};
+
+use serde::Deserialize;
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+};
+
+/// A payment to make at a given time
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct WorkloadRecord {
+    /// Seconds since the start of the simulation
+    pub time_secs: f64,
+    pub source: ID,
+    pub destination: ID,
+    pub amount_msat: usize,
+    /// The most the sender pays in fees
+    #[serde(default)]
+    pub max_fee_msat: Option<usize>,
+    /// `single` or `split`, the simulation's setting if not given
+    #[serde(default)]
+    pub parts: Option<PaymentParts>,
+}
+
+/// Reads a JSON array of records if the file ends in `.json` and a CSV file with a
+/// `time_secs,source,destination,amount_msat` header, optionally followed by `max_fee_msat` and
+/// `parts` columns, otherwise. The records are sorted by time.
+pub fn read_workload(path: &Path) -> io::Result<Vec<WorkloadRecord>> {
+    let reader = BufReader::new(File::open(path)?);
+    if path.extension().is_some_and(|ext| ext == "json") {
+        workload_from_json(reader)
+    } else {
+        workload_from_csv(reader)
+    }
+}
+
+/// See [read_workload]
+pub fn workload_from_json(reader: impl BufRead) -> io::Result<Vec<WorkloadRecord>> {
+    let records: Vec<WorkloadRecord> = serde_json::from_reader(reader).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid workload: {}", e),
+        )
+    })?;
+    validate(records)
+}
+
+/// See [read_workload]
+pub fn workload_from_csv(reader: impl BufRead) -> io::Result<Vec<WorkloadRecord>> {
+    let records = CsvRecords::new(reader)?;
+    let columns = records.columns();
+    let num_columns = columns.len();
+    let optional = &columns[num_columns.min(4)..];
+    if columns[..num_columns.min(4)] != ["time_secs", "source", "destination", "amount_msat"]
+        || !["max_fee_msat", "parts"].starts_with(optional)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Expected a time_secs,source,destination,amount_msat[,max_fee_msat[,parts]] \
+                header, got {}",
+                records.header
+            ),
+        ));
+    }
+    let records = records.parse("workload record", |fields| {
+        if fields.len() != num_columns {
+            return None;
+        }
+        // empty optional fields are not set
+        let field = |i: usize| fields.get(i).filter(|f| !f.is_empty());
+        Some(WorkloadRecord {
+            time_secs: fields[0].parse().ok()?,
+            source: fields[1].to_owned(),
+            destination: fields[2].to_owned(),
+            amount_msat: fields[3].parse().ok()?,
+            max_fee_msat: field(4).map(|f| f.parse()).transpose().ok()?,
+            parts: match field(5).copied() {
+                None => None,
+                Some("single") => Some(PaymentParts::Single),
+                Some("split") => Some(PaymentParts::Split),
+                Some(_) => return None,
+            },
+        })
+    })?;
+    validate(records)
+}
+
+/// The sorted sources and destinations of the records that are not in the graph
+pub fn unknown_nodes(records: &[WorkloadRecord], graph: &Graph) -> Vec<ID> {
+    let mut unknown: Vec<ID> = records
+        .iter()
+        .flat_map(|r| [&r.source, &r.destination])
+        .filter(|node| !graph.node_is_in_graph(node))
+        .cloned()
+        .collect();
+    unknown.sort();
+    unknown.dedup();
+    unknown
+}
+
+fn validate(mut records: Vec<WorkloadRecord>) -> io::Result<Vec<WorkloadRecord>> {
+    for (idx, record) in records.iter().enumerate() {
+        if !record.time_secs.is_finite() || record.time_secs < 0.0 || record.amount_msat == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Workload record {} needs a time of at least 0 and a positive amount",
+                    idx + 1
+                ),
+            ));
+        }
+    }
+    records.sort_by(|a, b| a.time_secs.total_cmp(&b.time_secs));
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_workloads() {
+        let csv = "time_secs,source,destination,amount_msat,max_fee_msat,parts\n\
+            60,bob,chan,5000,,split\n\
+            \n\
+            0.5,alice,dina,1000,10,\n";
+        let records = workload_from_csv(csv.as_bytes()).unwrap();
+        let expected = vec![
+            WorkloadRecord {
+                time_secs: 0.5,
+                source: "alice".to_owned(),
+                destination: "dina".to_owned(),
+                amount_msat: 1000,
+                max_fee_msat: Some(10),
+                parts: None,
+            },
+            WorkloadRecord {
+                time_secs: 60.0,
+                source: "bob".to_owned(),
+                destination: "chan".to_owned(),
+                amount_msat: 5000,
+                max_fee_msat: None,
+                parts: Some(PaymentParts::Split),
+            },
+        ];
+        assert_eq!(records, expected);
+        let json = r#"[
+            {"time_secs": 60, "source": "bob", "destination": "chan", "amount_msat": 5000, "parts": "split"},
+            {"time_secs": 0.5, "source": "alice", "destination": "dina", "amount_msat": 1000, "max_fee_msat": 10}
+        ]"#;
+        assert_eq!(workload_from_json(json.as_bytes()).unwrap(), expected);
+        let minimal = "time_secs,source,destination,amount_msat\n1,alice,dina,1000\n";
+        assert_eq!(workload_from_csv(minimal.as_bytes()).unwrap().len(), 1);
+        for invalid in [
+            "time,source,destination,amount_msat\n1,alice,dina,1000\n",
+            "time_secs,source,destination,amount_msat,parts\n1,alice,dina,1000,split\n",
+            "time_secs,source,destination,amount_msat\n1,alice,dina\n",
+            "time_secs,source,destination,amount_msat\n-1,alice,dina,1000\n",
+            "time_secs,source,destination,amount_msat\n1,alice,dina,0\n",
+            "time_secs,source,destination,amount_msat,max_fee_msat,parts\n1,alice,dina,1,2,mpp\n",
+        ] {
+            assert!(workload_from_csv(invalid.as_bytes()).is_err());
+        }
+        assert!(workload_from_json(r#"[{"time_secs": 1}]"#.as_bytes()).is_err());
+    }
+}
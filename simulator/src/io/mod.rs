@@ -5,6 +5,7 @@ use crate::{
     WeightPartsCombi,
 };
 use serde::Serialize;
+use std::collections::BTreeMap;
 
 pub mod output;
 
@@ -15,16 +16,59 @@ pub struct Output(Vec<Results>);
 #[derive(Debug, Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Report {
+    /// The simulation's amount in sat, only that of every payment with fixed amounts
     pub amount: usize,
     pub total_num: usize,
     pub num_succesful: usize,
     pub num_failed: usize,
+    /// The payments grouped by their amount
+    pub amount_buckets: Vec<AmountBucket>,
     pub payments: Vec<PaymentInfo>,
     pub adversaries: Vec<Adversaries>,
     pub path_distances: Vec<usize>,
     pub path_diversity: Vec<Diversity>,
 }
 
+/// The payments whose amount in sat is in `[lower, upper)`, a power of ten and the next one
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct AmountBucket {
+    pub lower: usize,
+    pub upper: usize,
+    pub total_num: usize,
+    pub num_succesful: usize,
+    pub num_failed: usize,
+}
+
+impl AmountBucket {
+    /// Groups the amounts in sat and whether their payments succeeded, ordered by amount
+    pub fn from_amounts(amounts: impl IntoIterator<Item = (usize, bool)>) -> Vec<Self> {
+        let mut buckets: BTreeMap<usize, Self> = BTreeMap::new();
+        for (amount, succeeded) in amounts {
+            let (lower, upper) = if amount == 0 {
+                (0, 1)
+            } else {
+                let lower = 10usize.pow(amount.ilog10());
+                (lower, lower.saturating_mul(10))
+            };
+            let bucket = buckets.entry(lower).or_insert(Self {
+                lower,
+                upper,
+                total_num: 0,
+                num_succesful: 0,
+                num_failed: 0,
+            });
+            bucket.total_num += 1;
+            if succeeded {
+                bucket.num_succesful += 1;
+            } else {
+                bucket.num_failed += 1;
+            }
+        }
+        buckets.into_values().collect()
+    }
+}
+
 /// run and reports
 #[derive(Debug, Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -38,12 +82,16 @@ pub struct Results {
 #[serde(rename_all = "camelCase")]
 pub struct PaymentInfo {
     pub id: usize,
+    /// In sat
+    pub amount: usize,
     pub succeeded: bool,
     /// Number of parts this payment has been split into
     pub num_parts: usize,
     pub htlc_attempts: usize,
     pub used_paths: Vec<PathInfo>,
     pub failed_paths: Vec<PathInfo>,
+    /// See [Payment::completion_time_ms]
+    pub completion_time_ms: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, PartialEq, Eq)]
@@ -79,11 +127,13 @@ impl PaymentInfo {
         let failed_paths = PathInfo::from_payment(&payment.failed_paths);
         Self {
             id: payment.payment_id,
+            amount: crate::to_sat(payment.amount_msat),
             succeeded: payment.succeeded,
             num_parts: payment.num_parts,
             htlc_attempts: payment.htlc_attempts,
             used_paths,
             failed_paths,
+            completion_time_ms: payment.completion_time_ms,
         }
     }
 }
@@ -143,10 +193,15 @@ mod tests {
             failed_amounts: Vec::default(),
             successful_shards: Vec::default(),
             failed_paths: vec![],
+            completion_time_ms: None,
+            max_fee_msat: None,
+            parts: None,
+            ..Default::default()
         };
         let actual = PaymentInfo::from_payment(&payment);
         let expected = PaymentInfo {
             id: 0,
+            amount: 2,
             num_parts: 1,
             htlc_attempts: 2,
             succeeded: false,
@@ -166,7 +221,27 @@ mod tests {
                 },
             ],
             failed_paths: vec![],
+            completion_time_ms: None,
         };
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn bucket_amounts() {
+        let amounts = [
+            (5000, true),
+            (0, false),
+            (10, true),
+            (99, false),
+            (9, true),
+            (1, true),
+        ];
+        let actual = AmountBucket::from_amounts(amounts);
+        let bounds: Vec<(usize, usize)> = actual.iter().map(|b| (b.lower, b.upper)).collect();
+        assert_eq!(bounds, vec![(0, 1), (1, 10), (10, 100), (1000, 10000)]);
+        assert_eq!(actual[1].num_succesful, 2);
+        assert_eq!(actual[2].total_num, 2);
+        assert_eq!(actual[2].num_failed, 1);
+        assert_eq!(actual.iter().map(|b| b.total_num).sum::<usize>(), 6);
+    }
 }
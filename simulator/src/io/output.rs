@@ -1,4 +1,4 @@
-use super::{Output, PaymentInfo, Report, Results};
+use super::{AmountBucket, Output, PaymentInfo, Report, Results};
 use crate::{sim::SimResult, WeightPartsCombi};
 
 use log::{error, info};
@@ -72,11 +72,17 @@ impl Report {
                 .iter()
                 .map(PaymentInfo::from_payment),
         );
+        let amount_buckets = AmountBucket::from_amounts(
+            payments
+                .iter()
+                .map(|payment| (payment.amount, payment.succeeded)),
+        );
         Self {
             amount: crate::to_sat(sim_result.amount),
             total_num: sim_result.total_num,
             num_succesful: sim_result.num_succesful,
             num_failed: sim_result.num_failed,
+            amount_buckets,
             payments,
             adversaries: sim_result.adversaries.to_owned(),
             path_distances: sim_result.path_distances.0.to_owned(),
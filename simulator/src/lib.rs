@@ -1,6 +1,6 @@
 use lazy_static::lazy_static;
 use rand::{rngs::StdRng, SeedableRng};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::{fmt, path::PathBuf, sync::Mutex};
 
 pub mod core_types;
@@ -37,6 +37,14 @@ pub(crate) static MAX_PARTS: usize = 16;
 pub static SAT_SCALE: usize = 1000;
 /// Up to 20 intermediaries (vaious [LND code snippets](https://github.com/lightningnetwork/lnd/blob/bbbf7d33fb1527acebb44e2a69d16fbcf24cc2fa/routing/pathfind_test.go#LL1690C34-L1690C34)
 pub static MAX_HOPS: usize = 20;
+/// Features an intermediary has to support in order to forward our (TLV) onions.
+/// Nodes that announce no features at all are not filtered.
+pub static FORWARDING_FEATURES: [network_parser::Feature; 1] =
+    [network_parser::Feature::VarOnionOptin];
+/// Features the receiver has to support in order to accept our payments, which always carry a
+/// payment secret. Nodes that announce no features at all are not checked.
+pub static RECEIVING_FEATURES: [network_parser::Feature; 1] =
+    [network_parser::Feature::PaymentSecret];
 
 /// Metric to use when looking for a route
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
@@ -48,7 +56,8 @@ pub enum RoutingMetric {
 }
 
 /// How should the payment be sent
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum PaymentParts {
     /// Send the whole payment at once
     Single,
@@ -72,6 +81,10 @@ pub enum AdversarySelection {
     HighDegree(#[serde(skip)] PathBuf),
     /// WASM callers can pass the deserialised rankings in order to avoid IO
     HighBetweennessWeb(#[serde(skip)] Vec<String>),
+    /// Random sampling among nodes that only announce IP or DNS addresses
+    ClearnetOnly,
+    /// Random sampling among nodes that only announce onion services
+    TorOnly,
 }
 
 impl fmt::Display for AdversarySelection {
@@ -80,6 +93,8 @@ impl fmt::Display for AdversarySelection {
             Self::Random => write!(f, "Random"),
             Self::HighBetweenness(_) | Self::HighBetweennessWeb(_) => write!(f, "High Betweenness"),
             Self::HighDegree(_) => write!(f, "High Degree"),
+            Self::ClearnetOnly => write!(f, "Clearnet Only"),
+            Self::TorOnly => write!(f, "Tor Only"),
         }
     }
 }
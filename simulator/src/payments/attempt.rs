@@ -1,7 +1,11 @@
 use crate::{
-    payment::{Payment, PaymentShard},
+    event::PaymentEvent,
+    htlc::{Htlc, InvoiceHold},
+    latency::{HtlcMessage, HtlcUpdate},
+    payment::{FailureReason, Payment, PaymentShard},
+    time::Time,
     traversal::pathfinding::{CandidatePath, PathFinder},
-    Simulation, ID,
+    PaymentId, Simulation, ID,
 };
 
 #[cfg(not(test))]
@@ -17,34 +21,46 @@ impl Simulation {
         &mut self,
         payment: &mut Payment,
     ) -> (bool, Vec<(ID, String, usize)>) {
-        let graph = self.graph.clone();
         let mut succeeded = false;
         let mut failed = false;
         let mut to_revert = Vec::new();
         // fail immediately if sender's balance on each of their edges < amount
         // Checked for single-path payments earlier already but the check is necessary here for
         // MPP.
-        let max_out_balance = graph.get_max_node_balance(&payment.source);
+        let max_out_balance = self.graph.get_max_node_balance(&payment.source);
         if max_out_balance < payment.amount_msat {
             error!("Payment shard failing. Sender {} does not have sufficient balance. Amount {}, max balance {}",  payment.source, payment.amount_msat, max_out_balance);
             failed = true;
         }
-        let graph_copy = self.graph.clone();
+        let receiver_supported = self
+            .graph
+            .get_node(&payment.dest)
+            .is_none_or(|n| n.features.is_compatible(&crate::RECEIVING_FEATURES));
+        if !receiver_supported {
+            error!(
+                "Payment shard failing. Receiver {} does not support the required features.",
+                payment.dest
+            );
+            failed = true;
+        }
         if !failed {
             let mut path_finder = PathFinder::new(
                 payment.source.clone(),
                 payment.dest.clone(),
                 payment.amount_msat,
-                &graph_copy,
+                &self.graph,
                 self.routing_metric,
-                self.payment_parts,
+                payment.parts.unwrap_or(self.payment_parts),
             );
-            path_finder
-                .graph
-                .set_edges(PathFinder::remove_inadequate_edges(
-                    &graph_copy,
-                    payment.amount_msat,
-                ));
+            let edges =
+                PathFinder::remove_inadequate_edges(&path_finder.graph, payment.amount_msat);
+            path_finder.graph.set_edges(edges);
+            let edges = PathFinder::remove_unsupported_intermediaries(
+                &path_finder.graph,
+                &payment.dest,
+                &crate::FORWARDING_FEATURES,
+            );
+            path_finder.graph.set_edges(edges);
             while !succeeded && !failed {
                 if let Some(candidate_path) = path_finder.find_path() {
                     let hops = candidate_path.path.hops.clone();
@@ -59,18 +75,20 @@ impl Simulation {
                     // maybe the sender's balance is not enough after we have discovered the full
                     // path's fees
                     let (sender, out_channel) = (&hops[0].0, &hops[0].3);
-                    let channel_balance = self.graph.get_channel_balance(sender, out_channel);
+                    let channel_balance = self.graph.get_spendable_balance(sender, out_channel);
                     if channel_balance < candidate_path.amount {
                         error!("Payment shard failing. Sender does not have sufficient balance to cover fees. Amount {}, channel balance {}", candidate_path.amount, channel_balance);
                         succeeded = false;
                         failed = true;
                     }
                     // edge's receive capacity not sufficient?
-                    let receive_channel = &hops[hops.len() - 1].3;
-                    if !self
-                        .graph
-                        .channel_can_receive_amount(receive_channel, payment.amount_msat)
-                    {
+                    let (receiver, receive_channel) =
+                        (&hops[hops.len() - 1].0, &hops[hops.len() - 1].3);
+                    if !self.graph.channel_can_receive_amount(
+                        receiver,
+                        receive_channel,
+                        payment.amount_msat,
+                    ) {
                         error!(
                             "Payment {} of {} msat failing at destination due to max capacity. Not trying to deliver..",
                             payment.payment_id, payment.amount_msat
@@ -78,6 +96,17 @@ impl Simulation {
                         succeeded = false;
                         failed = true;
                     }
+                    // cheaper paths are found first so the remaining ones cost at least as much
+                    if let Some(max_fee) = payment.max_fee_msat {
+                        if candidate_path.path_fees() > max_fee {
+                            error!(
+                                "Payment {} failing as the path's fees of {} msat exceed the maximum fee of {} msat.",
+                                payment.payment_id, candidate_path.path_fees(), max_fee
+                            );
+                            succeeded = false;
+                            failed = true;
+                        }
+                    }
                     if !failed {
                         let mut payment_shard = payment.to_shard(payment.amount_msat);
                         (succeeded, to_revert) = self.attempt_payment(
@@ -85,10 +114,24 @@ impl Simulation {
                             &candidate_path,
                             &mut path_finder,
                         );
-                        *payment = payment_shard.to_payment(1);
+                        let htlcs = std::mem::take(&mut payment_shard.htlcs);
+                        *payment = Payment {
+                            completion_time_ms: payment.completion_time_ms,
+                            max_fee_msat: payment.max_fee_msat,
+                            parts: payment.parts,
+                            dispatch_time: payment.dispatch_time,
+                            ..payment_shard.to_payment(1)
+                        };
                         if !succeeded {
                             self.revert_payment(&to_revert);
                         }
+                        // the HTLC was forwarded once for each hop that transferred an amount
+                        let reached: Vec<ID> = hops
+                            .iter()
+                            .take(to_revert.len().min(hops.len() - 1) + 1)
+                            .map(|h| h.0.clone())
+                            .collect();
+                        self.schedule_htlc_updates(payment, &reached, htlcs, succeeded);
                     }
                     // note paths that were attempted but failed for some reason
                     if failed || !succeeded {
@@ -117,6 +160,18 @@ impl Simulation {
         payment_shard: &mut PaymentShard,
         candidate_path: &CandidatePath,
         path_finder: &mut PathFinder,
+    ) -> (bool, Vec<(ID, String, usize)>) {
+        let (succeeded, transferred_amounts) =
+            self.transfer_along_path(payment_shard, candidate_path, path_finder);
+        self.trace_attempt(payment_shard, candidate_path, transferred_amounts.len());
+        (succeeded, transferred_amounts)
+    }
+
+    fn transfer_along_path(
+        &mut self,
+        payment_shard: &mut PaymentShard,
+        candidate_path: &CandidatePath,
+        path_finder: &mut PathFinder,
     ) -> (bool, Vec<(ID, String, usize)>) {
         let hops = candidate_path.path.hops.clone();
         info!(
@@ -129,26 +184,54 @@ impl Simulation {
         let mut remaining_transferable_amount = 0;
         // used in case we need to revert (node, channel_id, amount)
         let mut transferred_amounts: Vec<(ID, String, usize)> = Vec::new();
+        payment_shard.htlcs.clear();
+        let height = self.event_queue.block_height();
+        let expiries: Vec<usize> = candidate_path
+            .htlc_expiry_deltas()
+            .into_iter()
+            .map(|delta| height + delta)
+            .collect();
         for (idx, node) in hops.iter().enumerate() {
             let (id, fees, _timelock, channel_id) = node.clone();
             // Subtract payment amount (includes fees) from source
             if id == payment_shard.source {
                 let current_balance = self.graph.get_channel_balance(&id, &channel_id);
-                if current_balance > candidate_path.amount {
-                    self.graph.update_channel_balance(
+                let spendable_balance = self.graph.get_spendable_balance(&id, &channel_id);
+                if let Some(reason) = self.check_htlc(&id, &channel_id, candidate_path.amount) {
+                    error!(
+                        "Payment {} failed at source {} as channel {} cannot carry an HTLC of {} msat: {:?}.",
+                        payment_shard.payment_id, id, channel_id, candidate_path.amount, reason
+                    );
+                    path_finder.graph.remove_channel(&channel_id);
+                    payment_shard.htlc_attempts += 1;
+                    payment_shard.succeeded = false;
+                    payment_shard.failure_reason = Some(reason);
+                    return (payment_shard.succeeded, transferred_amounts);
+                } else if spendable_balance > candidate_path.amount {
+                    self.update_channel_balance(
+                        &id,
                         &channel_id,
                         current_balance - candidate_path.amount,
                     );
                     remaining_transferable_amount = candidate_path.amount;
+                    // the sender's balance already dropped by the amount so the HTLC only
+                    // takes a slot
+                    payment_shard.htlcs.push(Htlc {
+                        source: id.clone(),
+                        channel_id: channel_id.clone(),
+                        amount: 0,
+                        expiry: expiries[idx],
+                    });
                     transferred_amounts.push((id, channel_id, remaining_transferable_amount));
                     payment_shard.htlc_attempts += 1;
                 } else {
                     error!(
                         "Payment {} failed at source {} due to insufficient balance. available balamce {}, total amount {}",
-                        payment_shard.payment_id, payment_shard.source, current_balance, candidate_path.amount,
+                        payment_shard.payment_id, payment_shard.source, spendable_balance, candidate_path.amount,
                     );
                     payment_shard.htlc_attempts += 1;
                     payment_shard.succeeded = false;
+                    payment_shard.failure_reason = Some(FailureReason::InsufficientBalance);
                     return (payment_shard.succeeded, transferred_amounts);
                 }
             } else if id == payment_shard.dest {
@@ -165,6 +248,7 @@ impl Simulation {
                                 // receiver would exceed channel capacity - should never get this
                                 // far as we check before attempting
                                 if !self.graph.channel_can_receive_amount(
+                                    &id,
                                     &channel_id,
                                     remaining_transferable_amount,
                                 ) {
@@ -173,16 +257,16 @@ impl Simulation {
                                         payment_shard.payment_id
                                     );
                                     payment_shard.succeeded = false;
-                                    let src = &id;
-                                    let dest = hops[idx - 1].0.clone();
+                                    payment_shard.failure_reason =
+                                        Some(FailureReason::ExceedsCapacity);
                                     // this is the failing edge
                                     trace!("Discarding channel {} due to max capacity", channel_id,);
                                     path_finder.graph.remove_channel(&channel_id);
-                                    path_finder.graph.remove_edge(src, &dest);
                                 } else {
                                     let current_balance =
                                         self.graph.get_channel_balance(&id, &channel_id);
-                                    self.graph.update_channel_balance(
+                                    self.update_channel_balance(
+                                        &id,
                                         &channel_id,
                                         current_balance + remaining_transferable_amount,
                                     );
@@ -202,10 +286,17 @@ impl Simulation {
                                         remaining_transferable_amount,
                                     ));
                                     payment_shard.succeeded = true;
+                                    payment_shard.failure_reason = None;
+                                    self.hold_htlcs(
+                                        payment_shard.payment_id,
+                                        &payment_shard.dest,
+                                        payment_shard.htlcs.clone(),
+                                    );
                                 }
                             } else {
                                 error!("Payment failure at destination (no invoice). Payment {:?}, remaining_amount {}, invoice {:?}", payment_shard, remaining_transferable_amount, invoice);
                                 payment_shard.succeeded = false;
+                                payment_shard.failure_reason = Some(FailureReason::NoInvoice);
                             }
                         }
                     }
@@ -216,10 +307,9 @@ impl Simulation {
                         );
                         // we remove the edge because we otherwise risk running into an endless
                         // loop
-                        let src = &id;
                         path_finder.graph.remove_channel(&channel_id);
-                        path_finder.graph.remove_edge(src, &hops[idx - 1].0);
                         payment_shard.succeeded = false;
+                        payment_shard.failure_reason = Some(FailureReason::NoInvoice);
                     }
                 };
             // a hop along the path
@@ -227,14 +317,32 @@ impl Simulation {
                 payment_shard.htlc_attempts += 1;
                 // subtract fee and add to own balance
                 let current_balance = self.graph.get_channel_balance(&id, &channel_id);
-                if current_balance > (remaining_transferable_amount - fees)
-                    && self
-                        .graph
-                        .channel_can_receive_amount(&channel_id, remaining_transferable_amount)
+                let forwarded_amount = remaining_transferable_amount - fees;
+                if let Some(reason) = self.check_htlc(&id, &channel_id, forwarded_amount) {
+                    error!(
+                        "Payment {} failing at {} as channel {} cannot carry an HTLC of {} msat: {:?}.",
+                        payment_shard.payment_id, id, channel_id, forwarded_amount, reason
+                    );
+                    // this is the failing edge, the channel the payment arrived through is fine
+                    path_finder.graph.remove_channel(&channel_id);
+                    payment_shard.succeeded = false;
+                    payment_shard.failure_reason = Some(reason);
+                    return (payment_shard.succeeded, transferred_amounts);
+                } else if self.graph.get_spendable_balance(&id, &channel_id) > forwarded_amount
+                    && self.graph.channel_can_receive_amount(
+                        &id,
+                        &channel_id,
+                        remaining_transferable_amount,
+                    )
                 {
-                    self.graph
-                        .update_channel_balance(&channel_id, current_balance + fees);
+                    self.update_channel_balance(&id, &channel_id, current_balance + fees);
                     remaining_transferable_amount -= fees;
+                    payment_shard.htlcs.push(Htlc {
+                        source: id.clone(),
+                        channel_id: channel_id.clone(),
+                        amount: forwarded_amount,
+                        expiry: expiries[idx],
+                    });
                     transferred_amounts.push((id, channel_id, fees));
                 } else {
                     let src = &id;
@@ -251,8 +359,10 @@ impl Simulation {
                     );
                     // this is the failing edge
                     path_finder.graph.remove_channel(&channel_id);
-                    path_finder.graph.remove_edge(src, &hops[idx - 1].0);
+                    // and the channel the payment arrived through
+                    path_finder.graph.remove_channel(&hops[idx - 1].3);
                     payment_shard.succeeded = false;
+                    payment_shard.failure_reason = Some(FailureReason::InsufficientBalance);
                     return (payment_shard.succeeded, transferred_amounts);
                 }
             }
@@ -260,20 +370,283 @@ impl Simulation {
         (payment_shard.succeeded, transferred_amounts)
     }
 
+    /// Why the source cannot offer an HTLC of the amount over the channel, if it cannot
+    fn check_htlc(&self, src: &ID, channel_id: &str, amount: usize) -> Option<FailureReason> {
+        let edge = self.graph.get_channel(src, channel_id)?;
+        if !edge.htlc_limits_allow(amount) {
+            Some(FailureReason::HtlcLimits)
+        } else if amount < self.graph.channel_limits(edge).dust_limit_msat {
+            Some(FailureReason::BelowDustLimit)
+        } else if !self.graph.htlc_slot_available(edge) {
+            Some(FailureReason::NoHtlcSlots)
+        } else {
+            None
+        }
+    }
+
+    /// True if the HTLCs of payments to the receiver stay in flight after they were delivered
+    fn holds_htlcs(&self, dest: &ID) -> bool {
+        self.htlc_hold_time.is_some() || self.hold_invoices.contains_key(dest)
+    }
+
+    /// How long the HTLCs of a delivered payment to the receiver stay in flight, the hold time
+    /// plus the time the receiver holds its invoices. None if they are never settled.
+    fn settlement_delay(&self, dest: &ID) -> Option<Time> {
+        let hold_time = self.htlc_hold_time.unwrap_or(Time::from_secs(0.0));
+        match self.hold_invoices.get(dest) {
+            Some(InvoiceHold::Forever) => None,
+            Some(InvoiceHold::Secs(secs)) => Some(hold_time + Time::from_secs(*secs)),
+            None => Some(hold_time),
+        }
+    }
+
+    /// Locks the HTLCs of a delivered shard until its payment is settled or failed back.
+    /// Nothing is locked if the HTLCs are resolved immediately or if a latency model already
+    /// locked them when they were offered, see [Simulation::schedule_htlc_updates].
+    fn hold_htlcs(&mut self, payment_id: PaymentId, dest: &ID, htlcs: Vec<Htlc>) {
+        if !self.holds_htlcs(dest) || self.latency.is_some() {
+            return;
+        }
+        for htlc in htlcs.iter() {
+            self.graph.add_htlc(htlc);
+        }
+        self.in_flight.entry(payment_id).or_default().extend(htlcs);
+    }
+
+    /// Schedules the settlement of a delivered payment's HTLCs after its completion time and
+    /// the settlement delay. If the receiver holds them until the earliest of them expires, the
+    /// payment is stuck, its HTLCs expire on-chain and it fails. Returns whether it is settled.
+    pub(crate) fn settle_htlcs(
+        &mut self,
+        payment: &mut Payment,
+        to_revert: Vec<(ID, String, usize)>,
+    ) -> bool {
+        if !self.holds_htlcs(&payment.dest) {
+            return true;
+        }
+        let delay = self
+            .settlement_delay(&payment.dest)
+            .map(|delay| payment.completion_time() + delay);
+        let settled = self.resolve_htlcs(payment.payment_id, delay, true, to_revert);
+        if !settled {
+            info!(
+                "Payment {} is stuck as {} holds it until its HTLCs expire.",
+                payment.payment_id, payment.dest
+            );
+            payment.succeeded = false;
+            payment.failed_paths.append(&mut payment.used_paths);
+        }
+        settled
+    }
+
+    /// Fails back the HTLCs of a payment that was only partially delivered. Their transfers are
+    /// reverted after the payment's completion time and the hold time or immediately if there is
+    /// no hold time.
+    pub(crate) fn fail_htlcs(&mut self, payment: &Payment, to_revert: Vec<(ID, String, usize)>) {
+        match self.htlc_hold_time {
+            Some(hold_time) => {
+                let delay = payment.completion_time() + hold_time;
+                self.resolve_htlcs(payment.payment_id, Some(delay), false, to_revert);
+            }
+            None => {
+                self.revert_payment(&to_revert);
+                self.release_htlcs(payment.payment_id);
+            }
+        }
+    }
+
+    /// Schedules settling or failing back the payment's HTLCs after the delay unless the
+    /// earliest of them expires first, in which case their expiry is scheduled. Returns whether
+    /// they are resolved before they expire.
+    fn resolve_htlcs(
+        &mut self,
+        payment_id: PaymentId,
+        delay: Option<Time>,
+        settle: bool,
+        to_revert: Vec<(ID, String, usize)>,
+    ) -> bool {
+        let expires_in = self
+            .in_flight
+            .get(&payment_id)
+            .and_then(|htlcs| htlcs.iter().map(|h| h.expiry).min())
+            .map(|height| self.event_queue.until_height(height));
+        match delay {
+            Some(delay) if expires_in.is_none_or(|expires_in| delay < expires_in) => {
+                let event = if settle {
+                    PaymentEvent::SettleHtlcs { payment_id }
+                } else {
+                    PaymentEvent::FailHtlcs {
+                        payment_id,
+                        to_revert,
+                    }
+                };
+                self.event_queue.schedule(delay, event);
+                true
+            }
+            _ => {
+                self.event_queue.schedule(
+                    expires_in.unwrap_or(Time::from_secs(0.0)),
+                    PaymentEvent::ExpireHtlcs {
+                        payment_id,
+                        to_revert,
+                    },
+                );
+                false
+            }
+        }
+    }
+
+    /// Schedules the update_add_htlc messages to the nodes the HTLC reached and the
+    /// update_fulfill_htlc messages back to the sender if it was delivered. Failures travel back
+    /// the same way as update_fail_htlc messages. Adds the round trip to the payment's
+    /// completion time.
+    /// The offered HTLCs stay locked until the fulfil or fail reaches the node that offered them,
+    /// or until the payment is settled if the receiver holds it.
+    fn schedule_htlc_updates(
+        &mut self,
+        payment: &mut Payment,
+        reached: &[ID],
+        htlcs: Vec<Htlc>,
+        succeeded: bool,
+    ) {
+        let Some(latency) = &self.latency else {
+            return;
+        };
+        // delay of passing a message between a node and the next one in either direction
+        let delays: Vec<Time> = reached
+            .iter()
+            .skip(1)
+            .map(|node| latency.delay(node, &mut self.latency_rng))
+            .collect();
+        let payment_id = payment.payment_id;
+        for htlc in htlcs.iter() {
+            self.graph.add_htlc(htlc);
+        }
+        self.in_flight
+            .entry(payment_id)
+            .or_default()
+            .extend(htlcs.iter().cloned());
+        let held = succeeded && self.holds_htlcs(&payment.dest);
+        let mut elapsed = payment.completion_time();
+        for (node, delay) in reached.iter().skip(1).zip(delays.iter()) {
+            elapsed += *delay;
+            let node = node.clone();
+            self.event_queue
+                .schedule(elapsed, PaymentEvent::UpdateAddHtlc { payment_id, node });
+        }
+        for ((node, delay), htlc) in reached.iter().zip(delays.iter()).zip(htlcs).rev() {
+            elapsed += *delay;
+            if held {
+                continue;
+            }
+            let node = node.clone();
+            let event = if succeeded {
+                PaymentEvent::UpdateFulfillHtlc {
+                    payment_id,
+                    node,
+                    htlc,
+                }
+            } else {
+                PaymentEvent::UpdateFailHtlc {
+                    payment_id,
+                    node,
+                    htlc,
+                }
+            };
+            self.event_queue.schedule(elapsed, event);
+        }
+        payment.completion_time_ms = Some(elapsed.as_millis().round() as u64);
+    }
+
+    pub(crate) fn record_htlc_update(
+        &mut self,
+        payment_id: PaymentId,
+        node: ID,
+        message: HtlcMessage,
+    ) {
+        self.htlc_updates.push(HtlcUpdate {
+            payment_id,
+            node,
+            message,
+            time_ms: self.event_queue.now().as_millis(),
+        });
+    }
+
+    /// Releases what one of the payment's HTLCs locked unless it was released already
+    pub(crate) fn release_htlc(&mut self, payment_id: PaymentId, htlc: &Htlc) {
+        let Some(htlcs) = self.in_flight.get_mut(&payment_id) else {
+            return;
+        };
+        if let Some(idx) = htlcs.iter().position(|h| h == htlc) {
+            htlcs.remove(idx);
+            self.graph.remove_htlc(htlc);
+        }
+        if htlcs.is_empty() {
+            self.in_flight.remove(&payment_id);
+        }
+    }
+
+    /// Releases the liquidity and slots the payment's HTLCs locked
+    pub(crate) fn release_htlcs(&mut self, payment_id: PaymentId) {
+        for htlc in self.in_flight.remove(&payment_id).unwrap_or_default() {
+            self.graph.remove_htlc(&htlc);
+        }
+    }
+
+    /// Times out the payment's HTLCs on-chain, returning the amounts to the hops that offered
+    /// them
+    pub(crate) fn expire_htlcs(
+        &mut self,
+        payment_id: PaymentId,
+        to_revert: &[(ID, String, usize)],
+    ) {
+        let htlcs = self.in_flight.get(&payment_id).cloned().unwrap_or_default();
+        debug!(
+            "{} HTLCs of payment {} expired at block height {}.",
+            htlcs.len(),
+            payment_id,
+            self.event_queue.block_height()
+        );
+        self.revert_payment(to_revert);
+        self.release_htlcs(payment_id);
+        self.expired_htlcs.extend(htlcs);
+    }
+
+    /// When the payment's outcome is reported, after the round trips of its HTLC messages with
+    /// a latency model
+    pub(crate) fn outcome_delay(&self, payment: &Payment) -> Time {
+        match self.latency {
+            Some(_) => payment.completion_time(),
+            None => self.event_queue.now() + Time::from_secs(crate::SIM_DELAY_IN_SECS),
+        }
+    }
+
     /// Credits all edges in the path (Source gains whereas the rest lose)
     pub(crate) fn revert_payment(&mut self, amounts: &[(ID, String, usize)]) {
         let total: usize = amounts.iter().map(|t| t.2).sum::<usize>();
         debug!("Reverting {} msat.", total);
-        for (idx, (node, channel_id, amt)) in amounts.iter().enumerate() {
+        // MPP payments revert several shards at once so every entry belonging to the sender is
+        // credited, not just the first one
+        let sender = amounts.first().map(|a| a.0.clone()).unwrap_or_default();
+        for (node, channel_id, amt) in amounts.iter() {
+            // channels that left the graph had their HTLCs resolved on-chain
+            let Some(current_balance) = self.graph.get_channel(node, channel_id).map(|e| e.balance)
+            else {
+                continue;
+            };
             // source
-            if idx == 0 {
-                let current_balance = self.graph.get_channel_balance(node, channel_id);
-                self.graph
-                    .update_channel_balance(channel_id, current_balance + amt);
+            if *node == sender {
+                self.update_channel_balance(node, channel_id, current_balance + amt);
             } else {
-                let current_balance = self.graph.get_channel_balance(node, channel_id);
-                self.graph
-                    .update_channel_balance(channel_id, current_balance - amt);
+                // the receiver may have spent a held amount before the HTLCs were failed
+                let balance = current_balance.checked_sub(*amt).unwrap_or_else(|| {
+                    error!(
+                        "Channel {} of {} holds {} msat, cannot revert {} msat.",
+                        channel_id, node, current_balance, amt
+                    );
+                    0
+                });
+                self.update_channel_balance(node, channel_id, balance);
             }
         }
     }
@@ -284,8 +657,14 @@ pub(crate) mod tests {
 
     use super::*;
     use crate::{
-        core_types::graph::Graph, AdversarySelection, Invoice, PaymentParts, RoutingMetric,
+        core_types::{
+            graph::Graph,
+            reserve::{ChannelLimits, ChannelReserves},
+        },
+        AdversarySelection, Invoice, PaymentParts, RoutingMetric,
     };
+    use itertools::Itertools;
+    use std::collections::HashMap;
 
     pub fn init_sim(path: Option<String>, number_of_adversaries: Option<Vec<usize>>) -> Simulation {
         let seed = 0;
@@ -297,7 +676,7 @@ pub(crate) mod tests {
         };
         let path = std::path::Path::new(&path);
         let mut graph = Graph::to_sim_graph(
-            &network_parser::Graph::from_json_file(&path, network_parser::GraphSource::Lnresearch)
+            &network_parser::Graph::from_json_file(path, network_parser::GraphSource::Lnresearch)
                 .unwrap(),
             network_parser::GraphSource::Lnresearch,
         );
@@ -351,6 +730,35 @@ pub(crate) mod tests {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn reverse_multi_shard_payment() {
+        let balance = 4711;
+        let mut simulator = init_sim(None, None);
+        // two successful shards of a failed MPP payment from alice to chan
+        let amounts_to_reverse = Vec::from([
+            ("alice".to_string(), "alice1".to_string(), 130),
+            ("bob".to_string(), "bob2".to_string(), 30),
+            ("chan".to_string(), "chan1".to_string(), 100),
+            ("alice".to_string(), "alice1".to_string(), 70),
+            ("bob".to_string(), "bob2".to_string(), 20),
+            ("chan".to_string(), "chan1".to_string(), 50),
+        ]);
+        simulator.revert_payment(&amounts_to_reverse);
+        // the sender is refunded for both shards
+        let actual = simulator
+            .graph
+            .get_channel_balance(&"alice".to_string(), &"alice1".to_string());
+        assert_eq!(actual, balance + 200);
+        let actual = simulator
+            .graph
+            .get_channel_balance(&"bob".to_string(), &"bob2".to_string());
+        assert_eq!(actual, balance - 50);
+        let actual = simulator
+            .graph
+            .get_channel_balance(&"chan".to_string(), &"chan1".to_string());
+        assert_eq!(actual, balance - 150);
+    }
+
     #[test]
     fn payment_transfer_success() {
         let source = "alice".to_string();
@@ -379,6 +787,8 @@ pub(crate) mod tests {
             min_shard_amt: 10,
             htlc_attempts: 0,
             failed_paths: vec![],
+            failure_reason: None,
+            htlcs: vec![],
         };
         assert!(
             simulator
@@ -429,6 +839,8 @@ pub(crate) mod tests {
             min_shard_amt: 10,
             htlc_attempts: 0,
             failed_paths: vec![],
+            failure_reason: None,
+            htlcs: vec![],
         };
         let (success, transferred) =
             simulator.attempt_payment(payment_shard, &candidate_paths, &mut path_finder);
@@ -441,6 +853,22 @@ pub(crate) mod tests {
         }
     }
 
+    #[test]
+    fn receiver_lacking_features_fails() {
+        let amount = 1000;
+        let (source, dest) = ("alice".to_string(), "dina".to_string());
+        let mut simulator = init_sim(None, None);
+        simulator.add_invoice(Invoice::new(0, amount, &source, &dest));
+        // dina announces features but no payment secrets
+        for node in simulator.graph.nodes.iter_mut().filter(|n| n.id == dest) {
+            node.features
+                .set(network_parser::Feature::VarOnionOptin, true);
+        }
+        let payment = &mut Payment::new(0, source, dest, amount, None);
+        assert!(!simulator.send_single_payment(payment));
+        assert_eq!(payment.htlc_attempts, 0);
+    }
+
     #[test]
     // checking that balances are unaltered. Failure at the last node due to insufficient funds at
     // bob
@@ -452,7 +880,9 @@ pub(crate) mod tests {
         let balance = 100;
         let mut simulator = init_sim(None, None);
         let graph = simulator.graph.clone();
-        simulator.graph.update_channel_balance(&channel_id, balance);
+        simulator
+            .graph
+            .update_channel_balance(&"bob".to_string(), &channel_id, balance);
         let mut path_finder = PathFinder::new(
             source.clone(),
             dest.clone(),
@@ -472,6 +902,8 @@ pub(crate) mod tests {
             min_shard_amt: 10,
             htlc_attempts: 0,
             failed_paths: vec![],
+            failure_reason: None,
+            htlcs: vec![],
         };
         let (success, transferred) =
             simulator.attempt_payment(payment_shard, &candidate_paths, &mut path_finder);
@@ -494,7 +926,9 @@ pub(crate) mod tests {
         let balance = 100;
         let mut simulator = init_sim(None, None);
         let graph = simulator.graph.clone();
-        simulator.graph.update_channel_balance(&channel_id, balance);
+        simulator
+            .graph
+            .update_channel_balance(&"bob".to_string(), &channel_id, balance);
         let mut path_finder = PathFinder::new(
             source.clone(),
             dest.clone(),
@@ -514,6 +948,8 @@ pub(crate) mod tests {
             min_shard_amt: 10,
             htlc_attempts: 0,
             failed_paths: vec![],
+            failure_reason: None,
+            htlcs: vec![],
         };
         assert!(
             !simulator
@@ -525,10 +961,10 @@ pub(crate) mod tests {
             .graph
             .get_edge(&String::from("alice"), &String::from("bob"))
             .is_some());
-        assert!(!path_finder
+        assert!(path_finder
             .graph
             .get_edge(&String::from("alice"), &String::from("bob"))
-            .is_some());
+            .is_none());
         // 0 because edges have been removed and get_balance returns 0 if edge is not found
         assert_eq!(
             path_finder
@@ -544,6 +980,278 @@ pub(crate) mod tests {
         );
     }
 
+    #[test]
+    fn parallel_channels_are_kept_when_one_fails() {
+        let amount = 1000;
+        let source = "alice".to_string();
+        let dest = "carol".to_string();
+        let mut simulator = init_sim(
+            Some(String::from("../test_data/trivial_multigraph.json")),
+            None,
+        );
+        let graph = simulator.graph.clone();
+        simulator
+            .graph
+            .update_channel_balance(&"bob".to_string(), "100x3x0/0", 100);
+        let mut path_finder = PathFinder::new(
+            source.clone(),
+            dest.clone(),
+            amount,
+            &graph,
+            RoutingMetric::MinFee,
+            PaymentParts::Single,
+        );
+        let candidate_path = path_finder.find_path().unwrap();
+        // the cheaper of the two channels to bob
+        assert_eq!(candidate_path.path.hops[0].3, "100x2x0/0");
+        let payment_shard = &mut PaymentShard {
+            payment_id: 0,
+            source,
+            dest,
+            amount,
+            succeeded: false,
+            used_path: candidate_path.clone(),
+            min_shard_amt: 10,
+            htlc_attempts: 0,
+            failed_paths: vec![],
+            failure_reason: None,
+            htlcs: vec![],
+        };
+        assert!(
+            !simulator
+                .attempt_payment(payment_shard, &candidate_path, &mut path_finder)
+                .0
+        );
+        let channel_ids = |from: &str, to: &str| -> Vec<String> {
+            path_finder
+                .graph
+                .get_all_src_dest_edges(&from.to_string(), &to.to_string())
+                .into_iter()
+                .map(|e| e.channel_id)
+                .collect()
+        };
+        assert_eq!(channel_ids("alice", "bob"), vec!["100x1x0/0"]);
+        assert_eq!(channel_ids("bob", "alice"), vec!["100x1x0/1"]);
+        assert!(channel_ids("bob", "carol").is_empty());
+        assert!(channel_ids("carol", "bob").is_empty());
+    }
+
+    #[test]
+    fn htlc_limits_are_enforced() {
+        let source = "alice".to_string();
+        let dest = "carol".to_string();
+        let mut simulator = init_sim(
+            Some(String::from("../test_data/trivial_multigraph.json")),
+            None,
+        );
+        for e in simulator.graph.edges.values_mut().flatten() {
+            e.balance = 200000;
+        }
+        let path_finder = |simulator: &Simulation, amount| {
+            PathFinder::new(
+                source.clone(),
+                dest.clone(),
+                amount,
+                &simulator.graph,
+                RoutingMetric::MinFee,
+                PaymentParts::Single,
+            )
+        };
+        // the cheaper channel to bob has an htlc_maximum_msat of 50000
+        let candidate_path = path_finder(&simulator, 60000).find_path().unwrap();
+        assert_eq!(candidate_path.path.hops[0].3, "100x1x0/0");
+        let amount = 40000;
+        let mut path_finder = path_finder(&simulator, amount);
+        let candidate_path = path_finder.find_path().unwrap();
+        assert_eq!(candidate_path.path.hops[0].3, "100x2x0/0");
+        simulator
+            .graph
+            .edges
+            .get_mut("bob")
+            .unwrap()
+            .iter_mut()
+            .find(|e| e.channel_id == "100x3x0/0")
+            .unwrap()
+            .htlc_maximum_msat = amount - 1;
+        simulator.add_invoice(Invoice::new(0, amount, &source, &dest));
+        let payment_shard = &mut PaymentShard {
+            payment_id: 0,
+            source: source.clone(),
+            dest: dest.clone(),
+            amount,
+            succeeded: false,
+            used_path: candidate_path.clone(),
+            min_shard_amt: 10,
+            htlc_attempts: 0,
+            failed_paths: vec![],
+            failure_reason: None,
+            htlcs: vec![],
+        };
+        let (succeeded, to_revert) =
+            simulator.attempt_payment(payment_shard, &candidate_path, &mut path_finder);
+        assert!(!succeeded);
+        assert_eq!(
+            payment_shard.failure_reason,
+            Some(FailureReason::HtlcLimits)
+        );
+        // only the source was debited
+        assert_eq!(to_revert.len(), 1);
+        // unlike a lack of funds, the channel to the failing hop is kept
+        let channel_ids = path_finder
+            .graph
+            .get_all_src_dest_edges(&source, &"bob".to_string())
+            .into_iter()
+            .map(|e| e.channel_id)
+            .sorted()
+            .collect::<Vec<_>>();
+        assert_eq!(channel_ids, vec!["100x1x0/0", "100x2x0/0"]);
+        assert!(path_finder
+            .graph
+            .get_all_src_dest_edges(&"bob".to_string(), &dest)
+            .iter()
+            .all(|e| e.channel_id != "100x3x0/0"));
+    }
+
+    #[test]
+    fn dust_and_reserve_are_enforced() {
+        let amount = 40000;
+        let source = "alice".to_string();
+        let dest = "carol".to_string();
+        let mut simulator = init_sim(
+            Some(String::from("../test_data/trivial_multigraph.json")),
+            None,
+        );
+        for e in simulator.graph.edges.values_mut().flatten() {
+            e.balance = 200000;
+        }
+        simulator.add_invoice(Invoice::new(0, amount, &source, &dest));
+        let attempt = |simulator: &mut Simulation, reserves: ChannelReserves| {
+            simulator.graph.set_reserves(reserves);
+            let mut path_finder = PathFinder::new(
+                source.clone(),
+                dest.clone(),
+                amount,
+                &simulator.graph,
+                RoutingMetric::MinFee,
+                PaymentParts::Single,
+            );
+            let candidate_path = path_finder.find_path().unwrap();
+            assert_eq!(candidate_path.path.hops[0].3, "100x2x0/0");
+            let payment_shard = &mut PaymentShard {
+                payment_id: 0,
+                source: source.clone(),
+                dest: dest.clone(),
+                amount,
+                succeeded: false,
+                used_path: candidate_path.clone(),
+                min_shard_amt: 10,
+                htlc_attempts: 0,
+                failed_paths: vec![],
+                failure_reason: None,
+                htlcs: vec![],
+            };
+            let (succeeded, to_revert) =
+                simulator.attempt_payment(payment_shard, &candidate_path, &mut path_finder);
+            simulator.revert_payment(&to_revert);
+            (succeeded, payment_shard.failure_reason)
+        };
+        let dust = ChannelReserves {
+            dust_limit_msat: 50000,
+            ..Default::default()
+        };
+        assert_eq!(
+            attempt(&mut simulator, dust),
+            (false, Some(FailureReason::BelowDustLimit))
+        );
+        // only 30000 msat of the channel to bob can be spent
+        let reserve = ChannelReserves {
+            overrides: HashMap::from([(
+                "100x2x0".to_owned(),
+                ChannelLimits {
+                    reserve_msat: 170000,
+                    dust_limit_msat: 0,
+                },
+            )]),
+            ..Default::default()
+        };
+        assert_eq!(
+            attempt(&mut simulator, reserve),
+            (false, Some(FailureReason::InsufficientBalance))
+        );
+        assert_eq!(
+            attempt(&mut simulator, ChannelReserves::default()),
+            (true, None)
+        );
+    }
+
+    #[test]
+    fn in_flight_htlcs_occupy_slots() {
+        let amount = 40000;
+        let source = "alice".to_string();
+        let dest = "carol".to_string();
+        let mut simulator = init_sim(
+            Some(String::from("../test_data/trivial_multigraph.json")),
+            None,
+        );
+        for e in simulator.graph.edges.values_mut().flatten() {
+            e.balance = 200000;
+        }
+        simulator.set_htlc_hold_time(600.0);
+        simulator.graph.set_max_accepted_htlcs(1);
+        let path_finder = |simulator: &Simulation| {
+            PathFinder::new(
+                source.clone(),
+                dest.clone(),
+                amount,
+                &simulator.graph,
+                RoutingMetric::MinFee,
+                PaymentParts::Single,
+            )
+        };
+        let attempt =
+            |simulator: &mut Simulation, payment_id: usize, candidate_path: &CandidatePath| {
+                simulator.add_invoice(Invoice::new(payment_id, amount, &source, &dest));
+                let mut path_finder = path_finder(simulator);
+                let payment_shard = &mut PaymentShard {
+                    payment_id,
+                    source: source.clone(),
+                    dest: dest.clone(),
+                    amount,
+                    succeeded: false,
+                    used_path: candidate_path.clone(),
+                    min_shard_amt: 10,
+                    htlc_attempts: 0,
+                    failed_paths: vec![],
+                    failure_reason: None,
+                    htlcs: vec![],
+                };
+                let (succeeded, to_revert) =
+                    simulator.attempt_payment(payment_shard, candidate_path, &mut path_finder);
+                if !succeeded {
+                    simulator.revert_payment(&to_revert);
+                }
+                (succeeded, payment_shard.failure_reason)
+            };
+        let candidate_path = path_finder(&simulator).find_path().unwrap();
+        assert_eq!(attempt(&mut simulator, 0, &candidate_path), (true, None));
+        // alice's channel to bob and bob's channel to carol
+        assert_eq!(simulator.graph.pending_htlcs(), 2);
+        assert_eq!(simulator.in_flight[&0].len(), 2);
+        assert!(simulator.graph.locked_in_htlcs() > 0);
+        // bob has a single channel to carol
+        assert!(path_finder(&simulator).find_path().is_none());
+        assert_eq!(
+            attempt(&mut simulator, 1, &candidate_path),
+            (false, Some(FailureReason::NoHtlcSlots))
+        );
+        simulator.release_htlcs(0);
+        assert_eq!(simulator.graph.pending_htlcs(), 0);
+        assert_eq!(simulator.graph.locked_in_htlcs(), 0);
+        assert!(simulator.in_flight.is_empty());
+        let candidate_path = path_finder(&simulator).find_path().unwrap();
+        assert_eq!(attempt(&mut simulator, 2, &candidate_path), (true, None));
+    }
+
     #[test]
     #[ignore] // takes too long
     fn failing_channel_is_removed() {
@@ -551,7 +1259,7 @@ pub(crate) mod tests {
         let amount = 500000;
         let path = std::path::Path::new("../data/gossip-20210906_1000UTC.json");
         let graph = Graph::to_sim_graph(
-            &network_parser::Graph::from_json_file(&path, network_parser::GraphSource::Lnresearch)
+            &network_parser::Graph::from_json_file(path, network_parser::GraphSource::Lnresearch)
                 .unwrap(),
             network_parser::GraphSource::Lnresearch,
         );
@@ -583,6 +1291,10 @@ pub(crate) mod tests {
             failed_amounts: Vec::default(),
             successful_shards: Vec::default(),
             failed_paths: vec![],
+            completion_time_ms: None,
+            max_fee_msat: None,
+            parts: None,
+            ..Default::default()
         };
         simulator.add_invoice(Invoice::new(0, amount, &source, &dest));
         assert!(simulator.send_single_payment(payment));
@@ -611,6 +1323,10 @@ pub(crate) mod tests {
             failed_amounts: Vec::default(),
             successful_shards: Vec::default(),
             failed_paths: vec![],
+            completion_time_ms: None,
+            max_fee_msat: None,
+            parts: None,
+            ..Default::default()
         };
         assert!(!simulator.send_single_payment(payment));
     }
@@ -1,7 +1,9 @@
-use crate::{traversal::pathfinding::CandidatePath, PaymentId, ID};
+use crate::{
+    htlc::Htlc, time::Time, traversal::pathfinding::CandidatePath, PaymentId, PaymentParts, ID,
+};
 
 use log::error;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Default, Serialize)]
 pub struct Payment {
@@ -23,6 +25,33 @@ pub struct Payment {
     pub(crate) failed_amounts: Vec<usize>,
     pub(crate) successful_shards: Vec<(ID, String, usize)>,
     pub(crate) failed_paths: Vec<CandidatePath>,
+    /// Time in ms from dispatching the payment until the sender learnt its result, summed over
+    /// all attempts. Only recorded with a latency model.
+    pub completion_time_ms: Option<u64>,
+    /// The most the sender pays in fees, shared between shards by their amount
+    pub(crate) max_fee_msat: Option<usize>,
+    /// Sent as single or multi-part payment regardless of the simulation's [PaymentParts]
+    pub(crate) parts: Option<PaymentParts>,
+    /// Simulation time at which the payment is dispatched
+    #[serde(skip)]
+    pub(crate) dispatch_time: Time,
+}
+
+/// Why an attempt failed at a hop
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum FailureReason {
+    /// The hop's balance does not cover the amount it should forward
+    InsufficientBalance,
+    /// The receiving side of the channel would exceed its capacity
+    ExceedsCapacity,
+    /// The destination has no invoice for the payment
+    NoInvoice,
+    /// The amount is outside the hop's htlc_minimum_msat and htlc_maximum_msat
+    HtlcLimits,
+    /// The amount is below the channel's dust limit
+    BelowDustLimit,
+    /// The next hop accepts no further HTLCs over the channel until some are resolved
+    NoHtlcSlots,
 }
 
 #[derive(Debug, Clone)]
@@ -38,6 +67,10 @@ pub struct PaymentShard {
     pub(crate) min_shard_amt: usize,
     pub(crate) htlc_attempts: usize,
     pub(crate) failed_paths: Vec<CandidatePath>,
+    /// Set if the last attempt failed
+    pub(crate) failure_reason: Option<FailureReason>,
+    /// HTLCs the last attempt offered along its path
+    pub(crate) htlcs: Vec<Htlc>,
 }
 
 impl Payment {
@@ -65,9 +98,18 @@ impl Payment {
             failed_amounts: Vec::default(),
             successful_shards: Vec::default(),
             failed_paths: Vec::default(),
+            completion_time_ms: None,
+            max_fee_msat: None,
+            parts: None,
+            dispatch_time: Time::default(),
         }
     }
 
+    /// See [Payment::completion_time_ms], zero if not recorded
+    pub(crate) fn completion_time(&self) -> Time {
+        Time::from_millis(self.completion_time_ms.unwrap_or_default() as f32)
+    }
+
     /// All payments are sent as shards, regardless of mpp or single
     pub(crate) fn to_shard(&self, amount: usize) -> PaymentShard {
         PaymentShard::new(self, amount)
@@ -92,7 +134,7 @@ impl Payment {
         } else {
             // ceil one, floor the either
             let prev_amt = amt_to_split;
-            let shard1_amount = (prev_amt + 2 - 1) / 2;
+            let shard1_amount = prev_amt.div_ceil(2);
             let shard2_amount = prev_amt / 2;
             assert_eq!(
                 shard1_amount + shard2_amount,
@@ -101,14 +143,21 @@ impl Payment {
                 shard1_amount + shard2_amount,
                 amt_to_split
             );
+            let max_fee = |amount: usize| {
+                payment
+                    .max_fee_msat
+                    .map(|max_fee| max_fee * amount / amt_to_split)
+            };
             let shard1 = Payment {
                 amount_msat: shard1_amount,
                 htlc_attempts: 0,
+                max_fee_msat: max_fee(shard1_amount),
                 ..payment.clone()
             };
             let shard2 = Payment {
                 amount_msat: shard2_amount,
                 htlc_attempts: 0,
+                max_fee_msat: max_fee(shard2_amount),
                 ..payment.clone()
             };
             Some((shard1, shard2))
@@ -128,6 +177,8 @@ impl PaymentShard {
             succeeded: payment.succeeded,
             htlc_attempts: payment.htlc_attempts,
             failed_paths: payment.failed_paths.clone(),
+            failure_reason: None,
+            htlcs: Vec::default(),
         }
     }
 
@@ -145,6 +196,10 @@ impl PaymentShard {
             failed_amounts: Vec::default(),
             successful_shards: Vec::default(),
             failed_paths: self.failed_paths.clone(),
+            completion_time_ms: None,
+            max_fee_msat: None,
+            parts: None,
+            dispatch_time: Time::default(),
         }
     }
 }
@@ -187,6 +242,10 @@ mod tests {
             failed_amounts: Vec::default(),
             successful_shards: Vec::default(),
             failed_paths: vec![],
+            completion_time_ms: None,
+            max_fee_msat: None,
+            parts: None,
+            ..Default::default()
         };
         assert_eq!(actual, expected);
         assert_eq!(actual.succeeded, expected.succeeded);
@@ -214,6 +273,10 @@ mod tests {
             failed_amounts: Vec::default(),
             successful_shards: Vec::default(),
             failed_paths: vec![],
+            completion_time_ms: None,
+            max_fee_msat: None,
+            parts: None,
+            ..Default::default()
         };
         let shard = payment.to_shard(amount);
         assert_eq!(shard.payment_id, id);
@@ -243,6 +306,10 @@ mod tests {
             failed_amounts: Vec::default(),
             successful_shards: Vec::default(),
             failed_paths: vec![],
+            completion_time_ms: None,
+            max_fee_msat: None,
+            parts: None,
+            ..Default::default()
         };
         let actual = Payment::split_payment(&payment).unwrap();
         let expected = (
@@ -277,6 +344,10 @@ mod tests {
             failed_amounts: Vec::default(),
             successful_shards: Vec::default(),
             failed_paths: vec![],
+            completion_time_ms: None,
+            max_fee_msat: None,
+            parts: None,
+            ..Default::default()
         };
         assert!(Payment::split_payment(&payment).is_none());
     }
@@ -299,6 +370,10 @@ mod tests {
             failed_amounts: Vec::default(),
             successful_shards: Vec::default(),
             failed_paths: vec![],
+            completion_time_ms: None,
+            max_fee_msat: None,
+            parts: None,
+            ..Default::default()
         };
         let actual = Payment::split_payment(&payment).unwrap();
         let expected = (
@@ -342,6 +417,10 @@ mod tests {
             failed_amounts: Vec::default(),
             successful_shards: Vec::default(),
             failed_paths: vec![],
+            completion_time_ms: None,
+            max_fee_msat: None,
+            parts: None,
+            ..Default::default()
         };
         assert_eq!(actual, expected);
         assert_eq!(actual.succeeded, expected.succeeded);
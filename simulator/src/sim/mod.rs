@@ -5,6 +5,8 @@ use crate::{
 use serde::Serialize;
 
 mod simulator;
+mod topology;
+mod trace;
 pub use simulator::*;
 
 #[derive(Debug, Default, Serialize, Clone, PartialEq)]
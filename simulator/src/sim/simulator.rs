@@ -1,15 +1,25 @@
 use crate::{
+    amounts::AmountDistribution,
+    arrival::ArrivalProcess,
     core_types::graph::Graph,
     event::*,
+    htlc::{HoldInvoices, Htlc, MIN_FINAL_CLTV_EXPIRY_DELTA},
+    io::AmountBucket,
+    latency::{HtlcMessage, HtlcUpdate, LatencyModel},
     payment::Payment,
     sim::SimResult,
     stats::{Adversaries, PathDistances, PathDiversity},
     time::Time,
+    topology::{ChurnProcess, TopologyEvent},
+    trace::EventTrace,
+    workload::{unknown_nodes, WorkloadRecord},
     AdversarySelection, Invoice, PaymentId, PaymentParts, RoutingMetric, WeightPartsCombi, ID,
 };
-use log::{debug, error, info};
-use rand::{seq::IteratorRandom, SeedableRng};
+use log::{debug, error, info, warn};
+use network_parser::{Edge, ExportFormat, NodeAttributes};
+use rand::{rngs::StdRng, seq::IteratorRandom, SeedableRng};
 use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
 
 #[derive(Clone)]
 pub struct Simulation {
@@ -18,7 +28,7 @@ pub struct Simulation {
     /// Payment amount to simulate
     pub(crate) amount: usize,
     /// Sim seed
-    run: u64,
+    pub(crate) run: u64,
     /// Fee minimisation or probability maximisation
     pub(crate) routing_metric: RoutingMetric,
     /// Single or multi-path
@@ -42,6 +52,36 @@ pub struct Simulation {
     pub(crate) path_distances: PathDistances,
     pub(crate) path_diversity: PathDiversity,
     pub(crate) adversary_selection: Vec<AdversarySelection>,
+    /// How long HTLCs stay in flight before they are settled or failed back. Settled
+    /// immediately if not set.
+    pub(crate) htlc_hold_time: Option<Time>,
+    /// HTLCs of each payment that are still in flight
+    pub(crate) in_flight: HashMap<PaymentId, Vec<Htlc>>,
+    /// Delays of HTLC messages between hops. Routing is instantaneous if not set.
+    pub(crate) latency: Option<LatencyModel>,
+    /// Samples the delays, separate from [crate::RNG] so that the delays do not change which
+    /// payments are made and how they are routed
+    pub(crate) latency_rng: StdRng,
+    /// HTLC messages nodes received, in the order of the simulation time
+    pub(crate) htlc_updates: Vec<HtlcUpdate>,
+    /// When payments are dispatched
+    pub(crate) arrivals: ArrivalProcess,
+    /// How much each payment transfers
+    pub(crate) amounts: AmountDistribution,
+    /// Receivers that delay settling the payments to them
+    pub(crate) hold_invoices: HoldInvoices,
+    /// HTLCs that timed out on-chain as they were not resolved before their expiry
+    pub(crate) expired_htlcs: Vec<Htlc>,
+    /// Changes to the graph at given times
+    pub(crate) topology_events: Vec<TopologyEvent>,
+    /// Generates further changes to the graph
+    pub(crate) churn: Option<ChurnProcess>,
+    /// Channels of nodes that are offline, removed from the graph until they are online again
+    pub(crate) offline_channels: HashMap<ID, Vec<Edge>>,
+    /// Events, path attempts and balance changes, only recorded if enabled
+    pub(crate) trace: Option<EventTrace>,
+    /// Payments to make instead of the pairs passed to a run
+    pub(crate) workload: Option<Vec<WorkloadRecord>>,
 }
 
 impl Simulation {
@@ -80,9 +120,166 @@ impl Simulation {
             path_distances: PathDistances(vec![]),
             adversary_selection: adversary_selection.to_owned(),
             path_diversity: PathDiversity(vec![]),
+            htlc_hold_time: None,
+            in_flight: HashMap::default(),
+            latency: None,
+            latency_rng: StdRng::seed_from_u64(run),
+            htlc_updates: Vec::new(),
+            arrivals: ArrivalProcess::default(),
+            amounts: AmountDistribution::default(),
+            hold_invoices: HoldInvoices::default(),
+            expired_htlcs: Vec::new(),
+            topology_events: Vec::new(),
+            churn: None,
+            offline_channels: HashMap::default(),
+            trace: None,
+            workload: None,
         }
     }
 
+    /// Each hop delays the HTLC messages passed to it according to the model
+    pub fn set_latency_model(&mut self, latency: LatencyModel) {
+        self.latency = Some(latency);
+    }
+
+    /// The HTLC messages nodes received, only recorded with a latency model
+    pub fn htlc_updates(&self) -> &[HtlcUpdate] {
+        &self.htlc_updates
+    }
+
+    /// The receivers hold the HTLCs of payments delivered to them before settling them
+    pub fn set_hold_invoices(&mut self, hold_invoices: HoldInvoices) {
+        self.hold_invoices = hold_invoices;
+    }
+
+    /// HTLCs that timed out on-chain
+    pub fn expired_htlcs(&self) -> &[Htlc] {
+        &self.expired_htlcs
+    }
+
+    /// Applies the changes to the graph at their times during the run
+    pub fn set_topology_events(&mut self, events: Vec<TopologyEvent>) {
+        self.topology_events = events;
+    }
+
+    /// Generates random changes to the graph until the last payment is dispatched
+    pub fn set_churn(&mut self, churn: ChurnProcess) {
+        self.churn = Some(churn);
+    }
+
+    /// When payments are dispatched, every [crate::SIM_DELAY_IN_SECS] by default
+    pub fn set_arrival_process(&mut self, arrivals: ArrivalProcess) {
+        self.arrivals = arrivals;
+    }
+
+    /// How much each payment transfers, the simulation's amount by default
+    pub fn set_amount_distribution(&mut self, amounts: AmountDistribution) {
+        self.amounts = amounts;
+    }
+
+    /// Makes the payments of the workload instead of those between the pairs passed to a run.
+    /// Fails with the nodes that are not in the graph.
+    pub fn set_workload(&mut self, records: Vec<WorkloadRecord>) -> Result<(), Vec<ID>> {
+        let unknown = unknown_nodes(&records, &self.graph);
+        if !unknown.is_empty() {
+            return Err(unknown);
+        }
+        self.workload = Some(records);
+        Ok(())
+    }
+
+    /// Schedules a payment for each pair at the times of the arrival process and returns how
+    /// many were scheduled
+    pub(crate) fn schedule_payments(
+        &mut self,
+        payment_pairs: impl Iterator<Item = (ID, ID)>,
+        min_shard_amt: Option<usize>,
+    ) -> usize {
+        let payment_pairs: Vec<(ID, ID)> = payment_pairs.collect();
+        if let Some(workload) = self.workload.clone() {
+            if !payment_pairs.is_empty() {
+                warn!(
+                    "Ignoring {} payment pairs in favour of the workload.",
+                    payment_pairs.len()
+                );
+            }
+            return self.schedule_workload(workload, min_shard_amt);
+        }
+        let mut rng = StdRng::seed_from_u64(self.run);
+        let arrival_times = self.arrivals.arrival_times(payment_pairs.len(), &mut rng);
+        if arrival_times.len() < payment_pairs.len() {
+            warn!(
+                "Only {} arrival times for {} payment pairs, dropping the remaining pairs.",
+                arrival_times.len(),
+                payment_pairs.len()
+            );
+        }
+        // drawn after the arrival times so that these do not depend on the amounts
+        let amounts = self
+            .amounts
+            .amounts(payment_pairs.len(), self.amount, &mut rng);
+        if amounts.len() < payment_pairs.len() {
+            warn!(
+                "Only {} amounts for {} payment pairs, dropping the remaining pairs.",
+                amounts.len(),
+                payment_pairs.len()
+            );
+        }
+        let num_scheduled = arrival_times.len().min(amounts.len());
+        for (((src, dest), time), amount) in
+            payment_pairs.into_iter().zip(arrival_times).zip(amounts)
+        {
+            let payment_id = self.next_payment_id();
+            let payment = Payment::new(payment_id, src, dest, amount, min_shard_amt);
+            self.schedule_payment(payment, time);
+        }
+        num_scheduled
+    }
+
+    fn schedule_workload(
+        &mut self,
+        workload: Vec<WorkloadRecord>,
+        min_shard_amt: Option<usize>,
+    ) -> usize {
+        let num_scheduled = workload.len();
+        for record in workload {
+            let payment_id = self.next_payment_id();
+            let payment = Payment {
+                max_fee_msat: record.max_fee_msat,
+                parts: record.parts,
+                ..Payment::new(
+                    payment_id,
+                    record.source,
+                    record.destination,
+                    record.amount_msat,
+                    min_shard_amt,
+                )
+            };
+            self.schedule_payment(payment, Time::from_secs(record.time_secs as f32));
+        }
+        num_scheduled
+    }
+
+    /// Issues the payment's invoice and dispatches the payment after the delay
+    pub(crate) fn schedule_payment(&mut self, mut payment: Payment, delay: Time) {
+        let invoice = Invoice::new(
+            payment.payment_id,
+            payment.amount_msat,
+            &payment.source,
+            &payment.dest,
+        );
+        self.add_invoice(invoice);
+        payment.dispatch_time = self.event_queue.now() + delay;
+        let event = PaymentEvent::Scheduled { payment };
+        self.event_queue.schedule(delay, event);
+    }
+
+    /// Keeps the HTLCs of each payment in flight for the given number of seconds so that they
+    /// lock liquidity and HTLC slots on every hop while later payments are routed
+    pub fn set_htlc_hold_time(&mut self, secs: f32) {
+        self.htlc_hold_time = (secs > 0.0).then(|| Time::from_secs(secs));
+    }
+
     pub fn new_batch_simulator(
         run: u64,
         graph: Graph,
@@ -108,6 +305,64 @@ impl Simulation {
         )
     }
 
+    /// Processes one event of the simulation
+    pub(crate) fn handle_event(&mut self, event: PaymentEvent) {
+        self.trace_event(&event);
+        match event {
+            PaymentEvent::Scheduled { mut payment } => {
+                debug!(
+                    "Dispatching scheduled payment {} at simulation time = {}.",
+                    payment.payment_id,
+                    self.event_queue.now()
+                );
+                let _ = match payment.parts.unwrap_or(self.payment_parts) {
+                    PaymentParts::Single => self.send_single_payment(&mut payment),
+                    PaymentParts::Split => self.send_mpp_payment(&mut payment),
+                };
+            }
+            PaymentEvent::UpdateFailed { payment } => {
+                self.num_failed += 1;
+                self.failed_payments.push(payment.to_owned());
+            }
+            PaymentEvent::UpdateSuccesful { payment } => {
+                self.num_successful += 1;
+                self.successful_payments.push(payment.to_owned());
+            }
+            PaymentEvent::SettleHtlcs { payment_id } => self.release_htlcs(payment_id),
+            PaymentEvent::UpdateAddHtlc { payment_id, node } => {
+                self.record_htlc_update(payment_id, node, HtlcMessage::UpdateAddHtlc)
+            }
+            PaymentEvent::UpdateFulfillHtlc {
+                payment_id,
+                node,
+                htlc,
+            } => {
+                self.record_htlc_update(payment_id, node, HtlcMessage::UpdateFulfillHtlc);
+                self.release_htlc(payment_id, &htlc);
+            }
+            PaymentEvent::UpdateFailHtlc {
+                payment_id,
+                node,
+                htlc,
+            } => {
+                self.record_htlc_update(payment_id, node, HtlcMessage::UpdateFailHtlc);
+                self.release_htlc(payment_id, &htlc);
+            }
+            PaymentEvent::FailHtlcs {
+                payment_id,
+                to_revert,
+            } => {
+                self.revert_payment(&to_revert);
+                self.release_htlcs(payment_id);
+            }
+            PaymentEvent::ExpireHtlcs {
+                payment_id,
+                to_revert,
+            } => self.expire_htlcs(payment_id, &to_revert),
+            PaymentEvent::ChangeTopology { change } => self.change_topology(change),
+        }
+    }
+
     pub fn run(
         &mut self,
         payment_pairs: impl Iterator<Item = (ID, ID)> + Clone,
@@ -120,17 +375,8 @@ impl Simulation {
             self.routing_metric,
             self.payment_parts
         );
-        let mut now = Time::from_secs(0.0); // start simulation at (0)
-        for (src, dest) in payment_pairs {
-            let payment_id = self.next_payment_id();
-            let invoice = Invoice::new(payment_id, self.amount, &src, &dest);
-            self.add_invoice(invoice);
-            let payment = Payment::new(payment_id, src, dest, self.amount, min_shard_amt);
-            let event = PaymentEvent::Scheduled { payment };
-            self.event_queue.schedule(now, event);
-            now += Time::from_secs(crate::SIM_DELAY_IN_SECS);
-        }
-        self.total_num_payments = self.event_queue.queue_length();
+        self.total_num_payments = self.schedule_payments(payment_pairs, min_shard_amt);
+        self.schedule_topology_changes();
         debug!(
             "Queued {} events for simulation.",
             self.event_queue.queue_length()
@@ -139,28 +385,9 @@ impl Simulation {
         info!("Starting simulation.");
         // this is where the actual simulation happens
         while let Some(event) = self.event_queue.next() {
-            match event {
-                PaymentEvent::Scheduled { mut payment } => {
-                    debug!(
-                        "Dispatching scheduled payment {} at simulation time = {}.",
-                        payment.payment_id,
-                        self.event_queue.now()
-                    );
-                    let _ = match self.payment_parts {
-                        PaymentParts::Single => self.send_single_payment(&mut payment),
-                        PaymentParts::Split => self.send_mpp_payment(&mut payment),
-                    };
-                }
-                PaymentEvent::UpdateFailed { payment } => {
-                    self.num_failed += 1;
-                    self.failed_payments.push(payment.to_owned());
-                }
-                PaymentEvent::UpdateSuccesful { payment } => {
-                    self.num_successful += 1;
-                    self.successful_payments.push(payment.to_owned());
-                }
-            }
+            self.handle_event(event);
         }
+        self.finish_trace();
         assert_eq!(
             self.num_successful + self.num_failed,
             self.total_num_payments,
@@ -168,12 +395,60 @@ impl Simulation {
         );
         info!(
             "Completed simulation after {} simulation secs.",
-            now.as_secs(),
+            self.event_queue.now().as_secs(),
         );
         info!(
             "# Total payments = {}, # successful {}, # failed = {}.",
             self.total_num_payments, self.num_successful, self.num_failed
         );
+        if self.amounts != AmountDistribution::Fixed {
+            let amounts = self
+                .successful_payments
+                .iter()
+                .chain(self.failed_payments.iter())
+                .map(|p| (crate::to_sat(p.amount_msat), p.succeeded));
+            for bucket in AmountBucket::from_amounts(amounts) {
+                info!(
+                    "Payments of {} to {} sat: # total {}, # successful {}, # failed {}.",
+                    bucket.lower,
+                    bucket.upper,
+                    bucket.total_num,
+                    bucket.num_succesful,
+                    bucket.num_failed
+                );
+            }
+        }
+        let completion_times: Vec<u64> = self
+            .successful_payments
+            .iter()
+            .filter_map(|p| p.completion_time_ms)
+            .collect();
+        if !completion_times.is_empty() {
+            info!(
+                "Successful payments completed after {} ms on average.",
+                completion_times.iter().sum::<u64>() / completion_times.len() as u64
+            );
+        }
+        // the sender's HTLC expires last so that is how long liquidity could stay locked
+        if let Some(max_lock) = self
+            .successful_payments
+            .iter()
+            .flat_map(|p| p.used_paths.iter())
+            .map(|path| path.time + MIN_FINAL_CLTV_EXPIRY_DELTA)
+            .max()
+        {
+            info!(
+                "HTLCs of successful payments could have locked liquidity for up to {} blocks.",
+                max_lock
+            );
+        }
+        if !self.expired_htlcs.is_empty() {
+            info!(
+                "{} HTLCs expired on-chain by block height {}.",
+                self.expired_htlcs.len(),
+                self.event_queue.block_height()
+            );
+        }
         self.eval_adversaries(run_all_adversary_scenarios);
         self.eval_path_similarity();
         SimResult {
@@ -193,13 +468,13 @@ impl Simulation {
     pub fn draw_n_pairs_for_simulation(
         graph: &Graph,
         n: usize,
-    ) -> (impl Iterator<Item = (ID, ID)> + Clone) {
+    ) -> impl Iterator<Item = (ID, ID)> + Clone {
         info!("Drawing {} sender-receiver pairs for simulation.", n,);
         let g = graph.clone();
         g.get_random_pairs_of_nodes(n)
     }
 
-    pub fn draw_adversaries(nodes: &[ID], num_adv: usize) -> (impl Iterator<Item = ID> + Clone) {
+    pub fn draw_adversaries(nodes: &[ID], num_adv: usize) -> impl Iterator<Item = ID> + Clone {
         let mut rng = crate::RNG.lock().unwrap();
         nodes
             .iter()
@@ -208,6 +483,25 @@ impl Simulation {
             .into_iter()
     }
 
+    /// The number of times each node was included in a payment path
+    pub fn node_hits(&self) -> &HashMap<ID, usize> {
+        &self.node_hits
+    }
+
+    /// Writes the graph including the current balances and each node's hits
+    pub fn export_graph(&self, path: &Path, format: ExportFormat) -> std::io::Result<()> {
+        let node_attributes =
+            NodeAttributes::from([("node_hits".to_owned(), self.node_hits.clone())]);
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.graph.export(file, format, &node_attributes)
+    }
+
+    /// Writes the current balance of each channel direction so that another simulation can
+    /// start from it using [crate::balances::read_channel_balances]
+    pub fn export_balances(&self, path: &Path) -> std::io::Result<()> {
+        crate::balances::write_channel_balances(path, &self.graph.channel_balances())
+    }
+
     pub(crate) fn add_invoice(&mut self, invoice: Invoice) {
         // Has this node already issued invoices?
         match self.outstanding_invoices.get_mut(&invoice.destination) {
@@ -251,10 +545,44 @@ impl Simulation {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
 
     use super::*;
-    use std::path::Path;
+    use crate::htlc::InvoiceHold;
+    use crate::reserve::{ChannelLimits, ChannelReserves};
+    use crate::workload::WorkloadRecord;
+    use itertools::Itertools;
+
+    /// The lnbook example with half of every channel's capacity on either side, so payments
+    /// succeed in both directions
+    pub(crate) fn lnbook_graph() -> Graph {
+        let path_to_file = Path::new("../test_data/lnbook_example.json");
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                path_to_file,
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        for e in graph.edges.values_mut().flatten() {
+            e.balance = e.capacity / 2;
+        }
+        graph
+    }
+
+    /// Sends 10 sat payments over [lnbook_graph] without adversaries
+    pub(crate) fn lnbook_sim(payment_parts: PaymentParts) -> Simulation {
+        Simulation::new(
+            1,
+            lnbook_graph(),
+            crate::to_millisatoshi(10),
+            RoutingMetric::MinFee,
+            payment_parts,
+            Some(vec![0]),
+            &[AdversarySelection::Random],
+        )
+    }
 
     #[test]
     fn init_simulator() {
@@ -263,7 +591,7 @@ mod tests {
         let path_to_file = Path::new("../test_data/trivial.json");
         let graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &path_to_file,
+                path_to_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -290,7 +618,7 @@ mod tests {
         let path_to_file = Path::new("../test_data/trivial.json");
         let graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &path_to_file,
+                path_to_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -306,7 +634,7 @@ mod tests {
         let path_to_file = Path::new("../test_data/trivial_connected.json");
         let graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &path_to_file,
+                path_to_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -328,7 +656,7 @@ mod tests {
         let path_to_file = Path::new("../test_data/trivial.json");
         let graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &path_to_file,
+                path_to_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -361,11 +689,7 @@ mod tests {
         );
         simulator.add_invoice(invoice2.clone());
         assert_eq!(simulator.outstanding_invoices.len(), 1);
-        let actual = simulator
-            .outstanding_invoices
-            .get(&"dina".to_owned())
-            .unwrap()
-            .clone();
+        let actual = simulator.outstanding_invoices.get("dina").unwrap().clone();
         let expected = HashMap::from([(invoice.id, invoice), (invoice2.id, invoice2)]);
         assert_eq!(actual, expected);
     }
@@ -377,7 +701,7 @@ mod tests {
         let path_to_file = Path::new("../test_data/trivial.json");
         let graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &path_to_file,
+                path_to_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -423,7 +747,7 @@ mod tests {
         let path_to_file = Path::new("../test_data/trivial.json");
         let graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &path_to_file,
+                path_to_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -458,7 +782,7 @@ mod tests {
         let path_to_file = Path::new("../test_data/lnbook_example.json");
         let graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &path_to_file,
+                path_to_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -500,4 +824,342 @@ mod tests {
         }
         assert_eq!(expected_hits, simulator.node_hits);
     }
+
+    #[test]
+    fn htlcs_are_resolved_by_later_events() {
+        let mut simulator = lnbook_sim(PaymentParts::Split);
+        // longer than the delay between payments, so they overlap
+        simulator.set_htlc_hold_time(1000.0);
+        let pairs = vec![
+            ("alice".to_owned(), "dina".to_owned()),
+            ("dina".to_owned(), "alice".to_owned()),
+            ("bob".to_owned(), "chan".to_owned()),
+        ];
+        simulator.run(pairs.clone().into_iter(), None, false);
+        assert_eq!(simulator.num_successful + simulator.num_failed, pairs.len());
+        assert!(simulator.in_flight.is_empty());
+        assert_eq!(simulator.graph.pending_htlcs(), 0);
+        assert_eq!(simulator.graph.locked_in_htlcs(), 0);
+        for e in simulator.graph.get_edges().values().flatten() {
+            assert_eq!(e.liquidity, e.balance);
+        }
+    }
+
+    #[test]
+    fn hold_invoices_delay_settlement() {
+        let balances = |graph: &Graph| -> Vec<usize> {
+            graph
+                .get_edges()
+                .values()
+                .flatten()
+                .map(|e| e.balance)
+                .sorted()
+                .collect()
+        };
+        let mut simulator = lnbook_sim(PaymentParts::Single);
+        // chan settles an hour late while dina never does
+        simulator.set_hold_invoices(HoldInvoices::from([
+            ("chan".to_owned(), InvoiceHold::Secs(3600.0)),
+            ("dina".to_owned(), InvoiceHold::Forever),
+        ]));
+        let pairs = vec![
+            ("alice".to_owned(), "dina".to_owned()),
+            ("bob".to_owned(), "chan".to_owned()),
+        ];
+        simulator.run(pairs.into_iter(), None, false);
+        assert_eq!(simulator.num_successful, 1);
+        assert_eq!(simulator.successful_payments[0].dest, "chan");
+        assert_eq!(simulator.num_failed, 1);
+        assert!(simulator.failed_payments[0].used_paths.is_empty());
+        // all HTLCs of alice's payment expired once the one offered to dina did
+        let final_delta = crate::htlc::MIN_FINAL_CLTV_EXPIRY_DELTA;
+        let expiries: Vec<usize> = simulator.expired_htlcs().iter().map(|h| h.expiry).collect();
+        assert_eq!(expiries.len(), 3);
+        assert_eq!(expiries.iter().min(), Some(&final_delta));
+        assert!(expiries.windows(2).all(|w| w[0] > w[1]));
+        assert!(simulator.in_flight.is_empty());
+        assert_eq!(simulator.graph.pending_htlcs(), 0);
+
+        // the stuck payment's transfers were reverted, only the settled one moved funds
+        let mut settled = lnbook_sim(PaymentParts::Single);
+        settled.run(
+            vec![("bob".to_owned(), "chan".to_owned())].into_iter(),
+            None,
+            false,
+        );
+        assert_eq!(balances(&simulator.graph), balances(&settled.graph));
+    }
+
+    #[test]
+    fn receiver_spends_held_amount() {
+        let mut simulator = lnbook_sim(PaymentParts::Single);
+        let chan_balance =
+            |graph: &Graph| graph.get_channel_balance(&"chan".to_owned(), &"chan1".to_owned());
+        let held = 10000;
+        // chan returns all but the held amount and then spends most of that before its held
+        // HTLCs expire
+        let returned = chan_balance(&simulator.graph) - held;
+        let spent = 2 * held - 1;
+        simulator.set_hold_invoices(HoldInvoices::from([(
+            "chan".to_owned(),
+            InvoiceHold::Forever,
+        )]));
+        simulator
+            .set_amount_distribution(AmountDistribution::Sequence(vec![held, returned, spent]));
+        let pairs = vec![
+            ("bob".to_owned(), "chan".to_owned()),
+            ("chan".to_owned(), "bob".to_owned()),
+            ("chan".to_owned(), "bob".to_owned()),
+        ];
+        simulator.run(pairs.into_iter(), None, false);
+        assert_eq!(simulator.num_successful, 2);
+        assert_eq!(simulator.num_failed, 1);
+        assert_eq!(simulator.failed_payments[0].amount_msat, held);
+        assert!(!simulator.expired_htlcs().is_empty());
+        // reverting the held amount empties the channel instead of underflowing
+        assert_eq!(chan_balance(&simulator.graph), 0);
+    }
+
+    #[test]
+    fn payments_arrive_at_timestamps() {
+        let mut simulator = lnbook_sim(PaymentParts::Single);
+        // two payments at the same time and one pair without a timestamp
+        simulator.set_arrival_process(ArrivalProcess::Timestamps(vec![5.0, 5.0]));
+        let pairs = vec![
+            ("alice".to_owned(), "dina".to_owned()),
+            ("dina".to_owned(), "alice".to_owned()),
+            ("bob".to_owned(), "chan".to_owned()),
+        ];
+        simulator.run(pairs.into_iter(), None, false);
+        assert_eq!(simulator.total_num_payments, 2);
+        assert_eq!(simulator.num_successful + simulator.num_failed, 2);
+    }
+
+    #[test]
+    fn payments_carry_their_amounts() {
+        let mut simulator = lnbook_sim(PaymentParts::Single);
+        // the last amount exceeds every channel
+        simulator.set_amount_distribution(AmountDistribution::Sequence(vec![
+            5000,
+            20000,
+            crate::to_millisatoshi(1_000_000_000),
+        ]));
+        let pairs = vec![("alice".to_owned(), "dina".to_owned()); 4];
+        let result = simulator.run(pairs.into_iter(), None, false);
+        assert_eq!(simulator.total_num_payments, 3);
+        let mut amounts: Vec<(usize, bool)> = simulator
+            .successful_payments
+            .iter()
+            .chain(simulator.failed_payments.iter())
+            .map(|p| (p.amount_msat, p.succeeded))
+            .collect();
+        amounts.sort();
+        assert_eq!(
+            amounts,
+            vec![
+                (5000, true),
+                (20000, true),
+                (crate::to_millisatoshi(1_000_000_000), false)
+            ]
+        );
+        let report = crate::io::Report::sim_result_to_report(&result);
+        let buckets: Vec<(usize, usize, usize)> = report
+            .amount_buckets
+            .iter()
+            .map(|b| (b.lower, b.total_num, b.num_succesful))
+            .collect();
+        assert_eq!(buckets, vec![(1, 1, 1), (10, 1, 1), (1_000_000_000, 1, 0)]);
+    }
+
+    #[test]
+    fn workload_replaces_pairs() {
+        let mut simulator = lnbook_sim(PaymentParts::Single);
+        let record = |time_secs, source: &str, destination: &str, amount_msat| WorkloadRecord {
+            time_secs,
+            source: source.to_owned(),
+            destination: destination.to_owned(),
+            amount_msat,
+            max_fee_msat: None,
+            parts: None,
+        };
+        let unknown = vec![record(0.0, "alice", "eve", 1000)];
+        assert_eq!(simulator.set_workload(unknown), Err(vec!["eve".to_owned()]));
+        let workload = vec![
+            record(0.0, "alice", "dina", 5000),
+            WorkloadRecord {
+                parts: Some(PaymentParts::Split),
+                ..record(30.0, "dina", "alice", 20000)
+            },
+            // every path to dina charges fees
+            WorkloadRecord {
+                max_fee_msat: Some(0),
+                ..record(90.0, "alice", "dina", 5000)
+            },
+        ];
+        simulator.set_workload(workload).unwrap();
+        let pairs = vec![("bob".to_owned(), "chan".to_owned()); 5];
+        simulator.run(pairs.into_iter(), None, false);
+        assert_eq!(simulator.total_num_payments, 3);
+        let mut payments: Vec<(usize, &ID, usize, bool, Option<PaymentParts>)> = simulator
+            .successful_payments
+            .iter()
+            .chain(simulator.failed_payments.iter())
+            .map(|p| (p.payment_id, &p.source, p.amount_msat, p.succeeded, p.parts))
+            .collect();
+        payments.sort_by_key(|p| p.0);
+        let (alice, dina) = ("alice".to_owned(), "dina".to_owned());
+        assert_eq!(
+            payments,
+            vec![
+                (0, &alice, 5000, true, None),
+                (1, &dina, 20000, true, Some(PaymentParts::Split)),
+                (2, &alice, 5000, false, None),
+            ]
+        );
+    }
+
+    /// Sends the payments over trivial_multigraph with hops delaying HTLC messages by 50 ms
+    /// and carol by 100 ms
+    fn latency_sim(pairs: Vec<(ID, ID)>, reserves: ChannelReserves) -> Simulation {
+        let path_to_file = Path::new("../test_data/trivial_multigraph.json");
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                path_to_file,
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        for e in graph.edges.values_mut().flatten() {
+            e.balance = 200000;
+        }
+        graph.set_reserves(reserves);
+        let mut simulator = Simulation::new(
+            1,
+            graph,
+            10000,
+            RoutingMetric::MinFee,
+            PaymentParts::Single,
+            Some(vec![0]),
+            &[AdversarySelection::Random],
+        );
+        simulator.set_latency_model(LatencyModel {
+            hop_latency: crate::latency::HopLatency::Fixed(50.0),
+            node_latencies: HashMap::from([("carol".to_owned(), 100.0)]),
+        });
+        simulator.total_num_payments = simulator.schedule_payments(pairs.into_iter(), None);
+        simulator
+    }
+
+    /// Handles all events, returning each one's name, the ms since the first one and the
+    /// number of HTLCs in flight afterwards
+    fn handle_events(simulator: &mut Simulation) -> Vec<(&'static str, f32, usize)> {
+        let mut events = vec![];
+        let mut start = None;
+        while let Some(event) = simulator.event_queue.next() {
+            let name = event.name();
+            simulator.handle_event(event);
+            let now = simulator.event_queue.now().as_millis();
+            let since = now - *start.get_or_insert(now);
+            events.push((name, since, simulator.graph.pending_htlcs()));
+        }
+        events
+    }
+
+    #[test]
+    fn hops_delay_htlc_messages() {
+        let pairs = vec![("alice".to_owned(), "carol".to_owned())];
+        let mut simulator = latency_sim(pairs, ChannelReserves::default());
+        let events = handle_events(&mut simulator);
+        assert_eq!(simulator.num_successful, 1);
+        // alice -> bob -> carol and back
+        assert_eq!(
+            simulator.successful_payments[0].completion_time_ms,
+            Some(300)
+        );
+        let actual: Vec<(&str, HtlcMessage, f32)> = simulator
+            .htlc_updates()
+            .iter()
+            .map(|u| (u.node.as_str(), u.message, u.time_ms))
+            .collect();
+        let expected = vec![
+            ("bob", HtlcMessage::UpdateAddHtlc, 50.0),
+            ("carol", HtlcMessage::UpdateAddHtlc, 150.0),
+            ("bob", HtlcMessage::UpdateFulfillHtlc, 250.0),
+            ("alice", HtlcMessage::UpdateFulfillHtlc, 300.0),
+        ];
+        assert_eq!(actual, expected);
+        // alice's and bob's HTLCs are locked until the fulfil reaches them and the payment
+        // completes once it is back at alice
+        let expected = vec![
+            ("scheduled", 0.0, 2),
+            ("update_add_htlc", 50.0, 2),
+            ("update_add_htlc", 150.0, 2),
+            ("update_fulfill_htlc", 250.0, 1),
+            ("update_fulfill_htlc", 300.0, 0),
+            ("update_successful", 300.0, 0),
+        ];
+        assert_eq!(events, expected);
+        assert!(simulator.in_flight.is_empty());
+    }
+
+    #[test]
+    fn failed_htlcs_stay_locked_until_failed_back() {
+        // bob cannot forward to carol as the amount is dust there
+        let dust = ChannelLimits {
+            reserve_msat: 0,
+            dust_limit_msat: 1000000,
+        };
+        let reserves = ChannelReserves {
+            overrides: HashMap::from([("100x3x0".to_owned(), dust)]),
+            ..Default::default()
+        };
+        let pairs = vec![("alice".to_owned(), "carol".to_owned())];
+        let mut simulator = latency_sim(pairs, reserves);
+        let events = handle_events(&mut simulator);
+        assert_eq!(simulator.num_failed, 1);
+        // alice's HTLC is locked until bob's failure reaches her
+        let expected = vec![
+            ("scheduled", 0.0, 1),
+            ("update_add_htlc", 50.0, 1),
+            ("update_fail_htlc", 100.0, 0),
+            ("update_failed", 100.0, 0),
+        ];
+        assert_eq!(events, expected);
+        assert_eq!(simulator.failed_payments[0].completion_time_ms, Some(100));
+        assert!(simulator.in_flight.is_empty());
+    }
+
+    #[test]
+    fn chain_runs_using_exported_balances() {
+        let net_graph = network_parser::Graph::from_json_file(
+            Path::new("../test_data/lnbook_example.json"),
+            network_parser::GraphSource::Lnresearch,
+        )
+        .unwrap();
+        // enough liquidity in both directions for every payment to succeed
+        let mut simulator = lnbook_sim(PaymentParts::Single);
+        let start = simulator.graph.channel_balances();
+        let pairs = vec![
+            ("alice".to_owned(), "dina".to_owned()),
+            ("dina".to_owned(), "alice".to_owned()),
+            ("bob".to_owned(), "chan".to_owned()),
+        ];
+        simulator.run(pairs.into_iter(), None, false);
+        let end = simulator.graph.channel_balances();
+        assert_eq!(simulator.num_successful, 3);
+        assert_eq!(end.len(), start.len());
+        assert_ne!(end, start);
+        let path = std::env::temp_dir().join(format!("balances_{}.csv", std::process::id()));
+        simulator.export_balances(&path).unwrap();
+        let balances = crate::balances::read_channel_balances(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let next_day = Graph::to_sim_graph_with_balances(
+            &net_graph,
+            network_parser::GraphSource::Lnresearch,
+            &crate::balances::BalanceDistribution::Known(balances),
+        );
+        assert_eq!(next_day.channel_balances(), end);
+        assert_ne!(next_day.channel_balances(), start);
+    }
 }
@@ -0,0 +1,392 @@
+use crate::{event::PaymentEvent, time::Time, topology::TopologyChange, PaymentId, Simulation, ID};
+
+use log::{debug, info, warn};
+use network_parser::Edge;
+use rand::{rngs::StdRng, SeedableRng};
+
+impl Simulation {
+    /// Schedules the topology events and the changes the churn process generates until the last
+    /// payment is dispatched
+    pub(crate) fn schedule_topology_changes(&mut self) {
+        let mut events = self.topology_events.clone();
+        if let Some(churn) = &self.churn {
+            let horizon = self
+                .event_queue
+                .last_scheduled()
+                .unwrap_or(self.event_queue.now());
+            // sorted so that the seed determines the changes
+            let mut edges: Vec<&Edge> = self.graph.get_edges().values().flatten().collect();
+            edges.sort_by(|a, b| (&a.source, &a.channel_id).cmp(&(&b.source, &b.channel_id)));
+            let mut rng = StdRng::seed_from_u64(self.run);
+            events.extend(churn.events(&edges, horizon.as_secs() as f64, &mut rng));
+        }
+        if !events.is_empty() {
+            info!("Scheduled {} topology changes.", events.len());
+        }
+        for event in events {
+            self.event_queue.schedule(
+                event.time(),
+                PaymentEvent::ChangeTopology {
+                    change: event.change,
+                },
+            );
+        }
+    }
+
+    /// Applies the change to the graph used by all later payments
+    pub(crate) fn change_topology(&mut self, change: TopologyChange) {
+        debug!(
+            "Topology change at simulation time {}: {}.",
+            self.event_queue.now(),
+            change
+        );
+        match change {
+            TopologyChange::OpenChannel {
+                ref channel_id,
+                ref node1,
+                ref node2,
+                ..
+            } => {
+                if self.channel_exists(channel_id) {
+                    warn!("Channel {} exists already, not opening it.", channel_id);
+                    return;
+                }
+                let offline = [node1, node2]
+                    .into_iter()
+                    .find(|node| self.offline_channels.contains_key(*node))
+                    .cloned();
+                let edges = change.channel_edges().unwrap_or_default();
+                match offline {
+                    // usable once the node is online again
+                    Some(node) => self.offline_channels.entry(node).or_default().extend(edges),
+                    None => self.graph.add_edges(edges),
+                }
+            }
+            TopologyChange::CloseChannel { channel_id } => self.close_channel(channel_id),
+            TopologyChange::ForceCloseChannel { channel_id } => {
+                let htlcs = self.graph.htlcs_over(&channel_id);
+                if htlcs > 0 {
+                    debug!("{} HTLCs over {} are resolved on-chain.", htlcs, channel_id);
+                }
+                self.remove_channel(&channel_id);
+            }
+            TopologyChange::UpdatePolicy {
+                channel_id,
+                source,
+                policy,
+            } => {
+                let edge = match self.graph.get_channel_mut(&source, &channel_id) {
+                    Some(edge) => Some(edge),
+                    None => self
+                        .offline_channels
+                        .values_mut()
+                        .flatten()
+                        .find(|e| e.source == source && e.channel_id == channel_id),
+                };
+                match edge {
+                    Some(edge) => policy.apply(edge),
+                    None => debug!("No channel {} of {} to update.", channel_id, source),
+                }
+            }
+            TopologyChange::NodeOffline { node } => self.take_offline(node),
+            TopologyChange::NodeOnline { node } => self.bring_online(&node),
+        }
+    }
+
+    fn channel_exists(&self, channel_id: &str) -> bool {
+        self.graph.has_channel(channel_id)
+            || self
+                .offline_channels
+                .values()
+                .flatten()
+                .any(|e| e.channel_id == channel_id)
+    }
+
+    /// Stops forwarding over the channel and removes it once none of its HTLCs are in flight,
+    /// checking again every block
+    fn close_channel(&mut self, channel_id: String) {
+        if !self.channel_exists(&channel_id) {
+            debug!("Channel {} is closed already.", channel_id);
+        } else if self.graph.htlcs_over(&channel_id) > 0 {
+            self.graph.set_shutdown(&channel_id, true);
+            self.event_queue.schedule(
+                Time::from_blocks(1),
+                PaymentEvent::ChangeTopology {
+                    change: TopologyChange::CloseChannel { channel_id },
+                },
+            );
+        } else {
+            self.remove_channel(&channel_id);
+        }
+    }
+
+    fn remove_channel(&mut self, channel_id: &ID) {
+        let mut channel_ids = vec![channel_id.clone()];
+        if let Some(edge) = self
+            .graph
+            .get_edges()
+            .values()
+            .flatten()
+            .find(|e| e.channel_id == *channel_id)
+        {
+            channel_ids.extend(
+                self.graph
+                    .get_reverse_edge(edge)
+                    .map(|e| e.channel_id.clone()),
+            );
+        }
+        self.resolve_htlcs_on_chain(&channel_ids);
+        self.graph.remove_channel(channel_id);
+        self.graph.set_shutdown(channel_id, false);
+        for edges in self.offline_channels.values_mut() {
+            edges.retain(|e| e.channel_id != *channel_id);
+        }
+    }
+
+    /// Removes both directions of the node's channels until it is online again
+    fn take_offline(&mut self, node: ID) {
+        if self.offline_channels.contains_key(&node) {
+            return;
+        }
+        let mut channel_ids = vec![];
+        for edge in self.graph.get_outedges(&node) {
+            channel_ids.extend(
+                self.graph
+                    .get_reverse_edge(&edge)
+                    .map(|e| e.channel_id.clone()),
+            );
+            channel_ids.push(edge.channel_id);
+        }
+        // parked with the balances after the HTLCs are resolved
+        self.resolve_htlcs_on_chain(&channel_ids);
+        let mut edges = vec![];
+        for edge in self.graph.get_outedges(&node) {
+            if let Some(reverse_edge) = self.graph.get_reverse_edge(&edge) {
+                edges.push(reverse_edge.clone());
+            }
+            self.graph.remove_edge(&node, &edge.channel_id);
+            edges.push(edge);
+        }
+        self.offline_channels.insert(node, edges);
+    }
+
+    /// Resolves the HTLCs in flight over the channels on-chain before the channels leave the
+    /// graph. Payments waiting to be failed back or to expire are refunded, the HTLCs of all
+    /// others are settled and keep their transfers.
+    fn resolve_htlcs_on_chain(&mut self, channel_ids: &[ID]) {
+        let mut payment_ids: Vec<PaymentId> = self
+            .in_flight
+            .iter()
+            .filter(|(_, htlcs)| htlcs.iter().any(|h| channel_ids.contains(&h.channel_id)))
+            .map(|(payment_id, _)| *payment_id)
+            .collect();
+        payment_ids.sort();
+        for payment_id in payment_ids {
+            debug!("HTLCs of payment {} are resolved on-chain.", payment_id);
+            let refund = self.event_queue.take_first(|event| {
+                event.payment_id() == Some(payment_id)
+                    && matches!(
+                        event,
+                        PaymentEvent::FailHtlcs { .. } | PaymentEvent::ExpireHtlcs { .. }
+                    )
+            });
+            if let Some(
+                PaymentEvent::FailHtlcs { to_revert, .. }
+                | PaymentEvent::ExpireHtlcs { to_revert, .. },
+            ) = refund
+            {
+                self.revert_payment(&to_revert);
+            }
+            self.release_htlcs(payment_id);
+        }
+    }
+
+    /// Restores the node's channels except for those whose other node is offline, which it
+    /// restores once it is online
+    fn bring_online(&mut self, node: &ID) {
+        let Some(edges) = self.offline_channels.remove(node) else {
+            return;
+        };
+        let mut online = vec![];
+        for edge in edges {
+            let peer = if edge.source == *node {
+                &edge.destination
+            } else {
+                &edge.source
+            };
+            match self.offline_channels.get_mut(peer) {
+                Some(peer_edges) => peer_edges.push(edge),
+                None => online.push(edge),
+            }
+        }
+        self.graph.add_edges(online);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        htlc::{HoldInvoices, InvoiceHold},
+        sim::simulator::tests::lnbook_sim,
+        topology::{ChannelPolicy, ChurnProcess, TopologyChange, TopologyEvent},
+        PaymentParts, Simulation,
+    };
+
+    fn event(time_secs: f64, change: TopologyChange) -> TopologyEvent {
+        TopologyEvent { time_secs, change }
+    }
+
+    #[test]
+    fn changes_apply_to_later_payments() {
+        let mut simulator = lnbook_sim(PaymentParts::Single);
+        let (alice, dina) = (String::from("alice"), String::from("dina"));
+        // payments at 0 and 120 s, the direct channel opens in between
+        simulator.set_topology_events(vec![event(
+            60.0,
+            TopologyChange::OpenChannel {
+                channel_id: "alice-dina".to_owned(),
+                node1: alice.clone(),
+                node2: dina.clone(),
+                capacity_msat: crate::to_millisatoshi(100_000),
+                // both directions must carry the amount to be considered
+                push_msat: crate::to_millisatoshi(50_000),
+                node1_policy: ChannelPolicy::default(),
+                node2_policy: ChannelPolicy::default(),
+            },
+        )]);
+        let pairs = vec![(alice.clone(), dina.clone()), (alice.clone(), dina.clone())];
+        simulator.run(pairs.into_iter(), None, false);
+        assert_eq!(simulator.num_successful, 2);
+        let path_lengths: Vec<usize> = simulator
+            .successful_payments
+            .iter()
+            .map(|p| p.used_paths[0].path.path_length())
+            .collect();
+        assert!(path_lengths[0] > 1);
+        assert_eq!(path_lengths[1], 1);
+        assert!(simulator.graph.has_channel("alice-dina"));
+    }
+
+    #[test]
+    fn closes_and_offline_nodes() {
+        let mut simulator = lnbook_sim(PaymentParts::Single);
+        let (bob, chan) = (String::from("bob"), String::from("chan"));
+        let bob_chan = simulator.graph.get_edge(&bob, &chan).unwrap().channel_id;
+        let bob_channels = simulator.graph.get_outedges(&bob).len();
+        // a held HTLC delays the cooperative close
+        simulator.set_htlc_hold_time(1000.0);
+        simulator.change_topology(TopologyChange::NodeOffline { node: bob.clone() });
+        assert!(simulator.graph.get_outedges(&bob).is_empty());
+        assert!(simulator.graph.get_edge(&chan, &bob).is_none());
+        simulator.change_topology(TopologyChange::UpdatePolicy {
+            channel_id: bob_chan.clone(),
+            source: bob.clone(),
+            policy: ChannelPolicy {
+                fee_base_msat: 7,
+                ..Default::default()
+            },
+        });
+        simulator.change_topology(TopologyChange::NodeOffline { node: chan.clone() });
+        simulator.change_topology(TopologyChange::NodeOnline { node: bob.clone() });
+        // bob's channel to chan waits for chan
+        assert_eq!(simulator.graph.get_outedges(&bob).len(), bob_channels - 1);
+        simulator.change_topology(TopologyChange::NodeOnline { node: chan.clone() });
+        assert_eq!(simulator.graph.get_outedges(&bob).len(), bob_channels);
+        let updated = simulator.graph.get_channel(&bob, &bob_chan).unwrap();
+        assert_eq!(updated.fee_base_msat, 7);
+
+        simulator.run(vec![(bob.clone(), chan.clone())].into_iter(), None, false);
+        assert_eq!(simulator.num_successful, 1);
+        assert_eq!(simulator.graph.htlcs_over(&bob_chan), 0);
+        simulator.graph.add_htlc(&crate::htlc::Htlc {
+            source: bob.clone(),
+            channel_id: bob_chan.clone(),
+            amount: 1000,
+            expiry: 100,
+        });
+        simulator.change_topology(TopologyChange::CloseChannel {
+            channel_id: bob_chan.clone(),
+        });
+        // no new HTLCs while closing
+        assert!(simulator.graph.has_channel(&bob_chan));
+        let edge = simulator.graph.get_channel(&bob, &bob_chan).unwrap();
+        assert!(!simulator.graph.htlc_slot_available(edge));
+        simulator.change_topology(TopologyChange::ForceCloseChannel {
+            channel_id: bob_chan.clone(),
+        });
+        assert!(!simulator.graph.has_channel(&bob_chan));
+        assert!(simulator.graph.get_edge(&bob, &chan).is_none());
+    }
+
+    #[test]
+    fn cooperative_close_waits_for_htlcs() {
+        let mut simulator = lnbook_sim(PaymentParts::Single);
+        let (bob, chan) = (String::from("bob"), String::from("chan"));
+        let bob_chan = simulator.graph.get_edge(&bob, &chan).unwrap().channel_id;
+        simulator.set_htlc_hold_time(1000.0);
+        // the close is requested while the payment's HTLC is in flight
+        simulator.set_topology_events(vec![event(
+            10.0,
+            TopologyChange::CloseChannel {
+                channel_id: bob_chan.clone(),
+            },
+        )]);
+        simulator.run(vec![(bob.clone(), chan.clone())].into_iter(), None, false);
+        assert_eq!(simulator.num_successful, 1);
+        assert!(!simulator.graph.has_channel(&bob_chan));
+        // closed at the first block after the HTLCs were settled
+        assert!(simulator.event_queue.now() >= crate::time::Time::from_secs(1000.0));
+    }
+
+    #[test]
+    fn offline_receiver_refunds_held_htlcs() {
+        let mut simulator = lnbook_sim(PaymentParts::Single);
+        let (bob, chan) = (String::from("bob"), String::from("chan"));
+        let balances = |simulator: &Simulation| {
+            let mut balances: Vec<(String, String, usize)> = simulator
+                .graph
+                .get_edges()
+                .values()
+                .flatten()
+                .map(|e| (e.source.clone(), e.channel_id.clone(), e.balance))
+                .collect();
+            balances.sort();
+            balances
+        };
+        let initial = balances(&simulator);
+        // chan never settles and goes offline while its HTLCs are in flight
+        simulator.set_hold_invoices(HoldInvoices::from([(chan.clone(), InvoiceHold::Forever)]));
+        simulator.set_topology_events(vec![event(
+            10.0,
+            TopologyChange::NodeOffline { node: chan.clone() },
+        )]);
+        simulator.run(vec![(bob, chan.clone())].into_iter(), None, false);
+        assert_eq!(simulator.num_failed, 1);
+        assert!(simulator.in_flight.is_empty());
+        assert_eq!(simulator.graph.pending_htlcs(), 0);
+        // the parked channels come back refunded
+        simulator.change_topology(TopologyChange::NodeOnline { node: chan });
+        assert_eq!(balances(&simulator), initial);
+    }
+
+    #[test]
+    fn churn_is_seeded() {
+        let run = |churn: &str| {
+            let mut simulator = lnbook_sim(PaymentParts::Single);
+            simulator.set_churn(churn.parse::<ChurnProcess>().unwrap());
+            let pairs = vec![(String::from("alice"), String::from("dina")); 20];
+            simulator.run(pairs.into_iter(), None, false);
+            let mut edges: Vec<(String, String)> = simulator
+                .graph
+                .get_edges()
+                .values()
+                .flatten()
+                .map(|e| (e.source.clone(), e.channel_id.clone()))
+                .collect();
+            edges.sort();
+            (simulator.num_successful, edges)
+        };
+        assert_eq!(run("0.001:0.001:0.001:600"), run("0.001:0.001:0.001:600"));
+        assert_ne!(run("0:0.01:0:0").1, run("0:0:0:0").1);
+    }
+}
@@ -0,0 +1,356 @@
+use crate::{
+    event::PaymentEvent,
+    payment::PaymentShard,
+    trace::{
+        write_trace, BalanceMismatch, ChannelBalance, EventTrace, HopOutcome, HopTrace,
+        InFlightHtlc, TraceRecord,
+    },
+    traversal::pathfinding::CandidatePath,
+    Simulation, ID,
+};
+
+use log::warn;
+use std::{collections::BTreeSet, io, path::Path};
+
+/// Events after which a payment's HTLCs are no longer in flight
+static RESOLVING_EVENTS: [&str; 3] = ["settle_htlcs", "fail_htlcs", "expire_htlcs"];
+
+impl Simulation {
+    /// Records every processed event, path attempt and balance change from now on, starting
+    /// with the current balances and HTLCs in flight
+    pub fn record_trace(&mut self) {
+        let balances = ChannelBalance::from_balances(self.graph.channel_balances());
+        let mut htlcs: Vec<InFlightHtlc> = self
+            .in_flight
+            .iter()
+            .flat_map(|(payment_id, htlcs)| {
+                htlcs.iter().map(|htlc| InFlightHtlc {
+                    payment_id: *payment_id,
+                    htlc: htlc.clone(),
+                })
+            })
+            .collect();
+        // stable so that traces of the same run are equal
+        htlcs.sort_by_key(|h| h.payment_id);
+        self.trace = Some(EventTrace {
+            records: vec![TraceRecord::Start { balances, htlcs }],
+            payment_id: None,
+        });
+    }
+
+    /// The records so far if tracing
+    pub fn trace(&self) -> Option<&EventTrace> {
+        self.trace.as_ref()
+    }
+
+    /// Writes the trace as JSON lines, nothing if not tracing
+    pub fn export_trace(&self, path: &Path) -> io::Result<()> {
+        let records = self.trace.as_ref().map(|t| t.records.as_slice());
+        write_trace(path, records.unwrap_or_default())
+    }
+
+    pub(crate) fn trace_event(&mut self, event: &PaymentEvent) {
+        let time_ms = self.event_queue.now().as_millis();
+        if let Some(trace) = self.trace.as_mut() {
+            trace.payment_id = event.payment_id();
+            let change = match event {
+                PaymentEvent::ChangeTopology { change } => Some(change.clone()),
+                _ => None,
+            };
+            trace.records.push(TraceRecord::Event {
+                time_ms,
+                payment_id: trace.payment_id,
+                event: event.name().to_owned(),
+                change,
+            });
+        }
+    }
+
+    /// Records how far the attempt got given the hops that transferred an amount
+    pub(crate) fn trace_attempt(
+        &mut self,
+        payment_shard: &PaymentShard,
+        candidate_path: &CandidatePath,
+        num_transferred: usize,
+    ) {
+        let time_ms = self.event_queue.now().as_millis();
+        if let Some(trace) = self.trace.as_mut() {
+            let hops = candidate_path
+                .path
+                .hops
+                .iter()
+                .enumerate()
+                .map(|(idx, (node, _, _, channel_id))| {
+                    let outcome = if payment_shard.succeeded || idx < num_transferred {
+                        HopOutcome::Forwarded
+                    } else if idx == num_transferred {
+                        HopOutcome::Failed
+                    } else {
+                        HopOutcome::NotReached
+                    };
+                    HopTrace {
+                        node: node.clone(),
+                        channel_id: channel_id.clone(),
+                        outcome,
+                        failure_reason: (outcome == HopOutcome::Failed)
+                            .then_some(payment_shard.failure_reason)
+                            .flatten(),
+                    }
+                })
+                .collect();
+            trace.records.push(TraceRecord::Attempt {
+                time_ms,
+                payment_id: trace.payment_id,
+                amount_msat: candidate_path.amount,
+                hops,
+            });
+        }
+    }
+
+    /// Sets the balance of the channel's direction starting at src, recording the change if
+    /// tracing
+    pub(crate) fn update_channel_balance(&mut self, src: &ID, channel_id: &ID, balance: usize) {
+        self.graph.update_channel_balance(src, channel_id, balance);
+        let time_ms = self.event_queue.now().as_millis();
+        if let Some(trace) = self.trace.as_mut() {
+            trace.records.push(TraceRecord::Balance {
+                time_ms,
+                payment_id: trace.payment_id,
+                channel_id: channel_id.clone(),
+                source: src.clone(),
+                balance_msat: balance,
+            });
+        }
+    }
+
+    pub(crate) fn finish_trace(&mut self) {
+        let time_ms = self.event_queue.now().as_millis();
+        let balances = ChannelBalance::from_balances(self.graph.channel_balances());
+        if let Some(trace) = self.trace.as_mut() {
+            trace.payment_id = None;
+            trace.records.push(TraceRecord::End { time_ms, balances });
+        }
+    }
+
+    /// Re-applies the balance and topology changes of a trace to the graph, starting from the
+    /// trace's start balances and HTLCs, and returns the channels whose balances then differ from
+    /// those at its end. Without an end record every channel differs.
+    pub fn replay(&mut self, records: &[TraceRecord]) -> Vec<BalanceMismatch> {
+        let (start, end) = EventTrace::start_and_end_balances(records);
+        // the HTLCs lock liquidity until an event resolves them
+        for record in records {
+            if let TraceRecord::Start { htlcs, .. } = record {
+                for InFlightHtlc { payment_id, htlc } in htlcs {
+                    self.graph.add_htlc(htlc);
+                    self.in_flight
+                        .entry(*payment_id)
+                        .or_default()
+                        .push(htlc.clone());
+                }
+            }
+        }
+        for ((channel_id, source), balance) in start.unwrap_or_default() {
+            match self.graph.get_channel(&source, &channel_id) {
+                Some(_) => self
+                    .graph
+                    .update_channel_balance(&source, &channel_id, balance),
+                None => warn!(
+                    "Channel {} of {} is not in the graph, not replaying it.",
+                    channel_id, source
+                ),
+            }
+        }
+        for record in records {
+            match record {
+                TraceRecord::Event {
+                    change: Some(change),
+                    ..
+                } => self.change_topology(change.clone()),
+                TraceRecord::Event {
+                    payment_id: Some(payment_id),
+                    event,
+                    ..
+                } if RESOLVING_EVENTS.contains(&event.as_str()) => self.release_htlcs(*payment_id),
+                TraceRecord::Balance {
+                    channel_id,
+                    source,
+                    balance_msat,
+                    ..
+                } => self
+                    .graph
+                    .update_channel_balance(source, channel_id, *balance_msat),
+                _ => {}
+            }
+        }
+        let recorded = end.unwrap_or_default();
+        let replayed = self.graph.channel_balances();
+        let channels: BTreeSet<_> = recorded.keys().chain(replayed.keys()).collect();
+        channels
+            .into_iter()
+            .filter(|channel| recorded.get(*channel) != replayed.get(*channel))
+            .map(|(channel_id, source)| BalanceMismatch {
+                channel_id: channel_id.clone(),
+                source: source.clone(),
+                recorded: recorded.get(&(channel_id.clone(), source.clone())).copied(),
+                replayed: replayed.get(&(channel_id.clone(), source.clone())).copied(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        graph::Graph,
+        htlc::Htlc,
+        sim::simulator::tests::lnbook_sim,
+        topology::TopologyChange,
+        trace::{read_trace, HopOutcome, TraceRecord},
+        PaymentParts, Simulation,
+    };
+    use itertools::Itertools;
+
+    #[test]
+    fn trace_replays() {
+        for payment_parts in [PaymentParts::Single, PaymentParts::Split] {
+            let mut simulator = lnbook_sim(payment_parts);
+            let graph = simulator.graph.clone();
+            simulator.record_trace();
+            simulator.set_htlc_hold_time(60.0);
+            let pairs = Simulation::draw_n_pairs_for_simulation(&graph, 30);
+            simulator.run(pairs, None, false);
+            let records = &simulator.trace().unwrap().records;
+            let num_events = records
+                .iter()
+                .filter(|r| matches!(r, TraceRecord::Event { .. }))
+                .count();
+            assert!(num_events >= 2 * 30);
+            assert!(records
+                .iter()
+                .any(|r| matches!(r, TraceRecord::Balance { .. })));
+            for record in records {
+                if let TraceRecord::Attempt { hops, .. } = record {
+                    assert!(hops.len() >= 2);
+                    // at most one hop fails and everything after it is not reached
+                    let failed = hops.iter().position(|h| h.outcome != HopOutcome::Forwarded);
+                    if let Some(idx) = failed {
+                        assert_eq!(hops[idx].outcome, HopOutcome::Failed);
+                        assert!(hops[idx].failure_reason.is_some());
+                        assert!(hops[idx + 1..]
+                            .iter()
+                            .all(|h| h.outcome == HopOutcome::NotReached));
+                    }
+                }
+            }
+            assert!(matches!(records.last(), Some(TraceRecord::End { .. })));
+
+            // the replay starts from the recorded balances rather than its own
+            let mut replay = lnbook_sim(payment_parts);
+            assert_eq!(replay.replay(records), vec![]);
+        }
+    }
+
+    #[test]
+    fn replay_locks_htlcs_in_flight_at_start() {
+        let liquidities = |graph: &Graph| -> Vec<(String, String, usize)> {
+            graph
+                .get_edges()
+                .values()
+                .flatten()
+                .map(|e| (e.channel_id.clone(), e.source.clone(), e.liquidity))
+                .sorted()
+                .collect()
+        };
+        let mut simulator = lnbook_sim(PaymentParts::Single);
+        let bob_chan = simulator
+            .graph
+            .get_edge(&"bob".to_owned(), &"chan".to_owned())
+            .unwrap();
+        let htlc = Htlc {
+            source: bob_chan.source,
+            channel_id: bob_chan.channel_id,
+            amount: 1000,
+            expiry: 100,
+        };
+        simulator.graph.add_htlc(&htlc);
+        simulator.in_flight.insert(100, vec![htlc]);
+        simulator.record_trace();
+        let pairs = vec![
+            ("alice".to_owned(), "dina".to_owned()),
+            ("dina".to_owned(), "alice".to_owned()),
+        ];
+        simulator.run(pairs.into_iter(), None, false);
+        let mut records = simulator.trace().unwrap().records.clone();
+
+        let mut replay = lnbook_sim(PaymentParts::Single);
+        assert_eq!(replay.replay(&records), vec![]);
+        assert_eq!(replay.graph.locked_in_htlcs(), 1000);
+        assert_eq!(liquidities(&replay.graph), liquidities(&simulator.graph));
+
+        // failing the payment releases its HTLCs
+        records.insert(
+            records.len() - 1,
+            TraceRecord::Event {
+                time_ms: 0.0,
+                payment_id: Some(100),
+                event: "fail_htlcs".to_owned(),
+                change: None,
+            },
+        );
+        let mut replay = lnbook_sim(PaymentParts::Single);
+        assert_eq!(replay.replay(&records), vec![]);
+        assert_eq!(replay.graph.locked_in_htlcs(), 0);
+        assert!(replay.in_flight.is_empty());
+    }
+
+    #[test]
+    fn replay_detects_mismatches() {
+        let mut simulator = lnbook_sim(PaymentParts::Single);
+        let bob_chan = simulator
+            .graph
+            .get_edge(&"bob".to_owned(), &"chan".to_owned())
+            .unwrap()
+            .channel_id;
+        simulator.record_trace();
+        let pairs = vec![
+            ("alice".to_owned(), "dina".to_owned()),
+            ("dina".to_owned(), "alice".to_owned()),
+        ];
+        simulator.set_topology_events(vec![crate::topology::TopologyEvent {
+            time_secs: 10.0,
+            change: TopologyChange::ForceCloseChannel {
+                channel_id: bob_chan.clone(),
+            },
+        }]);
+        simulator.run(pairs.into_iter(), None, false);
+        let path = std::env::temp_dir().join("trace_replay_detects_mismatches.jsonl");
+        simulator.export_trace(&path).unwrap();
+        let mut records = read_trace(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&records, &simulator.trace().unwrap().records);
+
+        // replaying the closure removes the channel from the final graph as well
+        let mut replay = lnbook_sim(PaymentParts::Single);
+        assert_eq!(replay.replay(&records), vec![]);
+        assert!(!replay.graph.has_channel(&bob_chan));
+
+        // a changed balance is noticed
+        let Some(TraceRecord::Balance {
+            channel_id,
+            balance_msat,
+            ..
+        }) = records
+            .iter_mut()
+            .rev()
+            .find(|r| matches!(r, TraceRecord::Balance { .. }))
+        else {
+            panic!("No balance changes recorded.");
+        };
+        *balance_msat += 1;
+        let channel_id = channel_id.clone();
+        let mut replay = lnbook_sim(PaymentParts::Single);
+        let mismatches = replay.replay(&records);
+        assert!(!mismatches.is_empty());
+        assert!(mismatches.iter().all(|m| m.channel_id == channel_id));
+    }
+}
@@ -6,6 +6,7 @@ use crate::{
 
 #[cfg(not(test))]
 use log::{info, warn};
+use network_parser::Reachability;
 use rayon::prelude::*;
 use std::{
     collections::HashMap,
@@ -38,7 +39,12 @@ impl Simulation {
             for num_adv in number_of_adversaries.iter() {
                 let adv = match selected_adversaries.get(strategy) {
                     None => vec![],
-                    Some(selected_adversaries) => selected_adversaries[0..*num_adv].to_vec(),
+                    // there may be fewer nodes of a kind than adversaries
+                    Some(selected_adversaries) => selected_adversaries
+                        .iter()
+                        .take(*num_adv)
+                        .cloned()
+                        .collect(),
                 };
                 info!(
                     "Starting adversary scenario: {} sat: {:?} with {} nodes.",
@@ -275,6 +281,22 @@ impl Simulation {
                 AdversarySelection::HighBetweennessWeb(ranking) => {
                     ranking[0..number_of_adversaries].to_owned()
                 }
+                AdversarySelection::ClearnetOnly | AdversarySelection::TorOnly => {
+                    let reachability = if *strategy == AdversarySelection::TorOnly {
+                        Reachability::Tor
+                    } else {
+                        Reachability::Clearnet
+                    };
+                    let candidates = self.graph.get_node_ids_by_reachability(reachability);
+                    if candidates.len() < number_of_adversaries {
+                        warn!(
+                            "Only {} {} nodes available as adversaries.",
+                            candidates.len(),
+                            strategy
+                        );
+                    }
+                    Simulation::draw_adversaries(&candidates, number_of_adversaries).collect()
+                }
             };
             all_adversaries.insert(strategy.clone(), adv);
         }
@@ -347,7 +369,7 @@ mod tests {
         let number_of_adversaries = 4;
         let simulator = crate::attempt::tests::init_sim(None, Some(vec![number_of_adversaries]));
         let adversaries = simulator.get_adversaries(number_of_adversaries);
-        assert!(adversaries.get(&AdversarySelection::Random).is_some());
+        assert!(adversaries.contains_key(&AdversarySelection::Random));
         let actual = adversaries.get(&AdversarySelection::Random).unwrap();
         assert_eq!(actual.len(), number_of_adversaries);
         for node in simulator.graph.get_node_ids() {
@@ -355,6 +377,32 @@ mod tests {
         }
     }
 
+    #[test]
+    fn choose_adversaries_by_reachability() {
+        let number_of_adversaries = 4;
+        let mut simulator =
+            crate::attempt::tests::init_sim(None, Some(vec![number_of_adversaries]));
+        simulator.adversary_selection = vec![
+            AdversarySelection::TorOnly,
+            AdversarySelection::ClearnetOnly,
+        ];
+        for node in simulator.graph.nodes.iter_mut() {
+            let addr = match node.id.as_str() {
+                "alice" => "wu5mkpokybtbf6dwdaepnujbzxpm6mqqqm2hwob6ndt5k74iujd2pdyd.onion:9735",
+                _ => "1.2.3.4:9735",
+            };
+            node.addresses = vec![network_parser::Address::parse(addr).unwrap()];
+        }
+        let adversaries = simulator.get_adversaries(number_of_adversaries);
+        assert_eq!(
+            adversaries[&AdversarySelection::TorOnly],
+            vec!["alice".to_owned()]
+        );
+        let clearnet = &adversaries[&AdversarySelection::ClearnetOnly];
+        assert_eq!(clearnet.len(), simulator.graph.node_count() - 1);
+        assert!(!clearnet.contains(&"alice".to_owned()));
+    }
+
     #[test]
     fn count_correlations() {
         let number_of_adversaries = 4; // all four nodes are adversaries
@@ -426,6 +474,10 @@ mod tests {
                     amount: 1100,
                     time: 40,
                 }],
+                completion_time_ms: None,
+                max_fee_msat: None,
+                parts: None,
+                ..Default::default()
             },
             Payment {
                 payment_id: 2,
@@ -468,14 +520,18 @@ mod tests {
                     amount: 1100,
                     time: 40,
                 }],
+                completion_time_ms: None,
+                max_fee_msat: None,
+                parts: None,
+                ..Default::default()
             },
         ];
         let (correlation_count, correlation_count_successful) =
-            Simulation::colluding_adversaries(&payments, &adversaries);
+            Simulation::colluding_adversaries(&payments, adversaries);
         assert_eq!(correlation_count, 2); // bob sees the payment twice
         assert_eq!(correlation_count_successful, 1);
         let (prone_paths, prone_payments) =
-            Simulation::prone_paths_and_payments(&payments, &adversaries);
+            Simulation::prone_paths_and_payments(&payments, adversaries);
         // all paths are susceptible
         assert_abs_diff_eq!(prone_paths.0, 1.0, epsilon = 0.001f32);
         // all successful payemnts' paths are susceptible
@@ -42,7 +42,11 @@ impl Simulation {
                 let mut sd_anon_set = HashSet::new();
                 let mut rx_anon_set = HashSet::new();
                 // will only be one at most
-                if let Some(adv) = p.path.path_contains_adversary(&[adversary.clone()]).first() {
+                if let Some(adv) = p
+                    .path
+                    .path_contains_adversary(std::slice::from_ref(adversary))
+                    .first()
+                {
                     let adversary_id = adv.0.clone();
                     let (pred, succ, amount_to_succ, ttl_to_rx) =
                         Self::extract_tx_info(p, &adversary_id);
@@ -55,13 +59,9 @@ impl Simulation {
                                                                                               // stores (src, dest): path
                     let mut shortest_paths: HashMap<(ID, ID), CandidatePath> = HashMap::new();
                     // and capacity
-                    let phase1_paths = if let Some(paths) =
+                    let phase1_paths =
                         Self::get_all_reachable_paths(&g, &succ, amount_to_succ, ttl_to_rx)
-                    {
-                        paths
-                    } else {
-                        vec![]
-                    };
+                            .unwrap_or_default();
                     // for all Pi for a list of potential recipients as R and potential senders for each such recipient
                     // The union of the potential senders for all potential recipients is the sender anonymity set
                     info!(
@@ -152,11 +152,7 @@ impl Simulation {
             });
         });
         if let Ok(arc) = Arc::try_unwrap(all_anonymits_sets) {
-            if let Ok(mutex) = arc.into_inner() {
-                mutex
-            } else {
-                vec![]
-            }
+            arc.into_inner().unwrap_or_default()
         } else {
             vec![]
         }
@@ -451,7 +447,7 @@ mod tests {
         assert_eq!(actual.unwrap(), expected);
         let next = "bob".to_string();
         let ttl = 40;
-        let expected = vec![
+        let expected = [
             CandidatePath {
                 path: Path {
                     src: "bob".to_owned(),
@@ -578,7 +574,7 @@ mod tests {
     fn pred_is_distinct_sender() {
         // this doesn't make much sense but serves as a sanity test for path comparisons
         let simulator = crate::attempt::tests::init_sim(None, None);
-        let amount = 100;
+        let amount = 1000; // lnbook_example channels do not accept smaller HTLCs
         let pre = "bob".to_string();
         let next = "dina".to_string();
         // path from pre to next must not be equal to the found path
@@ -52,12 +52,11 @@ impl Simulation {
         for idx in 0..paths.len() {
             let mut div_min_path_i = f32::MAX;
             let mut alternate_paths = paths.to_vec();
-            // the base path should be the only item returned by drain
-            if let Some(base_path) = alternate_paths.drain(idx..idx + 1).last() {
-                for path in alternate_paths {
-                    let div = Self::calculate_path_diversity(&base_path, &path);
-                    div_min_path_i = f32::min(div_min_path_i, div);
-                }
+            // idx is always in bounds so the base path is always found
+            let base_path = alternate_paths.remove(idx);
+            for path in alternate_paths {
+                let div = Self::calculate_path_diversity(&base_path, &path);
+                div_min_path_i = f32::min(div_min_path_i, div);
             }
             aggregated_div += div_min_path_i;
         }
@@ -270,6 +269,10 @@ mod tests {
             failed_amounts: Vec::default(),
             successful_shards: Vec::default(),
             failed_paths: vec![],
+            completion_time_ms: None,
+            max_fee_msat: None,
+            parts: None,
+            ..Default::default()
         }];
         simulator.successful_payments = successful_payments;
         simulator.eval_path_similarity();
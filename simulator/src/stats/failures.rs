@@ -1,10 +1,5 @@
-use crate::{
-    event::*, io::PaymentInfo, payment::Payment, stats::TargetedAttack, time::Time, Invoice,
-    PaymentParts, Simulation, ID,
-};
+use crate::{event::*, io::PaymentInfo, payment::Payment, stats::TargetedAttack, Simulation, ID};
 
-use itertools::EitherOrBoth::{Both, Left, Right};
-use itertools::Itertools;
 #[cfg(not(test))]
 use log::{debug, info, trace};
 #[cfg(test)]
@@ -18,40 +13,30 @@ impl Simulation {
         );
         let mut sim = self.clone();
         sim.delete_targets(targets);
-        let (pp, min_shard_amt) = sim.reconstruct_payment_pairs();
+        let payments = sim.reconstruct_payments();
         sim.failed_payments.clear();
         sim.successful_payments.clear();
         sim.num_successful = 0;
         sim.num_failed = 0;
         sim.event_queue = EventQueue::new();
-        sim.total_num_payments = pp.size_hint().0;
         assert_eq!(sim.payment_parts, self.payment_parts);
         assert_eq!(sim.routing_metric, self.routing_metric);
-        sim.simulate(pp, min_shard_amt)
+        sim.simulate(payments)
     }
 
-    fn simulate(
-        &mut self,
-        payment_pairs: impl Iterator<Item = (ID, ID)>,
-        min_shard_amt: Option<usize>,
-    ) -> TargetedAttack {
+    fn simulate(&mut self, payments: Vec<Payment>) -> TargetedAttack {
         info!(
-            "# Payment pairs = {}, Pathfinding weight = {:?}, Single/MMP payments: {:?}",
-            payment_pairs.size_hint().0,
+            "# Payments = {}, Pathfinding weight = {:?}, Single/MMP payments: {:?}",
+            payments.len(),
             self.routing_metric,
             self.payment_parts
         );
-        let mut now = self.event_queue.now();
-        for (src, dest) in payment_pairs {
-            let payment_id = self.next_payment_id();
-            let invoice = Invoice::new(payment_id, self.amount, &src, &dest);
-            self.add_invoice(invoice);
-            let payment = Payment::new(payment_id, src, dest, self.amount, min_shard_amt);
-            let event = PaymentEvent::Scheduled { payment };
-            self.event_queue.schedule(now, event);
-            now += Time::from_secs(crate::SIM_DELAY_IN_SECS);
+        self.total_num_payments = payments.len();
+        for payment in payments {
+            let delay = payment.dispatch_time;
+            self.schedule_payment(payment, delay);
         }
-        self.total_num_payments = self.event_queue.queue_length();
+        self.schedule_topology_changes();
         debug!(
             "Queued {} events for simulation.",
             self.event_queue.queue_length()
@@ -59,28 +44,9 @@ impl Simulation {
 
         info!("Starting simulation.");
         while let Some(event) = self.event_queue.next() {
-            match event {
-                PaymentEvent::Scheduled { mut payment } => {
-                    debug!(
-                        "Dispatching scheduled payment {} at simulation time = {}.",
-                        payment.payment_id,
-                        self.event_queue.now()
-                    );
-                    match self.payment_parts {
-                        PaymentParts::Single => self.send_single_payment(&mut payment),
-                        PaymentParts::Split => self.send_mpp_payment(&mut payment),
-                    };
-                }
-                PaymentEvent::UpdateFailed { payment } => {
-                    self.num_failed += 1;
-                    self.failed_payments.push(payment.to_owned());
-                }
-                PaymentEvent::UpdateSuccesful { payment } => {
-                    self.num_successful += 1;
-                    self.successful_payments.push(payment.to_owned());
-                }
-            }
+            self.handle_event(event);
         }
+        self.finish_trace();
         info!("Completed simulation of targeted attacks.");
         self.eval_path_similarity();
         let mut payments: Vec<PaymentInfo> = self
@@ -105,38 +71,37 @@ impl Simulation {
         }
     }
 
-    fn reconstruct_payment_pairs(
-        &self,
-    ) -> ((impl Iterator<Item = (ID, ID)> + Clone), Option<usize>) {
-        let mut payment_pairs = vec![];
-        let mut min_shard_amt = None;
-        for payments_iter in self
+    /// The payments whose nodes are still in the graph as they were dispatched originally,
+    /// keeping their amounts, parts, fee limits and dispatch times, in the order they were
+    /// scheduled
+    fn reconstruct_payments(&self) -> Vec<Payment> {
+        let mut payments: Vec<Payment> = self
             .successful_payments
             .iter()
-            .zip_longest(self.failed_payments.iter())
-        {
-            let mut check_and_add_payment = |payment: &Payment| {
-                if self.graph.node_is_in_graph(&payment.source)
+            .chain(self.failed_payments.iter())
+            .filter(|payment| {
+                self.graph.node_is_in_graph(&payment.source)
                     && self.graph.node_is_in_graph(&payment.dest)
-                {
-                    min_shard_amt = Some(payment.min_shard_amt);
-                    payment_pairs.push((payment.source.clone(), payment.dest.clone()));
-                }
-            };
-            match payments_iter {
-                Both(s, f) => {
-                    check_and_add_payment(s);
-                    check_and_add_payment(f);
-                }
-                Left(s) => check_and_add_payment(s),
-                Right(f) => check_and_add_payment(f),
-            }
-        }
+            })
+            .map(|payment| Payment {
+                max_fee_msat: payment.max_fee_msat,
+                parts: payment.parts,
+                dispatch_time: payment.dispatch_time,
+                ..Payment::new(
+                    payment.payment_id,
+                    payment.source.clone(),
+                    payment.dest.clone(),
+                    payment.amount_msat,
+                    Some(payment.min_shard_amt),
+                )
+            })
+            .collect();
+        payments.sort_by_key(|payment| payment.payment_id);
         info!(
-            "Reusing {} % of payment pairs.",
-            (payment_pairs.len() as f32 / self.total_num_payments as f32) * 100.0
+            "Reusing {} % of payments.",
+            (payments.len() as f32 / self.total_num_payments as f32) * 100.0
         );
-        (payment_pairs.into_iter(), min_shard_amt)
+        payments
     }
 }
 
@@ -144,6 +109,7 @@ impl Simulation {
 mod tests {
 
     use super::*;
+    use crate::{time::Time, PaymentParts};
 
     #[test]
     fn delete_targets() {
@@ -155,62 +121,74 @@ mod tests {
     }
 
     #[test]
-    fn payment_pairs() {
+    fn reconstruct_payments() {
         let mut simulator = crate::attempt::tests::init_sim(None, None);
+        let payment = |payment_id, source: &str, dest: &str| Payment {
+            dispatch_time: Time::from_secs(payment_id as f32 * 60.0),
+            ..Payment::new(payment_id, source.to_owned(), dest.to_owned(), 1000, None)
+        };
         simulator.successful_payments = vec![
+            payment(0, "alice", "bob"),
             Payment {
-                payment_id: 0,
-                source: "alice".to_string(),
-                dest: "bob".to_string(),
-                ..Default::default()
-            },
-            Payment {
-                payment_id: 0,
-                source: "dina".to_string(),
-                dest: "alice".to_string(),
-                ..Default::default()
+                max_fee_msat: Some(10),
+                ..payment(3, "dina", "alice")
             },
         ];
         simulator.failed_payments = vec![Payment {
-            payment_id: 0,
-            source: "alice".to_string(),
-            dest: "chan".to_string(),
-            ..Default::default()
+            amount_msat: 5000,
+            parts: Some(PaymentParts::Split),
+            ..payment(1, "alice", "chan")
         }];
         let targets = ["bob".to_string()];
         simulator.delete_targets(&targets);
-        let expected = [
-            ("alice".to_string(), "chan".to_string()),
-            ("dina".to_string(), "alice".to_string()),
-        ]
-        .into_iter();
-        let (actual, _) = simulator.reconstruct_payment_pairs();
-        assert!(expected.eq(actual));
+        let actual: Vec<_> = simulator
+            .reconstruct_payments()
+            .into_iter()
+            .map(|p| {
+                (
+                    p.payment_id,
+                    p.source,
+                    p.dest,
+                    p.amount_msat,
+                    p.parts,
+                    p.max_fee_msat,
+                    p.dispatch_time,
+                )
+            })
+            .collect();
+        let expected = vec![
+            (
+                1,
+                "alice".to_owned(),
+                "chan".to_owned(),
+                5000,
+                Some(PaymentParts::Split),
+                None,
+                Time::from_secs(60.0),
+            ),
+            (
+                3,
+                "dina".to_owned(),
+                "alice".to_owned(),
+                1000,
+                None,
+                Some(10),
+                Time::from_secs(180.0),
+            ),
+        ];
+        assert_eq!(actual, expected);
     }
 
     #[test]
     fn run() {
         let mut simulator = crate::attempt::tests::init_sim(None, None);
-        simulator.successful_payments = vec![
-            Payment {
-                payment_id: 0,
-                source: "alice".to_string(),
-                dest: "bob".to_string(),
-                ..Default::default()
-            },
-            Payment {
-                payment_id: 2,
-                source: "dina".to_string(),
-                dest: "chan".to_string(),
-                ..Default::default()
-            },
-        ];
-        simulator.failed_payments = vec![Payment {
-            payment_id: 1,
-            source: "chan".to_string(),
-            dest: "dina".to_string(),
-            ..Default::default()
-        }];
+        // the rerun sends the original amounts
+        let payment = |payment_id, source: &str, dest: &str| {
+            Payment::new(payment_id, source.to_owned(), dest.to_owned(), 1000, None)
+        };
+        simulator.successful_payments =
+            vec![payment(0, "alice", "bob"), payment(2, "dina", "chan")];
+        simulator.failed_payments = vec![payment(1, "chan", "dina")];
         let targets = ["bob".to_string()];
         let actual = simulator.rerun_simulation(&targets);
         let expected = TargetedAttack {
@@ -218,7 +196,7 @@ mod tests {
             total_num: 2,
             num_successful: 2,
             num_failed: 0,
-            payments: vec![
+            payments: [
                 Payment {
                     payment_id: 0,
                     source: "alice".to_string(),
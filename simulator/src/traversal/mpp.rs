@@ -1,9 +1,10 @@
 use crate::{
-    core_types::{event::PaymentEvent, time::Time},
+    core_types::event::PaymentEvent,
     payment::Payment,
     traversal::pathfinding::{CandidatePath, PathFinder},
     Simulation,
 };
+use network_parser::Feature;
 
 #[cfg(not(test))]
 use log::{error, info, trace};
@@ -15,6 +16,18 @@ impl Simulation {
     /// Triggers an event either way
     /// Includes pathfinding and ultimate routing
     pub(crate) fn send_mpp_payment(&mut self, payment: &mut Payment) -> bool {
+        // the receiver's invoice would not allow MPP
+        if self
+            .graph
+            .get_node(&payment.dest)
+            .is_some_and(|n| !n.features.is_empty() && !n.features.supports(Feature::BasicMpp))
+        {
+            info!(
+                "Receiver {} does not support MPP. Sending payment {} in one part.",
+                payment.dest, payment.payment_id
+            );
+            return self.send_single_payment(payment);
+        }
         let mut succeeded = false;
         let mut failed = false;
         let graph = Box::new(self.graph.clone());
@@ -40,13 +53,17 @@ impl Simulation {
             payment.num_parts = 0;
             succeeded = self.send_mpp_shards(payment);
         }
-        let now = self.event_queue.now() + Time::from_secs(crate::SIM_DELAY_IN_SECS);
-        let event = if succeeded {
+        if succeeded {
             assert!(payment.succeeded);
             info!(
                 "Payment from {} to {} delivered in {} parts.",
                 payment.source, payment.dest, payment.num_parts
             );
+            let to_revert = payment.successful_shards.clone();
+            succeeded = self.settle_htlcs(payment, to_revert);
+        }
+        let delay = self.outcome_delay(payment);
+        let event = if succeeded {
             PaymentEvent::UpdateSuccesful {
                 payment: payment.to_owned(),
             }
@@ -57,7 +74,7 @@ impl Simulation {
                 payment: payment.to_owned(),
             }
         };
-        self.event_queue.schedule(now, event);
+        self.event_queue.schedule(delay, event);
         succeeded
     }
 
@@ -78,6 +95,10 @@ impl Simulation {
                 num_parts += 1;
                 let (success, mut to_reverse) = self.send_one_payment(&mut current_shard);
                 root.htlc_attempts += current_shard.htlc_attempts;
+                // shards are sent in parallel and inherit the time their parent took to fail
+                root.completion_time_ms = root
+                    .completion_time_ms
+                    .max(current_shard.completion_time_ms);
                 root.failed_paths.append(&mut current_shard.failed_paths);
                 if !success && !failed {
                     root.failed_amounts.push(current_shard.amount_msat);
@@ -127,7 +148,8 @@ impl Simulation {
         // total failure so revert succesful payments
         // some payment failed so all must now be reversed
         if !succeeded {
-            self.revert_payment(&root.successful_shards);
+            let to_revert = std::mem::take(&mut root.successful_shards);
+            self.fail_htlcs(root, to_revert);
             // remove any successful paths we may have stored after shards' success
             root.used_paths.clear();
         }
@@ -168,15 +190,19 @@ mod tests {
             failed_amounts: Vec::default(),
             successful_shards: Vec::default(),
             failed_paths: vec![],
+            completion_time_ms: None,
+            max_fee_msat: None,
+            parts: None,
+            ..Default::default()
         };
         simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
         assert!(!simulator.send_single_payment(payment));
         simulator
             .graph
-            .update_channel_balance(&String::from("alice-carol"), 100000);
+            .update_channel_balance(&source, "alice-carol", 100000);
         simulator
             .graph
-            .update_channel_balance(&String::from("alice-dave"), 250000);
+            .update_channel_balance(&source, "alice-dave", 250000);
 
         simulator.payment_parts = PaymentParts::Split;
         simulator.send_mpp_payment(payment);
@@ -212,6 +238,10 @@ mod tests {
             failed_amounts: Vec::default(),
             successful_shards: Vec::default(),
             failed_paths: vec![],
+            completion_time_ms: None,
+            max_fee_msat: None,
+            parts: None,
+            ..Default::default()
         };
         simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
         simulator.payment_parts = PaymentParts::Single;
@@ -222,6 +252,52 @@ mod tests {
         assert!(payment.num_parts > 1);
     }
 
+    #[test]
+    // same as above but alice does not advertise basic_mpp so the payment cannot be split
+    fn no_mpp_to_receivers_without_basic_mpp() {
+        let json_file = "../test_data/trivial_multipath.json";
+        let source = "bob".to_string();
+        let dest = "alice".to_string();
+        let mut simulator = crate::attempt::tests::init_sim(Some(json_file.to_string()), None);
+        for edges in simulator.graph.edges.values_mut() {
+            for e in edges {
+                e.balance = 10000;
+            }
+        }
+        for node in simulator.graph.nodes.iter_mut() {
+            node.features.set(Feature::VarOnionOptin, true);
+            node.features.set(Feature::PaymentSecret, true);
+        }
+        let amount_msat = 12000;
+        let payment = &mut Payment {
+            payment_id: 0,
+            source: source.clone(),
+            dest: dest.clone(),
+            amount_msat,
+            succeeded: false,
+            min_shard_amt: 10,
+            htlc_attempts: 0,
+            num_parts: 1,
+            used_paths: Vec::default(),
+            failed_amounts: Vec::default(),
+            successful_shards: Vec::default(),
+            failed_paths: vec![],
+            completion_time_ms: None,
+            max_fee_msat: None,
+            parts: None,
+            ..Default::default()
+        };
+        simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
+        simulator.payment_parts = PaymentParts::Split;
+        assert!(!simulator.send_mpp_payment(payment));
+        assert!(!payment.succeeded);
+        for node in simulator.graph.nodes.iter_mut() {
+            node.features.set(Feature::BasicMpp, false);
+        }
+        assert!(simulator.send_mpp_payment(payment));
+        assert!(payment.num_parts > 1);
+    }
+
     #[test]
     #[cfg_attr(tarpaulin, ignore)]
     // all edges except bob have 1k balance. Bob has a total of 15k spread across 3 channels and
@@ -243,13 +319,13 @@ mod tests {
         let bob_total_balance = 15000;
         simulator
             .graph
-            .update_channel_balance(&bob_eve_channel, bob_total_balance / 3);
+            .update_channel_balance(&source, &bob_eve_channel, bob_total_balance / 3);
         simulator
             .graph
-            .update_channel_balance(&bob_carol_channel, bob_total_balance / 3);
+            .update_channel_balance(&source, &bob_carol_channel, bob_total_balance / 3);
         simulator
             .graph
-            .update_channel_balance(&bob_dave_channel, bob_total_balance / 3);
+            .update_channel_balance(&source, &bob_dave_channel, bob_total_balance / 3);
         let amount_msat = 12000;
         let payment = &mut Payment {
             payment_id: 0,
@@ -264,6 +340,10 @@ mod tests {
             failed_amounts: Vec::default(),
             successful_shards: Vec::default(),
             failed_paths: vec![],
+            completion_time_ms: None,
+            max_fee_msat: None,
+            parts: None,
+            ..Default::default()
         };
         simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
         simulator.payment_parts = PaymentParts::Single;
@@ -298,6 +378,10 @@ mod tests {
             failed_amounts: Vec::default(),
             successful_shards: Vec::default(),
             failed_paths: vec![],
+            completion_time_ms: None,
+            max_fee_msat: None,
+            parts: None,
+            ..Default::default()
         };
         simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
         assert!(!simulator.send_single_payment(payment));
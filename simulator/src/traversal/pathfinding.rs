@@ -1,8 +1,14 @@
 use crate::{graph::Graph, Edge, EdgeWeight, PaymentParts, RoutingMetric, ID};
 
 use log::{debug, trace};
+use network_parser::Feature;
 use serde::Serialize;
-use std::collections::{HashMap, VecDeque};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+
+/// How often the k shortest paths search reruns Yen's algorithm with twice as many paths when
+/// HTLC limits rule out some of them
+pub(crate) static MAX_PATH_SEARCH_DOUBLINGS: usize = 4;
 
 /// Describes a path between two nodes
 #[derive(Debug, Clone, PartialEq, Default, Serialize)]
@@ -224,6 +230,20 @@ impl CandidatePath {
             0
         }
     }
+
+    /// Blocks until the HTLC each hop but the destination offers expires. The sender's HTLC
+    /// expires last, after the aggregated timelock and the receiver's final delta, and each
+    /// intermediary's after its own delta less.
+    pub(crate) fn htlc_expiry_deltas(&self) -> Vec<usize> {
+        let hops = &self.path.hops;
+        let mut delta = self.time + crate::htlc::MIN_FINAL_CLTV_EXPIRY_DELTA;
+        let mut deltas = vec![delta];
+        for hop in hops.iter().take(hops.len().saturating_sub(1)).skip(1) {
+            delta = delta.saturating_sub(hop.2);
+            deltas.push(delta);
+        }
+        deltas
+    }
 }
 
 impl PathFinder {
@@ -315,7 +335,7 @@ impl PathFinder {
                 // Edge from src to first hop
                 // safe because src is always last in the list
                 let (src, dest) = (node_id, candidate_path_hops[idx - 1].clone());
-                let cheapest_edge = match self.get_cheapest_edge(src, &dest) {
+                let cheapest_edge = match self.get_cheapest_edge(src, &dest, accumulated_amount) {
                     None => panic!("Edge in path does not exist! {src} -> {dest}"),
                     Some(e) => e,
                 };
@@ -349,7 +369,16 @@ impl PathFinder {
                 );
             } else if node_id.clone() == self.dest {
                 let (dest, src) = (node_id, candidate_path_hops[idx + 1].clone());
-                let cheapest_edge = match self.get_cheapest_edge(dest, &src) {
+                // no HTLC is offered over the destination's edge, so its limits and slots do
+                // not matter
+                let cheapest_edge = match self
+                    .get_cheapest_edge(dest, &src, accumulated_amount)
+                    .or_else(|| {
+                        self.graph
+                            .get_all_src_dest_edges(dest, &src)
+                            .into_iter()
+                            .next()
+                    }) {
                     None => panic!("Edge in path does not exist! {src} -> {dest}"),
                     Some(e) => e,
                 };
@@ -363,7 +392,7 @@ impl PathFinder {
                 let (src, dest) = (node_id, candidate_path_hops[idx - 1].clone());
                 // we are interested in the weight from src to dest (the previous node in the list) since that is the direction the
                 // payment will flow in
-                let cheapest_edge = match self.get_cheapest_edge(src, &dest) {
+                let cheapest_edge = match self.get_cheapest_edge(src, &dest, accumulated_amount) {
                     None => panic!("Edge in path does not exist! {src} -> {dest}"),
                     Some(e) => e,
                 };
@@ -397,34 +426,90 @@ impl PathFinder {
     }
 
     /// Computes the shortest path beween source and dest using Dijkstra's algorithm
+    /// The search runs from dest to source so that the amount each edge has to carry, including
+    /// the fees of all later hops, is known when the edge is considered. Each node is expanded
+    /// once with the amount of its cheapest path to dest.
     pub fn shortest_path_from(&self, node: &ID) -> Option<(Vec<ID>, EdgeWeight)> {
         trace!(
             "Looking for shortest paths between src {}, dest {} using {:?} as weight.",
-            self.src,
+            node,
             self.dest,
             self.routing_metric
         );
-        let successors = |node: &ID| -> Vec<(ID, EdgeWeight)> { self.get_successors(node) };
-        pathfinding::prelude::dijkstra(node, successors, |n| *n == self.dest)
+        let zero = ordered_float::OrderedFloat(0.0);
+        // weight and amount of the cheapest path found so far from each node to dest, together
+        // with its next hop
+        let mut best: HashMap<ID, (EdgeWeight, usize, Option<ID>)> =
+            HashMap::from([(self.dest.clone(), (zero, self.amount, None))]);
+        let mut expanded: HashSet<ID> = HashSet::new();
+        let mut queue = BinaryHeap::from([Reverse((zero, self.dest.clone()))]);
+        while let Some(Reverse((weight, hop))) = queue.pop() {
+            if !expanded.insert(hop.clone()) {
+                continue;
+            }
+            if hop == *node {
+                let mut path = vec![hop];
+                while let Some((_, _, Some(next))) = best.get(&path[path.len() - 1]) {
+                    path.push(next.clone());
+                }
+                return Some((path, weight));
+            }
+            let amount = best[&hop].1;
+            for (pred, pred_amount, edge_weight) in self.get_predecessors(node, &hop, amount) {
+                if expanded.contains(&pred) {
+                    continue;
+                }
+                let weight = weight + edge_weight;
+                if best.get(&pred).is_none_or(|(w, _, _)| weight < *w) {
+                    best.insert(pred.clone(), (weight, pred_amount, Some(hop.clone())));
+                    queue.push(Reverse((weight, pred)));
+                }
+            }
+        }
+        None
     }
 
     /// Computes the k shortest path beween source and dest using Dijkstra's algorithm
+    /// Yen's algorithm needs the nodes on its own, so the amount is only compared with the HTLC
+    /// maximum during the search and paths that cannot carry the fees of later hops are dropped
+    /// afterwards. More paths are searched until k of them remain, doubling their number at
+    /// most [MAX_PATH_SEARCH_DOUBLINGS] times.
     pub fn k_shortest_paths_from(&self, node: &ID, k: usize) -> Vec<(Vec<ID>, EdgeWeight)> {
         trace!(
             "Looking for {} shortest paths between src {}, dest {} using {:?} as weight.",
             k,
-            self.src,
+            node,
             self.dest,
             self.routing_metric
         );
         let successors = |node: &ID| -> Vec<(ID, EdgeWeight)> { self.get_successors(node) };
-        pathfinding::prelude::yen(node, successors, |n| *n == self.dest, k)
+        let mut num_paths = k;
+        let mut doublings = 0;
+        loop {
+            let paths = pathfinding::prelude::yen(node, successors, |n| *n == self.dest, num_paths);
+            let exhausted = paths.len() < num_paths;
+            let adequate: Vec<(Vec<ID>, EdgeWeight)> = paths
+                .into_iter()
+                .filter(|(path, _)| self.path_carries_amount(path))
+                .take(k)
+                .collect();
+            if exhausted || adequate.len() == k || doublings == MAX_PATH_SEARCH_DOUBLINGS {
+                return adequate;
+            }
+            num_paths *= 2;
+            doublings += 1;
+        }
     }
 
+    /// Edges whose HTLC maximum is below the amount are skipped
     fn get_successors(&self, node: &ID) -> Vec<(ID, EdgeWeight)> {
-        let succs = match self.graph.get_edges_for_node(node) {
+        let succs = match self.graph.get_edges().get(node) {
             Some(edges) => edges
                 .iter()
+                .filter(|e| {
+                    (e.htlc_maximum_msat == 0 || self.amount <= e.htlc_maximum_msat)
+                        && self.graph.htlc_slot_available(e)
+                })
                 .map(|e| {
                     (
                         e.destination.clone(),
@@ -443,16 +528,59 @@ impl PathFinder {
         succs
     }
 
+    /// True if every hop of the path from src to dest has an edge whose HTLC limits allow the
+    /// amount including the fees of all later hops
+    fn path_carries_amount(&self, path: &[ID]) -> bool {
+        let mut amount = self.amount;
+        for hop in path.windows(2).rev() {
+            let Some(edge) = self.get_cheapest_edge(&hop[0], &hop[1], amount) else {
+                return false;
+            };
+            if edge.source != self.src {
+                amount += Self::get_edge_fee(&edge, amount).into_inner() as usize;
+            }
+        }
+        true
+    }
+
+    /// The nodes that can forward the amount due at node to it, together with the amount they
+    /// in turn need to receive to cover their fee and the edge's weight. The source does not
+    /// charge a fee. Edges whose HTLC limits exclude the amount they would carry are skipped.
+    fn get_predecessors(&self, src: &ID, node: &ID, amount: usize) -> Vec<(ID, usize, EdgeWeight)> {
+        self.graph
+            .in_edges(node)
+            .filter(|e| e.htlc_limits_allow(amount) && self.graph.htlc_slot_available(e))
+            .map(|e| {
+                if e.source != *src {
+                    let fee = Self::get_edge_fee(e, amount).into_inner() as usize;
+                    (
+                        e.source.clone(),
+                        amount + fee,
+                        Self::get_edge_weight(e, amount, self.routing_metric),
+                    )
+                } else if self.routing_metric == RoutingMetric::MinFee {
+                    (e.source.clone(), amount, ordered_float::OrderedFloat(0.0))
+                } else {
+                    (e.source.clone(), amount, ordered_float::OrderedFloat(1.0))
+                }
+            })
+            .collect()
+    }
+
     /// Returns the "cheapest" edge between src and dist bearing the routing me in mind
     /// Used after finding the shortest paths and are therefore interested in routing along the
     /// edge
-    /// Necessary as we account for possible parallel edges
-    pub(crate) fn get_cheapest_edge(&mut self, from: &ID, to: &ID) -> Option<Edge> {
+    /// Necessary as we account for possible parallel edges, skipping those whose HTLC limits
+    /// exclude the amount the edge carries or that have no free HTLC slots
+    pub(crate) fn get_cheapest_edge(&self, from: &ID, to: &ID, amount: usize) -> Option<Edge> {
         let from_to_outedges = self.graph.get_all_src_dest_edges(from, to);
         let mut cheapest_edge = None;
         let mut min_weight = ordered_float::OrderedFloat(f32::MAX);
-        for edge in from_to_outedges.into_iter() {
-            let edge_weight = Self::get_edge_weight(&edge, self.amount, self.routing_metric);
+        for edge in from_to_outedges
+            .into_iter()
+            .filter(|e| e.htlc_limits_allow(amount) && self.graph.htlc_slot_available(e))
+        {
+            let edge_weight = Self::get_edge_weight(&edge, amount, self.routing_metric);
             if edge_weight < min_weight {
                 min_weight = edge_weight;
                 cheapest_edge = Some(edge);
@@ -462,21 +590,68 @@ impl PathFinder {
     }
 
     /// Remove edges that do not meet the minimum criteria (cap < amount) from the graph
+    /// together with their opposite direction. Directions whose HTLC maximum is below the amount
+    /// are removed on their own. The HTLC minimum is left to the path search as fees of later
+    /// hops may raise the amount above it.
     pub fn remove_inadequate_edges(graph: &Graph, amount: usize) -> HashMap<String, Vec<Edge>> {
-        debug!("Removing edges with insufficient funds.");
-        let mut copy = graph.clone();
-        let mut ctr = 0;
-        for edge in graph.edges.iter() {
-            // iter each node's edges
-            for e in edge.1 {
-                if e.balance < amount {
-                    ctr += 1;
-                    copy.remove_edge(&e.source, &e.destination);
+        debug!("Removing edges with insufficient funds or unsuitable HTLC limits.");
+        // (source, channel id) of the directions to remove
+        let mut inadequate: HashSet<(&ID, &String)> = HashSet::new();
+        for e in graph.edges.values().flatten() {
+            if graph.spendable_balance(e) < amount {
+                inadequate.insert((&e.source, &e.channel_id));
+                if let Some(reverse) = graph.get_reverse_edge(e) {
+                    inadequate.insert((&reverse.source, &reverse.channel_id));
                 }
+            } else if e.htlc_maximum_msat != 0 && e.htlc_maximum_msat < amount {
+                inadequate.insert((&e.source, &e.channel_id));
             }
         }
-        trace!("Removed {} edges with insufficient funds.", ctr);
-        copy.edges
+        trace!(
+            "Removed {} edges with insufficient funds or unsuitable HTLC limits.",
+            inadequate.len()
+        );
+        graph
+            .edges
+            .iter()
+            .map(|(id, edges)| {
+                let adequate = edges
+                    .iter()
+                    .filter(|e| !inadequate.contains(&(&e.source, &e.channel_id)))
+                    .cloned()
+                    .collect();
+                (id.clone(), adequate)
+            })
+            .collect()
+    }
+
+    /// Remove edges into nodes other than dest that cannot forward the payment, i.e. nodes lacking
+    /// any of the required features or requiring features we do not know
+    pub fn remove_unsupported_intermediaries(
+        graph: &Graph,
+        dest: &ID,
+        required: &[Feature],
+    ) -> HashMap<String, Vec<Edge>> {
+        debug!("Removing edges to intermediaries lacking required features.");
+        let unsupported: HashSet<&ID> = graph
+            .nodes
+            .iter()
+            .filter(|n| &n.id != dest && !n.features.is_compatible(required))
+            .map(|n| &n.id)
+            .collect();
+        let mut edges = graph.edges.clone();
+        let mut ctr = 0;
+        for out_edges in edges.values_mut() {
+            let len = out_edges.len();
+            out_edges.retain(|e| !unsupported.contains(&e.destination));
+            ctr += len - out_edges.len();
+        }
+        trace!(
+            "Removed {} edges to {} nodes lacking required features.",
+            ctr,
+            unsupported.len()
+        );
+        edges
     }
 }
 
@@ -553,7 +728,7 @@ mod tests {
         let json_file = std::path::Path::new("../test_data/lnbook_example.json");
         let mut graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &json_file,
+                json_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -599,7 +774,7 @@ mod tests {
         let json_file = std::path::Path::new("../test_data/lnbook_example.json");
         let mut graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &json_file,
+                json_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -653,7 +828,7 @@ mod tests {
         let json_file = std::path::Path::new("../test_data/lnbook_example.json");
         let graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &json_file,
+                json_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -676,8 +851,8 @@ mod tests {
                 ("bob".to_string(), 0, 0, "".to_string()),
             ]),
         };
-        let mut candidate_path = &mut CandidatePath::new_with_path(path);
-        PathFinder::get_aggregated_path_cost(&mut path_finder, &mut candidate_path, false);
+        let candidate_path = &mut CandidatePath::new_with_path(path);
+        PathFinder::get_aggregated_path_cost(&mut path_finder, candidate_path, false);
         let (actual_weight, actual_amount, actual_time) = (
             candidate_path.weight,
             candidate_path.amount,
@@ -690,6 +865,12 @@ mod tests {
         assert_eq!(actual_weight, expected_weight);
         assert_eq!(actual_amount, expected_amount);
         assert_eq!(actual_time, expected_time);
+        // dina's HTLC to chan and chan's to bob
+        let final_delta = crate::htlc::MIN_FINAL_CLTV_EXPIRY_DELTA;
+        assert_eq!(
+            candidate_path.htlc_expiry_deltas(),
+            vec![expected_time + final_delta, final_delta]
+        );
 
         path_finder.routing_metric = RoutingMetric::MaxProb;
     }
@@ -700,7 +881,7 @@ mod tests {
         let json_file = std::path::Path::new("../test_data/lnbook_example.json");
         let mut graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &json_file,
+                json_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -780,7 +961,7 @@ mod tests {
         let json_file = std::path::Path::new("../test_data/lnbook_example.json");
         let graph = Graph::to_sim_graph(
             &network_parser::Graph::from_json_file(
-                &json_file,
+                json_file,
                 network_parser::GraphSource::Lnresearch,
             )
             .unwrap(),
@@ -803,8 +984,8 @@ mod tests {
                 ("bob".to_string(), 0, 0, "".to_string()),
             ]),
         };
-        let mut candidate_path = &mut CandidatePath::new_with_path(path);
-        path_finder.get_aggregated_path_cost(&mut candidate_path, true);
+        let candidate_path = &mut CandidatePath::new_with_path(path);
+        path_finder.get_aggregated_path_cost(candidate_path, true);
         let (actual_weight, actual_amount, actual_time) = (
             candidate_path.weight,
             candidate_path.amount,
@@ -936,4 +1117,211 @@ mod tests {
         assert!(path.is_last_hop(&"chan".to_string()));
         assert!(!path.is_last_hop(&"dina".to_string()));
     }
+
+    #[test]
+    fn remove_inadequate_parallel_channels() {
+        let path_to_file = std::path::Path::new("../test_data/trivial_multigraph.json");
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                path_to_file,
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        for e in graph.edges.values_mut().flatten() {
+            e.balance = e.capacity / 2;
+        }
+        let edges = PathFinder::remove_inadequate_edges(&graph, 100000);
+        let mut remaining: Vec<&str> = edges
+            .values()
+            .flatten()
+            .map(|e| e.channel_id.as_str())
+            .collect();
+        remaining.sort();
+        assert_eq!(
+            remaining,
+            vec!["100x1x0/0", "100x1x0/1", "100x3x0/0", "100x3x0/1"]
+        );
+        let remaining = |graph: &Graph| {
+            let mut remaining: Vec<String> = PathFinder::remove_inadequate_edges(graph, 100000)
+                .into_values()
+                .flatten()
+                .map(|e| e.channel_id)
+                .collect();
+            remaining.sort();
+            remaining
+        };
+        // fees of later hops may still lift the amount above a minimum
+        for e in graph.edges.values_mut().flatten() {
+            if e.channel_id == "100x3x0/0" {
+                e.htlc_minimim_msat = 200000;
+            }
+        }
+        assert_eq!(
+            remaining(&graph),
+            vec!["100x1x0/0", "100x1x0/1", "100x3x0/0", "100x3x0/1"]
+        );
+        // a policy only excludes its own direction
+        for e in graph.edges.values_mut().flatten() {
+            if e.channel_id == "100x3x0/0" {
+                e.htlc_maximum_msat = 50000;
+            }
+        }
+        assert_eq!(
+            remaining(&graph),
+            vec!["100x1x0/0", "100x1x0/1", "100x3x0/1"]
+        );
+    }
+
+    #[test]
+    fn htlc_limits_include_downstream_fees() {
+        let path_to_file = std::path::Path::new("../test_data/trivial_multigraph.json");
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                path_to_file,
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        for e in graph.edges.values_mut().flatten() {
+            e.balance = 200000;
+        }
+        let candidate_path = |amount| {
+            PathFinder::new(
+                String::from("alice"),
+                String::from("carol"),
+                amount,
+                &graph,
+                RoutingMetric::MinFee,
+                PaymentParts::Single,
+            )
+            .find_path()
+            .unwrap()
+        };
+        // the cheaper channel to bob has an htlc_maximum_msat of 50000 and bob charges 100 msat
+        let candidate_path_below = candidate_path(49900);
+        assert_eq!(candidate_path_below.amount, 50000);
+        assert_eq!(candidate_path_below.path.hops[0].3, "100x2x0/0");
+        let candidate_path_above = candidate_path(49950);
+        assert_eq!(candidate_path_above.amount, 50050);
+        assert_eq!(candidate_path_above.path.hops[0].3, "100x1x0/0");
+    }
+
+    #[test]
+    fn skip_edges_without_htlc_slots() {
+        let path_to_file = std::path::Path::new("../test_data/trivial_multigraph.json");
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                path_to_file,
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        graph.set_max_accepted_htlcs(1);
+        let (alice, bob) = (String::from("alice"), String::from("bob"));
+        let occupy = |graph: &mut Graph, channel_id: &str| {
+            graph.add_htlc(&crate::htlc::Htlc {
+                source: alice.clone(),
+                channel_id: channel_id.to_owned(),
+                amount: 0,
+                expiry: 0,
+            })
+        };
+        let cheapest_edge = |graph: &Graph| {
+            let path_finder = PathFinder::new(
+                alice.clone(),
+                bob.clone(),
+                40000,
+                graph,
+                RoutingMetric::MinFee,
+                PaymentParts::Single,
+            );
+            path_finder
+                .get_cheapest_edge(&alice, &bob, 40000)
+                .map(|e| e.channel_id)
+        };
+        assert_eq!(cheapest_edge(&graph), Some("100x2x0/0".to_owned()));
+        occupy(&mut graph, "100x2x0/0");
+        assert_eq!(cheapest_edge(&graph), Some("100x1x0/0".to_owned()));
+        occupy(&mut graph, "100x1x0/0");
+        assert_eq!(cheapest_edge(&graph), None);
+    }
+
+    #[test]
+    fn remove_intermediaries_lacking_features() {
+        let json_file = std::path::Path::new("../test_data/lnbook_example.json");
+        let mut graph = Graph::to_sim_graph(
+            &network_parser::Graph::from_json_file(
+                json_file,
+                network_parser::GraphSource::Lnresearch,
+            )
+            .unwrap(),
+            network_parser::GraphSource::Lnresearch,
+        );
+        // alice can reach dina via bob or a direct channel to chan
+        let alice_chan = |edge: Edge, source: &str, destination: &str| Edge {
+            channel_id: format!("{}-{}", source, destination),
+            source: source.to_owned(),
+            destination: destination.to_owned(),
+            ..edge
+        };
+        let alice_bob = graph
+            .get_edge(&"alice".to_owned(), &"bob".to_owned())
+            .unwrap();
+        let bob_alice = graph
+            .get_edge(&"bob".to_owned(), &"alice".to_owned())
+            .unwrap();
+        graph.add_edges(vec![
+            alice_chan(alice_bob, "alice", "chan"),
+            alice_chan(bob_alice, "chan", "alice"),
+        ]);
+        for e in graph.edges.values_mut().flatten() {
+            e.balance = e.capacity / 2;
+        }
+        for node in graph.nodes.iter_mut() {
+            match node.id.as_str() {
+                // announces features but cannot forward
+                "bob" | "dina" => node.features.set(Feature::BasicMpp, false),
+                "chan" => node.features.set(Feature::VarOnionOptin, true),
+                // alice announces nothing
+                _ => {}
+            }
+        }
+        let dest = String::from("dina");
+        let edges = PathFinder::remove_unsupported_intermediaries(
+            &graph,
+            &dest,
+            &crate::FORWARDING_FEATURES,
+        );
+        let into = |node: &str| {
+            edges
+                .values()
+                .flatten()
+                .filter(|e| e.destination == node)
+                .count()
+        };
+        assert_eq!(into("bob"), 0);
+        assert!(into("alice") > 0);
+        assert!(into("chan") > 0);
+        assert!(into("dina") > 0);
+        // bob's own channels remain
+        assert_eq!(edges["bob"], graph.edges["bob"]);
+        graph.set_edges(edges);
+        let mut path_finder = PathFinder::new(
+            String::from("alice"),
+            dest,
+            5000,
+            &graph,
+            RoutingMetric::MinFee,
+            PaymentParts::Single,
+        );
+        let candidate = path_finder.find_path().unwrap();
+        assert_eq!(
+            candidate.path.get_involved_nodes(),
+            vec!["alice".to_owned(), "chan".to_owned(), "dina".to_owned()]
+        );
+    }
 }
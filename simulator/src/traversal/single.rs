@@ -1,5 +1,5 @@
 use crate::{
-    core_types::{event::PaymentEvent, time::Time},
+    core_types::event::PaymentEvent,
     payment::Payment,
     traversal::pathfinding::{CandidatePath, Path, PathFinder},
     Simulation,
@@ -20,11 +20,15 @@ impl Simulation {
             error!("Payment failing. Sender has no edge with sufficient balance. Amount {}, max balance {}", payment.amount_msat, max_out_balance);
             failed = true;
         }
-        // we are not interested in reversing payments here for single path payments
+        // the transfers are only reverted if the receiver holds the payment until it expires
         if !failed {
-            succeeded = self.send_one_payment(payment).0;
+            let to_revert;
+            (succeeded, to_revert) = self.send_one_payment(payment);
+            if succeeded {
+                succeeded = self.settle_htlcs(payment, to_revert);
+            }
         }
-        let now = self.event_queue.now() + Time::from_secs(crate::SIM_DELAY_IN_SECS);
+        let delay = self.outcome_delay(payment);
         let event = if succeeded {
             PaymentEvent::UpdateSuccesful {
                 payment: payment.to_owned(),
@@ -36,7 +40,7 @@ impl Simulation {
                 payment: payment.to_owned(),
             }
         };
-        self.event_queue.schedule(now, event);
+        self.event_queue.schedule(delay, event);
         succeeded
     }
 }
@@ -109,6 +113,10 @@ mod tests {
             failed_amounts: Vec::default(),
             successful_shards: Vec::default(),
             failed_paths: vec![],
+            completion_time_ms: None,
+            max_fee_msat: None,
+            parts: None,
+            ..Default::default()
         };
         simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
         assert!(simulator.send_single_payment(payment));
@@ -133,6 +141,10 @@ mod tests {
             failed_amounts: Vec::default(),
             successful_shards: Vec::default(),
             failed_paths: vec![],
+            completion_time_ms: None,
+            max_fee_msat: None,
+            parts: None,
+            ..Default::default()
         };
         simulator.add_invoice(Invoice::new(0, amount_msat, &source, &dest));
         assert!(simulator.send_single_payment(payment));
@@ -178,6 +190,10 @@ mod tests {
             failed_paths: vec![],
             failed_amounts: Vec::default(),
             successful_shards: Vec::default(),
+            completion_time_ms: None,
+            max_fee_msat: None,
+            parts: None,
+            ..Default::default()
         };
         assert!(!simulator.send_single_payment(&mut payment));
         assert!(!payment.failed_paths.is_empty());